@@ -0,0 +1,340 @@
+//! Finding public games. Servers started with `--announce` send the master server a heartbeat
+//! now and then, and `labyrinth browse` lists the games the master server has heard from.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use std::net::SocketAddr;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::game::{GameMode, GameState, Player};
+use crate::net::{transport, ServerListing, VERSION};
+use crate::server::MaxPlayers;
+use crate::{Cli, ClientOptions, LabyrinthPlugin};
+
+/// How often a server tells the master server it's still there. The master server is expected
+/// to drop servers it hasn't heard from in a few of these.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+const MASTER_SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the browser waits for a game to accept it, the same as the client's default
+const BROWSER_CONNECT_TIMEOUT: u64 = 10;
+
+/// Present on a server started with `--announce`
+#[derive(Resource)]
+pub(crate) struct Announcer {
+    url: String,
+    timer: Timer,
+    heartbeat: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Announcer {
+    pub(crate) fn new(url: String) -> Self {
+        let mut timer = Timer::new(ANNOUNCE_INTERVAL, TimerMode::Repeating);
+        // the first heartbeat goes out straight away
+        timer.set_elapsed(ANNOUNCE_INTERVAL);
+        Self {
+            url,
+            timer,
+            heartbeat: None,
+        }
+    }
+}
+
+/// The server browser screen, shown by `labyrinth browse` until a game is picked
+#[derive(Resource)]
+pub(crate) struct ServerBrowser {
+    url: String,
+    options: ClientOptions,
+    request: Option<JoinHandle<Result<Vec<ServerListing>, String>>>,
+    listings: Vec<ServerListing>,
+    selected: usize,
+    status: String,
+}
+
+impl ServerBrowser {
+    fn refresh(&mut self) {
+        let url = self.url.clone();
+        self.request = Some(thread::spawn(move || {
+            transport::fetch_server_list(&url, MASTER_SERVER_TIMEOUT).map_err(|err| err.to_string())
+        }));
+        self.status = format!("Fetching games from {}…", self.url);
+    }
+}
+
+/// Everything on the server browser screen, which is despawned once a game is picked
+#[derive(Component)]
+pub(crate) struct BrowserScreen;
+
+#[derive(Component)]
+pub(crate) struct BrowserText;
+
+impl LabyrinthPlugin {
+    /// Sends the master server a heartbeat every so often, and whenever the game starts or ends.
+    /// Heartbeats are sent from a thread of their own so that a slow master server doesn't hold
+    /// up the game.
+    pub(crate) fn server_announce(
+        mut announcer: ResMut<Announcer>,
+        time: Res<Time>,
+        cli: Res<Cli>,
+        players: Query<&Player>,
+        max_players: Res<MaxPlayers>,
+        game_mode: Res<GameMode>,
+        game_state: Res<State<GameState>>,
+    ) {
+        if announcer
+            .heartbeat
+            .as_ref()
+            .is_some_and(|heartbeat| heartbeat.is_finished())
+        {
+            let result = announcer.heartbeat.take().unwrap().join();
+            match result.unwrap_or_else(|_| Err("The heartbeat crashed".to_owned())) {
+                Ok(()) => debug!("Announced the server to {}", announcer.url),
+                Err(err) => warn!("Failed to announce the server to {}: {err}", announcer.url),
+            }
+        }
+        let due = announcer.timer.tick(time.delta()).just_finished();
+        if (!due && !game_state.is_changed()) || announcer.heartbeat.is_some() {
+            return;
+        }
+        let (Cli::Server(server_args)
+        | Cli::Host {
+            server: server_args,
+            ..
+        }) = &*cli
+        else {
+            return;
+        };
+
+        let listing = ServerListing {
+            // the default public IP only works on this machine
+            ip: server_args
+                .public_ip
+                .iter()
+                .copied()
+                .find(|ip| !ip.is_loopback()),
+            port: server_args.port,
+            version: VERSION.to_owned(),
+            mode: *game_mode,
+            players: players.iter().count(),
+            max_players: max_players.0,
+            game_state: *game_state.get(),
+            password: server_args.password.is_some(),
+        };
+        let url = announcer.url.clone();
+        announcer.heartbeat = Some(thread::spawn(move || {
+            transport::announce(&url, &listing, MASTER_SERVER_TIMEOUT)
+                .map_err(|err| err.to_string())
+        }));
+    }
+
+    pub(crate) fn browser_init(commands: &mut Commands, url: String, options: ClientOptions) {
+        let mut browser = ServerBrowser {
+            url,
+            options,
+            request: None,
+            listings: Vec::new(),
+            selected: 0,
+            status: String::new(),
+        };
+        browser.refresh();
+        commands.insert_resource(browser);
+
+        commands.spawn((Camera2dBundle::default(), BrowserScreen));
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    ..default()
+                },
+                BrowserScreen,
+            ))
+            .with_children(|parent| {
+                parent.spawn((TextBundle::default(), BrowserText));
+            });
+    }
+
+    pub(crate) fn browser_receive_list(mut browser: ResMut<ServerBrowser>) {
+        if !browser
+            .request
+            .as_ref()
+            .is_some_and(|request| request.is_finished())
+        {
+            return;
+        }
+        let result = browser.request.take().unwrap().join();
+        match result.unwrap_or_else(|_| Err("The request crashed".to_owned())) {
+            Ok(listings) => {
+                // games from other versions couldn't be joined anyway
+                browser.listings = listings
+                    .into_iter()
+                    .filter(|listing| listing.version == VERSION)
+                    .collect();
+                browser.selected = 0;
+                browser.status = if browser.listings.is_empty() {
+                    "Nobody is hosting a public game right now".to_owned()
+                } else {
+                    String::new()
+                };
+            }
+            Err(err) => {
+                error!("Failed to fetch the server list: {err}");
+                browser.status = format!("Failed to fetch games: {err}");
+            }
+        }
+    }
+
+    /// Up and Down pick a game, Enter joins it, R fetches the list again and Esc quits
+    pub(crate) fn browser_handle_input(
+        mut commands: Commands,
+        keys: Res<Input<KeyCode>>,
+        mut browser: ResMut<ServerBrowser>,
+        network_channels: Res<NetworkChannels>,
+        window: Query<&Window>,
+        assets: Res<AssetServer>,
+        mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+        screen: Query<Entity, With<BrowserScreen>>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        if keys.just_pressed(KeyCode::Escape) {
+            app_exit_events.send(AppExit);
+            return;
+        }
+        if keys.just_pressed(KeyCode::R) && browser.request.is_none() {
+            browser.refresh();
+            return;
+        }
+        let game_count = browser.listings.len();
+        if game_count == 0 {
+            return;
+        }
+        if keys.just_pressed(KeyCode::Up) {
+            browser.selected = (browser.selected + game_count - 1) % game_count;
+        }
+        if keys.just_pressed(KeyCode::Down) {
+            browser.selected = (browser.selected + 1) % game_count;
+        }
+        if !keys.just_pressed(KeyCode::Return) {
+            return;
+        }
+
+        let listing = &browser.listings[browser.selected];
+        if listing.password {
+            browser.status =
+                "That game needs a password, join it with `labyrinth client --password`".to_owned();
+            return;
+        }
+        let Some(ip) = listing.ip else {
+            browser.status = "The master server didn't say where that game is".to_owned();
+            return;
+        };
+        let port = listing.port;
+        info!("Joining {}", SocketAddr::new(ip, port));
+        // from here on it's the same as if the game had been joined with `labyrinth client`,
+        // which also lets the disconnect message retry the same game
+        let cli = Cli::Client {
+            ip,
+            port,
+            connect_timeout: BROWSER_CONNECT_TIMEOUT,
+            observe: false,
+            password: None,
+            room: None,
+            options: browser.options.clone(),
+        };
+        if let Err(err) = Self::client_connect(
+            &mut commands,
+            &network_channels,
+            ip,
+            port,
+            BROWSER_CONNECT_TIMEOUT,
+            false,
+            browser.options.name.as_deref(),
+            None,
+            None,
+            None,
+        ) {
+            error!("Failed to join {ip}: {err}");
+            browser.status = format!("Failed to join: {err}");
+            return;
+        }
+        for entity_id in screen.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+        commands.remove_resource::<ServerBrowser>();
+        Self::client_init(
+            &mut commands,
+            window.single(),
+            &assets,
+            &mut texture_atlases,
+            &browser.options,
+        );
+        commands.insert_resource(cli);
+    }
+
+    pub(crate) fn browser_update_text(
+        browser: Res<ServerBrowser>,
+        mut text: Query<&mut Text, With<BrowserText>>,
+    ) {
+        if !browser.is_changed() {
+            return;
+        }
+        let Ok(mut text) = text.get_single_mut() else {
+            return;
+        };
+        let text_style = |color| TextStyle {
+            font_size: 24.0,
+            color,
+            ..default()
+        };
+        let mut sections = vec![TextSection::new(
+            "Public games\n\n",
+            text_style(Color::WHITE),
+        )];
+        for (index, listing) in browser.listings.iter().enumerate() {
+            let address = listing
+                .ip
+                .map_or_else(|| "?".to_owned(), |ip| ip.to_string());
+            let state = match listing.game_state {
+                GameState::WaitingPlayers => "in the lobby",
+                GameState::InGame => "playing",
+                GameState::Paused => "paused",
+                GameState::Ended => "finished",
+            };
+            let color = if index == browser.selected {
+                Color::YELLOW
+            } else {
+                Color::WHITE
+            };
+            sections.push(TextSection::new(
+                format!(
+                    "{} {address}:{}  {:?}  {}/{} players, {state}{}\n",
+                    if index == browser.selected { ">" } else { " " },
+                    listing.port,
+                    listing.mode,
+                    listing.players,
+                    listing.max_players,
+                    if listing.password { ", password" } else { "" },
+                ),
+                text_style(color),
+            ));
+        }
+        if !browser.status.is_empty() {
+            sections.push(TextSection::new(
+                format!("\n{}\n", browser.status),
+                text_style(Color::GRAY),
+            ));
+        }
+        sections.push(TextSection::new(
+            "\nUp and Down to pick, Enter to join, R to refresh, Esc to quit",
+            text_style(Color::GRAY),
+        ));
+        text.sections = sections;
+    }
+}
@@ -0,0 +1,146 @@
+//! Zooming and panning the camera, so that the cells of a big board don't have to stay tiny
+
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+
+use crate::client::WindowSize;
+use crate::local::SplitCamera;
+use crate::LabyrinthPlugin;
+
+const MAX_ZOOM: f32 = 4.0;
+/// How much one notch of the scroll wheel zooms in or out
+const ZOOM_PER_LINE: f32 = 1.15;
+/// Trackpads scroll by pixels rather than by lines
+const PIXELS_PER_LINE: f32 = 100.0;
+
+/// Where the camera is looking. The board is laid out once, for the [`WindowSize`] the game
+/// started with, and the camera scales that layout to fit the window however it's resized. With
+/// no zoom the whole board is framed, which is where the camera starts.
+#[derive(Resource)]
+pub(crate) struct CameraController {
+    /// How many times bigger than the framed board everything looks
+    zoom: f32,
+    /// The point in the layout at the middle of the window
+    pan: Vec2,
+    /// Where the cursor was last frame while dragging the view around
+    drag_from: Option<Vec2>,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            pan: Vec2::ZERO,
+            drag_from: None,
+        }
+    }
+}
+
+impl CameraController {
+    /// How many layout units each of the window's pixels covers
+    fn scale(&self, window_size: Vec2, window: &Window) -> f32 {
+        let fit = window_size / Vec2::new(window.width(), window.height());
+        fit.max_element() / self.zoom
+    }
+
+    /// Zooms in by `factor`, or out if it's less than 1, keeping whatever is at `screen_pos` there
+    pub(crate) fn zoom_around(
+        &mut self,
+        factor: f32,
+        screen_pos: Option<Vec2>,
+        window_size: Vec2,
+        window: &Window,
+    ) {
+        let old_scale = self.scale(window_size, window);
+        self.zoom = (self.zoom * factor).clamp(1.0, MAX_ZOOM);
+        let new_scale = self.scale(window_size, window);
+        if let Some(screen_pos) = screen_pos {
+            let from_middle =
+                to_layout(screen_pos - Vec2::new(window.width(), window.height()) * 0.5);
+            self.pan += from_middle * (old_scale - new_scale);
+        }
+    }
+
+    /// Moves the view along with something dragged `screen_delta` across the window
+    pub(crate) fn drag(&mut self, screen_delta: Vec2, window_size: Vec2, window: &Window) {
+        self.pan -= to_layout(screen_delta) * self.scale(window_size, window);
+    }
+
+    /// Keeps the view on the layout, so that the board can't be dragged out of sight. At no zoom
+    /// this always centers it.
+    pub(crate) fn clamp_pan(&mut self, window_size: Vec2) {
+        let max_pan = window_size * 0.5 * (1.0 - 1.0 / self.zoom);
+        self.pan = self.pan.clamp(-max_pan, max_pan);
+    }
+}
+
+/// The layout's y axis points up, but the window's points down
+fn to_layout(screen_delta: Vec2) -> Vec2 {
+    screen_delta * Vec2::new(1.0, -1.0)
+}
+
+impl LabyrinthPlugin {
+    /// Scrolling zooms in on the cursor, dragging with the right or middle mouse button pans, and
+    /// F frames the whole board again
+    pub(crate) fn client_handle_camera_input(
+        mut controller: ResMut<CameraController>,
+        mut wheel_events: EventReader<MouseWheel>,
+        mouse_buttons: Res<Input<MouseButton>>,
+        keys: Res<Input<KeyCode>>,
+        window: Query<&Window>,
+        window_size: Res<WindowSize>,
+    ) {
+        let window = window.single();
+        let cursor = window.cursor_position();
+
+        for event in wheel_events.read() {
+            let lines = match event.unit {
+                MouseScrollUnit::Line => event.y,
+                MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+            };
+            controller.zoom_around(ZOOM_PER_LINE.powf(lines), cursor, window_size.0, window);
+        }
+
+        let dragging = mouse_buttons.any_pressed([MouseButton::Right, MouseButton::Middle]);
+        match (dragging, controller.drag_from, cursor) {
+            (true, Some(drag_from), Some(cursor)) => {
+                controller.drag(cursor - drag_from, window_size.0, window);
+                controller.drag_from = Some(cursor);
+            }
+            (true, None, cursor) => controller.drag_from = cursor,
+            _ => controller.drag_from = None,
+        }
+
+        if keys.just_pressed(KeyCode::F) {
+            *controller = CameraController::default();
+        }
+        controller.clamp_pan(window_size.0);
+    }
+
+    /// Points the camera where the controller says. This runs every frame rather than on
+    /// [`bevy::window::WindowResized`], so that the board is framed from the very first frame.
+    /// In split-screen, the halves of the window have their own cameras.
+    pub(crate) fn client_update_camera(
+        controller: Res<CameraController>,
+        window: Query<&Window>,
+        window_size: Res<WindowSize>,
+        mut camera: Query<
+            (&mut OrthographicProjection, &mut Transform),
+            (With<Camera2d>, Without<SplitCamera>),
+        >,
+    ) {
+        let window = window.single();
+        if window.width() <= 0.0 || window.height() <= 0.0 {
+            // minimized
+            return;
+        }
+        let (mut projection, mut transform) = camera.single_mut();
+        let scale = controller.scale(window_size.0, window);
+        if projection.scale != scale {
+            projection.scale = scale;
+        }
+        if transform.translation.truncate() != controller.pan {
+            transform.translation = controller.pan.extend(transform.translation.z);
+        }
+    }
+}
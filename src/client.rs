@@ -0,0 +1,3627 @@
+//! Drawing the board and turning the local player's input into requests to the server
+
+use bevy::app::AppExit;
+use bevy::asset::LoadState;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use bevy::window::{WindowCloseRequested, WindowFocused};
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::transport::{NetcodeClientTransport, NetcodeDisconnectReason};
+use clap::ValueEnum;
+use std::time::Duration;
+
+use crate::camera::CameraController;
+use crate::effects::Particles;
+use crate::game::{
+    to_hex, ChatBroadcast, ChatMessage, CurrentTurn, Dice, DiceCommitment, DiceRollRequest,
+    DiceSeedReveal, GameOver, GameOverReason, GameResult, GameState, Item, ItemCollected, Maze,
+    MoveRejected, MoveRejectionReason, MoveRequest, Movement, MovementChange, Player, PlayerLeft,
+    PlayerStartMoveAnimation, RaceState, ReadyToggle, RematchToggle, SpecialCell, TurnPhase,
+    VerifiableDice, ART_BOARD_SIZE, COLOR_NAMES, DEFAULT_BOARD_SIZE, ITEMS_TO_WIN,
+    MAX_CHAT_MESSAGE_LENGTH,
+};
+use crate::net::{LocalClientId, ReconnectToken, VERSION};
+use crate::palette::{Palette, PawnMarks};
+use crate::server::MaxPlayers;
+use crate::skins::{ChosenSkin, Skins};
+use crate::targets::KnownTargets;
+use crate::video::VideoSettings;
+use crate::{Cli, ClientOptions, LabyrinthPlugin};
+
+const CELL_SIZE: Vec2 = Vec2::new(0.152625, 0.1538);
+pub(crate) const PAWN_SIZE: f32 = 0.8;
+const BOARD_ASPECT_RATIO: f32 = 1600.0 / 1550.0;
+const BOARD_PADDING: f32 = 0.2;
+const MOVE_ANIM_DURATION: Duration = Duration::from_millis(500);
+const SHAKE_DURATION: Duration = Duration::from_millis(300);
+const SHAKE_WIGGLES: f32 = 3.0;
+// progress through a failed move at which the pawn explodes, and is then sent back to its start
+const FAIL_EXPLOSION_PROGRESS: f32 = 1.0 / 3.0;
+const FAIL_RESET_PROGRESS: f32 = 0.54;
+const EXPLOSION_FRAMES: usize = 22;
+const EXPLOSION_FRAME_TIME: Duration = Duration::from_nanos(
+    Duration::from_millis(500).subsec_nanos() as u64 / EXPLOSION_FRAMES as u64,
+);
+const COLLECT_EFFECT_DURATION: Duration = Duration::from_millis(800);
+pub(crate) const YOUR_TURN_FLASH_DURATION: Duration = Duration::from_secs(1);
+const HINT_DURATION: Duration = Duration::from_secs(3);
+const PLAYER_LEFT_NOTICE_DURATION: Duration = Duration::from_secs(3);
+const CHAT_HISTORY_LENGTH: usize = 8;
+/// Step pips wrap onto another row past this many, for the longer moves of two dice or a budget
+const STEP_PIPS_PER_ROW: u8 = 6;
+
+impl LabyrinthPlugin {
+    /// Sets up the local player's board and controls, for a client or the host
+    pub(crate) fn client_init(
+        commands: &mut Commands,
+        window: &Window,
+        assets: &AssetServer,
+        texture_atlases: &mut Assets<TextureAtlas>,
+        options: &ClientOptions,
+    ) {
+        let ClientOptions {
+            assist,
+            numpad,
+            ease,
+            palette,
+            pawn_marks,
+            mirror_controls,
+            debug_hud,
+            instant_moves,
+            confirm_moves,
+            assist_hints,
+            cursor_highlight,
+            move_hints,
+            ..
+        } = *options;
+
+        if assist {
+            commands.init_resource::<AutoWalk>();
+        }
+        if instant_moves {
+            commands.insert_resource(InstantMoves);
+        }
+        if confirm_moves {
+            commands.init_resource::<PlannedMoves>();
+        }
+        if let Some(crash_threshold) = assist_hints {
+            commands.insert_resource(AssistHints {
+                crash_threshold,
+                consecutive_crashes: 0,
+                timer: None,
+            });
+        }
+        commands.insert_resource(InputOptions {
+            numpad,
+            mirror_controls,
+        });
+        commands.insert_resource(ease);
+        commands.insert_resource(palette);
+        commands.insert_resource(pawn_marks);
+        if move_hints != MoveHints::Off {
+            commands.insert_resource(move_hints);
+        }
+        if let Some(skin) = &options.skin {
+            commands.insert_resource(ChosenSkin(skin.clone()));
+        }
+
+        #[cfg(feature = "presence")]
+        if let Some(app_id) = &options.discord_app_id {
+            Self::presence_init(commands, app_id);
+        }
+
+        Self::spawn_board(commands, window, assets, texture_atlases);
+        if debug_hud {
+            Self::spawn_debug_hud(commands);
+        }
+        if cursor_highlight {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgba(1.0, 1.0, 1.0, 0.15),
+                        ..default()
+                    },
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                CursorHighlight,
+            ));
+        }
+    }
+
+    /// Spawns the camera, board and HUD shared by every windowed mode, and starts loading assets
+    pub(crate) fn spawn_board(
+        commands: &mut Commands,
+        window: &Window,
+        assets: &AssetServer,
+        texture_atlases: &mut Assets<TextureAtlas>,
+    ) {
+        commands.insert_resource(WindowSize(Vec2::new(window.width(), window.height())));
+        commands.insert_resource(BoardCells(DEFAULT_BOARD_SIZE));
+        commands.insert_resource(Skins::scan());
+        commands.insert_resource(VideoSettings::load());
+
+        let background_texture: Handle<Image> = assets.load("background.png");
+
+        commands.spawn(Camera2dBundle::default());
+        commands.init_resource::<CameraController>();
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(8.0),
+                    width: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(6.0),
+                    ..default()
+                },
+                ..default()
+            },
+            TurnOrderDisplay,
+        ));
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        right: Val::Px(8.0),
+                        bottom: Val::Px(8.0),
+                        max_width: Val::Px(360.0),
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ChatPanel,
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    TextBundle::default()
+                        .with_style(Style {
+                            display: Display::None,
+                            ..default()
+                        })
+                        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+                    ChatInputText,
+                ));
+            });
+        commands.spawn((
+            SpriteBundle {
+                transform: Transform {
+                    translation: Vec3::NEG_Z,
+                    ..default()
+                },
+                sprite: Sprite {
+                    custom_size: Some(Self::calc_board_size(Vec2::new(
+                        window.width(),
+                        window.height(),
+                    ))),
+                    ..default()
+                },
+                texture: background_texture.clone(),
+                ..default()
+            },
+            Background,
+        ));
+
+        let atlas_layouts = AtlasLayouts::default();
+
+        let dice_texture: Handle<Image> = assets.load("dice.png");
+        let dice_atlas = atlas_layouts.dice.build(dice_texture.clone());
+        let dice_atlas_handle = texture_atlases.add(dice_atlas);
+
+        let explosion_texture: Handle<Image> = assets.load("explosion.png");
+        let explosion_atlas = atlas_layouts.explosion.build(explosion_texture.clone());
+        let explosion_atlas_handle = texture_atlases.add(explosion_atlas);
+
+        let items_atlas = atlas_layouts.items.build(background_texture.clone());
+        let items_atlas_handle = texture_atlases.add(items_atlas);
+
+        commands.insert_resource(TextureAtlases {
+            dice: dice_atlas_handle,
+            explosion: explosion_atlas_handle,
+            items: items_atlas_handle,
+        });
+
+        let pawn_texture: Handle<Image> = assets.load("pawn.png");
+        commands.insert_resource(LoadingAssets(vec![
+            ("background.png", background_texture.untyped()),
+            ("dice.png", dice_texture.untyped()),
+            ("explosion.png", explosion_texture.untyped()),
+            ("pawn.png", pawn_texture.untyped()),
+        ]));
+        commands.spawn((
+            Text2dBundle {
+                text: Text::from_section(
+                    "Loading…",
+                    TextStyle {
+                        font_size: 48.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                transform: Transform {
+                    translation: Vec3::Z * 2.0,
+                    ..default()
+                },
+                ..default()
+            },
+            LoadingText,
+        ));
+    }
+
+    fn spawn_debug_hud(commands: &mut Commands) {
+        commands.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.0),
+                bottom: Val::Px(8.0),
+                ..default()
+            })
+            .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+            DebugHud,
+        ));
+    }
+
+    pub(crate) fn spawn_overlay(
+        commands: &mut Commands,
+        message: String,
+        align_items: AlignItems,
+        marker: impl Component,
+    ) {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        justify_content: JustifyContent::Center,
+                        align_items,
+                        padding: UiRect::all(Val::Px(48.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                marker,
+            ))
+            .with_children(|parent| {
+                parent.spawn(
+                    TextBundle::from_section(
+                        message,
+                        TextStyle {
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    )
+                    .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+                );
+            });
+    }
+
+    pub(crate) fn client_update_connecting(
+        mut commands: Commands,
+        time: Res<Time>,
+        mut connecting: ResMut<Connecting>,
+        mut client: ResMut<RenetClient>,
+        connecting_text: Query<Entity, With<ConnectingText>>,
+    ) {
+        if client.is_connected() {
+            commands.remove_resource::<Connecting>();
+            for entity_id in connecting_text.iter() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+            return;
+        }
+
+        if connecting.0.tick(time.delta()).just_finished() && !client.is_disconnected() {
+            warn!("Timed out connecting to the server");
+            client.disconnect();
+        }
+    }
+
+    pub(crate) fn client_handle_retry(
+        mut commands: Commands,
+        keys: Res<Input<KeyCode>>,
+        cli: Res<Cli>,
+        network_channels: Res<NetworkChannels>,
+        reconnect_token: Option<Res<ReconnectToken>>,
+        disconnect_message: Query<Entity, With<DisconnectMessage>>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        if keys.just_pressed(KeyCode::Escape) {
+            app_exit_events.send(AppExit);
+            return;
+        }
+        if !keys.just_pressed(KeyCode::R) {
+            return;
+        }
+        let Cli::Client {
+            ip,
+            port,
+            connect_timeout,
+            observe,
+            ref password,
+            ref room,
+            ref options,
+        } = *cli
+        else {
+            return;
+        };
+
+        for entity_id in disconnect_message.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+        if let Err(err) = Self::client_connect(
+            &mut commands,
+            &network_channels,
+            ip,
+            port,
+            connect_timeout,
+            observe,
+            options.name.as_deref(),
+            password.as_deref(),
+            room.as_deref(),
+            reconnect_token.as_deref().copied(),
+        ) {
+            error!("Failed to reconnect: {err}");
+            app_exit_events.send(AppExit);
+        }
+    }
+
+    pub(crate) fn client_on_window_focus(
+        mut commands: Commands,
+        mut events: EventReader<WindowFocused>,
+        mut keys: ResMut<Input<KeyCode>>,
+        me: Query<(&Player, Option<&RaceState>), With<Me>>,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        game_state: Res<State<GameState>>,
+        focus_lost_dim: Query<Entity, With<FocusLostDim>>,
+    ) {
+        let Some(event) = events.read().last() else {
+            return;
+        };
+
+        if !event.focused {
+            // key releases while unfocused never reach us, so keys would otherwise stay held
+            keys.reset_all();
+            if focus_lost_dim.is_empty() {
+                commands.spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            width: Val::Percent(100.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                        ..default()
+                    },
+                    FocusLostDim,
+                ));
+            }
+            return;
+        }
+
+        for entity_id in focus_lost_dim.iter() {
+            commands.entity(entity_id).despawn();
+        }
+        if *game_state.get() != GameState::InGame {
+            return;
+        }
+        let Ok((me, race_state)) = me.get_single() else {
+            return;
+        };
+        if Self::client_my_phase(me, race_state, &current_turn, &turn_phase).is_some() {
+            Self::spawn_overlay(
+                &mut commands,
+                "Your turn!".to_string(),
+                AlignItems::Center,
+                YourTurnFlash(Timer::new(YOUR_TURN_FLASH_DURATION, TimerMode::Once)),
+            );
+        }
+    }
+
+    pub(crate) fn client_update_your_turn_flash(
+        mut commands: Commands,
+        time: Res<Time>,
+        mut flashes: Query<(Entity, &mut YourTurnFlash)>,
+    ) {
+        for (entity_id, mut flash) in flashes.iter_mut() {
+            if flash.0.tick(time.delta()).finished() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+        }
+    }
+
+    pub(crate) fn client_check_assets_loaded(
+        mut commands: Commands,
+        loading_assets: Res<LoadingAssets>,
+        assets: Res<AssetServer>,
+        loading_text: Query<Entity, With<LoadingText>>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        let mut all_loaded = true;
+        for (name, handle) in &loading_assets.0 {
+            match assets.get_load_state(handle.id()) {
+                Some(LoadState::Loaded) => {}
+                Some(LoadState::Failed) => {
+                    error!(
+                        "Failed to load asset {name}, check that it exists in the assets folder"
+                    );
+                    app_exit_events.send(AppExit);
+                    return;
+                }
+                _ => all_loaded = false,
+            }
+        }
+        if all_loaded {
+            commands.remove_resource::<LoadingAssets>();
+            for entity_id in loading_text.iter() {
+                commands.entity(entity_id).despawn();
+            }
+        }
+    }
+
+    pub(crate) fn client_on_disconnected(
+        mut commands: Commands,
+        client: Res<RenetClient>,
+        transport: Res<NetcodeClientTransport>,
+        connecting: Option<Res<Connecting>>,
+        connecting_text: Query<Entity, With<ConnectingText>>,
+        disconnect_message: Query<(), With<DisconnectMessage>>,
+        game_result: Option<Res<GameResult>>,
+        reconnect_token: Option<Res<ReconnectToken>>,
+        game_state: Res<State<GameState>>,
+        replicated: Query<Entity, With<Replication>>,
+    ) {
+        if !disconnect_message.is_empty() {
+            return;
+        }
+        // the server stops once the game is over, but leave the summary up until the window closes
+        if game_result.is_some() {
+            return;
+        }
+
+        // the server holds our pawn for a while, so we can reclaim it with a new connection
+        if connecting.is_none()
+            && reconnect_token.is_some()
+            && matches!(game_state.get(), GameState::InGame | GameState::Paused)
+        {
+            warn!(
+                "Lost connection to the server: {:?}",
+                client.disconnect_reason()
+            );
+            // the server replicates everything again when we reconnect
+            for entity_id in replicated.iter() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+            commands.remove_resource::<RenetClient>();
+            commands.remove_resource::<NetcodeClientTransport>();
+            Self::spawn_overlay(
+                &mut commands,
+                "Lost connection to the server.\nPress R to reconnect or Esc to quit.".to_string(),
+                AlignItems::Center,
+                DisconnectMessage,
+            );
+            return;
+        }
+
+        // the game hadn't started, so there's no pawn to reclaim, but the lobby can be joined
+        // again
+        if connecting.is_none() {
+            info!("Client disconnected: {:?}", client.disconnect_reason());
+            for entity_id in replicated.iter() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+            commands.remove_resource::<RenetClient>();
+            commands.remove_resource::<NetcodeClientTransport>();
+            Self::spawn_overlay(
+                &mut commands,
+                "Lost connection to the server.\n\
+                 Press R to retry, A to change the address or Esc to quit."
+                    .to_string(),
+                AlignItems::Center,
+                DisconnectMessage,
+            );
+            return;
+        }
+
+        for entity_id in connecting_text.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+
+        // A server with a different protocol ID ignores our connection requests entirely, so it
+        // looks the same as a server that isn't running.
+        let reason = match transport.disconnect_reason() {
+            Some(reason @ NetcodeDisconnectReason::ConnectionDenied) => {
+                error!("Failed to connect: {reason:?}");
+                "The server refused the connection, it may be full or the game may have started."
+                    .to_string()
+            }
+            reason => {
+                error!(
+                    "Server did not respond ({reason:?}), it may not be running or may be a \
+                    different version to this client ({VERSION})"
+                );
+                format!(
+                    "It may not be running, or it may be running a different version.\n\
+                    This client is version {VERSION}."
+                )
+            }
+        };
+        Self::spawn_overlay(
+            &mut commands,
+            format!(
+                "Could not connect to the server.\n{reason}\n\
+                 Press R to retry, A to change the address or Esc to quit."
+            ),
+            AlignItems::Center,
+            DisconnectMessage,
+        );
+    }
+
+    pub(crate) fn client_on_window_close_requested(
+        mut events: EventReader<WindowCloseRequested>,
+        mut client: Option<ResMut<RenetClient>>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        for _ in events.read() {
+            if let Some(client) = client.as_mut() {
+                client.disconnect();
+            }
+            app_exit_events.send(AppExit);
+        }
+    }
+
+    pub(crate) fn client_update_cursor_highlight(
+        window: Query<&Window>,
+        camera: Query<(&Camera, &GlobalTransform)>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        mut highlight: Query<(&mut Transform, &mut Sprite, &mut Visibility), With<CursorHighlight>>,
+    ) {
+        let (mut transform, mut sprite, mut visibility) = highlight.single_mut();
+        let (camera, camera_transform) = camera.single();
+        let board_size = Self::calc_board_size(window_size.0);
+        let Some(board_pos) = window
+            .single()
+            .cursor_position()
+            .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+            .and_then(|pos| Self::pos_to_board_pos(pos, board_size, cells.0))
+        else {
+            *visibility = Visibility::Hidden;
+            return;
+        };
+        *visibility = Visibility::Inherited;
+        transform.translation = Self::board_pos_to_pos(board_pos, board_size, cells.0).extend(-0.5);
+        sprite.custom_size = Some(Self::calc_cell_size(board_size, cells.0));
+    }
+
+    /// T opens the chat box, Enter sends the message and Esc discards it
+    pub(crate) fn client_handle_chat_input(
+        mut commands: Commands,
+        keys: Res<Input<KeyCode>>,
+        mut chars: EventReader<ReceivedCharacter>,
+        chat_input: Option<ResMut<ChatInput>>,
+        mut chat_input_text: Query<(&mut Text, &mut Style), With<ChatInputText>>,
+        mut chat_writer: EventWriter<ChatMessage>,
+    ) {
+        let Ok((mut text, mut style)) = chat_input_text.get_single_mut() else {
+            return;
+        };
+        let Some(mut chat_input) = chat_input else {
+            // the T that opened the chat box shouldn't be typed into it
+            chars.clear();
+            if keys.just_pressed(KeyCode::T) {
+                commands.init_resource::<ChatInput>();
+                style.display = Display::Flex;
+                *text = Self::chat_input_text("");
+            }
+            return;
+        };
+
+        if keys.just_pressed(KeyCode::Escape) || keys.just_pressed(KeyCode::Return) {
+            if keys.just_pressed(KeyCode::Return) && !chat_input.0.trim().is_empty() {
+                chat_writer.send(ChatMessage(chat_input.0.trim().to_owned()));
+            }
+            commands.remove_resource::<ChatInput>();
+            style.display = Display::None;
+            chars.clear();
+            return;
+        }
+        if keys.just_pressed(KeyCode::Back) {
+            chat_input.0.pop();
+        }
+        for event in chars.read() {
+            if !event.char.is_control() && chat_input.0.chars().count() < MAX_CHAT_MESSAGE_LENGTH {
+                chat_input.0.push(event.char);
+            }
+        }
+        if chat_input.is_changed() {
+            *text = Self::chat_input_text(&chat_input.0);
+        }
+    }
+
+    fn chat_input_text(message: &str) -> Text {
+        Text::from_section(
+            format!("> {message}_"),
+            TextStyle {
+                font_size: 16.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+    }
+
+    pub(crate) fn client_on_reconnect_token(
+        mut commands: Commands,
+        mut reconnect_token_events: EventReader<ReconnectToken>,
+    ) {
+        if let Some(&token) = reconnect_token_events.read().last() {
+            commands.insert_resource(token);
+        }
+    }
+
+    pub(crate) fn client_on_chat_broadcast(
+        mut commands: Commands,
+        mut chat_events: EventReader<ChatBroadcast>,
+        chat_panel: Query<(Entity, &Children), With<ChatPanel>>,
+        chat_lines: Query<(), With<ChatLine>>,
+        palette: Res<Palette>,
+    ) {
+        let Ok((chat_panel_id, children)) = chat_panel.get_single() else {
+            return;
+        };
+        let mut lines: Vec<_> = children
+            .iter()
+            .copied()
+            .filter(|&child| chat_lines.contains(child))
+            .collect();
+        for event in chat_events.read() {
+            let (sender, color) = match event.player_number {
+                Some(player_number) => (
+                    COLOR_NAMES.get(player_number).map_or_else(
+                        || format!("Player {player_number}"),
+                        |name| name.to_string(),
+                    ),
+                    palette.color(player_number),
+                ),
+                None => ("Observer".to_owned(), Color::GRAY),
+            };
+            info!("[{sender}] {}", event.text);
+            let line = commands
+                .spawn((
+                    TextBundle::from_sections([
+                        TextSection::new(
+                            format!("[{sender}] "),
+                            TextStyle {
+                                font_size: 16.0,
+                                color,
+                                ..default()
+                            },
+                        ),
+                        TextSection::new(
+                            event.text.clone(),
+                            TextStyle {
+                                font_size: 16.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ),
+                    ])
+                    .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+                    ChatLine,
+                ))
+                .id();
+            // new lines go above the input box, the oldest scroll off the top
+            commands
+                .entity(chat_panel_id)
+                .insert_children(lines.len(), &[line]);
+            lines.push(line);
+            if lines.len() > CHAT_HISTORY_LENGTH {
+                commands.entity(lines.remove(0)).despawn_recursive();
+            }
+        }
+    }
+
+    /// Says when someone drops out of the game, since their pawn being greyed out or disappearing
+    /// is easy to miss
+    pub(crate) fn client_on_player_left(
+        mut commands: Commands,
+        mut player_left_events: EventReader<PlayerLeft>,
+        players: Query<&Player>,
+        time: Res<Time>,
+        mut notices: Query<(Entity, &mut PlayerLeftNotice)>,
+    ) {
+        for (entity_id, mut notice) in notices.iter_mut() {
+            if notice.0.tick(time.delta()).finished() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+        }
+
+        for event in player_left_events.read() {
+            // the pawn of a player who left for good may already be gone
+            let name = players
+                .iter()
+                .find(|player| player.player_number == event.player_number)
+                .map(Player::display_name)
+                .or_else(|| {
+                    COLOR_NAMES
+                        .get(event.player_number)
+                        .map(|name| name.to_string())
+                })
+                .unwrap_or_else(|| format!("Player {}", event.player_number));
+            let message = if event.for_good {
+                format!("{name} left the game")
+            } else {
+                format!("{name} disconnected, skipping their turns")
+            };
+            info!("{message}");
+            for (entity_id, _) in notices.iter() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+            Self::spawn_overlay(
+                &mut commands,
+                message,
+                AlignItems::FlexStart,
+                PlayerLeftNotice(Timer::new(PLAYER_LEFT_NOTICE_DURATION, TimerMode::Once)),
+            );
+        }
+    }
+
+    pub(crate) fn client_toggle_settings_menu(
+        mut commands: Commands,
+        keys: Res<Input<KeyCode>>,
+        settings_menu: Option<Res<SettingsMenu>>,
+        settings_menu_display: Query<Entity, With<SettingsMenuDisplay>>,
+        planned_moves: Option<Res<PlannedMoves>>,
+        auto_walk: Option<Res<AutoWalk>>,
+    ) {
+        if !keys.just_pressed(KeyCode::Escape) {
+            return;
+        }
+        if planned_moves.is_some_and(|planned_moves| !planned_moves.steps.is_empty()) {
+            return;
+        }
+        // Escape stops auto-walk instead
+        if auto_walk.is_some_and(|auto_walk| auto_walk.active) {
+            return;
+        }
+        if settings_menu.is_some() {
+            commands.remove_resource::<SettingsMenu>();
+            for entity_id in settings_menu_display.iter() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+            return;
+        }
+
+        commands.init_resource::<SettingsMenu>();
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    ..default()
+                },
+                SettingsMenuDisplay,
+            ))
+            .with_children(|parent| {
+                parent.spawn(
+                    TextBundle::default()
+                        .with_style(Style {
+                            padding: UiRect::all(Val::Px(16.0)),
+                            ..default()
+                        })
+                        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+                );
+            });
+    }
+
+    pub(crate) fn client_update_settings_menu(
+        mut commands: Commands,
+        keys: Res<Input<KeyCode>>,
+        mut settings_menu: ResMut<SettingsMenu>,
+        mut ease: ResMut<Ease>,
+        mut palette: ResMut<Palette>,
+        mut pawn_marks: ResMut<PawnMarks>,
+        mut input_options: ResMut<InputOptions>,
+        mut video_settings: ResMut<VideoSettings>,
+        instant_moves: Option<Res<InstantMoves>>,
+        auto_walk: Option<Res<AutoWalk>>,
+        debug_hud: Query<Entity, With<DebugHud>>,
+        settings_menu_display: Query<&Children, With<SettingsMenuDisplay>>,
+        mut texts: Query<&mut Text>,
+    ) {
+        if keys.just_pressed(KeyCode::Up) {
+            settings_menu.selected =
+                (settings_menu.selected + Setting::ALL.len() - 1) % Setting::ALL.len();
+        }
+        if keys.just_pressed(KeyCode::Down) {
+            settings_menu.selected = (settings_menu.selected + 1) % Setting::ALL.len();
+        }
+
+        let forwards = keys.any_just_pressed([KeyCode::Right, KeyCode::Return]);
+        if forwards || keys.just_pressed(KeyCode::Left) {
+            match Setting::ALL[settings_menu.selected] {
+                Setting::Ease => *ease = cycle_value_enum(*ease, forwards),
+                Setting::Palette => *palette = cycle_value_enum(*palette, forwards),
+                Setting::PawnMarks => *pawn_marks = cycle_value_enum(*pawn_marks, forwards),
+                Setting::InstantMoves => {
+                    if instant_moves.is_some() {
+                        commands.remove_resource::<InstantMoves>();
+                    } else {
+                        commands.insert_resource(InstantMoves);
+                    }
+                }
+                Setting::MirrorControls => {
+                    input_options.mirror_controls =
+                        cycle_value_enum(input_options.mirror_controls, forwards);
+                }
+                Setting::Numpad => input_options.numpad = !input_options.numpad,
+                Setting::Assist => {
+                    if auto_walk.is_some() {
+                        commands.remove_resource::<AutoWalk>();
+                    } else {
+                        commands.init_resource::<AutoWalk>();
+                    }
+                }
+                Setting::DebugHud => {
+                    if debug_hud.is_empty() {
+                        Self::spawn_debug_hud(commands);
+                    } else {
+                        for entity_id in debug_hud.iter() {
+                            commands.entity(entity_id).despawn();
+                        }
+                    }
+                }
+                Setting::DisplayMode => {
+                    video_settings.display_mode =
+                        cycle_value_enum(video_settings.display_mode, forwards);
+                }
+                Setting::Vsync => video_settings.vsync = !video_settings.vsync,
+                Setting::Resolution => video_settings.cycle_resolution(forwards),
+            }
+        }
+
+        // toggled resources and entities only show up here next frame, which is soon enough
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_string();
+        let values = Setting::ALL.map(|setting| match setting {
+            Setting::Ease => value_enum_name(*ease),
+            Setting::Palette => value_enum_name(*palette),
+            Setting::PawnMarks => value_enum_name(*pawn_marks),
+            Setting::InstantMoves => on_off(instant_moves.is_some()),
+            Setting::MirrorControls => value_enum_name(input_options.mirror_controls),
+            Setting::Numpad => on_off(input_options.numpad),
+            Setting::Assist => on_off(auto_walk.is_some()),
+            Setting::DebugHud => on_off(!debug_hud.is_empty()),
+            Setting::DisplayMode => value_enum_name(video_settings.display_mode),
+            Setting::Vsync => on_off(video_settings.vsync),
+            Setting::Resolution => video_settings.resolution_name(),
+        });
+        let style = TextStyle {
+            font_size: 32.0,
+            color: Color::WHITE,
+            ..default()
+        };
+        let mut sections = vec![TextSection::new(
+            "Settings (arrow keys to change, Esc to close)\n",
+            style.clone(),
+        )];
+        for (index, (setting, value)) in Setting::ALL.iter().zip(values).enumerate() {
+            let selected = index == settings_menu.selected;
+            sections.push(TextSection::new(
+                format!(
+                    "\n{} {}: {value}",
+                    if selected { ">" } else { " " },
+                    setting.name()
+                ),
+                TextStyle {
+                    color: if selected {
+                        Color::YELLOW
+                    } else {
+                        Color::WHITE
+                    },
+                    ..style.clone()
+                },
+            ));
+        }
+        for children in settings_menu_display.iter() {
+            for &child in children {
+                if let Ok(mut text) = texts.get_mut(child) {
+                    text.sections = sections.clone();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn client_handle_keyboard_input(
+        keys: Res<Input<KeyCode>>,
+        input_options: Res<InputOptions>,
+        mut controls: TurnControls,
+    ) {
+        let pressed = |key: KeyCode, numpad_key: KeyCode| {
+            keys.just_pressed(key) || (input_options.numpad && keys.just_pressed(numpad_key))
+        };
+
+        let step = [
+            (KeyCode::W, KeyCode::Up, KeyCode::Numpad8, MoveRequest::Up),
+            (
+                KeyCode::S,
+                KeyCode::Down,
+                KeyCode::Numpad2,
+                MoveRequest::Down,
+            ),
+            (
+                KeyCode::A,
+                KeyCode::Left,
+                KeyCode::Numpad4,
+                MoveRequest::Left,
+            ),
+            (
+                KeyCode::D,
+                KeyCode::Right,
+                KeyCode::Numpad6,
+                MoveRequest::Right,
+            ),
+        ]
+        .into_iter()
+        .find(|&(key, arrow_key, numpad_key, _)| {
+            keys.just_pressed(key) || pressed(arrow_key, numpad_key)
+        })
+        .map(|(.., request)| request);
+
+        controls.apply(TurnActions {
+            roll: pressed(KeyCode::Space, KeyCode::Numpad5),
+            step,
+            confirm: keys.just_pressed(KeyCode::Return),
+            cancel: keys.just_pressed(KeyCode::Escape),
+        });
+    }
+
+    /// Clicking or tapping the dice rolls it, and clicking a cell next to the pawn moves there
+    pub(crate) fn client_handle_mouse_input(
+        mouse_buttons: Res<Input<MouseButton>>,
+        touches: Res<Touches>,
+        window: Query<&Window>,
+        camera: Query<(&Camera, &GlobalTransform)>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        not_moving_me: Query<
+            (&Player, Option<&RaceState>),
+            (With<Me>, Without<PlayerMoveAnimation>),
+        >,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        mut roll_requests: EventWriter<DiceRollRequest>,
+        mut move_requests: EventWriter<MoveRequest>,
+        auto_walk: Option<Res<AutoWalk>>,
+        planned_moves: Option<ResMut<PlannedMoves>>,
+        dice: Query<&Dice>,
+        maze: Option<Res<Maze>>,
+    ) {
+        let screen_pos = if mouse_buttons.just_pressed(MouseButton::Left) {
+            window.single().cursor_position()
+        } else if cfg!(feature = "touch") {
+            // touch screens tap and swipe through client_handle_touch_input instead
+            None
+        } else {
+            touches
+                .iter_just_pressed()
+                .next()
+                .map(|touch| touch.position())
+        };
+        let (camera, camera_transform) = camera.single();
+        let Some(pos) = screen_pos
+            .and_then(|screen_pos| camera.viewport_to_world_2d(camera_transform, screen_pos))
+        else {
+            return;
+        };
+
+        let Ok((not_moving_me, race_state)) = not_moving_me.get_single() else {
+            return;
+        };
+        let Some(phase) =
+            Self::client_my_phase(not_moving_me, race_state, &current_turn, &turn_phase)
+        else {
+            return;
+        };
+        let board_size = Self::calc_board_size(window_size.0);
+        match phase {
+            TurnPhase::Rolling => {
+                let dice_pos =
+                    Self::calc_dice_pos(window_size.0, board_size, not_moving_me.player_number);
+                let dice_size = Self::calc_dice_size(window_size.0, board_size);
+                if ((pos - dice_pos).abs() * 2.0).cmple(dice_size).all() {
+                    roll_requests.send(DiceRollRequest);
+                }
+            }
+            TurnPhase::Moving { steps_taken } => {
+                let Some(board_pos) = Self::pos_to_board_pos(pos, board_size, cells.0) else {
+                    return;
+                };
+
+                if let Some(mut planned_moves) = planned_moves {
+                    if planned_moves.confirmed {
+                        return;
+                    }
+                    let Some(dice) = dice
+                        .iter()
+                        .find(|dice| dice.corner(current_turn.0) == not_moving_me.player_number)
+                    else {
+                        return;
+                    };
+                    let steps_left =
+                        (dice.value + not_moving_me.bonus_steps).saturating_sub(steps_taken);
+                    // clicks extend the planned path from wherever it currently ends
+                    let end = planned_moves
+                        .path(not_moving_me.coords, maze.as_deref())
+                        .last()
+                        .map_or(not_moving_me.coords, |&(coords, _)| coords);
+                    if let Some(request) = MoveRequest::from_delta(board_pos - end) {
+                        if planned_moves.steps.len() < steps_left as usize {
+                            planned_moves.steps.push(request);
+                        }
+                    }
+                    return;
+                }
+
+                if auto_walk.is_some_and(|auto_walk| auto_walk.active) {
+                    return;
+                }
+                if let Some(request) = MoveRequest::from_delta(board_pos - not_moving_me.coords) {
+                    move_requests.send(request);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn client_send_planned_moves(
+        mut planned_moves: ResMut<PlannedMoves>,
+        not_moving_me: Query<
+            (&Player, Option<&RaceState>),
+            (With<Me>, Without<PlayerMoveAnimation>),
+        >,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        mut move_requests: EventWriter<MoveRequest>,
+    ) {
+        if !planned_moves.confirmed {
+            return;
+        }
+        let Ok((me, race_state)) = not_moving_me.get_single() else {
+            // wait for the previous step's animation to finish
+            return;
+        };
+        let Some(TurnPhase::Moving { steps_taken }) =
+            Self::client_my_phase(me, race_state, &current_turn, &turn_phase)
+        else {
+            // our move ended early, most likely by crashing
+            *planned_moves = PlannedMoves::default();
+            return;
+        };
+        if planned_moves.last_sent_step == Some(steps_taken) {
+            // the server hasn't processed our last step yet
+            return;
+        }
+        if planned_moves.steps.is_empty() {
+            *planned_moves = PlannedMoves::default();
+            return;
+        }
+        move_requests.send(planned_moves.steps.remove(0));
+        planned_moves.last_sent_step = Some(steps_taken);
+    }
+
+    pub(crate) fn client_update_planned_path(
+        mut commands: Commands,
+        planned_moves: Res<PlannedMoves>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        me: Query<&Player, With<Me>>,
+        maze: Option<Res<Maze>>,
+        planned_steps: Query<Entity, With<PlannedStep>>,
+        palette: Res<Palette>,
+    ) {
+        if !planned_moves.is_changed() && !window_size.is_changed() {
+            return;
+        }
+        for entity_id in planned_steps.iter() {
+            commands.entity(entity_id).despawn();
+        }
+        // once the steps start being sent, the pawn's own animation shows where it's going
+        if planned_moves.confirmed {
+            return;
+        }
+        let Ok(me) = me.get_single() else {
+            return;
+        };
+
+        let board_size = Self::calc_board_size(window_size.0);
+        let size = Self::calc_cell_size(board_size, cells.0).y * PAWN_SIZE * 0.4;
+        for (coords, legal) in planned_moves.path(me.coords, maze.as_deref()) {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: if legal {
+                            palette.color(me.player_number).with_a(0.5)
+                        } else {
+                            Color::RED.with_a(0.8)
+                        },
+                        custom_size: Some(Vec2::splat(size)),
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: Self::board_pos_to_pos(coords, board_size, cells.0)
+                            .extend(0.5),
+                        ..default()
+                    },
+                    ..default()
+                },
+                PlannedStep,
+            ));
+        }
+    }
+
+    /// Highlights where the local player can get to with the steps they have left. The hints are
+    /// only rebuilt when the pawn, its steps or its target change, and are hidden while the pawn
+    /// is moving.
+    pub(crate) fn client_update_move_hints(
+        mut commands: Commands,
+        move_hints: Res<MoveHints>,
+        not_moving_me: Query<
+            (&Player, Option<&RaceState>),
+            (With<Me>, Without<PlayerMoveAnimation>),
+        >,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        dice: Query<&Dice>,
+        maze: Res<Maze>,
+        targets: Res<KnownTargets>,
+        window_size: Res<WindowSize>,
+        hint_cells: Query<Entity, With<MoveHintCell>>,
+        palette: Res<Palette>,
+        mut shown: Local<Option<(IVec2, u8, Option<IVec2>)>>,
+    ) {
+        let wanted = not_moving_me
+            .get_single()
+            .ok()
+            .and_then(|(me, race_state)| {
+                let TurnPhase::Moving { steps_taken } =
+                    Self::client_my_phase(me, race_state, &current_turn, &turn_phase)?
+                else {
+                    return None;
+                };
+                let dice = dice
+                    .iter()
+                    .find(|dice| dice.corner(current_turn.0) == me.player_number)?;
+                let steps_left = (dice.value + me.bonus_steps).saturating_sub(steps_taken);
+                let target = targets
+                    .get(me.player_number)
+                    .map(|item| item.coords(maze.size));
+                (steps_left != 0).then_some((me.coords, steps_left, target))
+            });
+        if *shown == wanted && !maze.is_changed() {
+            return;
+        }
+        *shown = wanted;
+        for entity_id in hint_cells.iter() {
+            commands.entity(entity_id).despawn();
+        }
+        let (Some((coords, steps_left, target)), Ok((me, _))) =
+            (wanted, not_moving_me.get_single())
+        else {
+            return;
+        };
+
+        let board_size = Self::calc_board_size(window_size.0);
+        let cell_size = Self::calc_cell_size(board_size, maze.size);
+        for reachable in maze.reachable_within(coords, steps_left) {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: palette.color(me.player_number).with_a(0.2),
+                        custom_size: Some(cell_size * 0.9),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(
+                        Self::board_pos_to_pos(reachable, board_size, maze.size).extend(-0.45),
+                    ),
+                    ..default()
+                },
+                MoveHintCell,
+            ));
+        }
+
+        if *move_hints != MoveHints::Path {
+            return;
+        }
+        let Some(path) = target.and_then(|target| maze.find_path(coords, target)) else {
+            return;
+        };
+        let size = cell_size.y * PAWN_SIZE * 0.25;
+        for step in path {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::WHITE.with_a(0.6),
+                        custom_size: Some(Vec2::splat(size)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(
+                        Self::board_pos_to_pos(step, board_size, maze.size).extend(0.4),
+                    ),
+                    ..default()
+                },
+                MoveHintCell,
+            ));
+        }
+    }
+
+    pub(crate) fn client_update_assist_hints(
+        mut commands: Commands,
+        mut start_move_animation_events: EventReader<PlayerStartMoveAnimation>,
+        local_client_id: Res<LocalClientId>,
+        mut assist_hints: ResMut<AssistHints>,
+        time: Res<Time>,
+        me: Query<&Player, With<Me>>,
+        maze: Res<Maze>,
+        targets: Res<KnownTargets>,
+        window_size: Res<WindowSize>,
+        hint_cells: Query<Entity, With<HintCell>>,
+        palette: Res<Palette>,
+    ) {
+        let mut show_hint = false;
+        let mut clear_hint = assist_hints
+            .timer
+            .as_mut()
+            .is_some_and(|timer| timer.tick(time.delta()).finished());
+        for event in start_move_animation_events.read() {
+            if event.client_id != local_client_id.0 {
+                continue;
+            }
+            if event.fail {
+                assist_hints.consecutive_crashes += 1;
+                if assist_hints.consecutive_crashes >= assist_hints.crash_threshold {
+                    show_hint = true;
+                }
+            } else {
+                // a successful step counts as progress, so the player doesn't need the hint
+                assist_hints.consecutive_crashes = 0;
+                clear_hint = true;
+                show_hint = false;
+            }
+        }
+
+        if clear_hint || show_hint {
+            for entity_id in hint_cells.iter() {
+                commands.entity(entity_id).despawn();
+            }
+            assist_hints.timer = None;
+        }
+        if !show_hint {
+            return;
+        }
+        let Ok(me) = me.get_single() else {
+            return;
+        };
+        let Some(target_item) = targets.get(me.player_number) else {
+            return;
+        };
+        // crashing always sends the pawn back to its start, even if that hasn't replicated yet
+        let start = Self::get_player_start_coords(me.player_number, maze.size);
+        let Some(path) = maze.find_path(start, target_item.coords(maze.size)) else {
+            return;
+        };
+
+        assist_hints.timer = Some(Timer::new(HINT_DURATION, TimerMode::Once));
+        let board_size = Self::calc_board_size(window_size.0);
+        for coords in path {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: palette.color(me.player_number).with_a(0.25),
+                        custom_size: Some(Self::calc_cell_size(board_size, maze.size) * 0.9),
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: Self::board_pos_to_pos(coords, board_size, maze.size)
+                            .extend(-0.5),
+                        ..default()
+                    },
+                    ..default()
+                },
+                HintCell,
+            ));
+        }
+    }
+
+    pub(crate) fn client_on_rep_game_state(
+        mut commands: Commands,
+        mut game_state_events: EventReader<GameState>,
+        mut turn_phase_events: EventReader<TurnPhase>,
+        mut current_turn_events: EventReader<CurrentTurn>,
+        mut start_move_animation_events: EventReader<PlayerStartMoveAnimation>,
+        mut game_state: ResMut<NextState<GameState>>,
+        mut turn_phase: ResMut<NextState<TurnPhase>>,
+        mut current_turn: ResMut<CurrentTurn>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        players: Query<(Entity, &Player)>,
+        mut dice: Query<(&Dice, &mut Transform)>,
+        local_client_id: Res<LocalClientId>,
+        mut auto_walk: Option<ResMut<AutoWalk>>,
+        instant_moves: Option<Res<InstantMoves>>,
+        atlases: Res<TextureAtlases>,
+    ) {
+        if let Some(state) = game_state_events.read().last() {
+            game_state.set(*state);
+        }
+        if let Some(phase) = turn_phase_events.read().last() {
+            turn_phase.set(*phase);
+        }
+        if let Some(turn) = current_turn_events.read().last() {
+            *current_turn = *turn;
+            for (dice, mut transform) in dice.iter_mut() {
+                transform.translation = Self::calc_dice_pos(
+                    window_size.0,
+                    Self::calc_board_size(window_size.0),
+                    dice.corner(turn.0),
+                )
+                .extend(0.0);
+            }
+        }
+        for event in start_move_animation_events.read() {
+            if let Some((entity_id, player)) = players
+                .iter()
+                .find(|(_, player)| player.client_id == event.client_id)
+            {
+                if event.fail && event.client_id == local_client_id.0 {
+                    if let Some(auto_walk) = auto_walk.as_mut() {
+                        auto_walk.active = false;
+                    }
+                }
+                if instant_moves.is_some() {
+                    // the pawn snaps to its new coords as soon as they're replicated, so there is
+                    // no animation to hold up input. Crashes still get an explosion at the wall.
+                    if event.fail {
+                        let board_size = Self::calc_board_size(window_size.0);
+                        let from = Self::board_pos_to_pos(player.prev_coords, board_size, cells.0);
+                        let to = Self::board_pos_to_pos(event.move_to, board_size, cells.0);
+                        Self::spawn_explosion(
+                            &mut commands,
+                            (from + to) * 0.5,
+                            Self::calc_cell_size(board_size, cells.0),
+                            &atlases,
+                        );
+                    }
+                    continue;
+                }
+                commands.entity(entity_id).insert(PlayerMoveAnimation {
+                    fail: event.fail,
+                    move_to: event.move_to,
+                    ..default()
+                });
+            }
+        }
+    }
+
+    pub(crate) fn client_on_move_rejected(
+        mut commands: Commands,
+        mut move_rejected_events: EventReader<MoveRejected>,
+        me: Query<Entity, With<Me>>,
+        mut auto_walk: Option<ResMut<AutoWalk>>,
+    ) {
+        let mut shake = false;
+        for MoveRejected { reason } in move_rejected_events.read() {
+            info!("Move rejected: {reason:?}");
+            // the step auto-walk is waiting on will never happen, so stop rather than wait forever
+            if let Some(auto_walk) = &mut auto_walk {
+                **auto_walk = AutoWalk::default();
+            }
+            // crashing already has its own explosion, and other rejections mean we're out of sync
+            // rather than that the player pressed the wrong key
+            shake |= matches!(
+                reason,
+                MoveRejectionReason::OutOfBounds
+                    | MoveRejectionReason::Occupied
+                    | MoveRejectionReason::ShiftDisconnects
+            );
+        }
+        if !shake {
+            return;
+        }
+        if let Ok(me) = me.get_single() {
+            commands.entity(me).insert(PlayerShake::default());
+        }
+    }
+
+    pub(crate) fn client_on_item_collected(
+        mut commands: Commands,
+        mut particles: Particles,
+        mut item_collected_events: EventReader<ItemCollected>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        palette: Res<Palette>,
+    ) {
+        let board_size = Self::calc_board_size(window_size.0);
+        for event in item_collected_events.read() {
+            info!(
+                "{} collected {}",
+                COLOR_NAMES[event.player_number], event.item
+            );
+            let color = palette.color(event.player_number);
+            let pos = Self::board_pos_to_pos(event.item.coords(cells.0), board_size, cells.0);
+            let cell_size = Self::calc_cell_size(board_size, cells.0);
+            particles.sparkle(color, pos, cell_size);
+            Self::spawn_collect_effect(&mut commands, color, pos, cell_size);
+        }
+    }
+
+    pub(crate) fn client_on_rep_maze(
+        mut commands: Commands,
+        mut maze_events: EventReader<Maze>,
+        walls: Query<Entity, With<MazeWall>>,
+        board_cells: Query<Entity, With<BoardCell>>,
+        mut background: Query<(&mut Sprite, &mut Handle<Image>), With<Background>>,
+        window_size: Res<WindowSize>,
+        mut cells: ResMut<BoardCells>,
+        atlases: Res<TextureAtlases>,
+        server: Option<Res<RenetServer>>,
+    ) {
+        let Some(maze) = maze_events.read().last() else {
+            return;
+        };
+        // the host already has the whole maze, which a fogged one mustn't replace
+        if server.is_none() {
+            commands.insert_resource(maze.clone());
+        }
+        cells.0 = maze.size;
+
+        for wall_id in walls.iter().chain(board_cells.iter()) {
+            commands.entity(wall_id).despawn();
+        }
+        let board_size = Self::calc_board_size(window_size.0);
+
+        // the artwork only has cells and items in the right places for its own board size
+        if maze.size != ART_BOARD_SIZE {
+            if let Ok((mut sprite, mut texture)) = background.get_single_mut() {
+                *texture = Handle::default();
+                sprite.color = Color::rgb(0.15, 0.12, 0.1);
+            }
+            let cell_size = Self::calc_cell_size(board_size, maze.size);
+            for y in 0..maze.size as i32 {
+                for x in 0..maze.size as i32 {
+                    let coords = IVec2::new(x, y);
+                    commands.spawn((
+                        SpriteBundle {
+                            sprite: Sprite {
+                                color: Color::rgb(0.35, 0.3, 0.25),
+                                custom_size: Some(cell_size * 0.95),
+                                ..default()
+                            },
+                            transform: Transform::from_translation(
+                                Self::board_pos_to_pos(coords, board_size, maze.size).extend(-0.8),
+                            ),
+                            ..default()
+                        },
+                        BoardCell {
+                            coords,
+                            scale: 0.95,
+                        },
+                    ));
+                }
+            }
+            for item in Item::ALL {
+                let coords = item.coords(maze.size);
+                commands.spawn((
+                    SpriteSheetBundle {
+                        sprite: TextureAtlasSprite {
+                            index: item.atlas_index(),
+                            custom_size: Some(cell_size * 0.8),
+                            ..default()
+                        },
+                        texture_atlas: atlases.items.clone(),
+                        transform: Transform::from_translation(
+                            Self::board_pos_to_pos(coords, board_size, maze.size).extend(-0.7),
+                        ),
+                        ..default()
+                    },
+                    BoardCell { coords, scale: 0.8 },
+                ));
+            }
+        }
+
+        let cell_size = Self::calc_cell_size(board_size, maze.size);
+        for &(coords, special_cell) in &maze.special_cells {
+            let color = match special_cell {
+                // each pair gets its own colour, so that it's clear where a pawn will come out
+                SpecialCell::Teleporter(pair) => {
+                    Color::hsla((270.0 + pair as f32 * 36.0) % 360.0, 0.8, 0.6, 0.6)
+                }
+                SpecialCell::Trap => Color::rgba(0.1, 0.0, 0.0, 0.7),
+                SpecialCell::Bonus => Color::rgba(1.0, 0.85, 0.2, 0.6),
+            };
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(cell_size * 0.6),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(
+                        Self::board_pos_to_pos(coords, board_size, maze.size).extend(-0.6),
+                    ),
+                    ..default()
+                },
+                BoardCell { coords, scale: 0.6 },
+            ));
+        }
+
+        let horizontal_walls = maze
+            .horizontal_bars
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, &bar)| bar)
+                    .map(move |(x, _)| (true, IVec2::new(x as i32, y as i32)))
+            });
+        let vertical_walls = maze.vertical_bars.iter().enumerate().flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .filter(|(_, &bar)| bar)
+                .map(move |(x, _)| (false, IVec2::new(x as i32, y as i32)))
+        });
+        for (horizontal, coords) in horizontal_walls.chain(vertical_walls) {
+            let wall = MazeWall { horizontal, coords };
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::ORANGE,
+                        custom_size: Some(Self::calc_wall_size(board_size, cells.0, &wall)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(
+                        Self::calc_wall_pos(board_size, cells.0, &wall).extend(-0.5),
+                    ),
+                    ..default()
+                },
+                wall,
+            ));
+        }
+        Self::spawn_fog(&mut commands, maze, board_size);
+    }
+
+    pub(crate) fn client_update_board_cells(
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        mut board_cells: Query<(
+            &BoardCell,
+            &mut Transform,
+            Option<&mut Sprite>,
+            Option<&mut TextureAtlasSprite>,
+        )>,
+    ) {
+        if !window_size.is_changed() {
+            return;
+        }
+        let board_size = Self::calc_board_size(window_size.0);
+        let cell_size = Self::calc_cell_size(board_size, cells.0);
+        for (board_cell, mut transform, sprite, atlas_sprite) in board_cells.iter_mut() {
+            transform.translation = Self::board_pos_to_pos(board_cell.coords, board_size, cells.0)
+                .extend(transform.translation.z);
+            if let Some(mut sprite) = sprite {
+                sprite.custom_size = Some(cell_size * board_cell.scale);
+            }
+            if let Some(mut atlas_sprite) = atlas_sprite {
+                atlas_sprite.custom_size = Some(cell_size * board_cell.scale);
+            }
+        }
+    }
+
+    pub(crate) fn client_auto_walk(
+        mut auto_walk: ResMut<AutoWalk>,
+        not_moving_me: Query<
+            (&Player, Option<&RaceState>),
+            (With<Me>, Without<PlayerMoveAnimation>),
+        >,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        dice: Query<&Dice>,
+        maze: Res<Maze>,
+        targets: Res<KnownTargets>,
+        mut move_requests: EventWriter<MoveRequest>,
+    ) {
+        if !auto_walk.active {
+            return;
+        }
+        let Ok((me, race_state)) = not_moving_me.get_single() else {
+            // wait for the previous step's animation to finish
+            return;
+        };
+        let Some(TurnPhase::Moving { steps_taken }) =
+            Self::client_my_phase(me, race_state, &current_turn, &turn_phase)
+        else {
+            auto_walk.active = false;
+            return;
+        };
+        if auto_walk.last_sent_step == Some(steps_taken) {
+            // the server hasn't processed our last step yet
+            return;
+        }
+        let Some(dice) = dice
+            .iter()
+            .find(|dice| dice.corner(current_turn.0) == me.player_number)
+        else {
+            return;
+        };
+        if steps_taken >= dice.value {
+            auto_walk.active = false;
+            return;
+        }
+        let Some(target_item) = targets.get(me.player_number) else {
+            auto_walk.active = false;
+            return;
+        };
+        let Some(next_pos) = maze
+            .find_path(me.coords, target_item.coords(maze.size))
+            .and_then(|path| path.first().copied())
+        else {
+            auto_walk.active = false;
+            return;
+        };
+        let Some(request) = MoveRequest::from_delta(next_pos - me.coords) else {
+            auto_walk.active = false;
+            return;
+        };
+        move_requests.send(request);
+        auto_walk.last_sent_step = Some(steps_taken);
+    }
+
+    /// The phase the local player can currently act in, or `None` if they're waiting on others
+    pub(crate) fn client_my_phase(
+        me: &Player,
+        race_state: Option<&RaceState>,
+        current_turn: &CurrentTurn,
+        turn_phase: &State<TurnPhase>,
+    ) -> Option<TurnPhase> {
+        match race_state {
+            Some(race_state) => (!race_state.waiting).then_some(race_state.phase),
+            None => (me.player_number == current_turn.0).then(|| *turn_phase.get()),
+        }
+    }
+
+    pub(crate) fn client_on_dice_commitment(
+        mut commands: Commands,
+        mut dice_commitment_events: EventReader<DiceCommitment>,
+    ) {
+        if let Some(commitment) = dice_commitment_events.read().last() {
+            info!(
+                "Server committed to dice seed hash {}",
+                to_hex(&commitment.hash)
+            );
+            commands.insert_resource(DiceCommitmentHash(commitment.hash));
+        }
+    }
+
+    pub(crate) fn client_on_dice_seed_reveal(
+        mut commands: Commands,
+        mut dice_seed_reveal_events: EventReader<DiceSeedReveal>,
+        commitment: Option<Res<DiceCommitmentHash>>,
+    ) {
+        let Some(reveal) = dice_seed_reveal_events.read().last() else {
+            return;
+        };
+
+        let seed = to_hex(&reveal.seed);
+        let message = if commitment
+            .is_some_and(|commitment| *blake3::hash(&reveal.seed).as_bytes() == commitment.0)
+        {
+            info!("Dice seed {seed} matches the server's commitment");
+            let rolls = (0..reveal.rolls)
+                .map(|roll_number| VerifiableDice::roll(&reveal.seed, roll_number).to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            info!("Rolls derived from the seed: {rolls}");
+            format!("Dice seed {seed} verified")
+        } else {
+            error!("Dice seed {seed} does not match the server's commitment!");
+            format!("Dice seed {seed} does NOT match the server's commitment!")
+        };
+        commands.spawn(
+            TextBundle::from_section(
+                message,
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(8.0),
+                bottom: Val::Px(8.0),
+                ..default()
+            })
+            .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+        );
+    }
+
+    pub(crate) fn client_on_game_over(
+        mut commands: Commands,
+        mut game_over_events: EventReader<GameOver>,
+        me: Query<(), With<Me>>,
+        client: Option<Res<RenetClient>>,
+        server: Option<Res<RenetServer>>,
+    ) {
+        let Some(game_over) = game_over_events.read().last() else {
+            return;
+        };
+        commands.insert_resource(GameResult {
+            winner: game_over.winner,
+            reason: game_over.reason,
+        });
+
+        let mut summary = match game_over.reason {
+            GameOverReason::ItemsCollected => String::new(),
+            GameOverReason::TurnLimit => "Turn limit reached!\n".to_owned(),
+            GameOverReason::TimeLimit => "Time limit reached!\n".to_owned(),
+            GameOverReason::Unwinnable => "Not enough items left for anyone to win!\n".to_owned(),
+            GameOverReason::LastPlayerStanding => "Everyone else left!\n".to_owned(),
+            GameOverReason::ServerEnded => "A player left, so the game was ended.\n".to_owned(),
+        };
+        match game_over.winner {
+            Some(winner) => summary.push_str(&format!("{} wins!\n", COLOR_NAMES[winner])),
+            None => summary.push_str("Draw!\n"),
+        }
+        for player in &game_over.players {
+            let achieved_items = player
+                .achieved_items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            summary.push_str(&format!(
+                "\n{}: {}/{ITEMS_TO_WIN} ({achieved_items})",
+                COLOR_NAMES[player.player_number],
+                player.achieved_items.len(),
+            ));
+            if let Some(target_item) = player.target_item {
+                summary.push_str(&format!(", was looking for {target_item}"));
+            }
+            summary.push_str(&format!(", crashed {} times", player.crashes));
+        }
+        if let Some(match_seed) = &game_over.match_seed {
+            info!("Match seed: {match_seed}");
+            summary.push_str(&format!("\n\nMatch seed: {match_seed}"));
+        }
+
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        flex_direction: FlexDirection::Column,
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        row_gap: Val::Px(16.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                GameOverDisplay,
+            ))
+            .with_children(|parent| {
+                parent.spawn(
+                    TextBundle::from_section(
+                        summary,
+                        TextStyle {
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    )
+                    .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+                );
+                // only players in a networked game have a server to ask for another round
+                if me.is_empty() || (client.is_none() && server.is_none()) {
+                    return;
+                }
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                padding: UiRect::axes(Val::Px(16.0), Val::Px(8.0)),
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.2, 0.2, 0.3).into(),
+                            ..default()
+                        },
+                        RematchButton,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            TextBundle::from_section(
+                                "Rematch",
+                                TextStyle {
+                                    font_size: 32.0,
+                                    color: Color::WHITE,
+                                    ..default()
+                                },
+                            ),
+                            RematchText,
+                        ));
+                    });
+            });
+    }
+
+    /// Asks for a rematch, or stops asking, when the button is clicked or Enter is pressed, and
+    /// shows how many players are waiting for one
+    pub(crate) fn client_update_rematch(
+        keys: Res<Input<KeyCode>>,
+        buttons: Query<&Interaction, (Changed<Interaction>, With<RematchButton>)>,
+        mut rematch_writer: EventWriter<RematchToggle>,
+        players: Query<&Player>,
+        me: Query<&Player, With<Me>>,
+        mut rematch_text: Query<&mut Text, With<RematchText>>,
+    ) {
+        let (Ok(me), Ok(mut rematch_text)) = (me.get_single(), rematch_text.get_single_mut())
+        else {
+            return;
+        };
+        if keys.just_pressed(KeyCode::Return)
+            || buttons
+                .iter()
+                .any(|interaction| *interaction == Interaction::Pressed)
+        {
+            rematch_writer.send(RematchToggle);
+        }
+
+        let wanting = players.iter().filter(|player| player.wants_rematch).count();
+        let total = players.iter().count();
+        let text = if me.wants_rematch {
+            format!("Waiting for a rematch ({wanting}/{total})…")
+        } else if wanting > 0 {
+            format!("Rematch ({wanting}/{total})")
+        } else {
+            "Rematch".to_owned()
+        };
+        if rematch_text.sections[0].value != text {
+            rematch_text.sections[0].value = text;
+        }
+    }
+
+    pub(crate) fn client_despawn_game_over(
+        mut commands: Commands,
+        game_over_display: Query<Entity, With<GameOverDisplay>>,
+    ) {
+        commands.remove_resource::<GameResult>();
+        for entity_id in game_over_display.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+    }
+
+    pub(crate) fn client_on_rep_player(
+        mut commands: Commands,
+        spawned_players: Query<(Entity, &Player), Added<Player>>,
+        mut items_query: Query<(Entity, &ItemDisplay, &mut TextureAtlasSprite)>,
+        targets: Res<KnownTargets>,
+        palette: Res<Palette>,
+        local_client_id: Res<LocalClientId>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        assets: Res<AssetServer>,
+        atlases: Res<TextureAtlases>,
+        skins: Res<Skins>,
+    ) {
+        for (id, player) in spawned_players.iter() {
+            info!("Replicated player: {}", player.player_number);
+            if player.player_number >= 4 {
+                commands.entity(id).despawn();
+                continue;
+            }
+
+            let board_size = Self::calc_board_size(window_size.0);
+
+            commands.entity(id).insert(SpriteBundle {
+                sprite: Sprite {
+                    color: Self::pawn_color(player, *palette, &skins),
+                    custom_size: Some(Vec2::splat(
+                        Self::calc_cell_size(board_size, cells.0).y * PAWN_SIZE,
+                    )),
+                    ..default()
+                },
+                texture: Self::pawn_texture(player, &skins, &assets),
+                transform: Transform {
+                    translation: Self::board_pos_to_pos(player.coords, board_size, cells.0)
+                        .extend(0.0),
+                    ..default()
+                },
+                ..default()
+            });
+            if player.client_id == local_client_id.0 {
+                commands.entity(id).insert(Me);
+            }
+            // positioned and sized by client_update_name_labels
+            let name_label = |anchor| Text2dBundle {
+                text: Text::from_section(
+                    player.display_name(),
+                    TextStyle {
+                        color: palette.color(player.player_number),
+                        ..default()
+                    },
+                ),
+                text_anchor: anchor,
+                ..default()
+            };
+            commands.entity(id).with_children(|parent| {
+                parent.spawn((name_label(Anchor::BottomCenter), PawnNameLabel));
+            });
+            commands.spawn((
+                name_label(Anchor::Center),
+                CornerNameLabel(player.player_number),
+            ));
+            Self::sync_player_items(
+                &mut commands,
+                player,
+                targets.get(player.player_number),
+                &mut items_query,
+                &*atlases,
+                window_size.0,
+            );
+        }
+    }
+
+    pub(crate) fn client_on_player_removed(
+        mut commands: Commands,
+        mut removed_players: RemovedComponents<Player>,
+        players: Query<&Player>,
+        items_query: Query<(Entity, &ItemDisplay)>,
+        corner_name_labels: Query<(Entity, &CornerNameLabel)>,
+    ) {
+        if removed_players.read().count() == 0 {
+            return;
+        }
+        let is_removed = |player_number| {
+            !players
+                .iter()
+                .any(|player| player.player_number == player_number)
+        };
+        for (id, item_display) in items_query.iter() {
+            if is_removed(item_display.player_index) {
+                commands.entity(id).despawn();
+            }
+        }
+        for (id, label) in corner_name_labels.iter() {
+            if is_removed(label.0) {
+                commands.entity(id).despawn();
+            }
+        }
+    }
+
+    /// Keeps each player's name above their pawn and beside their items in their corner, the
+    /// right size for the board
+    pub(crate) fn client_update_name_labels(
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        changed_players: Query<(), Changed<Player>>,
+        added_labels: Query<(), Added<CornerNameLabel>>,
+        players: Query<&Player>,
+        mut pawn_labels: Query<
+            (&Parent, &mut Text, &mut Transform),
+            (With<PawnNameLabel>, Without<CornerNameLabel>),
+        >,
+        mut corner_labels: Query<(&CornerNameLabel, &mut Text, &mut Transform, &mut Anchor)>,
+        palette: Res<Palette>,
+    ) {
+        if !window_size.is_changed()
+            && !palette.is_changed()
+            && !cells.is_changed()
+            && changed_players.is_empty()
+            && added_labels.is_empty()
+        {
+            return;
+        }
+        let board_size = Self::calc_board_size(window_size.0);
+        let cell_size = Self::calc_cell_size(board_size, cells.0);
+        let dice_size = Self::calc_dice_size(window_size.0, board_size);
+
+        for (parent, mut text, mut transform) in pawn_labels.iter_mut() {
+            let Ok(player) = players.get(parent.get()) else {
+                continue;
+            };
+            text.sections[0].value = player.display_name();
+            text.sections[0].style.font_size = cell_size.y * 0.3;
+            text.sections[0].style.color = palette.color(player.player_number);
+            transform.translation = Vec3::new(0.0, cell_size.y * PAWN_SIZE * 0.5, 3.0);
+        }
+        for (label, mut text, mut transform, mut anchor) in corner_labels.iter_mut() {
+            let Some(player) = players
+                .iter()
+                .find(|player| player.player_number == label.0)
+            else {
+                continue;
+            };
+            text.sections[0].value = player.display_name();
+            text.sections[0].style.font_size = dice_size.y * 0.12;
+            text.sections[0].style.color = palette.color(player.player_number);
+            // on the side of the dice facing the middle of the window
+            let dice_pos = Self::calc_dice_pos(window_size.0, board_size, label.0);
+            let (offset, new_anchor) = if dice_pos.y > 0.0 {
+                (-dice_size.y * 0.5, Anchor::TopCenter)
+            } else {
+                (dice_size.y * 0.5, Anchor::BottomCenter)
+            };
+            transform.translation = Vec3::new(dice_pos.x, dice_pos.y + offset, 1.0);
+            *anchor = new_anchor;
+        }
+    }
+
+    pub(crate) fn client_update_player_data(
+        mut commands: Commands,
+        mut players: Query<
+            (
+                &Player,
+                &mut Transform,
+                Option<&PlayerMoveAnimation>,
+                Option<(&mut Sprite, &mut Handle<Image>)>,
+            ),
+            Changed<Player>,
+        >,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        ease: Res<Ease>,
+        mut items_query: Query<(Entity, &ItemDisplay, &mut TextureAtlasSprite)>,
+        targets: Res<KnownTargets>,
+        atlases: Res<TextureAtlases>,
+        palette: Res<Palette>,
+        skins: Res<Skins>,
+        assets: Res<AssetServer>,
+    ) {
+        for (player, mut transform, anim, sprite) in players.iter_mut() {
+            let board_size = Self::calc_board_size(window_size.0);
+            if let Some((mut sprite, mut texture)) = sprite {
+                sprite.color = Self::pawn_color(player, *palette, &skins);
+                // the player may have picked another skin
+                texture.set_if_neq(Self::pawn_texture(player, &skins, &assets));
+            }
+            transform.translation = Self::calc_player_pos(
+                player.prev_coords,
+                player.coords,
+                anim,
+                board_size,
+                cells.0,
+                *ease,
+            )
+            .extend(0.0);
+            Self::sync_player_items(
+                &mut commands,
+                player,
+                targets.get(player.player_number),
+                &mut items_query,
+                &*atlases,
+                window_size.0,
+            );
+        }
+    }
+
+    /// Shows the items a player has collected, and the one they're looking for if it's known
+    pub(crate) fn sync_player_items(
+        commands: &mut Commands,
+        player: &Player,
+        target_item: Option<Item>,
+        items_query: &mut Query<(Entity, &ItemDisplay, &mut TextureAtlasSprite)>,
+        atlases: &TextureAtlases,
+        window_size: Vec2,
+    ) {
+        let mut first_unspawned_index = 0;
+        let mut found_target = false;
+        for (entity_id, item_display, mut sprite) in items_query.iter_mut() {
+            if item_display.player_index != player.player_number {
+                continue;
+            }
+            match item_display.position {
+                ItemDisplayPosition::Achieved(index) => {
+                    first_unspawned_index = first_unspawned_index.max(index + 1);
+                    if index >= player.achieved_items.len() {
+                        commands.entity(entity_id).despawn();
+                    } else {
+                        sprite.index = player.achieved_items[index].atlas_index();
+                    }
+                }
+                ItemDisplayPosition::Target => {
+                    found_target = true;
+                    if let Some(target) = target_item {
+                        sprite.index = target.atlas_index();
+                    } else {
+                        commands.entity(entity_id).despawn();
+                    }
+                }
+            }
+        }
+
+        let board_size = Self::calc_board_size(window_size);
+
+        let mut spawn_item = |item: Item, item_display: ItemDisplay| {
+            let position = Self::calc_item_display_pos(window_size, board_size, &item_display);
+            commands.spawn(ItemDisplayBundle {
+                item: item_display,
+                sprite: SpriteSheetBundle {
+                    transform: Transform {
+                        translation: position.extend(1.0),
+                        ..default()
+                    },
+                    sprite: TextureAtlasSprite {
+                        custom_size: Some(Self::calc_item_display_size(window_size, board_size)),
+                        index: item.atlas_index(),
+                        ..default()
+                    },
+                    texture_atlas: atlases.items.clone(),
+                    ..default()
+                },
+            });
+        };
+
+        if !found_target {
+            if let Some(target) = target_item {
+                spawn_item(
+                    target,
+                    ItemDisplay {
+                        player_index: player.player_number,
+                        position: ItemDisplayPosition::Target,
+                    },
+                );
+            }
+        }
+
+        if first_unspawned_index < player.achieved_items.len() {
+            for index in first_unspawned_index..player.achieved_items.len() {
+                spawn_item(
+                    player.achieved_items[index],
+                    ItemDisplay {
+                        player_index: player.player_number,
+                        position: ItemDisplayPosition::Achieved(index),
+                    },
+                );
+            }
+        }
+    }
+
+    fn spawn_collect_effect(commands: &mut Commands, color: Color, pos: Vec2, cell_size: Vec2) {
+        let cell_size = cell_size.y;
+        commands.spawn((
+            Text2dBundle {
+                text: Text::from_section(
+                    "+1",
+                    TextStyle {
+                        font_size: cell_size * 0.4,
+                        color,
+                        ..default()
+                    },
+                ),
+                transform: Transform {
+                    translation: pos.extend(2.0),
+                    ..default()
+                },
+                ..default()
+            },
+            CollectEffect {
+                velocity: Vec2::Y * cell_size * 0.5,
+                ..default()
+            },
+        ));
+    }
+
+    pub(crate) fn client_update_collect_effects(
+        mut commands: Commands,
+        mut effects: Query<(Entity, &mut CollectEffect, &mut Transform, &mut Text)>,
+        time: Res<Time>,
+    ) {
+        for (entity_id, mut effect, mut transform, mut text) in effects.iter_mut() {
+            effect.time += time.delta();
+            if effect.time >= COLLECT_EFFECT_DURATION {
+                commands.entity(entity_id).despawn();
+                continue;
+            }
+            transform.translation += (effect.velocity * time.delta_seconds()).extend(0.0);
+            let alpha = 1.0 - effect.time.as_secs_f32() / COLLECT_EFFECT_DURATION.as_secs_f32();
+            for section in &mut text.sections {
+                section.style.color.set_a(alpha);
+            }
+        }
+    }
+
+    pub(crate) fn client_update_player_anim(
+        mut commands: Commands,
+        mut players: Query<(
+            Entity,
+            &mut Player,
+            &mut PlayerMoveAnimation,
+            &mut Transform,
+        )>,
+        time: Res<Time>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        atlases: Res<TextureAtlases>,
+        ease: Res<Ease>,
+    ) {
+        for (id, mut player, mut move_anim, mut transform) in players.iter_mut() {
+            let old_time = move_anim.time;
+            move_anim.time += time.delta();
+
+            if move_anim.fail
+                && Self::get_anim_progress(old_time) < FAIL_EXPLOSION_PROGRESS
+                && Self::get_anim_progress(move_anim.time) >= FAIL_EXPLOSION_PROGRESS
+            {
+                Self::spawn_explosion(
+                    &mut commands,
+                    transform.translation.xy(),
+                    Self::calc_cell_size(Self::calc_board_size(window_size.0), cells.0),
+                    &atlases,
+                );
+            }
+
+            if move_anim.time > MOVE_ANIM_DURATION {
+                move_anim.time = MOVE_ANIM_DURATION;
+                commands.entity(id).remove::<PlayerMoveAnimation>();
+                player.prev_coords = player.coords;
+            }
+            transform.translation = Self::calc_player_pos(
+                player.prev_coords,
+                player.coords,
+                Some(&*move_anim),
+                Self::calc_board_size(window_size.0),
+                cells.0,
+                *ease,
+            )
+            .extend(0.0);
+        }
+    }
+
+    pub(crate) fn client_update_player_shake(
+        mut commands: Commands,
+        mut players: Query<
+            (Entity, &Player, &mut PlayerShake, &mut Transform),
+            Without<PlayerMoveAnimation>,
+        >,
+        time: Res<Time>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+    ) {
+        for (id, player, mut shake, mut transform) in players.iter_mut() {
+            shake.time += time.delta();
+            let board_size = Self::calc_board_size(window_size.0);
+            let mut pos = Self::board_pos_to_pos(player.coords, board_size, cells.0);
+            if shake.time >= SHAKE_DURATION {
+                commands.entity(id).remove::<PlayerShake>();
+            } else {
+                let progress = shake.time.as_secs_f32() / SHAKE_DURATION.as_secs_f32();
+                let amplitude =
+                    Self::calc_cell_size(board_size, cells.0).x * 0.1 * (1.0 - progress);
+                pos.x += amplitude * (progress * SHAKE_WIGGLES * std::f32::consts::TAU).sin();
+            }
+            transform.translation = pos.extend(0.0);
+        }
+    }
+
+    fn spawn_explosion(
+        commands: &mut Commands,
+        pos: Vec2,
+        cell_size: Vec2,
+        atlases: &TextureAtlases,
+    ) {
+        commands.spawn(ExplosionBundle {
+            sprite: SpriteSheetBundle {
+                transform: Transform {
+                    translation: pos.extend(1.0),
+                    ..default()
+                },
+                sprite: TextureAtlasSprite {
+                    custom_size: Some(Vec2::splat(cell_size.y * PAWN_SIZE)),
+                    ..default()
+                },
+                texture_atlas: atlases.explosion.clone(),
+                ..default()
+            },
+            ..default()
+        });
+    }
+
+    fn get_anim_progress(anim_time: Duration) -> f32 {
+        (anim_time.as_secs_f32() / MOVE_ANIM_DURATION.as_secs_f32()).min(1.0)
+    }
+
+    fn calc_player_pos(
+        prev_coords: IVec2,
+        coords: IVec2,
+        anim: Option<&PlayerMoveAnimation>,
+        board_size: Vec2,
+        cells: usize,
+        ease: Ease,
+    ) -> Vec2 {
+        if let Some(anim) = anim {
+            let progress = Self::get_anim_progress(anim.time);
+            if anim.fail && progress >= FAIL_RESET_PROGRESS {
+                Self::board_pos_to_pos(coords, board_size, cells)
+            } else {
+                let prev_pos = Self::board_pos_to_pos(prev_coords, board_size, cells);
+                let to_pos = Self::board_pos_to_pos(anim.move_to, board_size, cells);
+                prev_pos + (to_pos - prev_pos) * ease.apply(progress)
+            }
+        } else {
+            Self::board_pos_to_pos(coords, board_size, cells)
+        }
+    }
+
+    pub(crate) fn client_on_rep_dice(
+        mut commands: Commands,
+        spawned_dice: Query<(Entity, &Dice), Added<Dice>>,
+        atlases: Res<TextureAtlases>,
+        window_size: Res<WindowSize>,
+        current_turn: Res<CurrentTurn>,
+    ) {
+        for (id, dice) in spawned_dice.iter() {
+            let board_size = Self::calc_board_size(window_size.0);
+            commands.entity(id).insert(SpriteSheetBundle {
+                transform: Transform {
+                    translation: Self::calc_dice_pos(
+                        window_size.0,
+                        board_size,
+                        dice.corner(current_turn.0),
+                    )
+                    .extend(0.0),
+                    ..default()
+                },
+                sprite: TextureAtlasSprite {
+                    custom_size: Some(Self::calc_dice_size(window_size.0, board_size)),
+                    index: (dice.value.clamp(1, 4) - 1) as usize,
+                    ..default()
+                },
+                texture_atlas: atlases.dice.clone(),
+                ..default()
+            });
+        }
+    }
+
+    pub(crate) fn client_on_dice_value_change(
+        mut dice: Query<(&Dice, &mut TextureAtlasSprite), Changed<Dice>>,
+    ) {
+        for (dice, mut sprite) in dice.iter_mut() {
+            sprite.index = (dice.value.clamp(1, 4) - 1) as usize;
+        }
+    }
+
+    pub(crate) fn board_pos_to_pos(board_pos: IVec2, board_size: Vec2, cells: usize) -> Vec2 {
+        (board_pos.as_vec2() - Vec2::splat((cells - 1) as f32 * 0.5))
+            * Self::calc_cell_size(board_size, cells)
+    }
+
+    /// The inverse of [`Self::board_pos_to_pos`], or `None` if `pos` isn't over the board
+    pub(crate) fn pos_to_board_pos(pos: Vec2, board_size: Vec2, cells: usize) -> Option<IVec2> {
+        let board_pos = (pos / Self::calc_cell_size(board_size, cells)
+            + Vec2::splat((cells - 1) as f32 * 0.5))
+        .round()
+        .as_ivec2();
+        let on_board =
+            (0..cells as i32).contains(&board_pos.x) && (0..cells as i32).contains(&board_pos.y);
+        on_board.then_some(board_pos)
+    }
+
+    /// Boards with more cells than the art squeeze them into the same space
+    pub(crate) fn calc_cell_size(board_size: Vec2, cells: usize) -> Vec2 {
+        board_size * CELL_SIZE * ART_BOARD_SIZE as f32 / cells as f32
+    }
+
+    pub(crate) fn calc_board_size(window_size: Vec2) -> Vec2 {
+        let adjusted_window_size = window_size * Vec2::new(1.0 / BOARD_ASPECT_RATIO, 1.0);
+        Vec2::splat(
+            adjusted_window_size
+                .min_element()
+                .min(adjusted_window_size.max_element() * (1.0 - BOARD_PADDING)),
+        ) * Vec2::new(BOARD_ASPECT_RATIO, 1.0)
+    }
+
+    pub(crate) fn calc_dice_pos(window_size: Vec2, board_size: Vec2, turn: usize) -> Vec2 {
+        let margin = (window_size - board_size).max_element() * 0.5;
+        Vec2::new(
+            if turn / 2 == 0 {
+                margin - window_size.x
+            } else {
+                window_size.x - margin
+            },
+            if turn % 2 == 0 {
+                margin - window_size.y
+            } else {
+                window_size.y - margin
+            },
+        ) * 0.5
+    }
+
+    pub(crate) fn calc_dice_size(window_size: Vec2, board_size: Vec2) -> Vec2 {
+        let margin = (window_size - board_size).max_element() * 0.5;
+        Vec2::splat(margin * 0.8)
+    }
+
+    fn calc_item_display_pos(
+        window_size: Vec2,
+        board_size: Vec2,
+        item_display: &ItemDisplay,
+    ) -> Vec2 {
+        let item_size = Self::calc_item_display_size(window_size, board_size);
+        let dice_size = Self::calc_dice_size(window_size, board_size);
+        let dice_pos = Self::calc_dice_pos(window_size, board_size, item_display.player_index);
+        let x = match item_display.position {
+            ItemDisplayPosition::Achieved(index) => {
+                dice_pos.x - dice_size.x * 0.5 + (index + 2) as f32 * 0.5 * item_size.x
+            }
+            ItemDisplayPosition::Target => dice_pos.x + dice_size.x * 0.5 - item_size.x,
+        };
+        Vec2::new(x, dice_pos.y)
+    }
+
+    fn calc_item_display_size(window_size: Vec2, board_size: Vec2) -> Vec2 {
+        Self::calc_dice_size(window_size, board_size) * 0.2
+    }
+
+    /// Halfway between the centres of the two cells the wall separates, i.e. on their shared edge
+    pub(crate) fn calc_wall_pos(board_size: Vec2, cells: usize, wall: &MazeWall) -> Vec2 {
+        let other_side = if wall.horizontal { IVec2::Y } else { IVec2::X };
+        (Self::board_pos_to_pos(wall.coords, board_size, cells)
+            + Self::board_pos_to_pos(wall.coords + other_side, board_size, cells))
+            * 0.5
+    }
+
+    fn calc_wall_size(board_size: Vec2, cells: usize, wall: &MazeWall) -> Vec2 {
+        let cell_size = Self::calc_cell_size(board_size, cells);
+        let thickness = cell_size.y * 0.08;
+        if wall.horizontal {
+            Vec2::new(cell_size.x, thickness)
+        } else {
+            Vec2::new(thickness, cell_size.y)
+        }
+    }
+
+    /// Enter toggles ready, and M asks for the next movement rule
+    pub(crate) fn client_handle_lobby_input(
+        keys: Res<Input<KeyCode>>,
+        me: Query<(), With<Me>>,
+        mut ready_writer: EventWriter<ReadyToggle>,
+        mut movement_change_writer: EventWriter<MovementChange>,
+    ) {
+        if me.is_empty() {
+            return;
+        }
+        if keys.just_pressed(KeyCode::Return) {
+            ready_writer.send(ReadyToggle);
+        }
+        if keys.just_pressed(KeyCode::M) {
+            movement_change_writer.send(MovementChange);
+        }
+    }
+
+    pub(crate) fn client_on_movement(
+        mut movement_events: EventReader<Movement>,
+        mut movement: ResMut<Movement>,
+    ) {
+        if let Some(&new_movement) = movement_events.read().last() {
+            movement.set_if_neq(new_movement);
+        }
+    }
+
+    /// Lists the players in the lobby with their ready state, rebuilt whenever a player changes
+    pub(crate) fn client_update_lobby(
+        mut commands: Commands,
+        players: Query<&Player>,
+        changed_players: Query<(), Changed<Player>>,
+        mut removed_players: RemovedComponents<Player>,
+        me: Query<(), With<Me>>,
+        max_players: Option<Res<MaxPlayers>>,
+        lobby_display: Query<Entity, With<LobbyDisplay>>,
+        palette: Res<Palette>,
+        movement: Res<Movement>,
+    ) {
+        let any_removed = removed_players.read().count() != 0;
+        let lobby_display = match lobby_display.get_single() {
+            Ok(lobby_display) => {
+                if changed_players.is_empty()
+                    && !any_removed
+                    && !palette.is_changed()
+                    && !movement.is_changed()
+                {
+                    return;
+                }
+                lobby_display
+            }
+            Err(_) => commands
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            width: Val::Percent(100.0),
+                            height: Val::Percent(100.0),
+                            flex_direction: FlexDirection::Column,
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            row_gap: Val::Px(6.0),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    LobbyDisplay,
+                ))
+                .id(),
+        };
+
+        let mut players: Vec<_> = players.iter().collect();
+        players.sort_by_key(|player| player.player_number);
+        let text_style = |color| TextStyle {
+            font_size: 24.0,
+            color,
+            ..default()
+        };
+        let footer = if me.is_empty() {
+            "Waiting for the players to ready up…".to_owned()
+        } else {
+            "Press Enter to toggle ready, M to change the movement rule, or S to change your pawn"
+                .to_owned()
+        };
+        let header = match max_players {
+            Some(max_players) => format!("Lobby ({}/{})", players.len(), max_players.0),
+            None => "Lobby".to_owned(),
+        };
+        commands
+            .entity(lobby_display)
+            .despawn_descendants()
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section(header, text_style(Color::WHITE)));
+                for player in players {
+                    let color = palette.color(player.player_number);
+                    let (ready_text, ready_color) = if player.ready {
+                        ("Ready", Color::GREEN)
+                    } else {
+                        ("Not ready", Color::GRAY)
+                    };
+                    parent.spawn(
+                        TextBundle::from_sections([
+                            TextSection::new("■ ", text_style(color)),
+                            TextSection::new(
+                                format!("{}  ", player.display_name()),
+                                text_style(Color::WHITE),
+                            ),
+                            TextSection::new(
+                                match &player.skin {
+                                    Some(skin) => format!("({skin})  "),
+                                    None => String::new(),
+                                },
+                                text_style(Color::GRAY),
+                            ),
+                            TextSection::new(ready_text, text_style(ready_color)),
+                        ])
+                        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+                    );
+                }
+                parent.spawn(TextBundle::from_section(
+                    format!("Movement: {}", *movement),
+                    text_style(Color::WHITE),
+                ));
+                parent.spawn(TextBundle::from_section(footer, text_style(Color::GRAY)));
+            });
+    }
+
+    pub(crate) fn client_despawn_lobby(
+        mut commands: Commands,
+        lobby_display: Query<Entity, With<LobbyDisplay>>,
+    ) {
+        for entity_id in lobby_display.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+    }
+
+    pub(crate) fn client_update_turn_order(
+        mut commands: Commands,
+        current_turn: Res<CurrentTurn>,
+        players: Query<&Player>,
+        added_players: Query<(), Added<Player>>,
+        mut removed_players: RemovedComponents<Player>,
+        turn_order_display: Query<Entity, With<TurnOrderDisplay>>,
+        palette: Res<Palette>,
+        marks: Res<PawnMarks>,
+    ) {
+        let any_removed = removed_players.read().count() != 0;
+        if !current_turn.is_changed()
+            && added_players.is_empty()
+            && !any_removed
+            && !palette.is_changed()
+            && !marks.is_changed()
+        {
+            return;
+        }
+        let Ok(turn_order_display) = turn_order_display.get_single() else {
+            return;
+        };
+
+        let mut player_numbers: Vec<_> = players
+            .iter()
+            .map(|player| player.player_number)
+            .filter(|&player_number| player_number < COLOR_NAMES.len())
+            .collect();
+        player_numbers.sort();
+        let first_index = player_numbers
+            .iter()
+            .position(|&player_number| player_number >= current_turn.0)
+            .unwrap_or(0);
+        player_numbers.rotate_left(first_index);
+
+        commands
+            .entity(turn_order_display)
+            .despawn_descendants()
+            .with_children(|parent| {
+                for player_number in player_numbers {
+                    let is_current = player_number == current_turn.0;
+                    let size = if is_current { 24.0 } else { 16.0 };
+                    let border = if is_current { 3.0 } else { 0.0 };
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                width: Val::Px(size),
+                                height: Val::Px(size),
+                                border: UiRect::all(Val::Px(border)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            background_color: palette.color(player_number).into(),
+                            border_color: Color::WHITE.into(),
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            marks.spawn_on_node(parent, player_number, size - border * 2.0);
+                        });
+                }
+            });
+    }
+
+    pub(crate) fn client_update_debug_hud(
+        mut debug_hud: Query<&mut Text, With<DebugHud>>,
+        me: Query<(&Player, Option<&RaceState>), With<Me>>,
+        dice: Query<&Dice>,
+        game_state: Res<State<GameState>>,
+        turn_phase: Res<State<TurnPhase>>,
+        current_turn: Res<CurrentTurn>,
+        cells: Res<BoardCells>,
+        targets: Res<KnownTargets>,
+    ) {
+        let mut text = debug_hud.single_mut();
+        let Ok((me, race_state)) = me.get_single() else {
+            text.sections[0].value = format!("Game state: {:?}", game_state.get());
+            return;
+        };
+
+        let target = match targets.get(me.player_number) {
+            Some(target_item) => format!("{target_item} at {}", target_item.coords(cells.0)),
+            None => "none".to_owned(),
+        };
+        let dice_value = dice
+            .iter()
+            .find(|dice| dice.owner.is_none() || dice.owner == Some(me.player_number))
+            .map_or(0, |dice| dice.value);
+        let turn_phase = match race_state {
+            Some(race_state) => format!("{:?} (waiting: {})", race_state.phase, race_state.waiting),
+            None => format!("{:?}", turn_phase.get()),
+        };
+        text.sections[0].value = format!(
+            "Player: {}\n\
+            Coords: {}\n\
+            Previous coords: {}\n\
+            Target: {target}\n\
+            Dice: {dice_value}\n\
+            Current turn: {}\n\
+            Game state: {:?}\n\
+            Turn phase: {turn_phase}",
+            me.player_number,
+            me.coords,
+            me.prev_coords,
+            current_turn.0,
+            game_state.get(),
+        );
+    }
+
+    /// Shows the steps left in a move as a row of pips under the dice, which go out one by one
+    /// as steps are taken, with the number left written underneath. Bonus steps get pips of
+    /// their own after the rolled ones.
+    pub(crate) fn client_update_step_pips(
+        mut commands: Commands,
+        window_size: Res<WindowSize>,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        dice: Query<&Dice>,
+        changed_dice: Query<(), Changed<Dice>>,
+        players: Query<&Player>,
+        changed_players: Query<(), Changed<Player>>,
+        race_states: Query<(&Player, Ref<RaceState>)>,
+        pips: Query<Entity, With<StepPip>>,
+        palette: Res<Palette>,
+    ) {
+        if !window_size.is_changed()
+            && !current_turn.is_changed()
+            && !turn_phase.is_changed()
+            && changed_dice.is_empty()
+            && changed_players.is_empty()
+            && !race_states
+                .iter()
+                .any(|(_, race_state)| race_state.is_changed())
+        {
+            return;
+        }
+        for entity_id in pips.iter() {
+            commands.entity(entity_id).despawn();
+        }
+
+        let board_size = Self::calc_board_size(window_size.0);
+        let dice_size = Self::calc_dice_size(window_size.0, board_size);
+        let pip_size = dice_size.x * 0.15;
+        for dice in dice.iter() {
+            let player_number = dice.corner(current_turn.0);
+            let phase = match dice.owner {
+                Some(owner) => race_states
+                    .iter()
+                    .find(|(player, _)| player.player_number == owner)
+                    .map(|(_, race_state)| race_state.phase),
+                None => Some(*turn_phase.get()),
+            };
+            let Some(TurnPhase::Moving { steps_taken }) = phase else {
+                continue;
+            };
+
+            let bonus_steps = players
+                .iter()
+                .find(|player| player.player_number == player_number)
+                .map_or(0, |player| player.bonus_steps);
+            let steps = dice.value + bonus_steps;
+            let steps_left = steps.saturating_sub(steps_taken);
+
+            let dice_pos = Self::calc_dice_pos(window_size.0, board_size, player_number);
+            let rows = steps.div_ceil(STEP_PIPS_PER_ROW);
+            for index in 0..steps {
+                let row = index / STEP_PIPS_PER_ROW;
+                let in_row = if row == rows - 1 {
+                    steps - row * STEP_PIPS_PER_ROW
+                } else {
+                    STEP_PIPS_PER_ROW
+                };
+                let column = index % STEP_PIPS_PER_ROW;
+                let offset = Vec2::new(
+                    (column as f32 - (in_row - 1) as f32 * 0.5) * pip_size * 1.5,
+                    -(row as f32) * pip_size * 1.5,
+                );
+                commands.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: if index < steps_taken {
+                                Color::DARK_GRAY
+                            } else if index < dice.value {
+                                palette.color(player_number)
+                            } else {
+                                Color::WHITE
+                            },
+                            custom_size: Some(Vec2::splat(pip_size)),
+                            ..default()
+                        },
+                        transform: Transform {
+                            translation: Vec3::new(
+                                dice_pos.x + offset.x,
+                                dice_pos.y - dice_size.y * 0.4 + offset.y,
+                                2.0,
+                            ),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    StepPip,
+                ));
+            }
+
+            commands.spawn((
+                Text2dBundle {
+                    text: Text::from_section(
+                        match steps_left {
+                            1 => "1 step left".to_owned(),
+                            steps_left => format!("{steps_left} steps left"),
+                        },
+                        TextStyle {
+                            font_size: pip_size * 1.8,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    ),
+                    transform: Transform::from_xyz(
+                        dice_pos.x,
+                        dice_pos.y - dice_size.y * 0.4 - rows as f32 * pip_size * 1.5 - pip_size,
+                        2.0,
+                    ),
+                    ..default()
+                },
+                StepPip,
+            ));
+        }
+    }
+
+    /// With two dice or a fixed budget the dice's face can't show the roll, so the number of
+    /// steps is written over it instead
+    pub(crate) fn client_update_dice_totals(
+        mut commands: Commands,
+        movement: Res<Movement>,
+        window_size: Res<WindowSize>,
+        mut dice: Query<(Entity, &Dice, &mut TextureAtlasSprite)>,
+        changed_dice: Query<(), (With<Dice>, Or<(Changed<Dice>, Added<TextureAtlasSprite>)>)>,
+        totals: Query<Entity, With<DiceTotal>>,
+    ) {
+        if !movement.is_changed() && !window_size.is_changed() && changed_dice.is_empty() {
+            return;
+        }
+        for entity_id in totals.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+
+        let board_size = Self::calc_board_size(window_size.0);
+        let dice_size = Self::calc_dice_size(window_size.0, board_size);
+        for (entity_id, dice, mut sprite) in dice.iter_mut() {
+            if *movement == Movement::OneDice {
+                sprite.color = Color::WHITE;
+                continue;
+            }
+            // dimmed, so that the number stands out
+            sprite.color = Color::rgb(0.35, 0.35, 0.35);
+            if dice.value == 0 {
+                continue;
+            }
+            commands.entity(entity_id).with_children(|parent| {
+                parent.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(
+                            dice.value.to_string(),
+                            TextStyle {
+                                font_size: dice_size.y * 0.6,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ),
+                        transform: Transform::from_xyz(0.0, 0.0, 0.1),
+                        ..default()
+                    },
+                    DiceTotal,
+                ));
+            });
+        }
+    }
+
+    pub(crate) fn client_update_explosion_anim(
+        mut commands: Commands,
+        mut explosions: Query<(Entity, &mut Explosion, &mut TextureAtlasSprite)>,
+        time: Res<Time>,
+    ) {
+        for (entity_id, mut explosion, mut sprite) in explosions.iter_mut() {
+            explosion.time += time.delta();
+            let frame =
+                (explosion.time.as_secs_f32() / EXPLOSION_FRAME_TIME.as_secs_f32()) as usize;
+            if frame >= EXPLOSION_FRAMES {
+                commands.entity(entity_id).despawn();
+            } else {
+                sprite.index = frame;
+            }
+        }
+    }
+}
+
+/// The easing curve used when animating pawn moves
+#[derive(ValueEnum, Resource, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ease {
+    /// Starts fast and slows down towards the end
+    Sine,
+    /// Moves at a constant speed
+    Linear,
+    /// Slows down more sharply towards the end
+    Cubic,
+    /// Bounces into place at the end
+    Bounce,
+}
+
+impl Ease {
+    /// Maps the progress through an animation, from 0 to 1, to how far along the pawn should be
+    pub(crate) fn apply(self, t: f32) -> f32 {
+        match self {
+            Ease::Sine => (t * std::f32::consts::FRAC_PI_2).sin(),
+            Ease::Linear => t,
+            Ease::Cubic => 1.0 - (1.0 - t).powi(3),
+            Ease::Bounce => {
+                const N: f32 = 7.5625;
+                const D: f32 = 2.75;
+                if t < 1.0 / D {
+                    N * t * t
+                } else if t < 2.0 / D {
+                    let t = t - 1.5 / D;
+                    N * t * t + 0.75
+                } else if t < 2.5 / D {
+                    let t = t - 2.25 / D;
+                    N * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D;
+                    N * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct Background;
+
+/// A maze bar drawn on the board, between `coords` and the cell above or to the right of it
+#[derive(Component)]
+pub(crate) struct MazeWall {
+    pub(crate) horizontal: bool,
+    pub(crate) coords: IVec2,
+}
+
+/// The list of players shown while waiting for the game to start
+#[derive(Component)]
+pub(crate) struct LobbyDisplay;
+
+/// Holds the recent chat lines, above the chat input box
+#[derive(Component)]
+pub(crate) struct ChatPanel;
+
+#[derive(Component)]
+pub(crate) struct ChatLine;
+
+#[derive(Component)]
+pub(crate) struct ChatInputText;
+
+/// The message being typed, present while the chat box is open
+#[derive(Resource, Default)]
+pub(crate) struct ChatInput(pub(crate) String);
+
+/// The HUD strip showing the order in which players take their turns
+#[derive(Component)]
+pub(crate) struct TurnOrderDisplay;
+
+/// One of the pips under a dice showing how many steps are left, or the count written under them
+#[derive(Component)]
+pub(crate) struct StepPip;
+
+/// The number of steps written over a dice when it isn't a single dice that's rolled
+#[derive(Component)]
+pub(crate) struct DiceTotal;
+
+/// Text showing the local player's state, enabled with `--debug-hud`
+#[derive(Component)]
+pub(crate) struct DebugHud;
+
+/// The dice seed hash the server committed to, kept by the client to check the revealed seed
+#[derive(Resource)]
+pub(crate) struct DiceCommitmentHash(pub(crate) [u8; 32]);
+
+/// The size the board is laid out for, which is the window's size when the game started. From
+/// then on the camera scales the layout to fit the window, see [`CameraController`].
+#[derive(Resource)]
+pub(crate) struct WindowSize(pub(crate) Vec2);
+
+/// The number of cells along each side of the board, taken from the [`Maze`] on the client
+#[derive(Resource)]
+pub(crate) struct BoardCells(pub(crate) usize);
+
+/// A plain cell, or the item on it, drawn in place of the artwork on boards that don't match it
+#[derive(Component)]
+pub(crate) struct BoardCell {
+    pub(crate) coords: IVec2,
+    pub(crate) scale: f32,
+}
+
+#[derive(Component)]
+pub(crate) struct Me;
+
+/// The player's name, as a child of their pawn
+#[derive(Component)]
+pub(crate) struct PawnNameLabel;
+
+/// The name of the player with this number, shown in their corner
+#[derive(Component)]
+pub(crate) struct CornerNameLabel(pub(crate) usize);
+
+#[derive(Resource)]
+pub(crate) struct InputOptions {
+    pub(crate) numpad: bool,
+    pub(crate) mirror_controls: MirrorControls,
+}
+
+/// What the board shows the local player while they're moving
+#[derive(ValueEnum, Resource, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MoveHints {
+    /// No hints are shown
+    Off,
+    /// The cells within reach of the steps left are highlighted
+    Reachable,
+    /// The cells within reach are highlighted, and the shortest way to the target item is drawn
+    Path,
+}
+
+/// Which movement keys are swapped, only affecting input and not what is shown on screen
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MirrorControls {
+    /// Controls are not mirrored
+    Off,
+    /// Left and right are swapped
+    Horizontal,
+    /// Left and right, and up and down, are swapped
+    Both,
+}
+
+impl MirrorControls {
+    fn apply(self, request: MoveRequest) -> MoveRequest {
+        match (self, request) {
+            (MirrorControls::Off, request) => request,
+            (_, MoveRequest::Left) => MoveRequest::Right,
+            (_, MoveRequest::Right) => MoveRequest::Left,
+            (MirrorControls::Both, MoveRequest::Up) => MoveRequest::Down,
+            (MirrorControls::Both, MoveRequest::Down) => MoveRequest::Up,
+            (_, request) => request,
+        }
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct GameOverDisplay;
+
+#[derive(Component)]
+pub(crate) struct RematchButton;
+
+#[derive(Component)]
+pub(crate) struct RematchText;
+
+/// Dims the board while the window is unfocused
+#[derive(Component)]
+pub(crate) struct FocusLostDim;
+
+/// Briefly shown when the window regains focus on the local player's turn, or when the keyboard
+/// is passed on in a hot-seat game
+#[derive(Component)]
+pub(crate) struct YourTurnFlash(pub(crate) Timer);
+
+/// Briefly shown when a player disconnects or leaves the game
+#[derive(Component)]
+pub(crate) struct PlayerLeftNotice(Timer);
+
+/// Present while the settings menu is open, which pauses game input
+#[derive(Resource, Default)]
+pub(crate) struct SettingsMenu {
+    pub(crate) selected: usize,
+}
+
+#[derive(Component)]
+pub(crate) struct SettingsMenuDisplay;
+
+/// A client option that can be changed while the game is running
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Setting {
+    Ease,
+    Palette,
+    PawnMarks,
+    InstantMoves,
+    MirrorControls,
+    Numpad,
+    Assist,
+    DebugHud,
+    DisplayMode,
+    Vsync,
+    Resolution,
+}
+
+impl Setting {
+    pub(crate) const ALL: [Setting; 11] = [
+        Setting::Ease,
+        Setting::Palette,
+        Setting::PawnMarks,
+        Setting::InstantMoves,
+        Setting::MirrorControls,
+        Setting::Numpad,
+        Setting::Assist,
+        Setting::DebugHud,
+        Setting::DisplayMode,
+        Setting::Vsync,
+        Setting::Resolution,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Setting::Ease => "Animation easing",
+            Setting::Palette => "Colour palette",
+            Setting::PawnMarks => "Pawn marks",
+            Setting::InstantMoves => "Instant moves",
+            Setting::MirrorControls => "Mirror controls",
+            Setting::Numpad => "Numpad controls",
+            Setting::Assist => "Auto-walk assist",
+            Setting::DebugHud => "Debug HUD",
+            Setting::DisplayMode => "Display mode",
+            Setting::Vsync => "VSync",
+            Setting::Resolution => "Resolution",
+        }
+    }
+}
+
+fn cycle_value_enum<T: ValueEnum + PartialEq + Copy>(value: T, forwards: bool) -> T {
+    let variants = T::value_variants();
+    let index = variants.iter().position(|&v| v == value).unwrap_or(0);
+    let index = if forwards {
+        (index + 1) % variants.len()
+    } else {
+        (index + variants.len() - 1) % variants.len()
+    };
+    variants[index]
+}
+
+fn value_enum_name<T: ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |value| value.get_name().to_string())
+}
+
+/// What the local player did this frame on their turn, from the keyboard or a controller
+#[derive(Default)]
+pub(crate) struct TurnActions {
+    pub(crate) roll: bool,
+    /// A step in the direction pressed, before `--mirror-controls` is applied
+    pub(crate) step: Option<MoveRequest>,
+    /// Sends the planned moves, or starts walking with `--assist`
+    pub(crate) confirm: bool,
+    /// Clears the planned moves, or stops walking with `--assist`
+    pub(crate) cancel: bool,
+}
+
+/// Everything needed to turn the local player's [`TurnActions`] into requests to the server
+#[derive(SystemParam)]
+pub(crate) struct TurnControls<'w, 's> {
+    not_moving_players: Query<
+        'w,
+        's,
+        (&'static Player, Option<&'static RaceState>, Has<Me>),
+        Without<PlayerMoveAnimation>,
+    >,
+    current_turn: Res<'w, CurrentTurn>,
+    turn_phase: Res<'w, State<TurnPhase>>,
+    roll_requests: EventWriter<'w, DiceRollRequest>,
+    move_requests: EventWriter<'w, MoveRequest>,
+    auto_walk: Option<ResMut<'w, AutoWalk>>,
+    input_options: Res<'w, InputOptions>,
+    planned_moves: Option<ResMut<'w, PlannedMoves>>,
+    dice: Query<'w, 's, &'static Dice>,
+    maze: Option<Res<'w, Maze>>,
+}
+
+impl TurnControls<'_, '_> {
+    pub(crate) fn apply(&mut self, actions: TurnActions) {
+        let Some(player_number) = self
+            .not_moving_players
+            .iter()
+            .find(|&(_, _, me)| me)
+            .map(|(player, _, _)| player.player_number)
+        else {
+            return;
+        };
+        self.apply_for(player_number, actions);
+    }
+
+    /// Like [`Self::apply`], but for the player with this number rather than the window's own,
+    /// so that each half of a split window plays for its own player
+    pub(crate) fn apply_for(&mut self, player_number: usize, actions: TurnActions) {
+        let Some((not_moving_player, race_state, _)) = self
+            .not_moving_players
+            .iter()
+            .find(|(player, _, _)| player.player_number == player_number)
+        else {
+            return;
+        };
+        let Some(phase) = LabyrinthPlugin::client_my_phase(
+            not_moving_player,
+            race_state,
+            &self.current_turn,
+            &self.turn_phase,
+        ) else {
+            return;
+        };
+        match phase {
+            TurnPhase::Rolling => {
+                if actions.roll {
+                    self.roll_requests.send(DiceRollRequest);
+                }
+            }
+            TurnPhase::Moving { steps_taken } => {
+                let request = actions
+                    .step
+                    .map(|request| self.input_options.mirror_controls.apply(request));
+
+                if let Some(planned_moves) = &mut self.planned_moves {
+                    if planned_moves.confirmed {
+                        return;
+                    }
+                    if actions.cancel {
+                        planned_moves.steps.clear();
+                        return;
+                    }
+                    if actions.confirm && !planned_moves.steps.is_empty() {
+                        let path =
+                            planned_moves.path(not_moving_player.coords, self.maze.as_deref());
+                        if path.iter().all(|&(_, legal)| legal) {
+                            planned_moves.confirmed = true;
+                            planned_moves.last_sent_step = None;
+                        }
+                        return;
+                    }
+                    let Some(dice) = self.dice.iter().find(|dice| {
+                        dice.corner(self.current_turn.0) == not_moving_player.player_number
+                    }) else {
+                        return;
+                    };
+                    let steps_left =
+                        (dice.value + not_moving_player.bonus_steps).saturating_sub(steps_taken);
+                    if let Some(request) = request {
+                        if planned_moves.steps.len() < steps_left as usize {
+                            planned_moves.steps.push(request);
+                        }
+                    }
+                    return;
+                }
+
+                if let Some(auto_walk) = &mut self.auto_walk {
+                    if actions.cancel {
+                        auto_walk.active = false;
+                    } else if actions.confirm {
+                        auto_walk.active = true;
+                        auto_walk.last_sent_step = None;
+                    }
+                    if auto_walk.active {
+                        return;
+                    }
+                }
+                if let Some(request) = request {
+                    self.move_requests.send(request);
+                }
+            }
+        }
+    }
+}
+
+/// Present with `--confirm-moves`, holding the steps queued up before they're sent
+#[derive(Resource, Default)]
+pub(crate) struct PlannedMoves {
+    pub(crate) steps: Vec<MoveRequest>,
+    pub(crate) confirmed: bool,
+    pub(crate) last_sent_step: Option<u8>,
+}
+
+impl PlannedMoves {
+    /// The cells the queued steps pass through, and whether each one can be legally reached.
+    /// Once a step is illegal, every step after it is too.
+    pub(crate) fn path(&self, start: IVec2, maze: Option<&Maze>) -> Vec<(IVec2, bool)> {
+        let mut coords = start;
+        let mut legal = true;
+        self.steps
+            .iter()
+            .map(|step| {
+                let next = coords + step.delta();
+                let in_bounds = match maze {
+                    Some(maze) => maze.in_bounds(next),
+                    None => {
+                        (0..DEFAULT_BOARD_SIZE as i32).contains(&next.x)
+                            && (0..DEFAULT_BOARD_SIZE as i32).contains(&next.y)
+                    }
+                };
+                legal =
+                    legal && in_bounds && !maze.is_some_and(|maze| maze.is_blocked(coords, next));
+                coords = next;
+                (coords, legal)
+            })
+            .collect()
+    }
+}
+
+/// Present with `--assist-hints`, tracking crashes towards showing the way to the target item
+#[derive(Resource)]
+pub(crate) struct AssistHints {
+    pub(crate) crash_threshold: u32,
+    pub(crate) consecutive_crashes: u32,
+    /// Counts down while a hint is being shown
+    pub(crate) timer: Option<Timer>,
+}
+
+/// Follows the mouse cursor around the board, enabled with `--cursor-highlight`
+#[derive(Component)]
+pub(crate) struct CursorHighlight;
+
+/// A cell highlighted on the way to the local player's target item
+#[derive(Component)]
+pub(crate) struct HintCell;
+
+/// A cell highlighted by [`MoveHints`], or a marker on the way to the target item
+#[derive(Component)]
+pub(crate) struct MoveHintCell;
+
+/// A ghost marker previewing one of the [`PlannedMoves`]
+#[derive(Component)]
+pub(crate) struct PlannedStep;
+
+/// Present when pawns should skip their move animations
+#[derive(Resource)]
+pub(crate) struct InstantMoves;
+
+#[derive(Resource, Default)]
+pub(crate) struct AutoWalk {
+    pub(crate) active: bool,
+    pub(crate) last_sent_step: Option<u8>,
+}
+
+#[derive(Component, Default)]
+pub(crate) struct PlayerMoveAnimation {
+    pub(crate) time: Duration,
+    pub(crate) fail: bool,
+    pub(crate) move_to: IVec2,
+}
+
+/// Shakes the pawn to show that a move was rejected
+#[derive(Component, Default)]
+pub(crate) struct PlayerShake {
+    pub(crate) time: Duration,
+}
+
+#[derive(Component, Default)]
+pub(crate) struct Explosion {
+    pub(crate) time: Duration,
+}
+
+/// The floating "+1" shown briefly when a player collects an item
+#[derive(Component, Default)]
+pub(crate) struct CollectEffect {
+    pub(crate) time: Duration,
+    pub(crate) velocity: Vec2,
+}
+
+#[derive(Bundle, Default)]
+struct ExplosionBundle {
+    explosion: Explosion,
+    sprite: SpriteSheetBundle,
+}
+
+/// Assets the client is still waiting on, along with their paths for error reporting
+#[derive(Resource)]
+pub(crate) struct LoadingAssets(pub(crate) Vec<(&'static str, UntypedHandle)>);
+
+#[derive(Component)]
+pub(crate) struct LoadingText;
+
+/// Shown in place of exiting when the client couldn't reach the server
+#[derive(Component)]
+pub(crate) struct DisconnectMessage;
+
+/// Present until the client first connects to the server, timing out the attempt
+#[derive(Resource)]
+pub(crate) struct Connecting(pub(crate) Timer);
+
+#[derive(Component)]
+pub(crate) struct ConnectingText;
+
+/// How a sprite sheet is divided up into a grid of sprites
+struct AtlasLayout {
+    tile_size: Vec2,
+    columns: usize,
+    rows: usize,
+    padding: Option<Vec2>,
+    offset: Option<Vec2>,
+}
+
+impl AtlasLayout {
+    pub(crate) fn build(&self, texture: Handle<Image>) -> TextureAtlas {
+        TextureAtlas::from_grid(
+            texture,
+            self.tile_size,
+            self.columns,
+            self.rows,
+            self.padding,
+            self.offset,
+        )
+    }
+}
+
+struct AtlasLayouts {
+    dice: AtlasLayout,
+    explosion: AtlasLayout,
+    /// The items are cut out of the background image, one per board cell
+    items: AtlasLayout,
+}
+
+impl Default for AtlasLayouts {
+    fn default() -> Self {
+        AtlasLayouts {
+            dice: AtlasLayout {
+                tile_size: Vec2::splat(415.0),
+                columns: 2,
+                rows: 2,
+                padding: None,
+                offset: None,
+            },
+            explosion: AtlasLayout {
+                tile_size: Vec2::splat(64.0),
+                columns: 8,
+                rows: 3,
+                padding: None,
+                offset: None,
+            },
+            // 250x237 + 110x123
+            // 146x126
+            items: AtlasLayout {
+                tile_size: Vec2::new(146.0, 126.0),
+                columns: ART_BOARD_SIZE,
+                rows: ART_BOARD_SIZE,
+                padding: Some(Vec2::new(104.0, 111.0)),
+                offset: Some(Vec2::new(110.0, 123.0)),
+            },
+        }
+    }
+}
+
+#[derive(Resource)]
+pub(crate) struct TextureAtlases {
+    pub(crate) dice: Handle<TextureAtlas>,
+    pub(crate) explosion: Handle<TextureAtlas>,
+    pub(crate) items: Handle<TextureAtlas>,
+}
+
+pub(crate) enum ItemDisplayPosition {
+    Achieved(usize),
+    Target,
+}
+
+#[derive(Component)]
+pub(crate) struct ItemDisplay {
+    pub(crate) player_index: usize,
+    pub(crate) position: ItemDisplayPosition,
+}
+
+#[derive(Bundle)]
+struct ItemDisplayBundle {
+    item: ItemDisplay,
+    sprite: SpriteSheetBundle,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::time::TimeUpdateStrategy;
+
+    const EPSILON: f32 = 1e-5;
+    const SAMPLES: usize = 1000;
+
+    /// How far the clock moves on each update of an [`animation_app`]
+    const FRAME_TIME: Duration = Duration::from_millis(50);
+
+    /// An app that runs the pawn and explosion animations, with the clock advanced by exactly
+    /// [`FRAME_TIME`] on each update instead of following real time
+    fn animation_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME_TIME))
+            .insert_resource(WindowSize(Vec2::new(800.0, 600.0)))
+            .insert_resource(BoardCells(DEFAULT_BOARD_SIZE))
+            .insert_resource(TextureAtlases {
+                dice: default(),
+                explosion: default(),
+                items: default(),
+            })
+            .insert_resource(Ease::Sine)
+            .add_systems(
+                Update,
+                (
+                    LabyrinthPlugin::client_update_player_anim,
+                    LabyrinthPlugin::client_update_explosion_anim,
+                ),
+            );
+        // the clock only starts on the first update
+        app.update();
+        app
+    }
+
+    #[test]
+    fn easing_starts_at_0_and_ends_at_1() {
+        for &ease in Ease::value_variants() {
+            assert!(
+                ease.apply(0.0).abs() < EPSILON,
+                "{ease:?} doesn't start at 0"
+            );
+            assert!(
+                (ease.apply(1.0) - 1.0).abs() < EPSILON,
+                "{ease:?} doesn't end at 1"
+            );
+        }
+    }
+
+    #[test]
+    fn easing_only_moves_forwards() {
+        for &ease in Ease::value_variants() {
+            let mut prev = ease.apply(0.0);
+            for i in 1..=SAMPLES {
+                let value = ease.apply(i as f32 / SAMPLES as f32);
+                assert!(
+                    (-EPSILON..=1.0 + EPSILON).contains(&value),
+                    "{ease:?} left 0..1"
+                );
+                // bouncing is the whole point of the bounce curve, so it's only kept on the board
+                if ease != Ease::Bounce {
+                    assert!(value >= prev - EPSILON, "{ease:?} went backwards");
+                }
+                prev = value;
+            }
+        }
+    }
+
+    #[test]
+    fn a_crash_animation_explodes_once_and_ends_where_the_pawn_is() {
+        let mut app = animation_app();
+        // the pawn crashed into a wall on its way from (1, 0) to (2, 0) and was sent back to the start
+        let pawn = app
+            .world
+            .spawn((
+                Player {
+                    coords: IVec2::ZERO,
+                    prev_coords: IVec2::new(1, 0),
+                    ..default()
+                },
+                PlayerMoveAnimation {
+                    fail: true,
+                    move_to: IVec2::new(2, 0),
+                    ..default()
+                },
+                Transform::default(),
+            ))
+            .id();
+        let explosions = |app: &mut App| app.world.query::<&Explosion>().iter(&app.world).count();
+
+        let frames = MOVE_ANIM_DURATION.as_nanos() / FRAME_TIME.as_nanos();
+        for frame in 1..=frames {
+            app.update();
+            let progress = frame as f32 / frames as f32;
+            assert_eq!(
+                usize::from(progress >= FAIL_EXPLOSION_PROGRESS),
+                explosions(&mut app),
+                "frame {frame}"
+            );
+            assert!(app.world.get::<PlayerMoveAnimation>(pawn).is_some());
+        }
+        // just past the end of the animation
+        app.update();
+        assert!(app.world.get::<PlayerMoveAnimation>(pawn).is_none());
+        assert_eq!(
+            IVec2::ZERO,
+            app.world.get::<Player>(pawn).unwrap().prev_coords
+        );
+        assert_eq!(1, explosions(&mut app));
+    }
+}
@@ -0,0 +1,122 @@
+//! The coach view, where observers of a server started with `--coach-view` can mark the cell of
+//! every player's target item on the board. The server sends them everyone's targets along with
+//! permission to show them.
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+
+use crate::client::{BoardCells, WindowSize};
+use crate::game::{CoachViewAllowed, Player};
+use crate::palette::Palette;
+use crate::server::Observers;
+use crate::targets::KnownTargets;
+use crate::LabyrinthPlugin;
+
+/// Present on a server started with `--coach-view`
+#[derive(Resource)]
+pub(crate) struct CoachView;
+
+/// Present on a client the server has allowed to show everyone's targets
+#[derive(Resource, Default)]
+pub(crate) struct TargetOverlay {
+    shown: bool,
+}
+
+/// Marks the cell of a player's target item
+#[derive(Component)]
+pub(crate) struct TargetMarker;
+
+impl LabyrinthPlugin {
+    pub(crate) fn server_allow_coach_view(
+        mut events: EventReader<ServerEvent>,
+        observers: Res<Observers>,
+        mut coach_view_writer: EventWriter<ToClients<CoachViewAllowed>>,
+    ) {
+        for event in events.read() {
+            if let ServerEvent::ClientConnected { client_id } = event {
+                if observers.0.contains(client_id) {
+                    coach_view_writer.send(ToClients {
+                        mode: SendMode::Direct(*client_id),
+                        event: CoachViewAllowed,
+                    });
+                }
+            }
+        }
+    }
+
+    pub(crate) fn client_on_coach_view_allowed(
+        mut commands: Commands,
+        mut coach_view_events: EventReader<CoachViewAllowed>,
+    ) {
+        if coach_view_events.read().count() != 0 {
+            info!("Press V to show every player's target");
+            commands.init_resource::<TargetOverlay>();
+        }
+    }
+
+    /// V shows and hides the markers
+    pub(crate) fn client_toggle_target_overlay(
+        keys: Res<Input<KeyCode>>,
+        mut overlay: ResMut<TargetOverlay>,
+    ) {
+        if keys.just_pressed(KeyCode::V) {
+            overlay.shown = !overlay.shown;
+        }
+    }
+
+    /// Redraws the markers whenever a target or the board changes. Each player's marker is a
+    /// little smaller than the one before, so that players looking for the same item all show.
+    pub(crate) fn client_update_target_markers(
+        mut commands: Commands,
+        overlay: Res<TargetOverlay>,
+        targets: Res<KnownTargets>,
+        players: Query<&Player>,
+        changed_players: Query<(), Changed<Player>>,
+        mut removed_players: RemovedComponents<Player>,
+        markers: Query<Entity, With<TargetMarker>>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        palette: Res<Palette>,
+    ) {
+        let players_changed = !changed_players.is_empty() || removed_players.read().count() != 0;
+        if !overlay.is_changed()
+            && !targets.is_changed()
+            && !players_changed
+            && !window_size.is_changed()
+            && !cells.is_changed()
+            && !palette.is_changed()
+        {
+            return;
+        }
+        for entity_id in markers.iter() {
+            commands.entity(entity_id).despawn();
+        }
+        if !overlay.shown {
+            return;
+        }
+
+        let board_size = Self::calc_board_size(window_size.0);
+        let cell_size = Self::calc_cell_size(board_size, cells.0);
+        for player in players.iter() {
+            let Some(target_item) = targets.get(player.player_number) else {
+                continue;
+            };
+            let coords = target_item.coords(cells.0);
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: palette.color(player.player_number).with_a(0.5),
+                        custom_size: Some(cell_size * (0.9 - 0.15 * player.player_number as f32)),
+                        ..default()
+                    },
+                    // over the fog, but under the pawns
+                    transform: Transform::from_translation(
+                        Self::board_pos_to_pos(coords, board_size, cells.0).extend(-0.3),
+                    ),
+                    ..default()
+                },
+                TargetMarker,
+            ));
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! What the player sees of their connection to the server: the ping once they're connected, the
+//! ping of whoever's turn it is, and a way to type in a different address when they can't connect
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
+use bevy_replicon::prelude::*;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::client::DisconnectMessage;
+use crate::game::{CurrentTurn, Ping, Player};
+use crate::net::{LocalClientId, ReconnectToken};
+use crate::{Cli, LabyrinthPlugin};
+
+/// How often the ping is updated, so that it can be read rather than flickering every frame
+const PING_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+/// How often the server measures its players' connections. Each change is replicated, so this is
+/// slower than the refresh of the player's own ping.
+const SERVER_PING_INTERVAL: Duration = Duration::from_secs(1);
+/// Long enough for an IPv6 address and port
+const MAX_ADDRESS_LENGTH: usize = 64;
+
+/// The round trip time to the server and the packets lost on the way, in the top right corner
+/// while connected. Under it is the ping of the player whose turn it is, if they're on a
+/// connection of their own.
+#[derive(Component)]
+pub(crate) struct PingText;
+
+/// The address being typed into the disconnect message
+#[derive(Resource)]
+pub(crate) struct AddressInput {
+    address: String,
+    /// What the disconnect message said before, to go back to if the player changes their mind
+    message: String,
+    invalid: bool,
+}
+
+impl LabyrinthPlugin {
+    /// Measures each connected player's ping for everyone else to see
+    pub(crate) fn server_update_pings(
+        mut commands: Commands,
+        server: Res<RenetServer>,
+        mut players: Query<(Entity, &Player, Option<&mut Ping>)>,
+        time: Res<Time>,
+        mut since_update: Local<Duration>,
+    ) {
+        *since_update += time.delta();
+        if *since_update < SERVER_PING_INTERVAL {
+            return;
+        }
+        *since_update = Duration::ZERO;
+
+        for (entity_id, player, ping) in players.iter_mut() {
+            let Ok(network_info) = server.network_info(ClientId::from_raw(player.client_id)) else {
+                // the host, or a player who has dropped out
+                if ping.is_some() {
+                    commands.entity(entity_id).remove::<Ping>();
+                }
+                continue;
+            };
+            let new_ping = Ping {
+                rtt_ms: (network_info.rtt * 1000.0).round().min(u16::MAX as f64) as u16,
+                packet_loss_percent: (network_info.packet_loss * 100.0).round().min(100.0) as u8,
+            };
+            match ping {
+                Some(mut ping) => {
+                    if *ping != new_ping {
+                        *ping = new_ping;
+                    }
+                }
+                None => {
+                    commands.entity(entity_id).insert(new_ping);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn client_update_ping(
+        mut commands: Commands,
+        client: Option<Res<RenetClient>>,
+        local_client_id: Res<LocalClientId>,
+        current_turn: Res<CurrentTurn>,
+        players: Query<(&Player, &Ping)>,
+        mut ping_text: Query<(Entity, &mut Text), With<PingText>>,
+        time: Res<Time>,
+        mut since_refresh: Local<Duration>,
+    ) {
+        let mut lines = Vec::new();
+        if let Some(client) = client.filter(|client| client.is_connected()) {
+            lines.push(format!(
+                "Ping: {:.0} ms, {:.0}% loss",
+                client.rtt() * 1000.0,
+                client.packet_loss() * 100.0
+            ));
+        }
+        let current_player = players.iter().find(|(player, _)| {
+            player.player_number == current_turn.0 && player.client_id != local_client_id.0
+        });
+        if let Some((player, ping)) = current_player {
+            lines.push(format!(
+                "{}: {} ms, {}% loss{}",
+                player.display_name(),
+                ping.rtt_ms,
+                ping.packet_loss_percent,
+                if ping.is_lagging() { " (lagging)" } else { "" }
+            ));
+        }
+        if lines.is_empty() {
+            for (entity_id, _) in ping_text.iter() {
+                commands.entity(entity_id).despawn();
+            }
+            return;
+        }
+        let ping = lines.join("\n");
+
+        let Ok((_, mut text)) = ping_text.get_single_mut() else {
+            commands.spawn((
+                TextBundle::from_section(
+                    ping,
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    ..default()
+                })
+                .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+                PingText,
+            ));
+            *since_refresh = Duration::ZERO;
+            return;
+        };
+        *since_refresh += time.delta();
+        // a new turn shouldn't wait for the refresh to show the new player's ping
+        if *since_refresh >= PING_REFRESH_INTERVAL || current_turn.is_changed() {
+            *since_refresh = Duration::ZERO;
+            text.sections[0].value = ping;
+        }
+    }
+
+    /// A opens the address box over the disconnect message, Enter connects to the address typed
+    /// into it and Esc goes back to the message. The port can be left out to keep the same one.
+    /// Runs after [`Self::client_handle_retry`], so that the Esc that closes the box doesn't quit
+    /// too.
+    pub(crate) fn client_handle_address_input(
+        mut commands: Commands,
+        keys: Res<Input<KeyCode>>,
+        mut chars: EventReader<ReceivedCharacter>,
+        address_input: Option<ResMut<AddressInput>>,
+        mut cli: ResMut<Cli>,
+        network_channels: Res<NetworkChannels>,
+        disconnect_message: Query<(Entity, &Children), With<DisconnectMessage>>,
+        mut texts: Query<&mut Text>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        let Cli::Client {
+            ref mut ip,
+            ref mut port,
+            connect_timeout,
+            observe,
+            ref password,
+            ref room,
+            ref options,
+        } = *cli
+        else {
+            return;
+        };
+        let Some(mut text) = disconnect_message
+            .iter()
+            .flat_map(|(_, children)| children.iter())
+            .find(|&&child| texts.contains(child))
+            .and_then(|&child| texts.get_mut(child).ok())
+        else {
+            return;
+        };
+
+        let Some(mut address_input) = address_input else {
+            // the A that opened the box shouldn't be typed into it
+            chars.clear();
+            if keys.just_pressed(KeyCode::A) {
+                commands.insert_resource(AddressInput {
+                    address: SocketAddr::new(*ip, *port).to_string(),
+                    message: text.sections[0].value.clone(),
+                    invalid: false,
+                });
+            }
+            return;
+        };
+
+        if keys.just_pressed(KeyCode::Escape) {
+            text.sections[0].value = address_input.message.clone();
+            commands.remove_resource::<AddressInput>();
+            chars.clear();
+            return;
+        }
+        if keys.just_pressed(KeyCode::Return) {
+            let address = address_input.address.trim();
+            let server_addr = address.parse::<SocketAddr>().or_else(|_| {
+                address
+                    .parse::<IpAddr>()
+                    .map(|ip| SocketAddr::new(ip, *port))
+            });
+            match server_addr {
+                Ok(server_addr) => {
+                    *ip = server_addr.ip();
+                    *port = server_addr.port();
+                    commands.remove_resource::<AddressInput>();
+                    // the old server's token is no use to a different one
+                    commands.remove_resource::<ReconnectToken>();
+                    for (entity_id, _) in disconnect_message.iter() {
+                        commands.entity(entity_id).despawn_recursive();
+                    }
+                    if let Err(err) = Self::client_connect(
+                        &mut commands,
+                        &network_channels,
+                        *ip,
+                        *port,
+                        connect_timeout,
+                        observe,
+                        options.name.as_deref(),
+                        password.as_deref(),
+                        room.as_deref(),
+                        None,
+                    ) {
+                        error!("Failed to connect: {err}");
+                        app_exit_events.send(AppExit);
+                    }
+                    chars.clear();
+                    return;
+                }
+                Err(_) => address_input.invalid = true,
+            }
+        }
+        if keys.just_pressed(KeyCode::Back) {
+            address_input.address.pop();
+        }
+        for event in chars.read() {
+            if !event.char.is_control() && address_input.address.len() < MAX_ADDRESS_LENGTH {
+                address_input.address.push(event.char);
+                address_input.invalid = false;
+            }
+        }
+        if address_input.is_changed() {
+            text.sections[0].value = format!(
+                "Server address: {}_\n{}Press Enter to connect or Esc to go back.",
+                address_input.address,
+                if address_input.invalid {
+                    "That isn't an IP address, with or without a port.\n"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Commands typed into a dedicated server's terminal, for managing a game while it runs
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::ClientId;
+use clap::Parser;
+use std::io;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::game::{CurrentTurn, GameMode, GameResult, GameState, Player, RaceState};
+use crate::server::{
+    AwaitingReconnect, LeftGame, MaxPlayers, NewRoundWriter, NextTurnWriter, Observers, RoundReset,
+};
+use crate::shutdown::ShuttingDown;
+use crate::{Cli, LabyrinthPlugin};
+
+/// A line typed into the server's terminal
+#[derive(Parser, Event, Debug)]
+#[command(no_binary_name = true)]
+pub(crate) enum ConsoleCommand {
+    /// Removes a player from the game, by player number or name
+    Kick { player: String },
+    /// Lists the players and observers
+    List,
+    /// Ends the current player's turn
+    SkipTurn,
+    /// Gives the turn to a player, by player number
+    SetTurn { player: usize },
+    /// Starts the round again on a new maze
+    RegenMaze,
+    /// Ends the game and stops the server
+    Shutdown,
+}
+
+/// The commands read from the terminal since last frame. Reading blocks, so it's done on a
+/// thread of its own.
+#[derive(Resource)]
+pub(crate) struct Console(Mutex<Receiver<ConsoleCommand>>);
+
+impl Console {
+    pub(crate) fn spawn() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                // the server may have been started without a terminal, in which case there's
+                // nothing to read
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match ConsoleCommand::try_parse_from(line.split_whitespace()) {
+                    Ok(command) => {
+                        if sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    // also prints the help
+                    Err(err) => {
+                        let _ = err.print();
+                    }
+                }
+            }
+        });
+        Self(Mutex::new(receiver))
+    }
+}
+
+impl LabyrinthPlugin {
+    pub(crate) fn server_read_console(
+        console: Res<Console>,
+        mut command_writer: EventWriter<ConsoleCommand>,
+    ) {
+        command_writer.send_batch(console.0.lock().unwrap().try_iter());
+    }
+
+    pub(crate) fn server_console_list(
+        mut console_commands: EventReader<ConsoleCommand>,
+        players: Query<(&Player, Has<AwaitingReconnect>)>,
+        observers: Res<Observers>,
+        game_state: Res<State<GameState>>,
+        current_turn: Res<CurrentTurn>,
+    ) {
+        for command in console_commands.read() {
+            let ConsoleCommand::List = command else {
+                continue;
+            };
+            let mut players: Vec<_> = players.iter().collect();
+            players.sort_by_key(|(player, _)| player.player_number);
+            info!(
+                "{} players, game state {:?}",
+                players.len(),
+                game_state.get()
+            );
+            for (player, awaiting_reconnect) in players {
+                info!(
+                    "  {} {} (client {}) has {} items{}{}",
+                    player.player_number,
+                    player.display_name(),
+                    player.client_id,
+                    player.achieved_items.len(),
+                    if player.player_number == current_turn.0 {
+                        ", their turn"
+                    } else {
+                        ""
+                    },
+                    if awaiting_reconnect {
+                        ", disconnected"
+                    } else {
+                        ""
+                    },
+                );
+            }
+            info!("{} observers", observers.0.len());
+        }
+    }
+
+    /// Kicked players leave the game like a player who disconnected for good
+    pub(crate) fn server_console_kick(
+        mut commands: Commands,
+        mut console_commands: EventReader<ConsoleCommand>,
+        players: Query<(Entity, &Player)>,
+        mut server: ResMut<RenetServer>,
+    ) {
+        for command in console_commands.read() {
+            let ConsoleCommand::Kick { player: kicked } = command else {
+                continue;
+            };
+            let Some((entity_id, player)) = players.iter().find(|(_, player)| {
+                kicked.parse().ok() == Some(player.player_number)
+                    || *kicked == player.display_name()
+            }) else {
+                warn!("There is no player {kicked}");
+                continue;
+            };
+            info!("Kicking player {}", player.player_number);
+            commands.entity(entity_id).insert(LeftGame);
+            server.disconnect(ClientId::from_raw(player.client_id));
+        }
+    }
+
+    pub(crate) fn server_console_turn(
+        mut console_commands: EventReader<ConsoleCommand>,
+        mut players: Query<&mut Player>,
+        game_state: Res<State<GameState>>,
+        game_mode: Res<GameMode>,
+        max_players: Res<MaxPlayers>,
+        mut current_turn: ResMut<CurrentTurn>,
+        mut next_turn_writer: NextTurnWriter,
+    ) {
+        for command in console_commands.read() {
+            if !matches!(
+                command,
+                ConsoleCommand::SkipTurn | ConsoleCommand::SetTurn { .. }
+            ) {
+                continue;
+            }
+            if *game_state.get() != GameState::InGame {
+                warn!("The game isn't being played");
+                continue;
+            }
+            if *game_mode != GameMode::Classic {
+                warn!("Everyone moves at once in a race, nobody has the turn");
+                continue;
+            }
+            match *command {
+                ConsoleCommand::SetTurn { player } => {
+                    if !players
+                        .iter()
+                        .any(|existing| existing.player_number == player)
+                    {
+                        warn!("There is no player {player}");
+                        continue;
+                    }
+                    current_turn.0 = player;
+                }
+                _ => {
+                    let mut players: Vec<_> = players.iter_mut().collect();
+                    Self::advance_turn(&mut current_turn, max_players.0, &mut players);
+                }
+            }
+            info!("It's now player {}'s turn", current_turn.0);
+            next_turn_writer.send(*current_turn);
+        }
+    }
+
+    /// Before the game starts this only swaps the maze, otherwise a new round starts on it
+    pub(crate) fn server_console_regen_maze(
+        mut commands: Commands,
+        mut console_commands: EventReader<ConsoleCommand>,
+        mut players: Query<(&mut Player, Option<&mut RaceState>)>,
+        game_state: Res<State<GameState>>,
+        mut round_reset: RoundReset,
+        mut new_round_writer: NewRoundWriter,
+    ) {
+        for command in console_commands.read() {
+            let ConsoleCommand::RegenMaze = command else {
+                continue;
+            };
+            if let Cli::Server(server_args) = &*round_reset.cli {
+                if server_args.maze_file.is_some() {
+                    warn!("The maze comes from a file, so it can't be regenerated");
+                    continue;
+                }
+            }
+            let mut players: Vec<_> = players.iter_mut().collect();
+            if !round_reset.reset(&mut players) {
+                continue;
+            }
+            if *game_state.get() == GameState::WaitingPlayers {
+                new_round_writer.maze_writer.send(ToClients {
+                    mode: SendMode::Broadcast,
+                    event: round_reset.maze.clone(),
+                });
+            } else {
+                info!("Starting a new round");
+                commands.remove_resource::<GameResult>();
+                new_round_writer.send(&round_reset.maze, *round_reset.current_turn);
+            }
+        }
+    }
+
+    pub(crate) fn server_console_shutdown(
+        mut commands: Commands,
+        mut console_commands: EventReader<ConsoleCommand>,
+    ) {
+        if console_commands
+            .read()
+            .any(|command| matches!(command, ConsoleCommand::Shutdown))
+        {
+            commands.insert_resource(ShuttingDown::new("The server was shut down by its admin."));
+        }
+    }
+}
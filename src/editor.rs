@@ -0,0 +1,225 @@
+//! Drawing mazes by hand with `labyrinth edit`, saved in the same format `--maze-file` loads
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::client::WindowSize;
+use crate::game::{Maze, SpecialCell};
+use crate::LabyrinthPlugin;
+
+/// How far from the middle of a cell towards its edge a click has to be to toggle the bar there,
+/// as a fraction of the cell's size
+const BAR_CLICK_THRESHOLD: f32 = 0.25;
+
+/// The maze being edited and where it's saved to
+#[derive(Resource)]
+pub(crate) struct MazeEditor {
+    file: PathBuf,
+    maze: Maze,
+    unsaved: bool,
+    status: String,
+}
+
+#[derive(Component)]
+pub(crate) struct EditorHud;
+
+impl LabyrinthPlugin {
+    /// Opens `file` if it already exists, or starts an empty board of `size` otherwise
+    pub(crate) fn editor_init(
+        commands: &mut Commands,
+        window: &Window,
+        assets: &AssetServer,
+        texture_atlases: &mut Assets<TextureAtlas>,
+        file: &Path,
+        size: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let maze = if file.exists() {
+            let maze: Maze = std::fs::read_to_string(file)?
+                .parse()
+                .map_err(|err| format!("Invalid maze file {}: {err}", file.display()))?;
+            info!("Editing {}", file.display());
+            maze
+        } else {
+            info!("Creating {} with a {size}x{size} board", file.display());
+            Maze::new(size)
+        };
+
+        Self::spawn_board(commands, window, assets, texture_atlases);
+        // drawn the same way a client draws the maze the server sends it
+        let sent_maze = maze.clone();
+        commands.add(move |world: &mut World| world.send_event(sent_maze));
+        commands.insert_resource(MazeEditor {
+            file: file.to_owned(),
+            maze,
+            unsaved: false,
+            status: String::new(),
+        });
+        commands.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.0),
+                top: Val::Px(8.0),
+                ..default()
+            }),
+            EditorHud,
+        ));
+        Ok(())
+    }
+
+    /// Clicking near the edge of a cell toggles the bar along that edge. Keys put special cells
+    /// on the cell under the cursor: T for a trap, B for a bonus cell, a digit for a teleporter
+    /// of that pair, and Delete to take it away again.
+    pub(crate) fn editor_handle_input(
+        mut editor: ResMut<MazeEditor>,
+        mut maze_events: EventWriter<Maze>,
+        mouse_buttons: Res<Input<MouseButton>>,
+        keys: Res<Input<KeyCode>>,
+        window: Query<&Window>,
+        camera: Query<(&Camera, &GlobalTransform)>,
+        window_size: Res<WindowSize>,
+    ) {
+        let (camera, camera_transform) = camera.single();
+        let Some(pos) = window
+            .single()
+            .cursor_position()
+            .and_then(|screen_pos| camera.viewport_to_world_2d(camera_transform, screen_pos))
+        else {
+            return;
+        };
+        let size = editor.maze.size;
+        let board_size = Self::calc_board_size(window_size.0);
+        let board_pos =
+            pos / Self::calc_cell_size(board_size, size) + Vec2::splat((size - 1) as f32 * 0.5);
+        let coords = board_pos.round().as_ivec2();
+        if !editor.maze.in_bounds(coords) {
+            return;
+        }
+
+        if mouse_buttons.just_pressed(MouseButton::Left) {
+            let offset = board_pos - coords.as_vec2();
+            let (horizontal, other_side) = if offset.x.abs() > offset.y.abs() {
+                (false, IVec2::new(offset.x.signum() as i32, 0))
+            } else {
+                (true, IVec2::new(0, offset.y.signum() as i32))
+            };
+            let neighbor = coords + other_side;
+            if offset.abs().max_element() < BAR_CLICK_THRESHOLD || !editor.maze.in_bounds(neighbor)
+            {
+                return;
+            }
+            // bars are stored against the cell below or to the left of them
+            let below = coords.min(neighbor);
+            let (x, y) = (below.x as usize, below.y as usize);
+            let bar = if horizontal {
+                &mut editor.maze.horizontal_bars[y][x]
+            } else {
+                &mut editor.maze.vertical_bars[y][x]
+            };
+            *bar = !*bar;
+        } else {
+            let digit_keys = [
+                KeyCode::Key0,
+                KeyCode::Key1,
+                KeyCode::Key2,
+                KeyCode::Key3,
+                KeyCode::Key4,
+                KeyCode::Key5,
+                KeyCode::Key6,
+                KeyCode::Key7,
+                KeyCode::Key8,
+                KeyCode::Key9,
+            ];
+            let special_cell = if keys.just_pressed(KeyCode::T) {
+                Some(SpecialCell::Trap)
+            } else if keys.just_pressed(KeyCode::B) {
+                Some(SpecialCell::Bonus)
+            } else if let Some(pair) = digit_keys.iter().position(|&key| keys.just_pressed(key)) {
+                Some(SpecialCell::Teleporter(pair as u8))
+            } else if keys.any_just_pressed([KeyCode::Delete, KeyCode::Back]) {
+                None
+            } else {
+                return;
+            };
+            if special_cell.is_some() && !editor.maze.can_be_special(coords) {
+                editor.status = "Special cells can't go on a start corner or an item".to_owned();
+                return;
+            }
+            editor
+                .maze
+                .special_cells
+                .retain(|(cell_coords, _)| *cell_coords != coords);
+            if let Some(special_cell) = special_cell {
+                editor.maze.special_cells.push((coords, special_cell));
+            }
+        }
+        editor.unsaved = true;
+        editor.status.clear();
+        maze_events.send(editor.maze.clone());
+    }
+
+    /// S saves the maze, as long as the server would accept it, and Esc quits
+    pub(crate) fn editor_save(
+        mut editor: ResMut<MazeEditor>,
+        keys: Res<Input<KeyCode>>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        if keys.just_pressed(KeyCode::Escape) {
+            if editor.unsaved {
+                warn!("Quitting without saving {}", editor.file.display());
+            }
+            app_exit_events.send(AppExit);
+            return;
+        }
+        if !keys.just_pressed(KeyCode::S) {
+            return;
+        }
+        let text = editor.maze.to_string();
+        // reading it back checks the teleporter pairs the same way loading it will
+        if let Err(err) = text.parse::<Maze>() {
+            editor.status = format!("Can't save: {err}");
+            return;
+        }
+        if !editor.maze.is_valid() {
+            editor.status = "Can't save: some cells can't be reached".to_owned();
+            return;
+        }
+        match std::fs::write(&editor.file, text) {
+            Ok(()) => {
+                info!("Saved {}", editor.file.display());
+                editor.unsaved = false;
+                editor.status = format!("Saved {}", editor.file.display());
+            }
+            Err(err) => {
+                error!("Failed to save {}: {err}", editor.file.display());
+                editor.status = format!("Failed to save: {err}");
+            }
+        }
+    }
+
+    pub(crate) fn editor_update_hud(
+        editor: Res<MazeEditor>,
+        mut hud: Query<&mut Text, With<EditorHud>>,
+    ) {
+        if !editor.is_changed() {
+            return;
+        }
+        let mut text = hud.single_mut();
+        text.sections[0].value = format!(
+            "{}{}\nClick near an edge to add or remove a bar\n\
+            T trap, B bonus, 0-9 teleporter, Delete to clear\nS to save, Esc to quit\n{}",
+            editor.file.display(),
+            if editor.unsaved { " (unsaved)" } else { "" },
+            editor.status,
+        );
+    }
+}
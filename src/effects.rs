@@ -0,0 +1,169 @@
+//! Short-lived particles for sparkles and confetti. Finished particles are hidden and kept for
+//! the next burst rather than despawned, so that a burst doesn't spawn a hundred new entities
+//! every time.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use rand::Rng;
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use crate::client::WindowSize;
+use crate::game::GameResult;
+use crate::palette::Palette;
+use crate::LabyrinthPlugin;
+
+/// Spare particles beyond this many are despawned rather than kept
+const MAX_POOLED_PARTICLES: usize = 256;
+const SPARKLES: usize = 8;
+const SPARKLE_DURATION: Duration = Duration::from_millis(800);
+const CONFETTI_PIECES: usize = 150;
+const CONFETTI_DURATION: Duration = Duration::from_secs(5);
+
+/// A particle that's in flight, moved by [`LabyrinthPlugin::client_update_particles`]
+#[derive(Component, Default)]
+pub(crate) struct Particle {
+    pub(crate) time: Duration,
+    pub(crate) lifetime: Duration,
+    pub(crate) velocity: Vec2,
+    /// How fast the particle falls, in pixels per second per second
+    pub(crate) gravity: f32,
+    /// In radians per second
+    pub(crate) spin: f32,
+    /// Whether the particle fades out over its lifetime, rather than vanishing at the end of it
+    pub(crate) fade: bool,
+}
+
+/// Hidden particles that have finished, ready to be shown again
+#[derive(Resource, Default)]
+pub(crate) struct ParticlePool(Vec<Entity>);
+
+#[derive(SystemParam)]
+pub(crate) struct Particles<'w, 's> {
+    commands: Commands<'w, 's>,
+    pool: ResMut<'w, ParticlePool>,
+}
+
+impl Particles<'_, '_> {
+    pub(crate) fn spawn(&mut self, particle: Particle, color: Color, size: Vec2, pos: Vec3) {
+        let bundle = (
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(size),
+                    ..default()
+                },
+                transform: Transform::from_translation(pos),
+                ..default()
+            },
+            particle,
+        );
+        match self.pool.0.pop() {
+            Some(entity_id) => {
+                self.commands.entity(entity_id).insert(bundle);
+            }
+            None => {
+                self.commands.spawn(bundle);
+            }
+        }
+    }
+
+    /// A ring of sparkles flying out from `pos`, sized to the board's cells
+    pub(crate) fn sparkle(&mut self, color: Color, pos: Vec2, cell_size: Vec2) {
+        let cell_size = cell_size.y;
+        for i in 0..SPARKLES {
+            let angle = i as f32 / SPARKLES as f32 * TAU;
+            self.spawn(
+                Particle {
+                    lifetime: SPARKLE_DURATION,
+                    velocity: Vec2::from_angle(angle) * cell_size,
+                    fade: true,
+                    ..default()
+                },
+                color,
+                Vec2::splat(cell_size * 0.1),
+                pos.extend(2.0),
+            );
+        }
+    }
+
+    /// Confetti in the players' colours falling from the top of the window
+    pub(crate) fn confetti(&mut self, window_size: Vec2, palette: Palette) {
+        let mut rng = rand::thread_rng();
+        let piece_size = window_size.y * 0.015;
+        for i in 0..CONFETTI_PIECES {
+            let pos = Vec2::new(
+                rng.gen_range(-0.5..0.5) * window_size.x,
+                window_size.y * rng.gen_range(0.5..0.8),
+            );
+            self.spawn(
+                Particle {
+                    lifetime: CONFETTI_DURATION,
+                    velocity: Vec2::new(
+                        rng.gen_range(-0.1..0.1) * window_size.x,
+                        -rng.gen_range(0.05..0.2) * window_size.y,
+                    ),
+                    gravity: window_size.y * 0.1,
+                    spin: rng.gen_range(-TAU..TAU),
+                    fade: false,
+                    ..default()
+                },
+                palette.color(i % 4),
+                Vec2::new(piece_size, piece_size * 0.5),
+                pos.extend(5.0),
+            );
+        }
+    }
+}
+
+impl LabyrinthPlugin {
+    pub(crate) fn client_update_particles(
+        mut commands: Commands,
+        mut particles: Query<(
+            Entity,
+            &mut Particle,
+            &mut Transform,
+            &mut Sprite,
+            &mut Visibility,
+        )>,
+        mut pool: ResMut<ParticlePool>,
+        time: Res<Time>,
+    ) {
+        for (entity_id, mut particle, mut transform, mut sprite, mut visibility) in
+            particles.iter_mut()
+        {
+            particle.time += time.delta();
+            if particle.time >= particle.lifetime {
+                if pool.0.len() < MAX_POOLED_PARTICLES {
+                    *visibility = Visibility::Hidden;
+                    commands.entity(entity_id).remove::<Particle>();
+                    pool.0.push(entity_id);
+                } else {
+                    commands.entity(entity_id).despawn();
+                }
+                continue;
+            }
+            let delta = time.delta_seconds();
+            particle.velocity.y -= particle.gravity * delta;
+            transform.translation += (particle.velocity * delta).extend(0.0);
+            transform.rotate_z(particle.spin * delta);
+            if particle.fade {
+                sprite
+                    .color
+                    .set_a(1.0 - particle.time.as_secs_f32() / particle.lifetime.as_secs_f32());
+            }
+        }
+    }
+
+    /// Celebrates the winner when the game over screen comes up
+    pub(crate) fn client_confetti_on_win(
+        result: Res<GameResult>,
+        mut particles: Particles,
+        window_size: Res<WindowSize>,
+        palette: Res<Palette>,
+    ) {
+        if result.is_added() && result.winner.is_some() {
+            particles.confetti(window_size.0, *palette);
+        }
+    }
+}
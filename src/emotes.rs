@@ -0,0 +1,157 @@
+//! Quick emotes, a handful of canned messages that players can send with a single key for when
+//! typing in the chat would take too long. Each one pops up in a bubble over the sender's pawn.
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use std::time::Duration;
+
+use crate::client::{BoardCells, WindowSize};
+use crate::game::{Emote, EmoteBroadcast, EmoteRequest, Player};
+use crate::palette::Palette;
+use crate::LabyrinthPlugin;
+
+const BUBBLE_DURATION: Duration = Duration::from_secs(3);
+
+/// The keys for each of [`Emote::ALL`]
+const EMOTE_KEYS: [KeyCode; 3] = [KeyCode::F1, KeyCode::F2, KeyCode::F3];
+
+/// An emote shown over a player's pawn, with its text as a child
+#[derive(Component)]
+pub(crate) struct EmoteBubble {
+    player_number: usize,
+    time: Duration,
+}
+
+impl LabyrinthPlugin {
+    /// Observers have no pawn to show a bubble over, so their emotes are dropped
+    pub(crate) fn server_receive_emotes(
+        mut emote_events: EventReader<FromClient<EmoteRequest>>,
+        players: Query<&Player>,
+        mut emote_writer: EventWriter<ToClients<EmoteBroadcast>>,
+    ) {
+        for FromClient { client_id, event } in emote_events.read() {
+            let Some(player) = players
+                .iter()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                continue;
+            };
+            emote_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: EmoteBroadcast {
+                    player_number: player.player_number,
+                    emote: event.0,
+                },
+            });
+        }
+    }
+
+    /// F1, F2 and F3 send the emotes
+    pub(crate) fn client_handle_emote_input(
+        keys: Res<Input<KeyCode>>,
+        mut emote_requests: EventWriter<EmoteRequest>,
+    ) {
+        for (key, emote) in EMOTE_KEYS.into_iter().zip(Emote::ALL) {
+            if keys.just_pressed(key) {
+                emote_requests.send(EmoteRequest(emote));
+            }
+        }
+    }
+
+    /// A new emote replaces any bubble the player already has up
+    pub(crate) fn client_on_emote(
+        mut commands: Commands,
+        mut emote_events: EventReader<EmoteBroadcast>,
+        bubbles: Query<(Entity, &EmoteBubble)>,
+        palette: Res<Palette>,
+    ) {
+        for event in emote_events.read() {
+            for (bubble_id, bubble) in bubbles.iter() {
+                if bubble.player_number == event.player_number {
+                    commands.entity(bubble_id).despawn_recursive();
+                }
+            }
+            commands
+                .spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::rgba(0.0, 0.0, 0.0, 0.75),
+                            ..default()
+                        },
+                        // hidden until it's been placed over the pawn
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    EmoteBubble {
+                        player_number: event.player_number,
+                        time: Duration::ZERO,
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn(Text2dBundle {
+                        text: Text::from_section(
+                            event.emote.text(),
+                            TextStyle {
+                                color: palette.color(event.player_number),
+                                ..default()
+                            },
+                        ),
+                        transform: Transform::from_xyz(0.0, 0.0, 0.1),
+                        ..default()
+                    });
+                });
+        }
+    }
+
+    /// Keeps each bubble above its pawn and sized to the board, and pops it once it's been up
+    /// long enough
+    pub(crate) fn client_update_emote_bubbles(
+        mut commands: Commands,
+        mut bubbles: Query<(
+            Entity,
+            &mut EmoteBubble,
+            &mut Sprite,
+            &mut Transform,
+            &mut Visibility,
+            &Children,
+        )>,
+        mut texts: Query<&mut Text>,
+        players: Query<(&Player, &Transform), Without<EmoteBubble>>,
+        time: Res<Time>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+    ) {
+        let board_size = Self::calc_board_size(window_size.0);
+        let cell_size = Self::calc_cell_size(board_size, cells.0);
+        for (bubble_id, mut bubble, mut sprite, mut transform, mut visibility, children) in
+            bubbles.iter_mut()
+        {
+            bubble.time += time.delta();
+            let pawn = players
+                .iter()
+                .find(|(player, _)| player.player_number == bubble.player_number);
+            let Some((_, pawn_transform)) = pawn.filter(|_| bubble.time < BUBBLE_DURATION) else {
+                commands.entity(bubble_id).despawn_recursive();
+                continue;
+            };
+
+            let font_size = cell_size.y * 0.3;
+            let mut chars = 0;
+            for &child in children.iter() {
+                if let Ok(mut text) = texts.get_mut(child) {
+                    text.sections[0].style.font_size = font_size;
+                    chars = text.sections[0].value.chars().count();
+                }
+            }
+            // the default font is monospaced, with characters a little over half as wide as
+            // they are tall
+            sprite.custom_size = Some(Vec2::new(
+                (chars as f32 * 0.6 + 1.0) * font_size,
+                font_size * 1.6,
+            ));
+            transform.translation =
+                pawn_transform.translation.truncate().extend(3.0) + Vec3::Y * cell_size.y * 0.7;
+            *visibility = Visibility::Inherited;
+        }
+    }
+}
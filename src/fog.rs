@@ -0,0 +1,134 @@
+//! Fog of war, where players only see the walls near where their pawn has been. Walls aren't
+//! entities, so rather than replicating them per client, the server sends each player a maze
+//! with only the part they've explored.
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::ClientId;
+
+use crate::client::BoardCell;
+use crate::game::{Maze, Player};
+use crate::server::{AwaitingReconnect, Observers};
+use crate::LabyrinthPlugin;
+
+/// Present on a server started with `--fog`
+#[derive(Resource)]
+pub(crate) struct FogOfWar {
+    /// How many cells away from a pawn its player can see
+    radius: i32,
+    /// The cells each player has been near, by player number. Forgotten when the maze changes.
+    explored: HashMap<usize, Vec<Vec<bool>>>,
+}
+
+impl FogOfWar {
+    pub(crate) fn new(radius: u8) -> Self {
+        Self {
+            radius: radius as i32,
+            explored: HashMap::new(),
+        }
+    }
+}
+
+impl LabyrinthPlugin {
+    /// Every maze the server sends comes through here on its way out, and is swapped for the
+    /// part of it each player has explored. Players are also sent their maze again whenever they
+    /// explore somewhere new. Observers aren't playing, so they see the whole maze.
+    pub(crate) fn server_update_fog_of_war(
+        mut fog: ResMut<FogOfWar>,
+        maze: Res<Maze>,
+        mut maze_events: ResMut<Events<ToClients<Maze>>>,
+        players: Query<&Player, Without<AwaitingReconnect>>,
+        observers: Res<Observers>,
+    ) {
+        if maze.is_changed() {
+            fog.explored.clear();
+        }
+
+        let clients: Vec<_> = players
+            .iter()
+            .map(|player| ClientId::from_raw(player.client_id))
+            .chain(observers.0.iter().copied())
+            .collect();
+        let mut recipients = HashSet::new();
+        for event in maze_events.drain() {
+            match event.mode {
+                SendMode::Broadcast => recipients.extend(clients.iter().copied()),
+                SendMode::BroadcastExcept(except_id) => recipients.extend(
+                    clients
+                        .iter()
+                        .copied()
+                        .filter(|&client_id| client_id != except_id),
+                ),
+                SendMode::Direct(client_id) => {
+                    recipients.insert(client_id);
+                }
+            }
+        }
+
+        let radius = fog.radius;
+        let last_cell = maze.size as i32 - 1;
+        for player in players.iter() {
+            let explored = fog
+                .explored
+                .entry(player.player_number)
+                .or_insert_with(|| vec![vec![false; maze.size]; maze.size]);
+            let mut explored_more = false;
+            for y in (player.coords.y - radius).max(0)..=(player.coords.y + radius).min(last_cell) {
+                for x in
+                    (player.coords.x - radius).max(0)..=(player.coords.x + radius).min(last_cell)
+                {
+                    let cell = &mut explored[y as usize][x as usize];
+                    explored_more |= !*cell;
+                    *cell = true;
+                }
+            }
+            if explored_more {
+                recipients.insert(ClientId::from_raw(player.client_id));
+            }
+        }
+
+        for client_id in recipients {
+            let player = players
+                .iter()
+                .find(|player| player.client_id == client_id.raw());
+            let event = match player.and_then(|player| fog.explored.get(&player.player_number)) {
+                Some(explored) => maze.fogged(explored),
+                None => maze.clone(),
+            };
+            maze_events.send(ToClients {
+                mode: SendMode::Direct(client_id),
+                event,
+            });
+        }
+    }
+
+    /// Darkens the cells the player hasn't explored yet. Called while the board is rebuilt for a
+    /// new maze, so the fog is cleared along with the rest of the board.
+    pub(crate) fn spawn_fog(commands: &mut Commands, maze: &Maze, board_size: Vec2) {
+        let Some(explored) = &maze.explored else {
+            return;
+        };
+        let cell_size = Self::calc_cell_size(board_size, maze.size);
+        for (y, row) in explored.iter().enumerate() {
+            for (x, _) in row.iter().enumerate().filter(|(_, &explored)| !explored) {
+                let coords = IVec2::new(x as i32, y as i32);
+                commands.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::rgba(0.0, 0.0, 0.0, 0.7),
+                            custom_size: Some(cell_size),
+                            ..default()
+                        },
+                        // over the board and its walls, but under the pawns
+                        transform: Transform::from_translation(
+                            Self::board_pos_to_pos(coords, board_size, maze.size).extend(-0.4),
+                        ),
+                        ..default()
+                    },
+                    BoardCell { coords, scale: 1.0 },
+                ));
+            }
+        }
+    }
+}
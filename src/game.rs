@@ -0,0 +1,1855 @@
+//! The rules of the game and the state that is shared between the server and its clients
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::ops::{DerefMut, RangeInclusive};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::net::fnv1a;
+use crate::LabyrinthPlugin;
+
+/// The number of cells along each side of the board in the background art
+pub const ART_BOARD_SIZE: usize = 6;
+pub const DEFAULT_BOARD_SIZE: usize = 6;
+pub const MAX_BOARD_SIZE: usize = 12;
+pub const COLOR_NAMES: [&str; 4] = ["Red", "Green", "Blue", "Yellow"];
+pub const ITEMS_TO_WIN: usize = 5;
+pub const TURN_LOG_LENGTH: usize = 50;
+const DICE_FACES: [u8; 6] = [1, 2, 2, 3, 3, 4];
+/// The highest face a dice can have, since the dice art only goes up to 4
+pub const MAX_DICE_FACE: u8 = 4;
+/// The steps a turn that [`Movement`] gives when it's changed to a fixed number in the lobby
+pub const DEFAULT_MOVEMENT_BUDGET: u8 = 3;
+pub const MAX_MOVEMENT_BUDGET: u8 = 12;
+pub const MAX_TILES: usize = Maze::max_tiles(DEFAULT_BOARD_SIZE);
+const MAX_TILE_ATTEMPTS: usize = 1000;
+/// The steps a target item is dealt from its player with [`ItemDealing::Balanced`], unless the
+/// server picks its own with `--target-distance`
+pub const DEFAULT_TARGET_DISTANCE: RangeInclusive<usize> = 3..=6;
+/// The special cells placed on a generated maze with `--special-cells`
+const GENERATED_SPECIAL_CELLS: [SpecialCell; 6] = [
+    SpecialCell::Teleporter(0),
+    SpecialCell::Teleporter(0),
+    SpecialCell::Trap,
+    SpecialCell::Trap,
+    SpecialCell::Bonus,
+    SpecialCell::Bonus,
+];
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 200;
+pub const MAX_PLAYER_NAME_LENGTH: usize = 16;
+pub const MAX_SKIN_NAME_LENGTH: usize = 32;
+pub const MATCH_SEED_LENGTH: usize = 8;
+
+impl LabyrinthPlugin {
+    /// Whether no player could reach [`ITEMS_TO_WIN`] any more, even if they were dealt every
+    /// remaining item
+    pub(crate) fn is_unwinnable<'a>(
+        mut players: impl Iterator<Item = &'a Player>,
+        available_items: &AvailableItems,
+    ) -> bool {
+        !players.any(|player| {
+            player.achieved_items.len()
+                + usize::from(player.target_item.is_some())
+                + available_items.items.len()
+                >= ITEMS_TO_WIN
+        })
+    }
+
+    /// Picks the player with the most items, then the fewest crashes, rerolling any remaining tie.
+    /// There's no leader if there are no players left.
+    pub(crate) fn find_leader<'a>(
+        players: impl Iterator<Item = &'a Player>,
+        rng: &mut impl Rng,
+    ) -> Option<usize> {
+        let players: Vec<_> = players.collect();
+        let score = |player: &Player| (player.achieved_items.len(), Reverse(player.crashes));
+        let best_score = players.iter().map(|&player| score(player)).max()?;
+        let leaders: Vec<_> = players
+            .iter()
+            .filter(|&&player| score(player) == best_score)
+            .map(|player| player.player_number)
+            .collect();
+        leaders.choose(rng).copied()
+    }
+
+    /// Passes the turn on from the current player, skipping seats left empty by players who
+    /// disconnected, players who might still reconnect and players who have to miss a turn for
+    /// crashing
+    pub(crate) fn advance_turn(
+        current_turn: &mut CurrentTurn,
+        player_count: usize,
+        players: &mut [impl DerefMut<Target = Player>],
+    ) {
+        if players.iter().all(|player| player.absent) {
+            return;
+        }
+        loop {
+            current_turn.0 = (current_turn.0 + 1) % player_count;
+            let Some(next_player) = players
+                .iter_mut()
+                .find(|player| player.player_number == current_turn.0 && !player.absent)
+            else {
+                continue;
+            };
+            if !next_player.skips_next_roll {
+                break;
+            }
+            next_player.skips_next_roll = false;
+            info!("Player {} misses a turn for crashing", current_turn.0);
+        }
+    }
+
+    pub(crate) fn get_player_start_coords(player_number: usize, board_size: usize) -> IVec2 {
+        IVec2::new(
+            (player_number / 2 * (board_size - 1)) as i32,
+            (player_number % 2 * (board_size - 1)) as i32,
+        )
+    }
+}
+
+/// How the player who takes the first turn is chosen
+#[derive(ValueEnum, Resource, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FirstPlayer {
+    /// Player 0 always goes first
+    Fixed,
+    /// A random player goes first
+    Random,
+}
+
+impl FirstPlayer {
+    /// Picks which of `num_players` players takes the first turn
+    pub fn pick(self, num_players: usize, rng: &mut impl Rng) -> usize {
+        match self {
+            FirstPlayer::Fixed => 0,
+            FirstPlayer::Random => rng.gen_range(0..num_players),
+        }
+    }
+}
+
+/// What happens when a pawn steps onto a cell another pawn is standing on
+#[derive(ValueEnum, Resource, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PawnCollisions {
+    /// Any number of pawns may stand on the same cell
+    Share,
+    /// The step is refused, without using it up
+    Block,
+    /// The pawn that was standing there is sent back to its start
+    Bump,
+}
+
+/// How many steps a player gets each time they roll. Sent to clients whenever it changes, and
+/// kept by them as a resource so that they can show it.
+#[derive(Event, Resource, Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Movement {
+    /// As many as one dice shows
+    #[default]
+    OneDice,
+    /// The total of two dice
+    TwoDice,
+    /// The same number of steps every turn, and rolling only starts the move
+    Budget(u8),
+}
+
+impl Movement {
+    pub fn roll(self, rng: &mut GameRng) -> u8 {
+        match self {
+            Movement::OneDice => rng.roll_dice(),
+            Movement::TwoDice => rng.roll_dice() + rng.roll_dice(),
+            Movement::Budget(steps) => steps,
+        }
+    }
+
+    /// The rule a player switches to by asking for another one in the lobby
+    pub fn next(self) -> Movement {
+        match self {
+            Movement::OneDice => Movement::TwoDice,
+            Movement::TwoDice => Movement::Budget(DEFAULT_MOVEMENT_BUDGET),
+            Movement::Budget(_) => Movement::OneDice,
+        }
+    }
+}
+
+impl std::fmt::Display for Movement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Movement::OneDice => f.write_str("one dice"),
+            Movement::TwoDice => f.write_str("two dice"),
+            Movement::Budget(1) => f.write_str("1 step a turn"),
+            Movement::Budget(steps) => write!(f, "{steps} steps a turn"),
+        }
+    }
+}
+
+/// `one-dice`, `two-dice`, or the number of steps for a fixed budget
+impl FromStr for Movement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "one-dice" => Ok(Movement::OneDice),
+            "two-dice" => Ok(Movement::TwoDice),
+            _ => match s.parse() {
+                Ok(steps @ 1..=MAX_MOVEMENT_BUDGET) => Ok(Movement::Budget(steps)),
+                _ => Err(format!(
+                    "expected one-dice, two-dice or a number of steps from 1 to \
+                     {MAX_MOVEMENT_BUDGET}"
+                )),
+            },
+        }
+    }
+}
+
+/// Sent by a player in the lobby to switch to the next [`Movement`] rule. Everyone has to ready
+/// up again afterwards, so that nobody starts a game with rules they didn't agree to.
+#[derive(Event, Serialize, Deserialize)]
+pub struct MovementChange;
+
+/// The rules the server runs the game with
+#[derive(ValueEnum, Resource, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameMode {
+    /// Players take turns to roll and move
+    Classic,
+    /// Experimental: everyone rolls, then everyone moves at the same time
+    Race,
+}
+
+/// Sent by a player in the lobby to switch between ready and not ready
+#[derive(Event, Serialize, Deserialize)]
+pub struct ReadyToggle;
+
+/// Sent by a player after the game ends to ask for, or stop asking for, another round
+#[derive(Event, Serialize, Deserialize)]
+pub struct RematchToggle;
+
+/// Sent by a player in the lobby to pick a pawn skin, or to go back to the plain pawn
+#[derive(Event, Serialize, Deserialize)]
+pub struct SkinChange(pub Option<String>);
+
+/// Whether a skin name is short and only made of characters that are safe in a file name, so
+/// that it can't point clients at files outside `assets/skins/`
+pub fn is_valid_skin_name(name: &str) -> bool {
+    (1..=MAX_SKIN_NAME_LENGTH).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A chat message typed by a client
+#[derive(Event, Serialize, Deserialize)]
+pub struct ChatMessage(pub String);
+
+/// A chat message sent to everyone, from a player or from an observer if `player_number` is `None`
+#[derive(Event, Clone, Serialize, Deserialize)]
+pub struct ChatBroadcast {
+    pub player_number: Option<usize>,
+    pub text: String,
+}
+
+/// A canned message players can send with a single key, shown in a bubble over their pawn
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Emote {
+    ThumbsUp,
+    Laughing,
+    HurryUp,
+}
+
+impl Emote {
+    pub const ALL: [Emote; 3] = [Emote::ThumbsUp, Emote::Laughing, Emote::HurryUp];
+
+    /// Spelled out, since the default font has no emoji
+    pub fn text(self) -> &'static str {
+        match self {
+            Emote::ThumbsUp => "Nice!",
+            Emote::Laughing => "Haha!",
+            Emote::HurryUp => "Hurry up!",
+        }
+    }
+}
+
+/// Sent by a player to show an emote to everyone
+#[derive(Event, Serialize, Deserialize)]
+pub struct EmoteRequest(pub Emote);
+
+/// Sent to everyone when a player shows an emote
+#[derive(Event, Clone, Serialize, Deserialize)]
+pub struct EmoteBroadcast {
+    pub player_number: usize,
+    pub emote: Emote,
+}
+
+/// Optional limits after which the server ends the game early or penalizes players
+#[derive(Resource, Default)]
+pub struct GameLimits {
+    pub max_turns: Option<u32>,
+    pub max_time: Option<Duration>,
+    /// Players skip their next roll each time they crash this many times
+    pub crash_penalty: Option<u32>,
+    /// Collecting an item doesn't use up a step
+    pub momentum: bool,
+    pub movement: Movement,
+    pub turns_taken: u32,
+    pub elapsed: Duration,
+    pub house_rules: Option<Arc<dyn HouseRules>>,
+}
+
+impl GameLimits {
+    pub fn apply_crash_penalty(&self, player: &mut Player) {
+        if self
+            .crash_penalty
+            .is_some_and(|crash_penalty| player.crashes % crash_penalty == 0)
+        {
+            player.skips_next_roll = true;
+        }
+    }
+
+    pub fn apply_momentum(&self, player: &mut Player) {
+        if self.momentum {
+            player.bonus_steps += 1;
+        }
+    }
+
+    pub fn apply_item_rules(&self, player: &mut Player, item: Item) {
+        if let Some(house_rules) = &self.house_rules {
+            house_rules.on_item_collected(player, item);
+        }
+    }
+
+    pub fn apply_special_cell_rules(&self, player: &mut Player, cell: SpecialCell) {
+        if let Some(house_rules) = &self.house_rules {
+            house_rules.on_special_cell(player, cell);
+        }
+    }
+}
+
+/// Rules a server operator adds on top of the built-in ones, such as from a `rules.rhai` script
+pub trait HouseRules: Send + Sync {
+    /// Called after a player collects an item, unless it won them the game
+    fn on_item_collected(&self, player: &mut Player, item: Item);
+    /// Called after a special cell has had its usual effect on the player who stepped onto it
+    fn on_special_cell(&self, player: &mut Player, cell: SpecialCell);
+}
+
+/// The server's source of randomness for game decisions
+#[derive(Resource)]
+pub struct GameRng {
+    pub rng: ChaCha8Rng,
+    /// The dice seed, if rolls are verifiable
+    pub verifiable: Option<VerifiableDice>,
+    /// Rolls that were decided in advance, which come first unless rolls are verifiable
+    pub forced_rolls: VecDeque<u8>,
+    /// The faces of the dice, which verifiable rolls ignore
+    pub faces: Vec<u8>,
+}
+
+impl GameRng {
+    /// Match seeds that are numbers, like the ones given with `--seed`, are used as the seed
+    /// directly, and anything else is hashed
+    pub fn from_match_seed(match_seed: &str) -> Self {
+        // FNV-1a, so that the same seed string gives the same match on every build
+        Self::from_seed(
+            match_seed
+                .parse()
+                .unwrap_or_else(|_| fnv1a(match_seed.as_bytes())),
+        )
+    }
+
+    pub fn from_seed(seed: u64) -> Self {
+        // ChaCha8, since StdRng's algorithm is allowed to change between versions of rand
+        GameRng {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            verifiable: None,
+            forced_rolls: VecDeque::new(),
+            faces: DICE_FACES.to_vec(),
+        }
+    }
+
+    /// Makes the next rolls come up as `rolls`, in order, before any random ones. Verifiable rolls
+    /// can't be decided in advance, so these are ignored with verifiable dice.
+    pub fn decide_rolls(&mut self, rolls: impl IntoIterator<Item = u8>) {
+        self.forced_rolls.extend(rolls);
+    }
+
+    pub fn roll_dice(&mut self) -> u8 {
+        match &mut self.verifiable {
+            // every roll has to match the seed revealed at the end of the game, and its count of
+            // rolls, so none of them can be decided in advance
+            Some(verifiable_dice) => {
+                let value = VerifiableDice::roll(&verifiable_dice.seed, verifiable_dice.rolls);
+                verifiable_dice.rolls += 1;
+                value
+            }
+            None => self
+                .forced_rolls
+                .pop_front()
+                .unwrap_or_else(|| *self.faces.choose(&mut self.rng).unwrap()),
+        }
+    }
+}
+
+/// The seed the server's [`GameRng`] was created from. Knowing it would let clients predict the
+/// dice rolls and target items, so it's only revealed to them when the game ends.
+#[derive(Resource)]
+pub struct MatchSeed(pub String);
+
+/// A secret seed that every dice roll is derived from. Its hash is sent to clients when the game
+/// starts and the seed itself when the game ends.
+pub struct VerifiableDice {
+    pub seed: [u8; 32],
+    pub rolls: u64,
+}
+
+impl VerifiableDice {
+    /// Roll number `roll_number` is the first 8 bytes of `blake3(seed ++ roll_number)` as a little
+    /// endian integer, modulo the number of faces on the dice
+    pub fn roll(seed: &[u8; 32], roll_number: u64) -> u8 {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(seed);
+        hasher.update(&roll_number.to_le_bytes());
+        let hash = hasher.finalize();
+        let value = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+        DICE_FACES[(value % DICE_FACES.len() as u64) as usize]
+    }
+}
+
+#[derive(Event, Serialize, Deserialize)]
+pub struct DiceCommitment {
+    pub hash: [u8; 32],
+}
+
+#[derive(Event, Serialize, Deserialize)]
+pub struct DiceSeedReveal {
+    pub seed: [u8; 32],
+    pub rolls: u64,
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[derive(Event, Resource, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct CurrentTurn(pub usize);
+
+#[derive(Component, Clone, Serialize, Deserialize, Default)]
+pub struct Player {
+    pub client_id: u64,
+    pub coords: IVec2,
+    pub prev_coords: IVec2,
+    pub player_number: usize,
+    /// Only known to the server. Players are sent their own with [`PlayerTarget`], so that
+    /// opponents can't see it.
+    #[serde(skip)]
+    pub target_item: Option<Item>,
+    pub achieved_items: Vec<Item>,
+    pub crashes: u32,
+    pub skips_next_roll: bool,
+    /// Extra steps on top of the dice roll for the current move, earned with `--momentum`
+    pub bonus_steps: u8,
+    /// Whether the player rolls again once they finish moving, from stepping onto a bonus cell
+    pub extra_roll: bool,
+    pub name: Option<String>,
+    /// The pawn skin the player picked in the lobby, the name of a picture in `assets/skins/`.
+    /// Clients that don't have it draw the plain pawn in the player's colour instead.
+    pub skin: Option<String>,
+    /// Whether the player has readied up in the lobby
+    pub ready: bool,
+    /// Whether the player has asked for a rematch since the game ended
+    pub wants_rematch: bool,
+    /// Whether the player has disconnected and the server is waiting for them to come back. Their
+    /// turns are skipped until they do.
+    pub absent: bool,
+}
+
+impl Player {
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => COLOR_NAMES.get(self.player_number).map_or_else(
+                || format!("Player {}", self.player_number),
+                |name| name.to_string(),
+            ),
+        }
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct PlayerBundle {
+    pub player: Player,
+    pub replication: Replication,
+}
+
+/// A player's progress through the current round in race mode
+#[derive(Component, Clone, Serialize, Deserialize, Default)]
+pub struct RaceState {
+    pub phase: TurnPhase,
+    /// Whether this player has finished the current phase and is waiting on the others
+    pub waiting: bool,
+}
+
+/// How a player's connection to the server is holding up, as the server measures it. The host
+/// and local players don't have a connection, so they don't have one of these.
+#[derive(Component, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct Ping {
+    pub rtt_ms: u16,
+    pub packet_loss_percent: u8,
+}
+
+impl Ping {
+    /// Bad enough that the other players will notice while it's this player's turn
+    pub fn is_lagging(&self) -> bool {
+        self.rtt_ms >= 250 || self.packet_loss_percent >= 5
+    }
+}
+
+/// The result of a player trying to take a step
+pub enum MoveOutcome {
+    OutOfBounds,
+    /// Another pawn is in the way and pawns block each other
+    Occupied,
+    Crashed,
+    Moved,
+    Collected,
+    /// Stepped onto a trap, which ends the player's move
+    Trapped,
+    Won,
+}
+
+#[derive(Event, Clone, Serialize, Deserialize)]
+pub struct PlayerStartMoveAnimation {
+    pub client_id: u64,
+    pub fail: bool,
+    pub move_to: IVec2,
+}
+
+/// Sent to a player whose move or roll didn't take
+#[derive(Event, Serialize, Deserialize)]
+pub struct MoveRejected {
+    pub reason: MoveRejectionReason,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MoveRejectionReason {
+    /// Another player is taking their turn
+    NotYourTurn,
+    /// The player still has to roll, or has no steps left
+    WrongPhase,
+    /// The move would have gone off the edge of the board, and was ignored
+    OutOfBounds,
+    /// Another pawn is standing where the move would have gone, and pawns block each other
+    Occupied,
+    /// The server wasn't started with `--shifting-maze`, or the player already pushed this turn
+    CantShift,
+    /// The push would have cut some cells off from the rest of the maze
+    ShiftDisconnects,
+    /// The server wasn't started with `--undo`, or the last step hit a wall, collected an item,
+    /// landed on a special cell or bumped a pawn
+    CantUndo,
+}
+
+/// Sent to everyone at the moment a player reaches their target item
+#[derive(Event, Clone, Serialize, Deserialize)]
+pub struct ItemCollected {
+    pub player_number: usize,
+    pub item: Item,
+}
+
+/// Sent to everyone when a player drops out of the game. Unless they're gone `for_good`, their
+/// pawn stays [`Player::absent`] in case they reconnect.
+#[derive(Event, Clone, Serialize, Deserialize)]
+pub struct PlayerLeft {
+    pub player_number: usize,
+    pub for_good: bool,
+}
+
+#[derive(
+    Event, States, Copy, Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize,
+)]
+pub enum GameState {
+    #[default]
+    WaitingPlayers,
+    InGame,
+    /// The game is on hold, and nobody can roll or move until it's resumed
+    Paused,
+    Ended,
+}
+
+/// Sent by a player to pause the game, or to resume it if it's paused
+#[derive(Event, Serialize, Deserialize)]
+pub struct PauseToggle;
+
+/// Sent to everyone along with [`GameState::Paused`], saying who paused the game
+#[derive(Event, Copy, Clone, Serialize, Deserialize)]
+pub struct GamePaused {
+    pub player_number: usize,
+}
+
+/// Sent to a player when their target item changes, and to observers allowed the coach view
+#[derive(Event, Copy, Clone, Serialize, Deserialize)]
+pub struct PlayerTarget {
+    pub player_number: usize,
+    pub target_item: Option<Item>,
+}
+
+/// Sent to observers of a server started with `--coach-view`, letting them mark every
+/// player's target on the board
+#[derive(Event, Copy, Clone, Serialize, Deserialize)]
+pub struct CoachViewAllowed;
+
+/// Sent to all clients when the server is about to stop, just before it disconnects them
+#[derive(Event, Clone, Serialize, Deserialize)]
+pub struct ServerShutdown {
+    pub reason: String,
+}
+
+/// Sent to all clients when the game ends, revealing everyone's items
+#[derive(Event, Clone, Serialize, Deserialize)]
+pub struct GameOver {
+    /// `None` if the game ended in a draw
+    pub winner: Option<usize>,
+    pub reason: GameOverReason,
+    pub players: Vec<PlayerSummary>,
+    /// The seed the match was played with, so that it can be replayed
+    pub match_seed: Option<String>,
+}
+
+impl GameOver {
+    pub fn new<'a>(
+        result: GameResult,
+        players: impl Iterator<Item = &'a Player>,
+        match_seed: Option<String>,
+    ) -> Self {
+        let mut players: Vec<_> = players
+            .map(|player| PlayerSummary {
+                player_number: player.player_number,
+                target_item: player.target_item,
+                achieved_items: player.achieved_items.clone(),
+                crashes: player.crashes,
+            })
+            .collect();
+        players.sort_by_key(|player| player.player_number);
+        Self {
+            winner: result.winner,
+            reason: result.reason,
+            players,
+            match_seed,
+        }
+    }
+}
+
+/// Why the game ended, so clients can explain the result
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameOverReason {
+    ItemsCollected,
+    TurnLimit,
+    TimeLimit,
+    Unwinnable,
+    /// Everyone else left, so the remaining player wins
+    LastPlayerStanding,
+    /// Everyone left, or the server was shut down mid-game
+    ServerEnded,
+}
+
+/// How the game ended, present once it has. A `winner` of `None` is a draw, which only happens
+/// when nobody can collect enough items any more or the server ended the game. Turn and time
+/// limits always have a winner, since a tie for the lead is rerolled, unless there's nobody left
+/// to win, in which case the server ended the game.
+#[derive(Resource, Copy, Clone, Debug)]
+pub struct GameResult {
+    pub winner: Option<usize>,
+    pub reason: GameOverReason,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerSummary {
+    pub player_number: usize,
+    pub target_item: Option<Item>,
+    pub achieved_items: Vec<Item>,
+    pub crashes: u32,
+}
+
+#[derive(
+    Event, States, Copy, Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize,
+)]
+pub enum TurnPhase {
+    #[default]
+    Rolling,
+    Moving {
+        steps_taken: u8,
+    },
+}
+
+#[derive(Component, Clone, Serialize, Deserialize, Default)]
+pub struct Dice {
+    pub value: u8,
+    /// The player this dice belongs to in race mode, otherwise it's shared by whoever's turn it is
+    pub owner: Option<usize>,
+}
+
+impl Dice {
+    /// The player whose corner this dice is shown in
+    pub fn corner(&self, current_turn: usize) -> usize {
+        self.owner.unwrap_or(current_turn)
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct DiceBundle {
+    pub dice: Dice,
+    pub replication: Replication,
+}
+
+/// Something that happened during the game, as shown in the turn log
+#[derive(Clone, Serialize, Deserialize)]
+pub enum TurnLogEntry {
+    Rolled {
+        player_number: usize,
+        value: u8,
+    },
+    Moved {
+        player_number: usize,
+        direction: MoveRequest,
+    },
+    Crashed {
+        player_number: usize,
+    },
+    Collected {
+        player_number: usize,
+        item: Item,
+    },
+    Teleported {
+        player_number: usize,
+    },
+    Trapped {
+        player_number: usize,
+    },
+    ExtraRoll {
+        player_number: usize,
+    },
+    /// Sent back to their start by another pawn stepping onto them
+    Bumped {
+        player_number: usize,
+    },
+    Shifted {
+        player_number: usize,
+        shift: ShiftRequest,
+    },
+    /// Took back their last step
+    Undone {
+        player_number: usize,
+    },
+}
+
+impl TurnLogEntry {
+    pub fn player_number(&self) -> usize {
+        match *self {
+            TurnLogEntry::Rolled { player_number, .. }
+            | TurnLogEntry::Moved { player_number, .. }
+            | TurnLogEntry::Crashed { player_number }
+            | TurnLogEntry::Collected { player_number, .. }
+            | TurnLogEntry::Teleported { player_number }
+            | TurnLogEntry::Trapped { player_number }
+            | TurnLogEntry::ExtraRoll { player_number }
+            | TurnLogEntry::Bumped { player_number }
+            | TurnLogEntry::Shifted { player_number, .. }
+            | TurnLogEntry::Undone { player_number } => player_number,
+        }
+    }
+}
+
+/// What happened in the game so far, oldest first, so that players who looked away can catch up.
+/// Only the last [`TURN_LOG_LENGTH`] entries are kept, since the whole log is replicated every
+/// time it grows.
+#[derive(Component, Clone, Serialize, Deserialize, Default)]
+pub struct TurnLog(pub VecDeque<TurnLogEntry>);
+
+impl TurnLog {
+    pub fn push(&mut self, entry: TurnLogEntry) {
+        if self.0.len() >= TURN_LOG_LENGTH {
+            self.0.pop_front();
+        }
+        self.0.push_back(entry);
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct TurnLogBundle {
+    pub turn_log: TurnLog,
+    pub replication: Replication,
+}
+
+#[derive(Event, Serialize, Deserialize)]
+pub struct DiceRollRequest;
+
+#[derive(Event, Copy, Clone, Serialize, Deserialize)]
+pub enum MoveRequest {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl MoveRequest {
+    pub fn delta(&self) -> IVec2 {
+        match self {
+            MoveRequest::Up => IVec2::Y,
+            MoveRequest::Down => IVec2::NEG_Y,
+            MoveRequest::Left => IVec2::NEG_X,
+            MoveRequest::Right => IVec2::X,
+        }
+    }
+
+    pub fn from_delta(delta: IVec2) -> Option<MoveRequest> {
+        [
+            MoveRequest::Up,
+            MoveRequest::Down,
+            MoveRequest::Left,
+            MoveRequest::Right,
+        ]
+        .into_iter()
+        .find(|request| request.delta() == delta)
+    }
+}
+
+/// Sent by a player before they roll to push the walls around a row or column of cells one cell
+/// along, with `--shifting-maze`. Left and right push the row `index` up from the bottom, up and
+/// down push the column `index` along from the left.
+#[derive(Event, Copy, Clone, Serialize, Deserialize)]
+pub struct ShiftRequest {
+    pub index: usize,
+    pub direction: MoveRequest,
+}
+
+/// Sent to everyone along with the shifted maze, so that its walls can be seen sliding
+#[derive(Event, Copy, Clone, Serialize, Deserialize)]
+pub struct MazeShifted(pub ShiftRequest);
+
+/// Sent by the player whose turn it is to take back their last step, with `--undo`
+#[derive(Event, Serialize, Deserialize)]
+pub struct UndoRequest;
+
+/// A cell that does something to pawns that step onto it. They're never on a start corner or
+/// an item, so they don't get in the way of collecting items.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpecialCell {
+    /// Sends the pawn on to the other teleporter with the same pair number
+    Teleporter(u8),
+    /// Ends the move of a pawn that steps onto it
+    Trap,
+    /// Lets the pawn's player roll again once they finish moving
+    Bonus,
+}
+
+#[derive(Event, Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Maze {
+    /// The number of cells along each side of the board
+    pub size: usize,
+    /// `size - 1` rows of `size` bars, each underneath a cell
+    pub horizontal_bars: Vec<Vec<bool>>,
+    /// `size` rows of `size - 1` bars, each to the right of a cell
+    pub vertical_bars: Vec<Vec<bool>>,
+    pub special_cells: Vec<(IVec2, SpecialCell)>,
+    /// With fog of war, the cells the receiving player has been near, indexed by `[y][x]`. Bars
+    /// and special cells away from these are left out of the maze they're sent.
+    pub explored: Option<Vec<Vec<bool>>>,
+}
+
+impl Maze {
+    pub fn new(size: usize) -> Maze {
+        Maze {
+            size,
+            horizontal_bars: vec![vec![false; size]; size - 1],
+            vertical_bars: vec![vec![false; size - 1]; size],
+            special_cells: Vec::new(),
+            explored: None,
+        }
+    }
+
+    /// The part of the maze a player has explored, with every bar that doesn't touch an explored
+    /// cell taken away
+    pub fn fogged(&self, explored: &[Vec<bool>]) -> Maze {
+        let is_explored = |x: usize, y: usize| explored[y][x];
+        let mut maze = Maze::new(self.size);
+        for (y, row) in self.horizontal_bars.iter().enumerate() {
+            for (x, &bar) in row.iter().enumerate() {
+                maze.horizontal_bars[y][x] = bar && (is_explored(x, y) || is_explored(x, y + 1));
+            }
+        }
+        for (y, row) in self.vertical_bars.iter().enumerate() {
+            for (x, &bar) in row.iter().enumerate() {
+                maze.vertical_bars[y][x] = bar && (is_explored(x, y) || is_explored(x + 1, y));
+            }
+        }
+        maze.special_cells = self
+            .special_cells
+            .iter()
+            .copied()
+            .filter(|(coords, _)| is_explored(coords.x as usize, coords.y as usize))
+            .collect();
+        maze.explored = Some(explored.to_vec());
+        maze
+    }
+
+    /// Every bar beyond this many would have to cut the board in two, as a connected board needs
+    /// at least one open edge per cell after the first
+    const fn max_tiles(size: usize) -> usize {
+        2 * size * (size - 1) - (size * size - 1)
+    }
+
+    pub fn in_bounds(&self, pos: IVec2) -> bool {
+        (0..self.size as i32).contains(&pos.x) && (0..self.size as i32).contains(&pos.y)
+    }
+
+    pub fn generate(
+        algorithm: MazeAlgorithm,
+        num_tiles: u8,
+        size: usize,
+        rng: &mut impl Rng,
+    ) -> Maze {
+        match algorithm {
+            MazeAlgorithm::Scattered => Self::generate_with_attempts(num_tiles, size, rng).0,
+            MazeAlgorithm::Backtracker => Self::generate_backtracker(num_tiles, size, rng),
+            MazeAlgorithm::Braided => {
+                Self::generate_scattered(num_tiles, size, rng, Maze::has_no_dead_ends).0
+            }
+            MazeAlgorithm::Symmetric => Self::generate_symmetric(num_tiles, size, rng),
+        }
+    }
+
+    /// Generates a maze, also returning how many random placements each tile took, or `None` for
+    /// tiles that had to fall back to trying every bar in turn
+    pub fn generate_with_attempts(
+        num_tiles: u8,
+        size: usize,
+        rng: &mut impl Rng,
+    ) -> (Maze, Vec<Option<usize>>) {
+        Self::generate_scattered(num_tiles, size, rng, |_| true)
+    }
+
+    /// Places tiles one at a time wherever they land, as long as the maze still follows `rule`
+    fn generate_scattered(
+        num_tiles: u8,
+        size: usize,
+        rng: &mut impl Rng,
+        rule: fn(&Maze) -> bool,
+    ) -> (Maze, Vec<Option<usize>>) {
+        let mut maze = Maze::new(size);
+        let num_tiles = Self::cap_tiles(num_tiles, size);
+
+        let mut attempts = Vec::with_capacity(num_tiles);
+        for placed in 0..num_tiles {
+            let random_attempts = (0..MAX_TILE_ATTEMPTS).position(|_| {
+                let bar = Self::random_bar(size, rng);
+                maze.try_place_bars(&[bar], rule)
+            });
+            if random_attempts.is_none()
+                && !Self::all_bars(size).any(|bar| maze.try_place_bars(&[bar], rule))
+            {
+                warn!("Could only place {placed} of {num_tiles} tiles");
+                break;
+            }
+            attempts.push(random_attempts.map(|attempt| attempt + 1));
+        }
+
+        (maze, attempts)
+    }
+
+    /// Carves a maze with a recursive backtracker and keeps some of the walls it leaves behind,
+    /// so that the tiles line up into corridors rather than being scattered
+    fn generate_backtracker(num_tiles: u8, size: usize, rng: &mut impl Rng) -> Maze {
+        let num_tiles = Self::cap_tiles(num_tiles, size);
+
+        let mut carved = Maze::new(size);
+        for bar in Self::all_bars(size) {
+            *carved.bar_mut(bar) = true;
+        }
+        let mut visited = vec![vec![false; size]; size];
+        let mut stack = vec![IVec2::ZERO];
+        visited[0][0] = true;
+        while let Some(&pos) = stack.last() {
+            let unvisited: Vec<_> = [IVec2::NEG_X, IVec2::X, IVec2::NEG_Y, IVec2::Y]
+                .into_iter()
+                .map(|delta| pos + delta)
+                .filter(|&next_pos| {
+                    carved.in_bounds(next_pos) && !visited[next_pos.y as usize][next_pos.x as usize]
+                })
+                .collect();
+            let Some(&next_pos) = unvisited.choose(rng) else {
+                stack.pop();
+                continue;
+            };
+            *carved.bar_mut(Self::bar_between(pos, next_pos)) = false;
+            visited[next_pos.y as usize][next_pos.x as usize] = true;
+            stack.push(next_pos);
+        }
+
+        let mut walls: Vec<_> = Self::all_bars(size)
+            .filter(|&bar| *carved.bar_mut(bar))
+            .collect();
+        walls.shuffle(rng);
+        let mut maze = Maze::new(size);
+        let placed = walls
+            .into_iter()
+            .filter(|&bar| maze.try_place_bars(&[bar], |_| true))
+            .take(num_tiles)
+            .count();
+        if placed < num_tiles {
+            warn!("Could only place {placed} of {num_tiles} tiles");
+        }
+        maze
+    }
+
+    /// Places tiles four at a time, each a quarter turn around the middle of the board from the
+    /// last, so that the maze looks the same from every start corner
+    fn generate_symmetric(num_tiles: u8, size: usize, rng: &mut impl Rng) -> Maze {
+        let max_tiles = Self::max_tiles(size) / 4 * 4;
+        let num_tiles = ((num_tiles as usize + 2) / 4 * 4).min(max_tiles);
+        info!("Placing {num_tiles} tiles to keep the maze symmetric");
+
+        let mut maze = Maze::new(size);
+        for placed in (0..num_tiles).step_by(4) {
+            let placed_randomly = (0..MAX_TILE_ATTEMPTS).any(|_| {
+                let bars = Self::rotations(Self::random_bar(size, rng), size);
+                maze.try_place_bars(&bars, Maze::is_fair)
+            });
+            if !placed_randomly
+                && !Self::all_bars(size)
+                    .any(|bar| maze.try_place_bars(&Self::rotations(bar, size), Maze::is_fair))
+            {
+                warn!("Could only place {placed} of {num_tiles} tiles");
+                break;
+            }
+        }
+        maze
+    }
+
+    fn cap_tiles(num_tiles: u8, size: usize) -> usize {
+        let max_tiles = Self::max_tiles(size);
+        if num_tiles as usize > max_tiles {
+            warn!("Too many tiles to keep the board connected, placing {max_tiles} instead");
+            max_tiles
+        } else {
+            num_tiles as usize
+        }
+    }
+
+    /// Scatters [`GENERATED_SPECIAL_CELLS`] over the cells that aren't start corners or items
+    pub fn place_special_cells(&mut self, rng: &mut impl Rng) {
+        let free_cells: Vec<_> = (0..self.size as i32)
+            .flat_map(|y| (0..self.size as i32).map(move |x| IVec2::new(x, y)))
+            .filter(|&coords| self.can_be_special(coords))
+            .collect();
+        self.special_cells = free_cells
+            .choose_multiple(rng, GENERATED_SPECIAL_CELLS.len())
+            .copied()
+            .zip(GENERATED_SPECIAL_CELLS)
+            .collect();
+        // a teleporter on its own would have nowhere to send pawns
+        if self
+            .special_cells
+            .iter()
+            .filter(|(_, cell)| *cell == SpecialCell::Teleporter(0))
+            .count()
+            == 1
+        {
+            self.special_cells
+                .retain(|(_, cell)| *cell != SpecialCell::Teleporter(0));
+        }
+    }
+
+    pub(crate) fn can_be_special(&self, coords: IVec2) -> bool {
+        let corner = self.size as i32 - 1;
+        !(coords.x % corner == 0 && coords.y % corner == 0)
+            && !Item::ALL
+                .iter()
+                .any(|item| item.coords(self.size) == coords)
+    }
+
+    pub fn special_cell(&self, coords: IVec2) -> Option<SpecialCell> {
+        self.special_cells
+            .iter()
+            .find(|(cell_coords, _)| *cell_coords == coords)
+            .map(|(_, cell)| *cell)
+    }
+
+    /// Where a pawn that steps onto the teleporter at `coords` comes out
+    pub fn teleporter_exit(&self, coords: IVec2, pair: u8) -> Option<IVec2> {
+        self.special_cells
+            .iter()
+            .find(|(exit, cell)| *exit != coords && *cell == SpecialCell::Teleporter(pair))
+            .map(|(exit, _)| *exit)
+    }
+
+    /// Places bars if there aren't any there already and they wouldn't disconnect the board or
+    /// break `rule`. The bars are `(horizontal, x, y)`, indexing into [`Self::horizontal_bars`]
+    /// or [`Self::vertical_bars`].
+    fn try_place_bars(&mut self, bars: &[(bool, usize, usize)], rule: fn(&Maze) -> bool) -> bool {
+        if bars.iter().any(|&bar| *self.bar_mut(bar)) {
+            return false;
+        }
+        for &bar in bars {
+            *self.bar_mut(bar) = true;
+        }
+        if self.is_valid() && rule(self) {
+            return true;
+        }
+        for &bar in bars {
+            *self.bar_mut(bar) = false;
+        }
+        false
+    }
+
+    fn bar_mut(&mut self, (horizontal, x, y): (bool, usize, usize)) -> &mut bool {
+        if horizontal {
+            &mut self.horizontal_bars[y][x]
+        } else {
+            &mut self.vertical_bars[y][x]
+        }
+    }
+
+    fn all_bars(size: usize) -> impl Iterator<Item = (bool, usize, usize)> {
+        let horizontal = (0..size - 1).flat_map(move |y| (0..size).map(move |x| (true, x, y)));
+        let vertical = (0..size).flat_map(move |y| (0..size - 1).map(move |x| (false, x, y)));
+        horizontal.chain(vertical)
+    }
+
+    fn random_bar(size: usize, rng: &mut impl Rng) -> (bool, usize, usize) {
+        if rng.gen::<bool>() {
+            (true, rng.gen_range(0..size), rng.gen_range(0..size - 1))
+        } else {
+            (false, rng.gen_range(0..size - 1), rng.gen_range(0..size))
+        }
+    }
+
+    /// The bar between two neighbouring cells
+    fn bar_between(a: IVec2, b: IVec2) -> (bool, usize, usize) {
+        let min = a.min(b);
+        (a.x == b.x, min.x as usize, min.y as usize)
+    }
+
+    /// A bar and the three it's turned into by turning the board a quarter at a time
+    fn rotations(
+        (horizontal, x, y): (bool, usize, usize),
+        size: usize,
+    ) -> [(bool, usize, usize); 4] {
+        let rotate = |pos: IVec2| IVec2::new(size as i32 - 1 - pos.y, pos.x);
+        let mut a = IVec2::new(x as i32, y as i32);
+        let mut b = if horizontal {
+            a + IVec2::Y
+        } else {
+            a + IVec2::X
+        };
+        [0; 4].map(|_| {
+            let bar = Self::bar_between(a, b);
+            (a, b) = (rotate(a), rotate(b));
+            bar
+        })
+    }
+
+    /// Whether every cell can be left in at least two directions, so that no corridor is a dead
+    /// end
+    fn has_no_dead_ends(&self) -> bool {
+        (0..self.size as i32)
+            .flat_map(|y| (0..self.size as i32).map(move |x| IVec2::new(x, y)))
+            .all(|pos| {
+                [IVec2::NEG_X, IVec2::X, IVec2::NEG_Y, IVec2::Y]
+                    .into_iter()
+                    .filter(|&delta| {
+                        self.in_bounds(pos + delta) && !self.is_blocked(pos, pos + delta)
+                    })
+                    .count()
+                    >= 2
+            })
+    }
+
+    /// Whether the paths from each start corner to the rest of the board are all the same
+    /// lengths, so that no player starts closer to more of the items
+    pub fn is_fair(&self) -> bool {
+        let mut path_lengths = (0..4).map(|player_number| {
+            let start = LabyrinthPlugin::get_player_start_coords(player_number, self.size);
+            let mut lengths: Vec<_> = self.distances_from(start).into_iter().flatten().collect();
+            lengths.sort();
+            lengths
+        });
+        let first = path_lengths.next().unwrap();
+        path_lengths.all(|lengths| lengths == first)
+    }
+
+    /// How many steps it takes to get to every cell from `from`, indexed by `[y][x]`. Cells that
+    /// can't be reached are `None`.
+    pub fn distances_from(&self, from: IVec2) -> Vec<Vec<Option<usize>>> {
+        let mut distances = vec![vec![None; self.size]; self.size];
+        let mut queue = VecDeque::new();
+        distances[from.y as usize][from.x as usize] = Some(0);
+        queue.push_back(from);
+        while let Some(pos) = queue.pop_front() {
+            let distance = distances[pos.y as usize][pos.x as usize].unwrap();
+            for delta in [IVec2::NEG_X, IVec2::X, IVec2::NEG_Y, IVec2::Y] {
+                let next_pos = pos + delta;
+                if self.in_bounds(next_pos)
+                    && distances[next_pos.y as usize][next_pos.x as usize].is_none()
+                    && !self.is_blocked(pos, next_pos)
+                {
+                    distances[next_pos.y as usize][next_pos.x as usize] = Some(distance + 1);
+                    queue.push_back(next_pos);
+                }
+            }
+        }
+        distances
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let mut reachable = vec![vec![false; self.size]; self.size];
+        self.dfs(IVec2::ZERO, &mut reachable);
+        reachable.iter().flatten().all(|b| *b)
+    }
+
+    fn dfs(&self, pos: IVec2, reachable: &mut [Vec<bool>]) {
+        reachable[pos.y as usize][pos.x as usize] = true;
+        for delta in [IVec2::NEG_X, IVec2::X, IVec2::NEG_Y, IVec2::Y] {
+            let next_pos = pos + delta;
+            if self.in_bounds(next_pos)
+                && !reachable[next_pos.y as usize][next_pos.x as usize]
+                && !self.is_blocked(pos, next_pos)
+            {
+                self.dfs(next_pos, reachable);
+            }
+        }
+    }
+
+    /// Returns every cell that can be reached from `from` in at most `steps` steps, excluding
+    /// `from` itself
+    pub fn reachable_within(&self, from: IVec2, steps: u8) -> Vec<IVec2> {
+        let mut distances = vec![vec![None; self.size]; self.size];
+        let mut queue = VecDeque::new();
+        let mut reachable = Vec::new();
+        distances[from.y as usize][from.x as usize] = Some(0);
+        queue.push_back(from);
+        while let Some(pos) = queue.pop_front() {
+            let distance = distances[pos.y as usize][pos.x as usize].unwrap();
+            if distance >= steps {
+                continue;
+            }
+            for delta in [IVec2::NEG_X, IVec2::X, IVec2::NEG_Y, IVec2::Y] {
+                let next_pos = pos + delta;
+                if self.in_bounds(next_pos)
+                    && distances[next_pos.y as usize][next_pos.x as usize].is_none()
+                    && !self.is_blocked(pos, next_pos)
+                {
+                    distances[next_pos.y as usize][next_pos.x as usize] = Some(distance + 1);
+                    reachable.push(next_pos);
+                    queue.push_back(next_pos);
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Returns the cells to step through to get from `from` to `to`, excluding `from`
+    pub fn find_path(&self, from: IVec2, to: IVec2) -> Option<Vec<IVec2>> {
+        let mut came_from = vec![vec![None; self.size]; self.size];
+        let mut queue = VecDeque::new();
+        came_from[from.y as usize][from.x as usize] = Some(from);
+        queue.push_back(from);
+        while let Some(pos) = queue.pop_front() {
+            if pos == to {
+                let mut path = Vec::new();
+                let mut pos = to;
+                while pos != from {
+                    path.push(pos);
+                    pos = came_from[pos.y as usize][pos.x as usize].unwrap();
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for delta in [IVec2::NEG_X, IVec2::X, IVec2::NEG_Y, IVec2::Y] {
+                let next_pos = pos + delta;
+                if !self.in_bounds(next_pos) {
+                    continue;
+                }
+                if came_from[next_pos.y as usize][next_pos.x as usize].is_none()
+                    && !self.is_blocked(pos, next_pos)
+                {
+                    came_from[next_pos.y as usize][next_pos.x as usize] = Some(pos);
+                    queue.push_back(next_pos);
+                }
+            }
+        }
+        None
+    }
+
+    /// Slides the bars between and alongside a row or column of cells one cell along, with the
+    /// bars pushed off one end coming back in at the other. Special cells stay where they are.
+    /// Returns `false` if there's no such row or column.
+    pub fn shift(&mut self, shift: ShiftRequest) -> bool {
+        let (size, index) = (self.size, shift.index);
+        if index >= size {
+            return false;
+        }
+        let forwards = matches!(shift.direction, MoveRequest::Up | MoveRequest::Right);
+        let rotate = |bars: &mut [bool]| {
+            if forwards {
+                bars.rotate_right(1);
+            } else {
+                bars.rotate_left(1);
+            }
+        };
+        // the lines of bars either side of the row or column, if it isn't along the edge
+        let sides = [index.checked_sub(1), (index < size - 1).then_some(index)];
+        match shift.direction {
+            MoveRequest::Left | MoveRequest::Right => {
+                rotate(&mut self.vertical_bars[index]);
+                for y in sides.into_iter().flatten() {
+                    rotate(&mut self.horizontal_bars[y]);
+                }
+            }
+            MoveRequest::Up | MoveRequest::Down => {
+                // columns are stored across the rows, so they're copied out to rotate them
+                let rotate_column = |rows: &mut [Vec<bool>], x: usize| {
+                    let mut column: Vec<_> = rows.iter().map(|row| row[x]).collect();
+                    rotate(&mut column);
+                    for (row, bar) in rows.iter_mut().zip(column) {
+                        row[x] = bar;
+                    }
+                };
+                rotate_column(&mut self.horizontal_bars, index);
+                for x in sides.into_iter().flatten() {
+                    rotate_column(&mut self.vertical_bars, x);
+                }
+            }
+        }
+        true
+    }
+
+    pub fn is_blocked(&self, from: IVec2, to: IVec2) -> bool {
+        assert_eq!(1, from.x.abs_diff(to.x) + from.y.abs_diff(to.y));
+        if from.x == to.x {
+            self.horizontal_bars[from.y.min(to.y) as usize][from.x as usize]
+        } else {
+            self.vertical_bars[from.y as usize][from.x.min(to.x) as usize]
+        }
+    }
+}
+
+/// Draws the maze with the top row first, `o` for cells, `|` for vertical bars and `-` for
+/// horizontal bars underneath a cell. Special cells are drawn as `x` for traps, `+` for bonus
+/// cells, and the digit of their pair for teleporters.
+impl std::fmt::Display for Maze {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for y in (0..self.size).rev() {
+            for x in 0..self.size {
+                f.write_char(match self.special_cell(IVec2::new(x as i32, y as i32)) {
+                    None => 'o',
+                    Some(SpecialCell::Teleporter(pair)) => {
+                        char::from_digit(pair as u32, 10).unwrap_or('o')
+                    }
+                    Some(SpecialCell::Trap) => 'x',
+                    Some(SpecialCell::Bonus) => '+',
+                })?;
+                if x != self.size - 1 {
+                    f.write_char(if self.vertical_bars[y][x] { '|' } else { ' ' })?;
+                }
+            }
+            if y != 0 {
+                f.write_char('\n')?;
+                for x in 0..self.size {
+                    f.write_char(if self.horizontal_bars[y - 1][x] {
+                        '-'
+                    } else {
+                        ' '
+                    })?;
+                    if x != self.size - 1 {
+                        f.write_char(' ')?;
+                    }
+                }
+                f.write_char('\n')?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Maze {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the board size comes from the number of lines, which alternate between cells and bars
+        let lines: Vec<_> = s.lines().collect();
+        let size = (lines.len() + 1) / 2;
+        if lines.len() % 2 == 0 || !(DEFAULT_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&size) {
+            return Err(format!(
+                "expected an odd number of lines between {} and {}, found {}",
+                DEFAULT_BOARD_SIZE * 2 - 1,
+                MAX_BOARD_SIZE * 2 - 1,
+                lines.len()
+            ));
+        }
+        let mut maze = Maze::new(size);
+        for (line_index, line) in lines.into_iter().enumerate() {
+            let chars: Vec<_> = line.chars().collect();
+            if chars.len() > size * 2 - 1 {
+                return Err(format!("line {} is too long", line_index + 1));
+            }
+            // trailing spaces are easily lost when editing by hand
+            let char_at = |column: usize| chars.get(column).copied().unwrap_or(' ');
+            let y = size - 1 - line_index / 2;
+            for column in 0..size * 2 - 1 {
+                let x = column / 2;
+                let bar = match (line_index % 2, column % 2, char_at(column)) {
+                    (0, 0, 'o') | (1, 1, ' ') => continue,
+                    (0, 0, c @ ('x' | '+' | '0'..='9')) => {
+                        let coords = IVec2::new(x as i32, y as i32);
+                        if !maze.can_be_special(coords) {
+                            return Err(format!(
+                                "special cell '{c}' at line {}, column {} is on a start corner or \
+                                an item",
+                                line_index + 1,
+                                column + 1
+                            ));
+                        }
+                        let cell = match c {
+                            'x' => SpecialCell::Trap,
+                            '+' => SpecialCell::Bonus,
+                            _ => SpecialCell::Teleporter(c.to_digit(10).unwrap() as u8),
+                        };
+                        maze.special_cells.push((coords, cell));
+                        continue;
+                    }
+                    (0, 1, '|') | (1, 0, '-') => true,
+                    (0, 1, ' ') | (1, 0, ' ') => false,
+                    (_, _, c) => {
+                        return Err(format!(
+                            "unexpected '{c}' at line {}, column {}",
+                            line_index + 1,
+                            column + 1
+                        ))
+                    }
+                };
+                if line_index % 2 == 0 {
+                    maze.vertical_bars[y][x] = bar;
+                } else {
+                    maze.horizontal_bars[y - 1][x] = bar;
+                }
+            }
+        }
+
+        for pair in 0..10 {
+            let teleporters = maze
+                .special_cells
+                .iter()
+                .filter(|(_, cell)| *cell == SpecialCell::Teleporter(pair))
+                .count();
+            if teleporters != 0 && teleporters != 2 {
+                return Err(format!(
+                    "teleporter {pair} appears {teleporters} times, but teleporters come in pairs"
+                ));
+            }
+        }
+
+        Ok(maze)
+    }
+}
+
+macro_rules! items {
+    ($(($name:ident @ $x:literal, $y: literal),)*) => {
+        #[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+        pub enum Item {
+            #[default]
+            $($name,)*
+        }
+
+        impl Item {
+            pub const ALL: [Item; 24] = [$(Item::$name,)*];
+
+            /// Where the item is drawn in the background art
+            pub fn art_coords(&self) -> IVec2 {
+                match self {
+                    $(Item::$name => IVec2::new($x, $y),)*
+                }
+            }
+        }
+
+        impl std::fmt::Display for Item {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    $(Item::$name => f.write_str(stringify!($name)),)*
+                }
+            }
+        }
+    }
+}
+
+items! {
+    (Bracelet @ 2, 0),
+    (YinYang @ 3, 0),
+    (Lightning @ 1, 1),
+    (Moon @ 2, 1),
+    (ShootingStar @ 3, 1),
+    (Fire @ 4, 1),
+    (Bird @ 0, 2),
+    (Dagger @ 1, 2),
+    (Crown @ 2, 2),
+    (Mushroom @ 3, 2),
+    (Ring @ 4, 2),
+    (Mouse @ 5, 2),
+    (Sun @ 0, 3),
+    (Snake @ 1, 3),
+    (Flower @ 2, 3),
+    (Candle @ 3, 3),
+    (Feather @ 4, 3),
+    (Cat @ 5, 3),
+    (SpiderWeb @ 1, 4),
+    (Bat @ 2, 4),
+    (Owl @ 3, 4),
+    (Eye @ 4, 4),
+    (PartyHat @ 2, 5),
+    (MagicWand @ 3, 5),
+}
+
+impl Item {
+    /// Where the item is on a board of the given size, spread out from where it is in the art so
+    /// that items stay on distinct cells
+    pub fn coords(&self, board_size: usize) -> IVec2 {
+        self.art_coords() * (board_size as i32 - 1) / (ART_BOARD_SIZE as i32 - 1)
+    }
+
+    pub fn atlas_index(&self) -> usize {
+        let coords = self.art_coords();
+        (ART_BOARD_SIZE - 1 - coords.y as usize) * ART_BOARD_SIZE + coords.x as usize
+    }
+}
+
+/// How the walls of a generated maze are laid out
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MazeAlgorithm {
+    /// Tiles are placed wherever they land, as long as every cell can still be reached
+    Scattered,
+    /// Tiles are taken from the walls of a maze carved by a recursive backtracker, so they line
+    /// up into corridors
+    Backtracker,
+    /// Like scattered, but no tile may leave a dead end
+    Braided,
+    /// Tiles are placed in fours, turned a quarter around the board from each other, so that
+    /// every start corner has the same paths ahead of it. The number of tiles is rounded to a
+    /// multiple of four.
+    Symmetric,
+}
+
+/// How target items are dealt out to players
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ItemDealing {
+    /// Any remaining item may be picked
+    Random,
+    /// Items a moderate distance along the maze away from the player are preferred, see
+    /// `--target-distance`
+    Balanced,
+}
+
+#[derive(Resource)]
+pub struct AvailableItems {
+    pub items: Vec<Item>,
+    pub dealing: ItemDealing,
+    /// How many steps away from its player a target is dealt, with [`ItemDealing::Balanced`]
+    pub target_distance: RangeInclusive<usize>,
+    pub log_assignments: bool,
+}
+
+impl AvailableItems {
+    pub fn new(dealing: ItemDealing, log_assignments: bool) -> Self {
+        let mut items = Vec::with_capacity(24);
+        items.extend_from_slice(&Item::ALL);
+        AvailableItems {
+            items,
+            dealing,
+            target_distance: DEFAULT_TARGET_DISTANCE,
+            log_assignments,
+        }
+    }
+
+    /// Returns a player's target item to the pool when they leave the game
+    pub fn give_back(&mut self, item: Item) {
+        self.items.push(item);
+    }
+
+    pub fn take_target(
+        &mut self,
+        player_number: usize,
+        from: IVec2,
+        maze: &Maze,
+        rng: &mut impl Rng,
+    ) -> Option<Item> {
+        if self.items.is_empty() {
+            return None;
+        }
+
+        // one search from the player finds the distance to every item
+        let distances = maze.distances_from(from);
+        let distance = |item: &Item| {
+            let coords = item.coords(maze.size);
+            distances[coords.y as usize][coords.x as usize].unwrap_or(usize::MAX)
+        };
+
+        let index = match self.dealing {
+            ItemDealing::Random => rng.gen_range(0..self.items.len()),
+            ItemDealing::Balanced => {
+                let band = &self.target_distance;
+                let in_band: Vec<_> = (0..self.items.len())
+                    .filter(|&index| band.contains(&distance(&self.items[index])))
+                    .collect();
+                if let Some(&index) = in_band.choose(rng) {
+                    index
+                } else {
+                    // nothing in the band, pick whatever is closest to it
+                    (0..self.items.len())
+                        .min_by_key(|&index| {
+                            let distance = distance(&self.items[index]);
+                            band.start()
+                                .saturating_sub(distance)
+                                .max(distance.saturating_sub(*band.end()))
+                        })
+                        .unwrap()
+                }
+            }
+        };
+
+        let item = self.items.remove(index);
+        if self.log_assignments {
+            info!(
+                "Assigned {item} to player {player_number} at {from}, {} steps away",
+                distance(&item)
+            );
+        }
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_random_first_player_is_the_same_for_the_same_seed() {
+        let first_player = |first_player: FirstPlayer, seed: usize| {
+            let mut rng = GameRng::from_match_seed(&format!("first-player-{seed}")).rng;
+            first_player.pick(2, &mut rng)
+        };
+        let first_players: Vec<_> = (0..16)
+            .map(|seed| first_player(FirstPlayer::Random, seed))
+            .collect();
+        for (seed, &player_number) in first_players.iter().enumerate() {
+            assert_eq!(player_number, first_player(FirstPlayer::Random, seed));
+        }
+        // and it really is random between match seeds
+        assert!(first_players.contains(&0) && first_players.contains(&1));
+        assert!((0..16).all(|seed| first_player(FirstPlayer::Fixed, seed) == 0));
+    }
+
+    #[test]
+    fn nobody_leads_when_no_players_are_left() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1127);
+        assert_eq!(
+            None,
+            LabyrinthPlugin::find_leader(std::iter::empty(), &mut rng)
+        );
+        // the same items, so the fewest crashes leads
+        let players = [
+            Player {
+                player_number: 0,
+                crashes: 2,
+                ..default()
+            },
+            Player {
+                player_number: 1,
+                crashes: 1,
+                ..default()
+            },
+        ];
+        assert_eq!(
+            Some(1),
+            LabyrinthPlugin::find_leader(players.iter(), &mut rng)
+        );
+    }
+
+    #[test]
+    fn the_same_match_seed_sets_up_the_same_game() {
+        let set_up = |match_seed| {
+            let mut rng = GameRng::from_match_seed(match_seed);
+            let maze = Maze::generate(
+                MazeAlgorithm::Scattered,
+                20,
+                DEFAULT_BOARD_SIZE,
+                &mut rng.rng,
+            );
+            let mut items = AvailableItems::new(ItemDealing::Balanced, false);
+            let targets: Vec<_> =
+                std::iter::from_fn(|| items.take_target(0, IVec2::ZERO, &maze, &mut rng.rng))
+                    .map(|item| item.to_string())
+                    .collect();
+            (maze.horizontal_bars, maze.vertical_bars, targets)
+        };
+        assert_eq!(set_up("daily-challenge"), set_up("daily-challenge"));
+        assert_ne!(set_up("daily-challenge"), set_up("another-challenge"));
+    }
+
+    #[test]
+    fn the_same_seed_sets_up_the_same_game() {
+        let set_up = |seed| {
+            let mut rng = GameRng::from_seed(seed);
+            let maze = Maze::generate(
+                MazeAlgorithm::Scattered,
+                20,
+                DEFAULT_BOARD_SIZE,
+                &mut rng.rng,
+            );
+            let mut items = AvailableItems::new(ItemDealing::Random, false);
+            let targets: Vec<_> =
+                std::iter::from_fn(|| items.take_target(0, IVec2::ZERO, &maze, &mut rng.rng))
+                    .map(|item| item.to_string())
+                    .collect();
+            let rolls: Vec<_> = (0..20).map(|_| rng.roll_dice()).collect();
+            (maze.horizontal_bars, maze.vertical_bars, targets, rolls)
+        };
+        for seed in [0, 42, u64::MAX] {
+            assert_eq!(set_up(seed), set_up(seed), "seed {seed}");
+            assert_ne!(set_up(seed), set_up(seed.wrapping_add(1)), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn a_numeric_match_seed_is_used_as_the_seed() {
+        let rolls = |mut rng: GameRng| (0..20).map(|_| rng.roll_dice()).collect::<Vec<_>>();
+        assert_eq!(
+            rolls(GameRng::from_seed(42)),
+            rolls(GameRng::from_match_seed("42"))
+        );
+        assert_ne!(
+            rolls(GameRng::from_seed(42)),
+            rolls(GameRng::from_match_seed("42nd"))
+        );
+    }
+
+    #[test]
+    fn rolls_decided_in_advance_only_count_without_verifiable_dice() {
+        let mut rng = GameRng::from_seed(48);
+        rng.decide_rolls([4, 1]);
+        assert_eq!(4, rng.roll_dice());
+        assert_eq!(1, rng.roll_dice());
+
+        let seed = [48; 32];
+        let mut rng = GameRng::from_seed(48);
+        rng.verifiable = Some(VerifiableDice { seed, rolls: 0 });
+        rng.decide_rolls([4, 1]);
+        for roll_number in 0..2 {
+            assert_eq!(VerifiableDice::roll(&seed, roll_number), rng.roll_dice());
+        }
+        assert_eq!(2, rng.verifiable.as_ref().unwrap().rolls);
+    }
+
+    #[test]
+    fn generating_with_the_most_tiles_keeps_every_cell_reachable() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1130);
+        for &algorithm in MazeAlgorithm::value_variants() {
+            let maze = Maze::generate(algorithm, MAX_TILES as u8, DEFAULT_BOARD_SIZE, &mut rng);
+            assert!(
+                maze.is_valid(),
+                "{algorithm:?} cut the board in two:\n{maze}"
+            );
+            let max_tiles = Maze::max_tiles(MAX_BOARD_SIZE) as u8;
+            let maze = Maze::generate(algorithm, max_tiles, MAX_BOARD_SIZE, &mut rng);
+            assert!(
+                maze.is_valid(),
+                "{algorithm:?} cut the board in two:\n{maze}"
+            );
+        }
+        // more tiles than that are capped rather than placed
+        let maze = Maze::generate(
+            MazeAlgorithm::Scattered,
+            u8::MAX,
+            DEFAULT_BOARD_SIZE,
+            &mut rng,
+        );
+        assert!(maze.is_valid());
+    }
+
+    #[test]
+    fn generated_mazes_survive_a_round_trip_through_text() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1136);
+        for &algorithm in MazeAlgorithm::value_variants() {
+            for size in [DEFAULT_BOARD_SIZE, 9, MAX_BOARD_SIZE] {
+                let maze = Maze::generate(algorithm, 20, size, &mut rng);
+                assert_eq!(Ok(&maze), maze.to_string().parse::<Maze>().as_ref());
+            }
+        }
+    }
+
+    #[test]
+    fn special_cells_survive_a_round_trip_through_text() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1136);
+        let mut maze = Maze::generate(MazeAlgorithm::Scattered, 20, DEFAULT_BOARD_SIZE, &mut rng);
+        maze.place_special_cells(&mut rng);
+        let parsed: Maze = maze.to_string().parse().unwrap();
+        // the cells are listed in the order they're read, which needn't be the order they were
+        // placed in
+        assert_eq!(maze.special_cells.len(), parsed.special_cells.len());
+        assert_eq!(maze.to_string(), parsed.to_string());
+    }
+
+    #[test]
+    fn malformed_maze_text_is_rejected() {
+        let maze = Maze::new(DEFAULT_BOARD_SIZE).to_string();
+        assert!(maze.parse::<Maze>().is_ok());
+        // a line of cells without the bars underneath it
+        let even_lines = maze.lines().skip(1).collect::<Vec<_>>().join("\n");
+        assert!(even_lines.parse::<Maze>().is_err());
+        assert!(maze.replacen('o', "?", 1).parse::<Maze>().is_err());
+        // a bar where a cell should be
+        assert!(maze.replacen('o', "|", 1).parse::<Maze>().is_err());
+        assert!(maze.replacen('\n', " o\n", 1).parse::<Maze>().is_err());
+        // a trap on a start corner
+        assert!(maze.replacen('o', "x", 1).parse::<Maze>().is_err());
+    }
+
+    #[test]
+    fn mazes_outside_the_board_sizes_are_rejected() {
+        assert!(Maze::new(MAX_BOARD_SIZE)
+            .to_string()
+            .parse::<Maze>()
+            .is_ok());
+        assert!(Maze::new(MAX_BOARD_SIZE + 1)
+            .to_string()
+            .parse::<Maze>()
+            .is_err());
+        assert!(Maze::new(DEFAULT_BOARD_SIZE - 1)
+            .to_string()
+            .parse::<Maze>()
+            .is_err());
+        assert!("".parse::<Maze>().is_err());
+    }
+
+    #[test]
+    fn items_map_to_their_cells_in_the_atlas() {
+        // the atlas is read from the top row down, while art coords count up from the bottom
+        assert_eq!(2, Item::PartyHat.atlas_index());
+        assert_eq!(3, Item::MagicWand.atlas_index());
+        assert_eq!(12, Item::Sun.atlas_index());
+        assert_eq!(23, Item::Mouse.atlas_index());
+        assert_eq!(32, Item::Bracelet.atlas_index());
+        assert_eq!(33, Item::YinYang.atlas_index());
+
+        let mut indices: Vec<_> = Item::ALL.iter().map(Item::atlas_index).collect();
+        indices.sort();
+        indices.dedup();
+        assert_eq!(Item::ALL.len(), indices.len());
+        assert!(indices
+            .iter()
+            .all(|&index| index < ART_BOARD_SIZE * ART_BOARD_SIZE));
+    }
+}
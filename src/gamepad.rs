@@ -0,0 +1,176 @@
+//! Playing with a controller. The d-pad or left stick moves, the south button rolls and
+//! confirms, and menus are worked by pressing the keys they listen for.
+
+use bevy::input::gamepad::{GamepadConnection, GamepadConnectionEvent};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::time::Duration;
+
+use crate::client::{DisconnectMessage, SettingsMenu, TurnActions, TurnControls};
+use crate::game::{GameState, MoveRequest};
+use crate::LabyrinthPlugin;
+
+/// How far the left stick has to be pushed before it counts as a step
+const STICK_PRESS_THRESHOLD: f32 = 0.6;
+/// How far back towards the middle the stick has to come before it can step again, so that a
+/// stick resting near the threshold doesn't step over and over
+const STICK_RELEASE_THRESHOLD: f32 = 0.3;
+const GAMEPAD_NOTICE_DURATION: Duration = Duration::from_secs(2);
+
+impl LabyrinthPlugin {
+    /// The controller's counterpart to [`LabyrinthPlugin::client_handle_keyboard_input`]
+    pub(crate) fn client_handle_gamepad_input(
+        gamepads: Res<Gamepads>,
+        buttons: Res<Input<GamepadButton>>,
+        axes: Res<Axis<GamepadAxis>>,
+        mut sticks: Local<HashMap<Gamepad, Option<MoveRequest>>>,
+        mut controls: TurnControls,
+    ) {
+        let mut actions = TurnActions::default();
+        for gamepad in gamepads.iter() {
+            let just_pressed =
+                |button_type| buttons.just_pressed(GamepadButton::new(gamepad, button_type));
+            actions.roll |= just_pressed(GamepadButtonType::South);
+            actions.confirm |= just_pressed(GamepadButtonType::South);
+            actions.cancel |= just_pressed(GamepadButtonType::East);
+            let step = gamepad_step(gamepad, &buttons, &axes, &mut sticks);
+            actions.step = actions.step.or(step);
+        }
+        controls.apply(actions);
+    }
+
+    /// Lets a controller work the settings menu, the lobby, the game over screen and the
+    /// disconnect message, by pressing the keys they listen for. Start always stands in for Esc,
+    /// so it opens and closes the settings menu during the game.
+    pub(crate) fn client_map_gamepad_to_keys(
+        mut keys: ResMut<Input<KeyCode>>,
+        gamepads: Res<Gamepads>,
+        buttons: Res<Input<GamepadButton>>,
+        axes: Res<Axis<GamepadAxis>>,
+        mut sticks: Local<HashMap<Gamepad, Option<MoveRequest>>>,
+        settings_menu: Option<Res<SettingsMenu>>,
+        game_state: Res<State<GameState>>,
+        disconnect_message: Query<(), With<DisconnectMessage>>,
+    ) {
+        let mut tap = |key| {
+            keys.press(key);
+            keys.release(key);
+        };
+        // during the game, the other buttons are handled by client_handle_gamepad_input
+        let in_menu = settings_menu.is_some()
+            || *game_state.get() != GameState::InGame
+            || !disconnect_message.is_empty();
+        for gamepad in gamepads.iter() {
+            let just_pressed =
+                |button_type| buttons.just_pressed(GamepadButton::new(gamepad, button_type));
+            let step = gamepad_step(gamepad, &buttons, &axes, &mut sticks);
+            if just_pressed(GamepadButtonType::Start) {
+                tap(KeyCode::Escape);
+            }
+            if !in_menu {
+                continue;
+            }
+            if let Some(step) = step {
+                tap(match step {
+                    MoveRequest::Up => KeyCode::Up,
+                    MoveRequest::Down => KeyCode::Down,
+                    MoveRequest::Left => KeyCode::Left,
+                    MoveRequest::Right => KeyCode::Right,
+                });
+            }
+            if just_pressed(GamepadButtonType::South) {
+                tap(KeyCode::Return);
+            }
+            if just_pressed(GamepadButtonType::East) && settings_menu.is_some() {
+                tap(KeyCode::Escape);
+            }
+            if just_pressed(GamepadButtonType::West) && !disconnect_message.is_empty() {
+                tap(KeyCode::R);
+            }
+        }
+    }
+
+    /// Says when a controller is plugged in or unplugged, since they can come and go at any time
+    pub(crate) fn client_on_gamepad_connection(
+        mut commands: Commands,
+        mut connection_events: EventReader<GamepadConnectionEvent>,
+        time: Res<Time>,
+        mut notices: Query<(Entity, &mut GamepadNotice)>,
+    ) {
+        for (entity_id, mut notice) in notices.iter_mut() {
+            if notice.0.tick(time.delta()).finished() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+        }
+
+        for event in connection_events.read() {
+            let message = match &event.connection {
+                GamepadConnection::Connected(info) => {
+                    info!("Controller {} connected: {}", event.gamepad.id, info.name);
+                    format!("{} connected", info.name)
+                }
+                GamepadConnection::Disconnected => {
+                    info!("Controller {} disconnected", event.gamepad.id);
+                    "Controller disconnected".to_string()
+                }
+            };
+            for (entity_id, _) in notices.iter() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+            Self::spawn_overlay(
+                &mut commands,
+                message,
+                AlignItems::FlexStart,
+                GamepadNotice(Timer::new(GAMEPAD_NOTICE_DURATION, TimerMode::Once)),
+            );
+        }
+    }
+}
+
+/// The step a controller asks for this frame, from a d-pad press or from pushing the left stick
+/// out of the middle. `sticks` remembers which way each stick is already pushed.
+fn gamepad_step(
+    gamepad: Gamepad,
+    buttons: &Input<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+    sticks: &mut HashMap<Gamepad, Option<MoveRequest>>,
+) -> Option<MoveRequest> {
+    let d_pad = [
+        (GamepadButtonType::DPadUp, MoveRequest::Up),
+        (GamepadButtonType::DPadDown, MoveRequest::Down),
+        (GamepadButtonType::DPadLeft, MoveRequest::Left),
+        (GamepadButtonType::DPadRight, MoveRequest::Right),
+    ]
+    .into_iter()
+    .find(|&(button_type, _)| buttons.just_pressed(GamepadButton::new(gamepad, button_type)))
+    .map(|(_, request)| request);
+
+    let axis = |axis_type| {
+        axes.get(GamepadAxis::new(gamepad, axis_type))
+            .unwrap_or(0.0)
+    };
+    let stick = Vec2::new(
+        axis(GamepadAxisType::LeftStickX),
+        axis(GamepadAxisType::LeftStickY),
+    );
+    let held = sticks.entry(gamepad).or_default();
+    let mut stick_step = None;
+    if stick.length() < STICK_RELEASE_THRESHOLD {
+        *held = None;
+    } else if held.is_none() && stick.length() >= STICK_PRESS_THRESHOLD {
+        // diagonals go whichever way the stick is pushed furthest
+        let delta = if stick.x.abs() > stick.y.abs() {
+            IVec2::new(stick.x.signum() as i32, 0)
+        } else {
+            IVec2::new(0, stick.y.signum() as i32)
+        };
+        *held = MoveRequest::from_delta(delta);
+        stick_step = *held;
+    }
+
+    d_pad.or(stick_step)
+}
+
+/// Briefly shown when a controller is plugged in or unplugged
+#[derive(Component)]
+pub(crate) struct GamepadNotice(Timer);
@@ -0,0 +1,1161 @@
+//! A networked version of the Labyrinth board game. [`LabyrinthPlugin`] runs it as a server, a
+//! client or both, depending on the [`Cli`] resource it finds in the app.
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy_replicon::client_disconnected;
+use bevy_replicon::prelude::*;
+use clap::{Args, Parser};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use crate::browser::{Announcer, ServerBrowser};
+use crate::client::{
+    AssistHints, AutoWalk, BoardCell, ChatInput, Connecting, CursorHighlight, DebugHud,
+    DisconnectMessage, InputOptions, LoadingAssets, PlannedMoves, SettingsMenu, WindowSize,
+};
+use crate::coach::{CoachView, TargetOverlay};
+use crate::connection::AddressInput;
+use crate::console::{Console, ConsoleCommand};
+use crate::editor::MazeEditor;
+use crate::effects::{Particle, ParticlePool};
+use crate::fog::FogOfWar;
+use crate::game::{
+    is_valid_skin_name, AvailableItems, ChatBroadcast, ChatMessage, CoachViewAllowed, CurrentTurn,
+    Dice, DiceBundle, DiceCommitment, DiceRollRequest, DiceSeedReveal, EmoteBroadcast,
+    EmoteRequest, FirstPlayer, GameLimits, GameMode, GameOver, GamePaused, GameResult, GameState,
+    ItemCollected, ItemDealing, Maze, MazeAlgorithm, MazeShifted, MoveRejected, MoveRequest,
+    Movement, MovementChange, PauseToggle, PawnCollisions, Ping, Player, PlayerBundle, PlayerLeft,
+    PlayerStartMoveAnimation, PlayerTarget, RaceState, ReadyToggle, RematchToggle, ServerShutdown,
+    ShiftRequest, SkinChange, TurnLog, TurnPhase, UndoRequest, DEFAULT_BOARD_SIZE,
+    MATCH_SEED_LENGTH, MAX_BOARD_SIZE, MAX_SKIN_NAME_LENGTH, MAX_TILES,
+};
+use crate::local::{HotSeat, SplitScreen};
+use crate::net::{
+    transport, ConnectTokenRequest, LocalClientId, ReconnectToken, DEFAULT_PORT,
+    MAX_ROOM_CODE_LENGTH,
+};
+use crate::pause::PausedBy;
+use crate::practice::{Practice, PracticeHud};
+use crate::replay::{ReplayPlayback, ReplayRecorder};
+use crate::rewind::TurnHistory;
+use crate::rooms::Room;
+use crate::server::{AwaitingReconnect, LeftGame, MaxPlayers, RequestBudgets};
+use crate::shifting::MazeSlide;
+use crate::shutdown::ShuttingDown;
+use crate::skins::Skins;
+use crate::targets::KnownTargets;
+use crate::testing::InMemoryServer;
+use crate::tutorial::Tutorial;
+use crate::video::VideoSettings;
+
+mod browser;
+mod camera;
+mod client;
+mod coach;
+mod connection;
+mod console;
+mod editor;
+mod effects;
+mod emotes;
+mod fog;
+pub mod game;
+mod gamepad;
+mod local;
+#[cfg(feature = "metrics")]
+mod metrics;
+pub mod net;
+mod palette;
+mod pause;
+mod practice;
+#[cfg(feature = "presence")]
+mod presence;
+mod replay;
+mod rewind;
+mod rooms;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod server;
+mod shifting;
+mod shutdown;
+mod skins;
+mod targets;
+pub mod testing;
+#[cfg(feature = "touch")]
+mod touch;
+mod turn_log;
+mod tutorial;
+mod undo;
+mod video;
+
+pub use client::{Ease, MirrorControls, MoveHints};
+pub use palette::{Palette, PawnMarks};
+pub use rooms::run_rooms;
+pub use shutdown::server_runner;
+
+pub const DEFAULT_WINDOW_WIDTH: f32 = 1280.0;
+pub const DEFAULT_WINDOW_HEIGHT: f32 = 720.0;
+
+/// Lists everything that's sent over the network, in the order it's registered in. Replicon
+/// numbers the channels in that order, so the list also goes into [`net::PROTOCOL_ID`], and
+/// builds that disagree about it can't connect to each other.
+macro_rules! protocol {
+    (
+        components: [$($component:ty),* $(,)?],
+        server_events: [$($server_event:ty),* $(,)?],
+        client_events: [$($client_event:ty),* $(,)?] $(,)?
+    ) => {
+        pub(crate) const PROTOCOL_TYPES: &[&str] = &[
+            $(concat!("component ", stringify!($component)),)*
+            $(concat!("server event ", stringify!($server_event)),)*
+            $(concat!("client event ", stringify!($client_event)),)*
+        ];
+
+        impl LabyrinthPlugin {
+            fn register_protocol(app: &mut App) {
+                $(app.replicate::<$component>();)*
+                $(app.add_server_event::<$server_event>(EventType::Ordered);)*
+                $(app.add_client_event::<$client_event>(EventType::Ordered);)*
+            }
+
+            #[cfg(feature = "metrics")]
+            fn register_metrics(app: &mut App) {
+                $(app.add_systems(
+                    PreUpdate,
+                    Self::metrics_count_client_events::<$client_event>
+                        .run_if(resource_exists::<metrics::Metrics>())
+                        .after(ServerSet::Receive),
+                );)*
+            }
+        }
+    };
+}
+
+protocol! {
+    components: [Player, Dice, RaceState, TurnLog, Ping],
+    server_events: [
+        GameState,
+        TurnPhase,
+        CurrentTurn,
+        PlayerStartMoveAnimation,
+        Maze,
+        GameOver,
+        MoveRejected,
+        ItemCollected,
+        DiceCommitment,
+        DiceSeedReveal,
+        ChatBroadcast,
+        ReconnectToken,
+        PlayerLeft,
+        MazeShifted,
+        GamePaused,
+        CoachViewAllowed,
+        PlayerTarget,
+        Movement,
+        EmoteBroadcast,
+        ServerShutdown,
+    ],
+    client_events: [
+        DiceRollRequest,
+        MoveRequest,
+        ChatMessage,
+        ReadyToggle,
+        RematchToggle,
+        ShiftRequest,
+        PauseToggle,
+        MovementChange,
+        UndoRequest,
+        EmoteRequest,
+        SkinChange,
+    ],
+}
+
+/// Adds the whole game to an app, which needs a [`Cli`] resource saying how to run it
+pub struct LabyrinthPlugin;
+
+impl Plugin for LabyrinthPlugin {
+    fn build(&self, app: &mut App) {
+        Self::register_protocol(app);
+        #[cfg(feature = "metrics")]
+        Self::register_metrics(app);
+        app.add_event::<ConsoleCommand>();
+        app.add_state::<GameState>();
+        app.add_state::<TurnPhase>();
+        app.init_resource::<CurrentTurn>();
+        app.init_resource::<TurnHistory>();
+        app.init_resource::<KnownTargets>();
+        app.init_resource::<Movement>();
+        app.init_resource::<RequestBudgets>();
+        app.init_resource::<Skins>();
+        app.init_resource::<ParticlePool>();
+        app.add_systems(OnExit(GameState::Ended), Self::client_reset_turn_history);
+        app.add_systems(
+            Startup,
+            (
+                Self::init.map(Result::unwrap),
+                apply_deferred,
+                Self::server_spawn_host.run_if(
+                    resource_exists::<RenetServer>().and_then(resource_exists::<LocalClientId>()),
+                ),
+                Self::local_start.run_if(resource_exists::<HotSeat>()),
+                Self::local_split_screen.run_if(resource_exists::<SplitScreen>()),
+            )
+                .chain(),
+        );
+        app.add_systems(
+            OnExit(GameState::WaitingPlayers),
+            Self::client_despawn_lobby,
+        );
+        app.add_systems(OnExit(GameState::Ended), Self::client_despawn_game_over);
+        app.add_systems(
+            OnEnter(GameState::InGame),
+            (
+                // resuming a paused game isn't the start of a new one
+                Self::server_commit_dice_seed
+                    .run_if(has_authority())
+                    .run_if(not(resource_exists::<ReplayPlayback>()))
+                    .run_if(not(resource_exists::<PausedBy>())),
+                Self::server_clear_pause.run_if(resource_exists::<PausedBy>()),
+            )
+                .chain(),
+        );
+        app.add_systems(
+            OnExit(GameState::Paused),
+            Self::client_despawn_pause_overlay,
+        );
+        app.add_systems(
+            OnEnter(GameState::Ended),
+            (
+                Self::server_reveal_dice_seed
+                    .run_if(has_authority())
+                    .run_if(not(resource_exists::<ReplayPlayback>())),
+                Self::practice_record_best.run_if(resource_exists::<Practice>()),
+                Self::server_clear_pause.run_if(resource_exists::<PausedBy>()),
+            ),
+        );
+        app.add_systems(
+            Update,
+            (
+                // client systems
+                (
+                    (
+                        Self::client_handle_keyboard_input
+                            .run_if(in_state(GameState::InGame))
+                            .run_if(not(resource_exists::<SplitScreen>()))
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_handle_gamepad_input
+                            .run_if(in_state(GameState::InGame))
+                            .run_if(not(resource_exists::<SplitScreen>()))
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_handle_mouse_input
+                            .run_if(in_state(GameState::InGame))
+                            .run_if(not(resource_exists::<SplitScreen>()))
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_auto_walk
+                            .run_if(resource_exists::<AutoWalk>())
+                            .run_if(resource_exists::<Maze>())
+                            .run_if(in_state(GameState::InGame))
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_send_planned_moves
+                            .run_if(resource_exists::<PlannedMoves>())
+                            .run_if(in_state(GameState::InGame)),
+                        Self::client_update_planned_path.run_if(resource_exists::<PlannedMoves>()),
+                        Self::client_update_assist_hints
+                            .run_if(resource_exists::<AssistHints>())
+                            .run_if(resource_exists::<Maze>()),
+                        Self::client_update_move_hints
+                            .run_if(resource_exists::<MoveHints>())
+                            .run_if(resource_exists::<Maze>()),
+                        // Esc clears planned moves before it opens the menu
+                        Self::client_toggle_settings_menu
+                            .run_if(not(any_with_component::<DisconnectMessage>()))
+                            .run_if(not(resource_exists::<ChatInput>()))
+                            .before(Self::client_handle_keyboard_input),
+                        Self::client_update_settings_menu.run_if(resource_exists::<SettingsMenu>()),
+                        Self::client_handle_camera_input
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_update_camera.after(Self::client_handle_camera_input),
+                        Self::client_on_window_close_requested,
+                        Self::client_on_window_focus,
+                        Self::client_update_cursor_highlight
+                            .run_if(any_with_component::<CursorHighlight>())
+                            .run_if(not(resource_exists::<SplitScreen>())),
+                        Self::client_update_rematch
+                            .run_if(in_state(GameState::Ended))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_handle_shift_input
+                            .run_if(in_state(GameState::InGame))
+                            .run_if(not(resource_exists::<SplitScreen>()))
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_update_dice_totals,
+                        Self::client_handle_undo_input
+                            .run_if(in_state(GameState::InGame))
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_toggle_pause
+                            .run_if(
+                                in_state(GameState::InGame).or_else(in_state(GameState::Paused)),
+                            )
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                    ),
+                    (
+                        Self::client_update_your_turn_flash,
+                        Self::client_check_assets_loaded.run_if(resource_exists::<LoadingAssets>()),
+                        Self::client_update_player_anim,
+                        Self::client_update_player_shake,
+                        Self::client_update_explosion_anim,
+                        Self::client_update_collect_effects,
+                        Self::client_update_step_pips,
+                        Self::client_update_name_labels,
+                        Self::client_update_debug_hud.run_if(any_with_component::<DebugHud>()),
+                        Self::client_update_turn_order,
+                        Self::client_update_board_cells.run_if(any_with_component::<BoardCell>()),
+                        Self::client_handle_chat_input
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .before(Self::client_toggle_settings_menu),
+                        Self::client_update_lobby
+                            .run_if(in_state(GameState::WaitingPlayers))
+                            .run_if(not(resource_exists::<Connecting>())),
+                        (
+                            Self::client_handle_lobby_input,
+                            Self::client_handle_skin_input,
+                        )
+                            .run_if(in_state(GameState::WaitingPlayers))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_on_gamepad_connection,
+                        Self::client_toggle_turn_log
+                            .run_if(not(resource_exists::<SettingsMenu>()))
+                            .run_if(not(resource_exists::<ChatInput>())),
+                        Self::client_update_turn_log.after(Self::client_toggle_turn_log),
+                        Self::client_update_maze_slide.run_if(resource_exists::<MazeSlide>()),
+                        (Self::client_apply_palette, Self::client_update_player_marks),
+                        (
+                            Self::client_sync_local_targets
+                                .run_if(has_authority())
+                                .run_if(not(resource_exists::<ReplayPlayback>())),
+                            Self::client_update_target_displays,
+                            Self::client_toggle_target_overlay
+                                .run_if(resource_exists::<TargetOverlay>())
+                                .run_if(not(resource_exists::<ChatInput>())),
+                            Self::client_update_target_markers
+                                .run_if(resource_exists::<TargetOverlay>()),
+                        )
+                            .chain(),
+                    ),
+                )
+                    .run_if(resource_exists::<LocalClientId>()),
+                // client connection systems
+                (
+                    Self::client_on_disconnected.run_if(client_disconnected()),
+                    // the connection attempt hasn't started until there's a connect token
+                    Self::client_update_connecting
+                        .run_if(resource_exists::<Connecting>())
+                        .run_if(not(resource_exists::<ConnectTokenRequest>())),
+                    Self::client_receive_connect_token
+                        .run_if(resource_exists::<ConnectTokenRequest>()),
+                    Self::client_on_server_shutdown.before(Self::client_on_disconnected),
+                )
+                    .run_if(resource_exists::<RenetClient>()),
+                // the client is removed while waiting to reconnect, so this can't depend on it
+                (
+                    Self::client_handle_retry.run_if(not(resource_exists::<AddressInput>())),
+                    Self::client_handle_address_input.after(Self::client_handle_retry),
+                )
+                    .run_if(any_with_component::<DisconnectMessage>()),
+                Self::client_update_ping.run_if(resource_exists::<LocalClientId>()),
+                Self::client_apply_video_settings.run_if(resource_exists::<VideoSettings>()),
+                // effects systems
+                (
+                    Self::client_update_particles.run_if(any_with_component::<Particle>()),
+                    Self::client_confetti_on_win
+                        .run_if(resource_exists::<GameResult>())
+                        .run_if(resource_exists::<WindowSize>())
+                        .run_if(resource_exists::<Palette>()),
+                ),
+                // server browser systems
+                (
+                    Self::browser_receive_list,
+                    Self::browser_handle_input,
+                    Self::browser_update_text,
+                )
+                    .chain()
+                    .run_if(resource_exists::<ServerBrowser>()),
+                // practice systems
+                (
+                    Self::practice_count_moves,
+                    Self::practice_update_hud,
+                    Self::practice_reset.run_if(not(resource_exists::<ChatInput>())),
+                )
+                    .run_if(resource_exists::<Practice>()),
+                // tutorial systems
+                (Self::tutorial_check_progress, Self::tutorial_update_prompt)
+                    .chain()
+                    .run_if(resource_exists::<Tutorial>()),
+                // emote systems
+                (
+                    Self::client_handle_emote_input
+                        .run_if(not(resource_exists::<SettingsMenu>()))
+                        .run_if(not(resource_exists::<ChatInput>())),
+                    Self::client_on_emote,
+                    Self::client_update_emote_bubbles,
+                )
+                    .chain()
+                    .run_if(resource_exists::<LocalClientId>()),
+                // maze editor systems
+                (
+                    Self::editor_handle_input.run_if(not(resource_exists::<LoadingAssets>())),
+                    Self::editor_save,
+                    Self::editor_update_hud,
+                    Self::client_check_assets_loaded.run_if(resource_exists::<LoadingAssets>()),
+                    Self::client_handle_camera_input,
+                    Self::client_update_camera.after(Self::client_handle_camera_input),
+                )
+                    .run_if(resource_exists::<MazeEditor>()),
+                Self::local_pass_turn
+                    .run_if(resource_exists::<HotSeat>())
+                    .run_if(in_state(GameState::InGame)),
+                // split-screen systems
+                (
+                    Self::local_handle_split_screen_input
+                        .run_if(in_state(GameState::InGame))
+                        .run_if(not(resource_exists::<SettingsMenu>()))
+                        .run_if(not(resource_exists::<ChatInput>()))
+                        .after(Self::local_pass_turn),
+                    Self::local_update_split_cameras
+                        .after(Self::client_update_player_anim)
+                        .after(Self::client_update_player_shake)
+                        .after(Self::client_show_rewound_pawns),
+                )
+                    .run_if(resource_exists::<SplitScreen>()),
+                // rewind systems
+                (
+                    Self::client_record_turn_history
+                        .run_if(in_state(GameState::InGame))
+                        .run_if(resource_changed::<CurrentTurn>()),
+                    Self::client_handle_rewind_input
+                        .run_if(not(resource_exists::<SettingsMenu>()))
+                        .run_if(not(resource_exists::<ChatInput>())),
+                    (
+                        Self::client_show_rewound_pawns,
+                        Self::client_show_live_pawns,
+                    )
+                        .after(Self::client_update_player_anim)
+                        .after(Self::client_update_player_shake),
+                    Self::client_update_rewind_label
+                        .run_if(not(in_state(GameState::WaitingPlayers))),
+                )
+                    .chain()
+                    .run_if(resource_exists::<LocalClientId>())
+                    .run_if(resource_exists::<WindowSize>()),
+                // server systems
+                (
+                    (
+                        Self::server_on_events,
+                        Self::server_catch_up_clients.after(Self::server_on_events),
+                        Self::server_on_player_disconnected,
+                        Self::server_update_reconnect_grace
+                            .run_if(any_with_component::<AwaitingReconnect>()),
+                        Self::server_receive_rematch.run_if(in_state(GameState::Ended)),
+                        Self::server_catch_up_pause.run_if(resource_exists::<PausedBy>()),
+                        Self::server_allow_coach_view
+                            .run_if(resource_exists::<CoachView>())
+                            .after(Self::server_on_events),
+                        Self::server_send_targets.after(Self::server_on_events),
+                        Self::server_update_pings,
+                    )
+                        .run_if(resource_exists::<RenetServer>()),
+                    Self::server_remove_left_players.run_if(any_with_component::<LeftGame>()),
+                    Self::server_update_time_limit.run_if(in_state(GameState::InGame)),
+                    Self::server_receive_chat,
+                    Self::server_receive_emotes,
+                    Self::server_receive_movement_changes
+                        .run_if(in_state(GameState::WaitingPlayers))
+                        .before(Self::server_receive_ready),
+                    Self::server_receive_ready.run_if(in_state(GameState::WaitingPlayers)),
+                    Self::server_receive_skin_changes.run_if(in_state(GameState::WaitingPlayers)),
+                    Self::server_announce.run_if(resource_exists::<Announcer>()),
+                    Self::server_check_interrupted,
+                    Self::server_shut_down.run_if(resource_exists::<ShuttingDown>()),
+                    (
+                        Self::server_read_console,
+                        (
+                            Self::server_console_list,
+                            Self::server_console_kick.before(Self::server_remove_left_players),
+                            Self::server_console_turn,
+                            Self::server_console_regen_maze,
+                            Self::server_console_shutdown,
+                        ),
+                    )
+                        .chain()
+                        .run_if(resource_exists::<Console>()),
+                )
+                    .run_if(has_authority())
+                    // a replay has no server behind it, only recorded events
+                    .run_if(not(resource_exists::<ReplayPlayback>())),
+            ),
+        );
+        app.add_systems(
+            PreUpdate,
+            (
+                Self::replay_play
+                    .run_if(resource_exists::<ReplayPlayback>())
+                    .before(ClientSet::Receive),
+                Self::client_map_gamepad_to_keys
+                    .run_if(resource_exists::<LocalClientId>())
+                    .run_if(not(resource_exists::<ChatInput>()))
+                    .after(InputSystem),
+                // client on-rep systems
+                (
+                    Self::client_on_rep_game_state,
+                    Self::client_on_move_rejected,
+                    Self::client_on_item_collected,
+                    Self::client_on_rep_maze,
+                    Self::client_on_maze_shifted.after(Self::client_on_rep_maze),
+                    Self::client_on_game_paused,
+                    Self::client_on_coach_view_allowed,
+                    Self::client_on_player_target,
+                    Self::client_on_movement,
+                    Self::client_on_game_over,
+                    Self::client_on_dice_commitment,
+                    Self::client_on_dice_seed_reveal,
+                    Self::client_on_rep_player,
+                    Self::client_update_player_data,
+                    Self::client_on_player_removed,
+                    Self::client_on_rep_dice,
+                    Self::client_on_dice_value_change,
+                    Self::client_on_chat_broadcast,
+                    Self::client_on_player_left,
+                    Self::client_on_reconnect_token,
+                )
+                    .run_if(resource_exists::<LocalClientId>())
+                    .after(ClientSet::Receive),
+                Self::client_on_rep_maze.run_if(resource_exists::<MazeEditor>()),
+                // server on-rep systems
+                (
+                    Self::server_receive_shifts
+                        .before(Self::server_receive_requests)
+                        .run_if(resource_exists_and_equals(GameMode::Classic)),
+                    Self::server_receive_requests
+                        .run_if(resource_exists_and_equals(GameMode::Classic)),
+                    Self::server_receive_undos
+                        .after(Self::server_receive_requests)
+                        .run_if(resource_exists_and_equals(GameMode::Classic)),
+                    Self::server_receive_race_requests
+                        .run_if(resource_exists_and_equals(GameMode::Race)),
+                )
+                    .run_if(has_authority())
+                    .run_if(not(resource_exists::<ReplayPlayback>()))
+                    .run_if(in_state(GameState::InGame))
+                    .after(ServerSet::Receive),
+                // in PreUpdate like the requests, so that a pause takes effect straight away
+                Self::server_receive_pause_toggles
+                    .run_if(has_authority())
+                    .run_if(not(resource_exists::<ReplayPlayback>()))
+                    .run_if(in_state(GameState::InGame).or_else(in_state(GameState::Paused)))
+                    .after(ServerSet::Receive),
+            ),
+        );
+        app.add_systems(
+            Update,
+            Self::server_close_idle_room
+                .run_if(resource_exists::<Room>())
+                .run_if(resource_exists::<RenetServer>()),
+        );
+        app.add_systems(
+            PostUpdate,
+            (
+                // before fog of war turns the broadcast mazes into ones for each player
+                Self::server_record_replay
+                    .run_if(resource_exists::<ReplayRecorder>())
+                    .before(Self::server_update_fog_of_war)
+                    .before(ServerSet::Send),
+                Self::server_update_fog_of_war
+                    .run_if(resource_exists::<FogOfWar>())
+                    .before(ServerSet::Send),
+            ),
+        );
+        #[cfg(feature = "presence")]
+        app.add_systems(
+            Update,
+            Self::client_update_presence.run_if(resource_exists::<presence::Presence>()),
+        );
+        #[cfg(feature = "metrics")]
+        app.add_systems(
+            First,
+            Self::metrics_start_tick.run_if(resource_exists::<metrics::Metrics>()),
+        )
+        .add_systems(
+            Last,
+            Self::metrics_end_tick.run_if(resource_exists::<metrics::Metrics>()),
+        );
+        #[cfg(feature = "touch")]
+        app.add_systems(
+            Update,
+            (
+                Self::client_handle_touch_input
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(not(resource_exists::<SplitScreen>()))
+                    .run_if(not(resource_exists::<SettingsMenu>()))
+                    .run_if(not(resource_exists::<ChatInput>())),
+                Self::client_handle_pinch_zoom
+                    .run_if(not(resource_exists::<SettingsMenu>()))
+                    .before(Self::client_update_camera),
+            )
+                .run_if(resource_exists::<LocalClientId>()),
+        );
+    }
+}
+
+impl LabyrinthPlugin {
+    fn init(
+        mut commands: Commands,
+        window: Query<&Window>,
+        cli: Res<Cli>,
+        network_channels: Res<NetworkChannels>,
+        texture_atlases: Option<ResMut<Assets<TextureAtlas>>>,
+        assets: Option<Res<AssetServer>>,
+        mut next_game_state: ResMut<NextState<GameState>>,
+        room: Option<Res<Room>>,
+        in_memory: Option<Res<InMemoryServer>>,
+    ) -> Result<(), Box<dyn Error>> {
+        match *cli {
+            Cli::Server(ref server) if in_memory.is_some() => {
+                Self::server_init_in_memory(&mut commands, &network_channels, server)?;
+            }
+            Cli::Server(ref server) => {
+                Self::server_init(&mut commands, &network_channels, server, room.as_deref())?;
+                // rooms share the room server's terminal
+                if room.is_none() {
+                    commands.insert_resource(Console::spawn());
+                }
+            }
+            Cli::Client {
+                ip,
+                port,
+                connect_timeout,
+                observe,
+                ref password,
+                ref room,
+                ref options,
+            } => {
+                Self::client_connect(
+                    &mut commands,
+                    &network_channels,
+                    ip,
+                    port,
+                    connect_timeout,
+                    observe,
+                    options.name.as_deref(),
+                    password.as_deref(),
+                    room.as_deref(),
+                    None,
+                )?;
+                Self::client_init(
+                    &mut commands,
+                    window.single(),
+                    &assets.unwrap(),
+                    &mut texture_atlases.unwrap(),
+                    options,
+                );
+            }
+            Cli::Host {
+                ref server,
+                ref options,
+            } => {
+                if server.rooms.is_some() {
+                    return Err("Rooms are only for dedicated servers".into());
+                }
+                Self::server_init(&mut commands, &network_channels, server, None)?;
+                // the host plays as the server itself, their pawn is added once the maze exists
+                commands.insert_resource(LocalClientId(SERVER_ID.raw()));
+                Self::client_init(
+                    &mut commands,
+                    window.single(),
+                    &assets.unwrap(),
+                    &mut texture_atlases.unwrap(),
+                    options,
+                );
+            }
+            Cli::Local {
+                players,
+                split_screen,
+                ref options,
+            } => {
+                if split_screen && players != 2 {
+                    return Err("Split-screen is for two players".into());
+                }
+                Self::server_init_game(
+                    &mut commands,
+                    &ServerArgs {
+                        max_players: players,
+                        ..default()
+                    },
+                )?;
+                commands.insert_resource(HotSeat);
+                if split_screen {
+                    commands.insert_resource(SplitScreen);
+                }
+                // the first seat's pawn has the server's ID, the others take over when it's
+                // their turn
+                commands.insert_resource(LocalClientId(SERVER_ID.raw()));
+                Self::client_init(
+                    &mut commands,
+                    window.single(),
+                    &assets.unwrap(),
+                    &mut texture_atlases.unwrap(),
+                    options,
+                );
+            }
+            Cli::Browse {
+                ref url,
+                ref options,
+            } => {
+                transport::check_sockets_available()?;
+                Self::browser_init(&mut commands, url.clone(), options.clone());
+            }
+            Cli::Replay {
+                ref file,
+                speed,
+                ref options,
+            } => {
+                commands.insert_resource(ReplayPlayback::load(file, speed)?);
+                // nobody in the replay is us, so it's watched the same way an observer would
+                commands.insert_resource(LocalClientId(u64::MAX));
+                Self::client_init(
+                    &mut commands,
+                    window.single(),
+                    &assets.unwrap(),
+                    &mut texture_atlases.unwrap(),
+                    options,
+                );
+            }
+            Cli::Practice { tiles, ref seed } => {
+                let assets = assets.unwrap();
+                let mut texture_atlases = texture_atlases.unwrap();
+
+                let practice = Practice::new(
+                    seed.clone().unwrap_or_else(|| {
+                        rand::thread_rng()
+                            .sample_iter(&Alphanumeric)
+                            .take(MATCH_SEED_LENGTH)
+                            .map(char::from)
+                            .collect()
+                    }),
+                    tiles,
+                );
+                info!("Practice seed: {}", practice.seed);
+                let (mut rng, maze) = practice.start();
+                let mut available_items = AvailableItems::new(ItemDealing::Random, false);
+                let coords = Self::get_player_start_coords(0, maze.size);
+                commands.spawn(PlayerBundle {
+                    player: Player {
+                        client_id: SERVER_ID.raw(),
+                        coords,
+                        prev_coords: coords,
+                        player_number: 0,
+                        target_item: available_items.take_target(0, coords, &maze, &mut rng.rng),
+                        ..default()
+                    },
+                    ..default()
+                });
+                commands.spawn(DiceBundle::default());
+
+                commands.insert_resource(MaxPlayers(1));
+                commands.insert_resource(GameMode::Classic);
+                commands.insert_resource(PawnCollisions::Share);
+                commands.init_resource::<GameLimits>();
+                commands.insert_resource(maze);
+                commands.insert_resource(rng);
+                commands.insert_resource(available_items);
+                commands.insert_resource(practice);
+                commands.insert_resource(LocalClientId(SERVER_ID.raw()));
+                commands.insert_resource(InputOptions {
+                    numpad: false,
+                    mirror_controls: MirrorControls::Off,
+                });
+                commands.insert_resource(Ease::Sine);
+                commands.insert_resource(Palette::Classic);
+                commands.insert_resource(PawnMarks::Off);
+                next_game_state.set(GameState::InGame);
+
+                Self::spawn_board(
+                    &mut commands,
+                    window.single(),
+                    &assets,
+                    &mut texture_atlases,
+                );
+                commands.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 24.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    )
+                    .with_style(Style {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(8.0),
+                        top: Val::Px(8.0),
+                        ..default()
+                    }),
+                    PracticeHud,
+                ));
+            }
+            Cli::Tutorial => {
+                Self::tutorial_init(
+                    &mut commands,
+                    window.single(),
+                    &assets.unwrap(),
+                    &mut texture_atlases.unwrap(),
+                );
+                next_game_state.set(GameState::InGame);
+            }
+            Cli::Edit { ref file, size } => {
+                Self::editor_init(
+                    &mut commands,
+                    window.single(),
+                    &assets.unwrap(),
+                    &mut texture_atlases.unwrap(),
+                    file,
+                    size as usize,
+                )?;
+            }
+            Cli::Bench { .. } => unreachable!("benchmarks run without starting the app"),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Parser, PartialEq, Resource)]
+pub enum Cli {
+    Server(ServerArgs),
+    Client {
+        #[arg(short, long, default_value_t = Ipv4Addr::LOCALHOST.into())]
+        ip: IpAddr,
+        #[arg(short, long, default_value_t = DEFAULT_PORT)]
+        port: u16,
+        /// How many seconds to wait for the server to respond before giving up
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+        connect_timeout: u64,
+        /// Watches the game without taking part, if the server has room for observers. Clients
+        /// that join a full or started game observe it anyway when there's room
+        #[arg(long, visible_alias = "spectate")]
+        observe: bool,
+        /// The password the server was started with, if it has one
+        #[arg(long)]
+        password: Option<String>,
+        /// The code of the room to play in, for servers started with --rooms. Everyone who
+        /// picks the same code plays together
+        #[arg(long, value_parser = parse_room_code)]
+        room: Option<String>,
+        #[command(flatten)]
+        options: ClientOptions,
+    },
+    /// Runs the server and plays in the same window, so that nobody has to start a separate
+    /// server to play with friends
+    Host {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[command(flatten)]
+        options: ClientOptions,
+    },
+    /// Plays a game with friends in one window without any networking, passing the keyboard
+    /// to whoever's turn it is
+    Local {
+        #[arg(default_value_t = 2, value_parser = clap::value_parser!(u8).range(2..=4))]
+        players: u8,
+        /// Splits the window between two players, each half following its own player's pawn.
+        /// The first player moves with WASD and rolls with Space, the second with the arrow keys
+        /// and Enter. The mouse and controllers aren't used.
+        #[arg(long)]
+        split_screen: bool,
+        #[command(flatten)]
+        options: ClientOptions,
+    },
+    /// Lists the public games that servers started with `--announce` have told the master server
+    /// at this URL about, to pick one to join
+    Browse {
+        url: String,
+        #[command(flatten)]
+        options: ClientOptions,
+    },
+    /// Plays back a match recorded by a server started with `--record`
+    Replay {
+        file: PathBuf,
+        /// How many times faster than real time to play the match
+        #[arg(long, default_value_t = 1.0)]
+        speed: f32,
+        #[command(flatten)]
+        options: ClientOptions,
+    },
+    /// Plays alone on a local board, collecting items in as few moves as possible
+    Practice {
+        #[arg(short, long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(15..=20))]
+        tiles: u8,
+        /// Seeds the maze, dice rolls and items, so that records can be compared between runs.
+        /// A random seed is generated and logged if omitted
+        #[arg(long)]
+        seed: Option<String>,
+    },
+    /// Learns how to play, one step at a time, on a small maze with a single wall
+    Tutorial,
+    /// Draws a maze by hand, to be played on with `labyrinth server --maze-file`. Click near the
+    /// edge of a cell to add or remove a bar there
+    Edit {
+        /// The maze file to edit, which is created when saving if it doesn't exist yet
+        file: PathBuf,
+        /// The number of cells along each side of a new board
+        #[arg(long, default_value_t = DEFAULT_BOARD_SIZE as u8, value_parser = clap::value_parser!(u8).range(DEFAULT_BOARD_SIZE as i64..=MAX_BOARD_SIZE as i64))]
+        size: u8,
+    },
+    /// Generates many mazes without starting the game, and prints how long they took as JSON
+    Bench {
+        #[arg(short, long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(0..=MAX_TILES as i64))]
+        tiles: u8,
+        #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+    },
+}
+
+/// The rules and limits of a game, for a dedicated server or a hosted one
+#[derive(Args, Clone, PartialEq)]
+pub struct ServerArgs {
+    #[arg(short, long, default_value_t = DEFAULT_PORT, value_parser = clap::value_parser!(u16).range(1024..))]
+    pub port: u16,
+    #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(1..=4))]
+    pub max_players: u8,
+    /// How many seconds a disconnected player's pawn waits for them to reconnect during the
+    /// game before they're removed, 0 to remove them straight away
+    #[arg(long, default_value_t = 30)]
+    pub reconnect_grace_secs: u64,
+    /// How many observers may watch the game, on top of the players
+    #[arg(long, default_value_t = 0)]
+    pub max_observers: u8,
+    #[arg(short, long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(15..=20))]
+    pub tiles: u8,
+    /// The number of cells along each side of the board. Items are spread out over bigger
+    /// boards, which are drawn without the usual artwork.
+    #[arg(long, default_value_t = DEFAULT_BOARD_SIZE as u8, value_parser = clap::value_parser!(u8).range(DEFAULT_BOARD_SIZE as i64..=MAX_BOARD_SIZE as i64))]
+    pub board_size: u8,
+    #[arg(long, value_enum, default_value_t = FirstPlayer::Fixed)]
+    pub first_player: FirstPlayer,
+    #[arg(long, value_enum, default_value_t = ItemDealing::Random)]
+    pub item_dealing: ItemDealing,
+    /// How many steps away from each player their targets are dealt with balanced dealing, as
+    /// MIN-MAX or a single number. Defaults to 3-6.
+    #[arg(long, value_parser = parse_target_distance)]
+    pub target_distance: Option<RangeInclusive<usize>>,
+    /// Logs each target item assignment along with its distance from the player
+    #[arg(long)]
+    pub log_targets: bool,
+    /// Seeds the maze, dice rolls and item dealing so that a match can be replayed exactly.
+    /// A random seed is generated and logged if omitted. Players are shown the seed when the
+    /// game ends
+    #[arg(long)]
+    pub match_seed: Option<String>,
+    /// Seeds the match with a number, the same as giving that number as the match seed
+    #[arg(long, conflicts_with = "match_seed")]
+    pub seed: Option<u64>,
+    /// Ends the game after this many turns, and the player with the most items wins
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_turns: Option<u32>,
+    /// Ends the game after this many minutes, and the player with the most items wins
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_minutes: Option<u64>,
+    #[arg(long, value_enum, default_value_t = GameMode::Classic)]
+    pub mode: GameMode,
+    /// What happens when a pawn steps onto a cell another pawn is standing on
+    #[arg(long, value_enum, default_value_t = PawnCollisions::Share)]
+    pub pawn_collisions: PawnCollisions,
+    /// How many steps players get each turn: one-dice, two-dice, or a number of steps for the
+    /// same every turn. Players can change it in the lobby
+    #[arg(long, default_value_t = Movement::OneDice)]
+    pub movement: Movement,
+    /// Loads the maze from a file instead of generating one, in the same format the server
+    /// logs generated mazes in
+    #[arg(long, conflicts_with = "tiles")]
+    pub maze_file: Option<PathBuf>,
+    /// How the walls of generated mazes are laid out
+    #[arg(long, value_enum, default_value_t = MazeAlgorithm::Scattered, conflicts_with = "maze_file")]
+    pub maze_algo: MazeAlgorithm,
+    /// Commits to a hash of the dice seed when the game starts and reveals the seed when it
+    /// ends, so that players can check the rolls weren't tampered with
+    #[arg(long)]
+    pub verifiable_dice: bool,
+    /// Makes players skip their next roll every time they crash into a wall this many times
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub crash_penalty: Option<u32>,
+    /// Gives players a free extra step each time they collect an item, to speed up endgames
+    #[arg(long)]
+    pub momentum: bool,
+    /// Scatters a pair of teleporters, traps that end a move and bonus cells that give an extra
+    /// roll over the maze. Maze files draw their own special cells
+    #[arg(long, conflicts_with = "maze_file")]
+    pub special_cells: bool,
+    /// Lets the player whose turn it is push the walls around a row or column of cells one cell
+    /// along before they roll, once per turn, as long as every cell can still be reached.
+    /// Classic games only
+    #[arg(long)]
+    pub shifting_maze: bool,
+    /// Lets the player whose turn it is take back the steps they've taken since they rolled, as
+    /// long as those steps didn't hit a wall, collect an item, land on a special cell or bump a
+    /// pawn. Classic games only
+    #[arg(long)]
+    pub undo: bool,
+    /// Lets observers mark every player's target item on the board, for streaming and coaching
+    #[arg(long)]
+    pub coach_view: bool,
+    /// Only shows players the walls within this many cells of where their pawn has been
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    pub fog: Option<u8>,
+    /// Records everything that happens in the match to this file, to be watched later with
+    /// `labyrinth replay`
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// The addresses players reach the server on, such as its LAN or internet IP. Clients are
+    /// only given connect tokens for these addresses
+    #[arg(long, default_values_t = [IpAddr::from(Ipv4Addr::LOCALHOST)])]
+    pub public_ip: Vec<IpAddr>,
+    /// Only gives connect tokens to clients that know this password
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Hosts up to this many games at once, each in a room that's opened when a player first
+    /// asks for its code with --room. Room games are played on the ports after --port
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub rooms: Option<u16>,
+    /// Lists the game on the master server at this URL, so that players can find it with
+    /// `labyrinth browse`. The first non-local --public-ip is the address that's listed
+    #[arg(long, conflicts_with = "rooms")]
+    pub announce: Option<String>,
+    /// Serves Prometheus metrics at /metrics on this address, such as 127.0.0.1:9100
+    #[cfg(feature = "metrics")]
+    #[arg(long, conflicts_with = "rooms")]
+    pub metrics: Option<std::net::SocketAddr>,
+    /// Loads house rules from this Rhai script. Without it, rules.rhai is loaded if there is one
+    #[cfg(feature = "scripting")]
+    #[arg(long)]
+    pub rules: Option<PathBuf>,
+}
+
+impl Default for ServerArgs {
+    /// The rules of a server started without any options
+    fn default() -> Self {
+        #[derive(Parser)]
+        struct DefaultServerArgs {
+            #[command(flatten)]
+            server: ServerArgs,
+        }
+        DefaultServerArgs::parse_from(["labyrinth"]).server
+    }
+}
+
+fn parse_room_code(code: &str) -> Result<String, String> {
+    let code = code.trim();
+    if code.is_empty() || code.len() > MAX_ROOM_CODE_LENGTH {
+        return Err(format!(
+            "room codes must be between 1 and {MAX_ROOM_CODE_LENGTH} bytes long"
+        ));
+    }
+    Ok(code.to_owned())
+}
+
+fn parse_skin_name(name: &str) -> Result<String, String> {
+    if !is_valid_skin_name(name) {
+        return Err(format!(
+            "skin names are up to {MAX_SKIN_NAME_LENGTH} letters, digits, dashes and underscores"
+        ));
+    }
+    Ok(name.to_owned())
+}
+
+fn parse_target_distance(distance: &str) -> Result<RangeInclusive<usize>, String> {
+    let parse = |steps: &str| {
+        steps
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("expected a number of steps, not {steps:?}"))
+    };
+    let distance = match distance.split_once('-') {
+        Some((min, max)) => parse(min)?..=parse(max)?,
+        None => parse(distance)?..=parse(distance)?,
+    };
+    if distance.is_empty() {
+        return Err("the minimum distance can't be more than the maximum".to_owned());
+    }
+    Ok(distance)
+}
+
+/// How the window looks and the game is controlled, for a client or the host
+#[derive(Args, Clone, PartialEq)]
+pub struct ClientOptions {
+    #[arg(long, default_value_t = DEFAULT_WINDOW_WIDTH)]
+    pub width: f32,
+    #[arg(long, default_value_t = DEFAULT_WINDOW_HEIGHT)]
+    pub height: f32,
+    #[arg(short, long, requires = "y", allow_negative_numbers = true)]
+    pub x: Option<i32>,
+    #[arg(short, long, requires = "x", allow_negative_numbers = true)]
+    pub y: Option<i32>,
+    /// Allows pressing Enter while moving to automatically walk towards the target item, and
+    /// Escape to stop
+    #[arg(long)]
+    pub assist: bool,
+    /// Enables numpad controls: 8/2/4/6 to move and 5 to roll
+    #[arg(long)]
+    pub numpad: bool,
+    /// The easing curve pawns follow when they move from one cell to the next
+    #[arg(long, value_enum, default_value_t = Ease::Sine)]
+    pub ease: Ease,
+    /// The colours players are shown in
+    #[arg(long, value_enum, default_value_t = Palette::Classic)]
+    pub palette: Palette,
+    /// Draws stripes, dots or numbers on pawns, dice and the turn order, so players can be told
+    /// apart without colour
+    #[arg(long, value_enum, default_value_t = PawnMarks::Off)]
+    pub pawn_marks: PawnMarks,
+    /// Swaps movement directions, e.g. for left-handed layouts or mirrored screens
+    #[arg(long, value_enum, default_value_t = MirrorControls::Off)]
+    pub mirror_controls: MirrorControls,
+    /// Shows the local player's position, target, dice and turn state in the corner
+    #[arg(long)]
+    pub debug_hud: bool,
+    /// Moves pawns instantly instead of animating them
+    #[arg(long)]
+    pub instant_moves: bool,
+    /// The name shown to other players in the lobby, instead of your colour
+    #[arg(long)]
+    pub name: Option<String>,
+    /// The pawn skin to play with, the name of a picture in assets/skins/ without the .png
+    #[arg(long, value_parser = parse_skin_name)]
+    pub skin: Option<String>,
+    /// Queues up a whole move with the movement keys, then Enter sends it or Esc clears it
+    #[arg(long)]
+    pub confirm_moves: bool,
+    /// Briefly shows the way to the target item after crashing this many times in a row
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub assist_hints: Option<u32>,
+    /// Highlights the board cell under the mouse cursor
+    #[arg(long)]
+    pub cursor_highlight: bool,
+    /// Highlights the cells within reach while moving, and can also draw the shortest way to the
+    /// target item
+    #[arg(long, value_enum, default_value_t = MoveHints::Off)]
+    pub move_hints: MoveHints,
+    /// The ID of the Discord application to show the game on your Discord profile as. Discord
+    /// has to be running
+    #[cfg(feature = "presence")]
+    #[arg(long)]
+    pub discord_app_id: Option<String>,
+}
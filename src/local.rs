@@ -0,0 +1,264 @@
+//! Hot-seat games, where everyone plays in the same window and takes turns at the keyboard. Two
+//! players can also split the window with `--split-screen`, each with their own half following
+//! their own pawn and their own keys.
+
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::*;
+use bevy::render::camera::Viewport;
+use bevy::render::view::RenderLayers;
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::ClientId;
+
+use crate::client::{
+    AutoWalk, Me, PlannedMoves, TurnActions, TurnControls, WindowSize, YourTurnFlash,
+    YOUR_TURN_FLASH_DURATION,
+};
+use crate::game::{
+    AvailableItems, CurrentTurn, FirstPlayer, GameMode, GameRng, GameState, Maze, MoveRequest,
+    Player,
+};
+use crate::net::LocalClientId;
+use crate::server::MaxPlayers;
+use crate::LabyrinthPlugin;
+
+/// Present in a hot-seat game. There's no transport, the window's requests go straight to the
+/// game through local events, and the window plays as whoever's turn it is.
+#[derive(Resource)]
+pub(crate) struct HotSeat;
+
+impl HotSeat {
+    /// Every seat gets its own client ID so that the game can tell their pawns apart. The
+    /// first seat is the server's own, like the host's.
+    pub(crate) fn client_id(player_number: usize) -> ClientId {
+        ClientId::from_raw(SERVER_ID.raw() + player_number as u64)
+    }
+}
+
+/// How much of the board each half of a split window shows, as a fraction of its size
+const SPLIT_VIEW_FRACTION: f32 = 0.6;
+/// The layer nothing on the board is drawn on, so that the window's own camera only draws the UI
+/// over the two halves
+const UI_ONLY_LAYER: u8 = 1;
+
+/// Present in a split-screen hot-seat game, which is always for two players
+#[derive(Resource)]
+pub(crate) struct SplitScreen;
+
+/// The camera for one half of a split window, following the pawn of the player with this number
+#[derive(Component)]
+pub(crate) struct SplitCamera(pub(crate) usize);
+
+/// The keys each half of a split window plays with, as up, down, left, right and roll
+const SPLIT_SCREEN_KEYS: [[KeyCode; 5]; 2] = [
+    [
+        KeyCode::W,
+        KeyCode::S,
+        KeyCode::A,
+        KeyCode::D,
+        KeyCode::Space,
+    ],
+    [
+        KeyCode::Up,
+        KeyCode::Down,
+        KeyCode::Left,
+        KeyCode::Right,
+        KeyCode::Return,
+    ],
+];
+
+impl LabyrinthPlugin {
+    /// Seats everyone and starts the game straight away, there's nobody to wait for
+    pub(crate) fn local_start(
+        mut commands: Commands,
+        max_players: Res<MaxPlayers>,
+        first_player: Res<FirstPlayer>,
+        mut available_items: ResMut<AvailableItems>,
+        maze: Res<Maze>,
+        mut rng: ResMut<GameRng>,
+        mut current_turn: ResMut<CurrentTurn>,
+        mut maze_writer: EventWriter<ToClients<Maze>>,
+        mut current_turn_writer: EventWriter<ToClients<CurrentTurn>>,
+        mut game_state: ResMut<NextState<GameState>>,
+        mut game_state_writer: EventWriter<ToClients<GameState>>,
+    ) {
+        info!("Starting a hot-seat game with {} players", max_players.0);
+        for player_number in 0..max_players.0 {
+            Self::server_spawn_player(
+                &mut commands,
+                HotSeat::client_id(player_number),
+                player_number,
+                None,
+                &mut available_items,
+                &maze,
+                &mut rng,
+                GameMode::Classic,
+            );
+        }
+        maze_writer.send(ToClients {
+            mode: SendMode::Direct(SERVER_ID),
+            event: maze.clone(),
+        });
+
+        current_turn.0 = first_player.pick(max_players.0, &mut rng.rng);
+        current_turn_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: *current_turn,
+        });
+        game_state.set(GameState::InGame);
+        game_state_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: GameState::InGame,
+        });
+    }
+
+    /// Hands the window over to the player whose turn it is, and tells everyone who that is
+    pub(crate) fn local_pass_turn(
+        mut commands: Commands,
+        current_turn: Res<CurrentTurn>,
+        mut local_client_id: ResMut<LocalClientId>,
+        players: Query<(Entity, &Player)>,
+        me: Query<Entity, With<Me>>,
+        auto_walk: Option<ResMut<AutoWalk>>,
+        planned_moves: Option<ResMut<PlannedMoves>>,
+    ) {
+        let Some((entity_id, player)) = players
+            .iter()
+            .find(|(_, player)| player.player_number == current_turn.0)
+        else {
+            return;
+        };
+        if local_client_id.0 == player.client_id && me.contains(entity_id) {
+            return;
+        }
+
+        info!("Passing the keyboard to player {}", player.player_number);
+        local_client_id.0 = player.client_id;
+        for me in me.iter() {
+            commands.entity(me).remove::<Me>();
+        }
+        commands.entity(entity_id).insert(Me);
+        // the last player's walk and plan don't carry over to the next
+        if let Some(mut auto_walk) = auto_walk {
+            *auto_walk = AutoWalk::default();
+        }
+        if let Some(mut planned_moves) = planned_moves {
+            *planned_moves = PlannedMoves::default();
+        }
+        Self::spawn_overlay(
+            &mut commands,
+            format!("{}'s turn!", player.display_name()),
+            AlignItems::Center,
+            YourTurnFlash(Timer::new(YOUR_TURN_FLASH_DURATION, TimerMode::Once)),
+        );
+    }
+}
+
+impl LabyrinthPlugin {
+    /// Gives each player a camera for their half of the window. The window's own camera stays to
+    /// draw the UI across the whole window, on top of both halves.
+    pub(crate) fn local_split_screen(
+        mut commands: Commands,
+        mut camera: Query<(Entity, &mut Camera, &mut Camera2d), Without<SplitCamera>>,
+    ) {
+        let (entity_id, mut camera, mut camera_2d) = camera.single_mut();
+        camera.order = SPLIT_SCREEN_KEYS.len() as isize;
+        camera_2d.clear_color = ClearColorConfig::None;
+        commands
+            .entity(entity_id)
+            .insert(RenderLayers::layer(UI_ONLY_LAYER));
+        for player_number in 0..SPLIT_SCREEN_KEYS.len() {
+            commands.spawn((
+                Camera2dBundle {
+                    camera: Camera {
+                        order: player_number as isize,
+                        ..default()
+                    },
+                    ..default()
+                },
+                UiCameraConfig { show_ui: false },
+                SplitCamera(player_number),
+            ));
+        }
+    }
+
+    /// Fits each camera to its half of the window, which changes whenever the window is resized,
+    /// and centers it on its player's pawn
+    pub(crate) fn local_update_split_cameras(
+        window: Query<&Window>,
+        window_size: Res<WindowSize>,
+        players: Query<(&Player, &Transform), Without<SplitCamera>>,
+        mut cameras: Query<(
+            &SplitCamera,
+            &mut Camera,
+            &mut OrthographicProjection,
+            &mut Transform,
+        )>,
+    ) {
+        let window = window.single();
+        let half_width = window.physical_width() / SPLIT_SCREEN_KEYS.len() as u32;
+        if half_width == 0 || window.physical_height() == 0 {
+            // minimized
+            return;
+        }
+        let half_size = UVec2::new(half_width, window.physical_height());
+        let view_size = Self::calc_board_size(window_size.0) * SPLIT_VIEW_FRACTION;
+        // the projection works in logical pixels
+        let logical_half_size = half_size.as_vec2() / window.scale_factor() as f32;
+        let scale = (view_size / logical_half_size).max_element();
+
+        for (split_camera, mut camera, mut projection, mut transform) in cameras.iter_mut() {
+            let viewport = Viewport {
+                physical_position: UVec2::new(half_width * split_camera.0 as u32, 0),
+                physical_size: half_size,
+                ..default()
+            };
+            if camera
+                .viewport
+                .as_ref()
+                .map(|viewport| viewport.physical_size)
+                != Some(viewport.physical_size)
+            {
+                camera.viewport = Some(viewport);
+            }
+            if projection.scale != scale {
+                projection.scale = scale;
+            }
+            if let Some((_, pawn)) = players
+                .iter()
+                .find(|(player, _)| player.player_number == split_camera.0)
+            {
+                transform.translation = pawn.translation.xy().extend(transform.translation.z);
+            }
+        }
+    }
+
+    /// Each half of the window has its own keys, which always play for that half's player, even
+    /// when the window is playing as the other one. Keys pressed out of turn are ignored like any
+    /// other request made out of turn.
+    pub(crate) fn local_handle_split_screen_input(
+        keys: Res<Input<KeyCode>>,
+        mut controls: TurnControls,
+    ) {
+        for (player_number, &[up, down, left, right, roll]) in SPLIT_SCREEN_KEYS.iter().enumerate()
+        {
+            let step = [
+                (up, MoveRequest::Up),
+                (down, MoveRequest::Down),
+                (left, MoveRequest::Left),
+                (right, MoveRequest::Right),
+            ]
+            .into_iter()
+            .find(|&(key, _)| keys.just_pressed(key))
+            .map(|(_, request)| request);
+
+            controls.apply_for(
+                player_number,
+                TurnActions {
+                    roll: keys.just_pressed(roll),
+                    step,
+                    ..default()
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! A Prometheus endpoint for people running dedicated servers, for builds with the `metrics`
+//! feature. A server started with `--metrics` answers `GET /metrics` on that address with the
+//! numbers in Prometheus' text format, worked out again once a second.
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::game::GameState;
+use crate::LabyrinthPlugin;
+
+/// How often the page is redrawn, and the window the per-second numbers are taken over
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a scrape has to send its request line before it's dropped
+const METRICS_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const GAME_STATES: [GameState; 4] = [
+    GameState::WaitingPlayers,
+    GameState::InGame,
+    GameState::Paused,
+    GameState::Ended,
+];
+
+/// What's been counted since the page was last drawn, and the page the HTTP thread serves
+#[derive(Resource)]
+pub(crate) struct Metrics {
+    page: Arc<Mutex<String>>,
+    window_start: Instant,
+    events_total: u64,
+    events_in_window: u64,
+    ticks_in_window: u32,
+    tick_start: Option<Instant>,
+    tick_time_in_window: Duration,
+    longest_tick_in_window: Duration,
+}
+
+impl LabyrinthPlugin {
+    pub(crate) fn metrics_init(
+        commands: &mut Commands,
+        addr: SocketAddr,
+    ) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Serving metrics on http://{addr}/metrics");
+        let page = Arc::new(Mutex::new(String::new()));
+        let served_page = page.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept a metrics request: {err}");
+                        continue;
+                    }
+                };
+                let page = served_page.clone();
+                thread::spawn(move || {
+                    if let Err(err) = serve_metrics(stream, &page) {
+                        warn!("Failed to serve metrics: {err}");
+                    }
+                });
+            }
+        });
+        commands.insert_resource(Metrics {
+            page,
+            window_start: Instant::now(),
+            events_total: 0,
+            events_in_window: 0,
+            ticks_in_window: 0,
+            tick_start: None,
+            tick_time_in_window: Duration::ZERO,
+            longest_tick_in_window: Duration::ZERO,
+        });
+        Ok(())
+    }
+
+    /// Added for every client event in the protocol, so that they're all counted
+    pub(crate) fn metrics_count_client_events<T: Event>(
+        mut events: EventReader<FromClient<T>>,
+        mut metrics: ResMut<Metrics>,
+    ) {
+        let count = events.read().count() as u64;
+        metrics.events_total += count;
+        metrics.events_in_window += count;
+    }
+
+    pub(crate) fn metrics_start_tick(mut metrics: ResMut<Metrics>) {
+        metrics.tick_start = Some(Instant::now());
+    }
+
+    /// Times the tick and, once a second, redraws the page with everything counted since the
+    /// last time
+    pub(crate) fn metrics_end_tick(
+        mut metrics: ResMut<Metrics>,
+        server: Res<RenetServer>,
+        network_channels: Res<NetworkChannels>,
+        game_state: Res<State<GameState>>,
+    ) {
+        let now = Instant::now();
+        if let Some(tick_start) = metrics.tick_start.take() {
+            let tick_time = now - tick_start;
+            metrics.ticks_in_window += 1;
+            metrics.tick_time_in_window += tick_time;
+            metrics.longest_tick_in_window = metrics.longest_tick_in_window.max(tick_time);
+        }
+        let window = now - metrics.window_start;
+        if window < METRICS_INTERVAL {
+            return;
+        }
+
+        let mut page = String::new();
+        let clients = server.clients_id();
+        metric(
+            &mut page,
+            "labyrinth_connected_clients",
+            "gauge",
+            "Players and observers connected to the server",
+        );
+        let _ = writeln!(page, "labyrinth_connected_clients {}", clients.len());
+
+        metric(
+            &mut page,
+            "labyrinth_game_state",
+            "gauge",
+            "1 for the state the game is in, 0 for the others",
+        );
+        for state in GAME_STATES {
+            let _ = writeln!(
+                page,
+                "labyrinth_game_state{{state=\"{state:?}\"}} {}",
+                u8::from(*game_state.get() == state)
+            );
+        }
+
+        metric(
+            &mut page,
+            "labyrinth_client_events_total",
+            "counter",
+            "Events received from clients since the server started",
+        );
+        let _ = writeln!(
+            page,
+            "labyrinth_client_events_total {}",
+            metrics.events_total
+        );
+        metric(
+            &mut page,
+            "labyrinth_client_events_per_second",
+            "gauge",
+            "Events received from clients over the last second",
+        );
+        let _ = writeln!(
+            page,
+            "labyrinth_client_events_per_second {}",
+            metrics.events_in_window as f64 / window.as_secs_f64()
+        );
+
+        metric(
+            &mut page,
+            "labyrinth_channel_backlog_bytes",
+            "gauge",
+            "Bytes waiting to be sent or acknowledged on each channel to each client",
+        );
+        for client_id in clients {
+            for channel in network_channels.get_server_configs() {
+                let backlog = channel
+                    .max_memory_usage_bytes
+                    .saturating_sub(server.channel_available_memory(client_id, channel.channel_id));
+                let _ = writeln!(
+                    page,
+                    "labyrinth_channel_backlog_bytes{{client=\"{client_id}\",channel=\"{}\"}} \
+                     {backlog}",
+                    channel.channel_id
+                );
+            }
+        }
+
+        metric(
+            &mut page,
+            "labyrinth_tick_seconds_average",
+            "gauge",
+            "How long the server took to run a tick, on average over the last second",
+        );
+        let average_tick = match metrics.ticks_in_window {
+            0 => Duration::ZERO,
+            ticks => metrics.tick_time_in_window / ticks,
+        };
+        let _ = writeln!(
+            page,
+            "labyrinth_tick_seconds_average {}",
+            average_tick.as_secs_f64()
+        );
+        metric(
+            &mut page,
+            "labyrinth_tick_seconds_max",
+            "gauge",
+            "The longest tick over the last second",
+        );
+        let _ = writeln!(
+            page,
+            "labyrinth_tick_seconds_max {}",
+            metrics.longest_tick_in_window.as_secs_f64()
+        );
+
+        *metrics.page.lock().unwrap() = page;
+        metrics.window_start = now;
+        metrics.events_in_window = 0;
+        metrics.ticks_in_window = 0;
+        metrics.tick_time_in_window = Duration::ZERO;
+        metrics.longest_tick_in_window = Duration::ZERO;
+    }
+}
+
+fn metric(page: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(page, "# HELP {name} {help}");
+    let _ = writeln!(page, "# TYPE {name} {kind}");
+}
+
+/// Answers a single request. Only the request line is looked at, the headers are ignored.
+fn serve_metrics(mut stream: TcpStream, page: &Mutex<String>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(METRICS_REQUEST_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let page = page.lock().unwrap().clone();
+            format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{page}",
+                page.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+    };
+    stream.write_all(response.as_bytes())
+}
@@ -0,0 +1,395 @@
+//! Connecting to the server, and what clients tell it about themselves when they do
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::transport::{
+    ConnectToken, NetcodeClientTransport, NETCODE_KEY_BYTES, NETCODE_USER_DATA_BYTES,
+};
+use bevy_replicon::renet::ConnectionConfig;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use crate::client::{Connecting, ConnectingText, DisconnectMessage};
+use crate::game::{GameMode, GameState, MAX_PLAYER_NAME_LENGTH};
+use crate::LabyrinthPlugin;
+
+pub(crate) mod transport;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+// clients and servers from different versions, or that send different things over the network,
+// won't be able to connect to each other
+pub const PROTOCOL_ID: u64 = protocol_id(VERSION, crate::PROTOCOL_TYPES);
+pub const DEFAULT_PORT: u16 = 5000;
+/// How long a client has to start connecting with a connect token once it's been issued
+const CONNECT_TOKEN_EXPIRE_SECS: u64 = 30;
+/// How long either side waits without hearing from the other before dropping the connection
+const CONNECTION_TIMEOUT_SECS: i32 = 15;
+/// How long the server waits for a client to send its connect token request
+const TOKEN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PASSWORD_LENGTH: usize = u8::MAX as usize;
+pub const MAX_ROOM_CODE_LENGTH: usize = 32;
+
+impl LabyrinthPlugin {
+    pub(crate) fn client_connect(
+        commands: &mut Commands,
+        network_channels: &NetworkChannels,
+        ip: IpAddr,
+        port: u16,
+        connect_timeout: u64,
+        observe: bool,
+        name: Option<&str>,
+        password: Option<&str>,
+        room: Option<&str>,
+        reconnect_token: Option<ReconnectToken>,
+    ) -> Result<(), Box<dyn Error>> {
+        transport::check_sockets_available()?;
+        info!("Connecting to {ip}:{port} with client version {VERSION}");
+        if let Some(room) = room {
+            info!("Joining room {room}");
+        }
+        if observe {
+            info!("Joining as an observer");
+        }
+
+        let server_channels_config = network_channels.get_server_configs();
+        let client_channels_config = network_channels.get_client_configs();
+
+        let client = RenetClient::new(ConnectionConfig {
+            server_channels_config,
+            client_channels_config,
+            ..default()
+        });
+
+        let server_addr = SocketAddr::new(ip, port);
+        let password = password.unwrap_or_default();
+        if password.len() > MAX_PASSWORD_LENGTH {
+            return Err(
+                format!("Passwords can be at most {MAX_PASSWORD_LENGTH} bytes long").into(),
+            );
+        }
+        let room = room.unwrap_or_default();
+        if room.len() > MAX_ROOM_CODE_LENGTH {
+            return Err(
+                format!("Room codes can be at most {MAX_ROOM_CODE_LENGTH} bytes long").into(),
+            );
+        }
+        let mut request =
+            Vec::with_capacity(10 + password.len() + room.len() + NETCODE_USER_DATA_BYTES);
+        request.extend_from_slice(&PROTOCOL_ID.to_le_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        request.push(room.len() as u8);
+        request.extend_from_slice(room.as_bytes());
+        request.extend_from_slice(&ConnectionKind::user_data(observe, name, reconnect_token));
+        let timeout = Duration::from_secs(connect_timeout);
+        // the transport is added once the server has handed out a connect token, which is done
+        // on another thread so that the window keeps responding
+        let token_request = thread::spawn(move || {
+            transport::request_connect_token(server_addr, timeout, &request)
+                .map_err(|err| err.to_string())
+        });
+
+        commands.insert_resource(client);
+        commands.insert_resource(ConnectTokenRequest(Some(token_request)));
+        commands.insert_resource(Connecting(Timer::new(
+            Duration::from_secs(connect_timeout),
+            TimerMode::Once,
+        )));
+        Self::spawn_overlay(
+            commands,
+            format!("Connecting to {server_addr}…"),
+            AlignItems::FlexEnd,
+            ConnectingText,
+        );
+        Ok(())
+    }
+
+    /// Starts connecting with the token the server gave us, or explains why it didn't
+    pub(crate) fn client_receive_connect_token(
+        mut commands: Commands,
+        mut token_request: ResMut<ConnectTokenRequest>,
+        connecting_text: Query<Entity, With<ConnectingText>>,
+    ) {
+        if !token_request
+            .0
+            .as_ref()
+            .is_some_and(|token_request| token_request.is_finished())
+        {
+            return;
+        }
+        commands.remove_resource::<ConnectTokenRequest>();
+        let result = token_request
+            .0
+            .take()
+            .unwrap()
+            .join()
+            .unwrap_or_else(|_| Err("The connection attempt crashed.".to_string()));
+
+        match result {
+            Ok((client_id, transport)) => {
+                commands.insert_resource(transport);
+                commands.insert_resource(LocalClientId(client_id));
+            }
+            Err(reason) => {
+                error!("Failed to get a connect token: {reason}");
+                commands.remove_resource::<Connecting>();
+                for entity_id in connecting_text.iter() {
+                    commands.entity(entity_id).despawn_recursive();
+                }
+                Self::spawn_overlay(
+                    &mut commands,
+                    format!(
+                        "Could not connect to the server.\n{reason}\n\
+                         Press R to retry, A to change the address or Esc to quit."
+                    ),
+                    AlignItems::Center,
+                    DisconnectMessage,
+                );
+            }
+        }
+    }
+}
+
+/// A connect token being fetched from the server, which is needed before the client can connect
+#[derive(Resource)]
+pub(crate) struct ConnectTokenRequest(
+    Option<JoinHandle<Result<(u64, NetcodeClientTransport), String>>>,
+);
+
+/// Hands out connect tokens to clients that know the password, from a thread of its own so that
+/// slow clients don't hold up the game. Tokens are only valid for the IP the client reached us
+/// on, so that has to be one of the public IPs, and for the port `room_port` gives for the room
+/// code the client asked for, which is empty if it didn't ask for one.
+pub(crate) fn spawn_connect_token_server(
+    listener: TcpListener,
+    public_ips: Vec<IpAddr>,
+    private_key: [u8; NETCODE_KEY_BYTES],
+    password: Option<String>,
+    room_port: impl Fn(&str) -> Result<u16, String> + Send + Sync + 'static,
+) {
+    let room_port = Arc::new(room_port);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("Failed to accept a connect token request: {err}");
+                    continue;
+                }
+            };
+            let public_ips = public_ips.clone();
+            let password = password.clone();
+            let room_port = room_port.clone();
+            thread::spawn(move || {
+                let response = issue_connect_token(
+                    &mut stream,
+                    &public_ips,
+                    &private_key,
+                    password.as_deref(),
+                    &*room_port,
+                );
+                let result = match response {
+                    Ok(response) => stream.write_all(&response),
+                    Err(reason) => {
+                        info!(
+                            "Refused a connect token to {}: {reason}",
+                            stream.peer_addr().map_or_else(
+                                |_| "an unknown client".to_string(),
+                                |addr| addr.to_string()
+                            )
+                        );
+                        stream
+                            .write_all(&[1])
+                            .and_then(|()| stream.write_all(reason.to_string().as_bytes()))
+                    }
+                };
+                if let Err(err) = result {
+                    warn!("Failed to reply to a connect token request: {err}");
+                }
+            });
+        }
+    });
+}
+
+/// Reads a connect token request, as sent by [`transport::request_connect_token`],
+/// and returns the reply granting it
+fn issue_connect_token(
+    stream: &mut TcpStream,
+    public_ips: &[IpAddr],
+    private_key: &[u8; NETCODE_KEY_BYTES],
+    password: Option<&str>,
+    room_port: &dyn Fn(&str) -> Result<u16, String>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    stream.set_read_timeout(Some(TOKEN_REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(TOKEN_REQUEST_TIMEOUT))?;
+
+    // the whole request is read before checking any of it, so that the reply isn't lost to the
+    // connection being reset with unread data
+    let mut protocol_id = [0; 8];
+    stream.read_exact(&mut protocol_id)?;
+    let mut password_len = [0];
+    stream.read_exact(&mut password_len)?;
+    let mut given_password = vec![0; password_len[0] as usize];
+    stream.read_exact(&mut given_password)?;
+    let mut room_len = [0];
+    stream.read_exact(&mut room_len)?;
+    let mut room = vec![0; room_len[0] as usize];
+    stream.read_exact(&mut room)?;
+    let mut user_data = [0; NETCODE_USER_DATA_BYTES];
+    stream.read_exact(&mut user_data)?;
+    if u64::from_le_bytes(protocol_id) != PROTOCOL_ID {
+        return Err(format!("The server is running a different version ({VERSION}).").into());
+    }
+    if password.is_some_and(|password| password.as_bytes() != given_password) {
+        return Err("Wrong password, pass the server's password with --password.".into());
+    }
+
+    // the client has to come back over UDP to the same IP it reached us on
+    let server_ip = stream.local_addr()?.ip();
+    if !public_ips.contains(&server_ip) {
+        warn!(
+            "A client tried to connect on {server_ip}, which isn't one of the server's --public-ip \
+            addresses"
+        );
+        return Err(format!("The server doesn't accept players on {server_ip}.").into());
+    }
+    // checked last, since asking for a room can open it
+    let room = String::from_utf8(room).map_err(|_| "The room code isn't valid text.")?;
+    let server_addr = SocketAddr::new(server_ip, room_port(&room)?);
+
+    let client_id = rand::thread_rng().gen_range(1..=u64::MAX);
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let connect_token = ConnectToken::generate(
+        current_time,
+        PROTOCOL_ID,
+        CONNECT_TOKEN_EXPIRE_SECS,
+        client_id,
+        CONNECTION_TIMEOUT_SECS,
+        vec![server_addr],
+        Some(&user_data),
+        private_key,
+    )?;
+    let mut response = vec![0];
+    response.extend_from_slice(&client_id.to_le_bytes());
+    connect_token.write(&mut response)?;
+    Ok(response)
+}
+
+/// A secret the server gives each player so that they can reclaim their pawn if their connection
+/// drops. It's kept on the player's entity on the server and as a resource on the client.
+#[derive(Component, Resource, Event, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconnectToken(pub u64);
+
+/// Whether a client is connecting to play or to observe, sent in the netcode user data
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionKind {
+    Player,
+    Observer,
+}
+
+impl ConnectionKind {
+    /// The kind goes in the first byte, then the reconnect token (0 for none), then the length of
+    /// the player's name and the name
+    pub fn user_data(
+        observe: bool,
+        name: Option<&str>,
+        reconnect_token: Option<ReconnectToken>,
+    ) -> [u8; NETCODE_USER_DATA_BYTES] {
+        let kind = if observe {
+            ConnectionKind::Observer
+        } else {
+            ConnectionKind::Player
+        };
+        let mut user_data = [0; NETCODE_USER_DATA_BYTES];
+        user_data[0] = kind as u8;
+        if let Some(ReconnectToken(token)) = reconnect_token {
+            user_data[1..9].copy_from_slice(&token.to_le_bytes());
+        }
+        if let Some(name) = name {
+            let name: String = name.trim().chars().take(MAX_PLAYER_NAME_LENGTH).collect();
+            user_data[9] = name.len() as u8;
+            user_data[10..10 + name.len()].copy_from_slice(name.as_bytes());
+        }
+        user_data
+    }
+
+    pub fn reconnect_token_from_user_data(
+        user_data: &[u8; NETCODE_USER_DATA_BYTES],
+    ) -> Option<ReconnectToken> {
+        let token = u64::from_le_bytes(user_data[1..9].try_into().unwrap());
+        (token != 0).then_some(ReconnectToken(token))
+    }
+
+    pub fn name_from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Option<String> {
+        let name = user_data.get(10..10 + user_data[9] as usize)?;
+        let name: String = String::from_utf8_lossy(name)
+            .chars()
+            .filter(|char| !char.is_control())
+            .take(MAX_PLAYER_NAME_LENGTH)
+            .collect();
+        (!name.trim().is_empty()).then_some(name)
+    }
+
+    pub fn from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Self {
+        if user_data[0] == ConnectionKind::Observer as u8 {
+            ConnectionKind::Observer
+        } else {
+            ConnectionKind::Player
+        }
+    }
+}
+
+pub const fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_continue(0xcbf29ce484222325, bytes)
+}
+
+const fn fnv1a_continue(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u64).wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Hashes the version together with the names of everything that's replicated, so that a
+/// network type added or reordered without bumping the version still changes the protocol
+const fn protocol_id(version: &str, types: &[&str]) -> u64 {
+    let mut hash = fnv1a(version.as_bytes());
+    let mut i = 0;
+    while i < types.len() {
+        // separated so that moving a character from one name to the next changes the hash
+        hash = fnv1a_continue(hash, b"\0");
+        hash = fnv1a_continue(hash, types[i].as_bytes());
+        i += 1;
+    }
+    hash
+}
+
+/// What a server started with `--announce` tells the master server about its game, and what the
+/// master server lists for `labyrinth browse`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ServerListing {
+    /// The address to connect to. Servers without a public IP leave it out, and the master server
+    /// fills in the address the heartbeat came from.
+    pub ip: Option<IpAddr>,
+    pub port: u16,
+    pub version: String,
+    pub mode: GameMode,
+    pub players: usize,
+    pub max_players: usize,
+    pub game_state: GameState,
+    /// Whether joining needs the server's password
+    pub password: bool,
+}
+
+/// The client ID that the local player's pawn belongs to. In a hot-seat game it's the pawn
+/// whose turn it is.
+#[derive(Resource, Clone, Copy)]
+pub struct LocalClientId(pub u64);
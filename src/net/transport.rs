@@ -0,0 +1,98 @@
+//! The sockets a client plays over. Native builds ask the server for a connect token over TCP
+//! and then play over UDP, and find public games through the master server over HTTP. Browsers can't open either kind of socket, and renet has no
+//! WebTransport or WebSocket transport for them to use instead, so a browser build can only play
+//! the games that don't need a server.
+
+use bevy_replicon::renet::transport::{ClientAuthentication, ConnectToken, NetcodeClientTransport};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, SystemTime};
+
+use crate::net::ServerListing;
+
+/// Fails with an explanation if this build can't open the sockets that playing online needs
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn check_sockets_available() -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+/// Fails with an explanation if this build can't open the sockets that playing online needs
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn check_sockets_available() -> Result<(), Box<dyn Error>> {
+    Err("Browsers can't connect to servers yet, play `local` or `practice` instead".into())
+}
+
+/// Sends the master server a heartbeat for this server's game, which is a POST of the listing as
+/// JSON to the URL given with `--announce`
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn announce(
+    url: &str,
+    listing: &ServerListing,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    ureq::post(url).timeout(timeout).send_json(listing)?;
+    Ok(())
+}
+
+/// Fetches the games the master server knows about, with a GET of the same URL servers announce
+/// themselves to
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn fetch_server_list(
+    url: &str,
+    timeout: Duration,
+) -> Result<Vec<ServerListing>, Box<dyn Error>> {
+    Ok(ureq::get(url).timeout(timeout).call()?.into_json()?)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn announce(
+    _url: &str,
+    _listing: &ServerListing,
+    _timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    check_sockets_available()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn fetch_server_list(
+    _url: &str,
+    _timeout: Duration,
+) -> Result<Vec<ServerListing>, Box<dyn Error>> {
+    check_sockets_available().map(|_| Vec::new())
+}
+
+/// Asks the server for a connect token over TCP, on the same port as the game, and opens the UDP
+/// socket to play with it on. The request is the protocol ID, the length of the password and the
+/// password, the length of the room code and the code, then the netcode user data. The server
+/// replies with 0 followed by our client ID and the token, or with any other byte followed by the
+/// reason it refused.
+pub(super) fn request_connect_token(
+    server_addr: SocketAddr,
+    timeout: Duration,
+    request: &[u8],
+) -> Result<(u64, NetcodeClientTransport), Box<dyn Error>> {
+    let mut stream = TcpStream::connect_timeout(&server_addr, timeout)
+        .map_err(|err| format!("It may not be running ({err})."))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(request)?;
+
+    let mut status = [0];
+    stream.read_exact(&mut status)?;
+    if status[0] != 0 {
+        let mut reason = String::new();
+        stream.read_to_string(&mut reason)?;
+        return Err(reason.into());
+    }
+    let mut client_id = [0; 8];
+    stream.read_exact(&mut client_id)?;
+    let client_id = u64::from_le_bytes(client_id);
+    let connect_token = ConnectToken::read(&mut stream)?;
+
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0))?;
+    let authentication = ClientAuthentication::Secure { connect_token };
+    let transport = NetcodeClientTransport::new(current_time, authentication, socket)?;
+    Ok((client_id, transport))
+}
@@ -0,0 +1,230 @@
+//! The colours players are shown in, and the marks that tell them apart without relying on
+//! colour alone
+
+use bevy::prelude::*;
+use clap::ValueEnum;
+
+use crate::client::{BoardCells, WindowSize, PAWN_SIZE};
+use crate::game::{CurrentTurn, Dice, Player, COLOR_NAMES};
+use crate::skins::Skins;
+use crate::LabyrinthPlugin;
+
+/// How dark the marks are drawn over a player's colour
+const MARK_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+
+/// The set of colours players are shown in
+#[derive(ValueEnum, Resource, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Palette {
+    /// Pure red, green, blue and yellow
+    Classic,
+    /// The Okabe-Ito colours, which stay apart with the common kinds of colour blindness
+    Colorblind,
+}
+
+impl Palette {
+    /// White for anyone past the four players there are colours for
+    pub(crate) fn color(self, player_number: usize) -> Color {
+        let colors = match self {
+            Palette::Classic => [Color::RED, Color::GREEN, Color::BLUE, Color::YELLOW],
+            // still red, green, blue and yellow, so that the colour names are right
+            Palette::Colorblind => [
+                Color::rgb_u8(213, 94, 0),
+                Color::rgb_u8(0, 158, 115),
+                Color::rgb_u8(0, 114, 178),
+                Color::rgb_u8(240, 228, 66),
+            ],
+        };
+        colors.get(player_number).copied().unwrap_or(Color::WHITE)
+    }
+}
+
+/// What's drawn over each player's pawn, dice and place in the turn order
+#[derive(ValueEnum, Resource, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PawnMarks {
+    /// Players are only told apart by colour
+    Off,
+    /// Horizontal stripes, vertical stripes, four dots and one big dot
+    Patterns,
+    /// The player's number, counting from 1
+    Numbers,
+}
+
+impl PawnMarks {
+    /// The rectangles making up a player's pattern, as their centre and size within a square
+    /// from -0.5 to 0.5
+    fn pattern(player_number: usize) -> &'static [(Vec2, Vec2)] {
+        const STRIPES: Vec2 = Vec2::new(0.7, 0.14);
+        const DOT: Vec2 = Vec2::splat(0.16);
+        match player_number {
+            0 => &[
+                (Vec2::new(0.0, 0.15), STRIPES),
+                (Vec2::new(0.0, -0.15), STRIPES),
+            ],
+            1 => &[
+                (Vec2::new(0.15, 0.0), Vec2::new(STRIPES.y, STRIPES.x)),
+                (Vec2::new(-0.15, 0.0), Vec2::new(STRIPES.y, STRIPES.x)),
+            ],
+            2 => &[
+                (Vec2::new(-0.17, -0.17), DOT),
+                (Vec2::new(0.17, -0.17), DOT),
+                (Vec2::new(-0.17, 0.17), DOT),
+                (Vec2::new(0.17, 0.17), DOT),
+            ],
+            _ => &[(Vec2::ZERO, Vec2::splat(0.36))],
+        }
+    }
+
+    /// Draws the mark `size` across as children of a sprite, `offset` from its middle
+    pub(crate) fn spawn_on_sprite(
+        self,
+        parent: &mut ChildBuilder,
+        player_number: usize,
+        size: f32,
+        offset: Vec2,
+    ) {
+        match self {
+            PawnMarks::Off => {}
+            PawnMarks::Patterns => {
+                for &(center, rect_size) in Self::pattern(player_number) {
+                    parent.spawn((
+                        SpriteBundle {
+                            sprite: Sprite {
+                                color: MARK_COLOR,
+                                custom_size: Some(rect_size * size),
+                                ..default()
+                            },
+                            // just in front of whatever it's on
+                            transform: Transform::from_translation(
+                                (offset + center * size).extend(0.1),
+                            ),
+                            ..default()
+                        },
+                        PlayerMark,
+                    ));
+                }
+            }
+            PawnMarks::Numbers => {
+                parent.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(
+                            (player_number + 1).to_string(),
+                            TextStyle {
+                                font_size: size * 0.6,
+                                color: MARK_COLOR,
+                                ..default()
+                            },
+                        ),
+                        transform: Transform::from_translation(offset.extend(0.1)),
+                        ..default()
+                    },
+                    PlayerMark,
+                ));
+            }
+        }
+    }
+
+    /// Draws the mark as children of a UI node that's `size` pixels across
+    pub(crate) fn spawn_on_node(self, parent: &mut ChildBuilder, player_number: usize, size: f32) {
+        match self {
+            PawnMarks::Off => {}
+            PawnMarks::Patterns => {
+                for &(center, rect_size) in Self::pattern(player_number) {
+                    // UI nodes are placed from their top left corner, with y pointing down
+                    let top_left = (Vec2::new(center.x, -center.y) - rect_size * 0.5 + 0.5) * size;
+                    parent.spawn(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(top_left.x),
+                            top: Val::Px(top_left.y),
+                            width: Val::Px(rect_size.x * size),
+                            height: Val::Px(rect_size.y * size),
+                            ..default()
+                        },
+                        background_color: MARK_COLOR.into(),
+                        ..default()
+                    });
+                }
+            }
+            PawnMarks::Numbers => {
+                parent.spawn(TextBundle::from_section(
+                    (player_number + 1).to_string(),
+                    TextStyle {
+                        font_size: size * 0.8,
+                        color: MARK_COLOR,
+                        ..default()
+                    },
+                ));
+            }
+        }
+    }
+}
+
+/// Part of a mark drawn on a pawn or dice
+#[derive(Component)]
+pub(crate) struct PlayerMark;
+
+impl LabyrinthPlugin {
+    /// Redraws the marks on the pawns and dice when the marks are changed, the dice moves to
+    /// another player, or something new appears to put them on
+    pub(crate) fn client_update_player_marks(
+        mut commands: Commands,
+        marks: Res<PawnMarks>,
+        window_size: Res<WindowSize>,
+        current_turn: Res<CurrentTurn>,
+        pawns: Query<(Entity, &Player), With<Sprite>>,
+        dice: Query<(Entity, &Dice), With<TextureAtlasSprite>>,
+        added_pawns: Query<(), (With<Player>, Added<Sprite>)>,
+        added_dice: Query<(), (With<Dice>, Added<TextureAtlasSprite>)>,
+        existing_marks: Query<Entity, With<PlayerMark>>,
+        cells: Res<BoardCells>,
+    ) {
+        if !marks.is_changed()
+            && !window_size.is_changed()
+            && !current_turn.is_changed()
+            && !cells.is_changed()
+            && added_pawns.is_empty()
+            && added_dice.is_empty()
+        {
+            return;
+        }
+        for entity_id in existing_marks.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+
+        let board_size = Self::calc_board_size(window_size.0);
+        let pawn_size = Self::calc_cell_size(board_size, cells.0).y * PAWN_SIZE;
+        for (entity_id, player) in pawns.iter() {
+            if player.player_number >= COLOR_NAMES.len() {
+                continue;
+            }
+            commands.entity(entity_id).with_children(|parent| {
+                marks.spawn_on_sprite(parent, player.player_number, pawn_size * 0.6, Vec2::ZERO);
+            });
+        }
+        let dice_size = Self::calc_dice_size(window_size.0, board_size).y;
+        for (entity_id, dice) in dice.iter() {
+            // the corner the dice is in is the player whose dice it is at the moment
+            let player_number = dice.corner(current_turn.0);
+            commands.entity(entity_id).with_children(|parent| {
+                // in the corner, so that the dice's face can still be read
+                let offset = Vec2::splat(dice_size * 0.35);
+                marks.spawn_on_sprite(parent, player_number, dice_size * 0.3, offset);
+            });
+        }
+    }
+
+    /// Recolours the pawns when the palette is changed in the settings. Everything else is
+    /// either rebuilt when the palette changes or only shown briefly.
+    pub(crate) fn client_apply_palette(
+        palette: Res<Palette>,
+        mut pawns: Query<(&Player, &mut Sprite)>,
+        skins: Res<Skins>,
+    ) {
+        if !palette.is_changed() {
+            return;
+        }
+        for (player, mut sprite) in pawns.iter_mut() {
+            sprite.color = Self::pawn_color(player, *palette, &skins);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Pausing the game, which the server does for whoever's turn it is or the host, so that nobody
+//! can roll or move until it's resumed
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+
+use crate::game::{
+    CurrentTurn, GameMode, GamePaused, GameState, MoveRejectionReason, PauseToggle, Player,
+};
+use crate::net::LocalClientId;
+use crate::server::{ClientRequests, MoveEventWriter};
+use crate::LabyrinthPlugin;
+
+/// Present on the server while the game is paused, and until the game has picked up again
+#[derive(Resource)]
+pub(crate) struct PausedBy(usize);
+
+/// Dims the board while the game is paused
+#[derive(Component)]
+pub(crate) struct PauseOverlay;
+
+impl LabyrinthPlugin {
+    /// In a classic game the player whose turn it is may pause, and in a race anyone may. The
+    /// host can always pause, and the game can be resumed by whoever could have paused it or
+    /// whoever did.
+    pub(crate) fn server_receive_pause_toggles(
+        mut commands: Commands,
+        mut requests: ClientRequests,
+        players: Query<&Player>,
+        game_mode: Res<GameMode>,
+        current_turn: Res<CurrentTurn>,
+        local_client_id: Option<Res<LocalClientId>>,
+        paused_by: Option<Res<PausedBy>>,
+        game_state: Res<State<GameState>>,
+        mut next_game_state: ResMut<NextState<GameState>>,
+        mut game_state_writer: EventWriter<ToClients<GameState>>,
+        mut game_paused_writer: EventWriter<ToClients<GamePaused>>,
+        mut move_writer: MoveEventWriter,
+    ) {
+        let mut paused = *game_state.get() == GameState::Paused;
+        for client_id in requests.pause_toggles() {
+            let Some(player) = players
+                .iter()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                warn!("Client {client_id} asked to pause without a pawn");
+                continue;
+            };
+            let is_host = local_client_id
+                .as_ref()
+                .is_some_and(|local_client_id| local_client_id.0 == player.client_id);
+            let may_pause = *game_mode == GameMode::Race
+                || player.player_number == current_turn.0
+                || is_host
+                || (paused
+                    && paused_by
+                        .as_ref()
+                        .is_some_and(|paused_by| paused_by.0 == player.player_number));
+            if !may_pause {
+                move_writer.reject(player, MoveRejectionReason::NotYourTurn);
+                continue;
+            }
+
+            paused = !paused;
+            let state = if paused {
+                info!("Player {} paused the game", player.player_number);
+                commands.insert_resource(PausedBy(player.player_number));
+                game_paused_writer.send(ToClients {
+                    mode: SendMode::Broadcast,
+                    event: GamePaused {
+                        player_number: player.player_number,
+                    },
+                });
+                GameState::Paused
+            } else {
+                info!("Player {} resumed the game", player.player_number);
+                GameState::InGame
+            };
+            next_game_state.set(state);
+            game_state_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: state,
+            });
+        }
+    }
+
+    /// Runs once the game is back in play, after [`LabyrinthPlugin::server_commit_dice_seed`]
+    /// has had the chance to see that it was only resumed
+    pub(crate) fn server_clear_pause(mut commands: Commands) {
+        commands.remove_resource::<PausedBy>();
+    }
+
+    /// Sends the pause to players who join while the game is paused
+    pub(crate) fn server_catch_up_pause(
+        mut events: EventReader<ServerEvent>,
+        paused_by: Res<PausedBy>,
+        mut game_paused_writer: EventWriter<ToClients<GamePaused>>,
+    ) {
+        for event in events.read() {
+            if let ServerEvent::ClientConnected { client_id } = event {
+                game_paused_writer.send(ToClients {
+                    mode: SendMode::Direct(*client_id),
+                    event: GamePaused {
+                        player_number: paused_by.0,
+                    },
+                });
+            }
+        }
+    }
+
+    /// P pauses the game, and resumes it again
+    pub(crate) fn client_toggle_pause(
+        keys: Res<Input<KeyCode>>,
+        mut pause_toggles: EventWriter<PauseToggle>,
+    ) {
+        if keys.just_pressed(KeyCode::P) {
+            pause_toggles.send(PauseToggle);
+        }
+    }
+
+    pub(crate) fn client_on_game_paused(
+        mut commands: Commands,
+        mut game_paused_events: EventReader<GamePaused>,
+        players: Query<&Player>,
+        overlay: Query<Entity, With<PauseOverlay>>,
+    ) {
+        let Some(event) = game_paused_events.read().last() else {
+            return;
+        };
+        for entity_id in overlay.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+        let name = players
+            .iter()
+            .find(|player| player.player_number == event.player_number)
+            .map_or_else(|| "another player".to_owned(), Player::display_name);
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    ..default()
+                },
+                PauseOverlay,
+            ))
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section(
+                    format!("Paused by {name}\nPress P to resume"),
+                    TextStyle {
+                        font_size: 32.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ));
+            });
+    }
+
+    pub(crate) fn client_despawn_pause_overlay(
+        mut commands: Commands,
+        overlay: Query<Entity, With<PauseOverlay>>,
+    ) {
+        for entity_id in overlay.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Single player practice on a local board, with records of the best runs
+
+use bevy::prelude::*;
+use std::io::Write as _;
+
+use crate::client::{GameOverDisplay, PlayerMoveAnimation, PlayerShake};
+use crate::game::{
+    AvailableItems, GameLimits, GameResult, GameRng, GameState, ItemDealing, Maze, MazeAlgorithm,
+    Player, PlayerStartMoveAnimation, TurnPhase, DEFAULT_BOARD_SIZE, ITEMS_TO_WIN,
+};
+use crate::LabyrinthPlugin;
+
+const PRACTICE_RECORDS_FILE: &str = "practice_records.txt";
+
+impl LabyrinthPlugin {
+    pub(crate) fn practice_count_moves(
+        mut practice: ResMut<Practice>,
+        mut start_move_animation_events: EventReader<PlayerStartMoveAnimation>,
+    ) {
+        practice.moves += start_move_animation_events.read().count() as u32;
+    }
+
+    pub(crate) fn practice_update_hud(
+        practice: Res<Practice>,
+        players: Query<&Player>,
+        mut hud: Query<&mut Text, With<PracticeHud>>,
+    ) {
+        let Ok(player) = players.get_single() else {
+            return;
+        };
+        let mut text = hud.single_mut();
+        text.sections[0].value = format!(
+            "Items: {}/{ITEMS_TO_WIN}\nMoves: {}\nCrashes: {}\nBest: {}\nPress R to restart",
+            player.achieved_items.len(),
+            practice.moves,
+            player.crashes,
+            practice
+                .best
+                .map_or_else(|| "-".to_string(), |best| best.to_string()),
+        );
+    }
+
+    pub(crate) fn practice_record_best(mut practice: ResMut<Practice>) {
+        info!("Practice finished in {} moves", practice.moves);
+        if practice.best.is_some_and(|best| best <= practice.moves) {
+            return;
+        }
+        info!("New best for seed {}", practice.seed);
+        practice.best = Some(practice.moves);
+        if let Err(err) = practice.save_record() {
+            warn!("Failed to save practice record to {PRACTICE_RECORDS_FILE}: {err}");
+        }
+    }
+
+    pub(crate) fn practice_reset(
+        mut commands: Commands,
+        keys: Res<Input<KeyCode>>,
+        mut practice: ResMut<Practice>,
+        mut players: Query<(Entity, &mut Player)>,
+        mut next_game_state: ResMut<NextState<GameState>>,
+        mut next_turn_phase: ResMut<NextState<TurnPhase>>,
+        game_over_display: Query<Entity, With<GameOverDisplay>>,
+    ) {
+        if !keys.just_pressed(KeyCode::R) {
+            return;
+        }
+        let Ok((entity_id, mut player)) = players.get_single_mut() else {
+            return;
+        };
+
+        let (mut rng, maze) = practice.start();
+        let mut available_items = AvailableItems::new(ItemDealing::Random, false);
+        let coords = Self::get_player_start_coords(0, maze.size);
+        *player = Player {
+            client_id: player.client_id,
+            coords,
+            prev_coords: coords,
+            player_number: 0,
+            target_item: available_items.take_target(0, coords, &maze, &mut rng.rng),
+            ..default()
+        };
+        commands
+            .entity(entity_id)
+            .remove::<(PlayerMoveAnimation, PlayerShake)>();
+        for entity_id in game_over_display.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+
+        commands.insert_resource(rng);
+        commands.insert_resource(available_items);
+        commands.init_resource::<GameLimits>();
+        commands.remove_resource::<GameResult>();
+        practice.moves = 0;
+        next_game_state.set(GameState::InGame);
+        next_turn_phase.set(TurnPhase::Rolling);
+    }
+}
+
+/// Progress through a practice game, along with the best result for its seed
+#[derive(Resource)]
+pub(crate) struct Practice {
+    pub(crate) seed: String,
+    pub(crate) tiles: u8,
+    pub(crate) moves: u32,
+    pub(crate) best: Option<u32>,
+}
+
+impl Practice {
+    pub(crate) fn new(seed: String, tiles: u8) -> Self {
+        let best = match std::fs::read_to_string(PRACTICE_RECORDS_FILE) {
+            Ok(records) => records
+                .lines()
+                .filter_map(|line| {
+                    let mut parts = line.rsplitn(3, ' ');
+                    let moves = parts.next()?.parse().ok()?;
+                    let record_tiles = parts.next()?.parse::<u8>().ok()?;
+                    (parts.next()? == seed && record_tiles == tiles).then_some(moves)
+                })
+                .min(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                warn!("Failed to read {PRACTICE_RECORDS_FILE}: {err}");
+                None
+            }
+        };
+        Practice {
+            seed,
+            tiles,
+            moves: 0,
+            best,
+        }
+    }
+
+    /// Generates the maze and the random state to play it with, which is the same for every
+    /// attempt at this seed
+    pub(crate) fn start(&self) -> (GameRng, Maze) {
+        let mut rng = GameRng::from_match_seed(&self.seed);
+        let maze = Maze::generate(
+            MazeAlgorithm::Scattered,
+            self.tiles,
+            DEFAULT_BOARD_SIZE,
+            &mut rng.rng,
+        );
+        (rng, maze)
+    }
+
+    fn save_record(&self) -> std::io::Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(PRACTICE_RECORDS_FILE)?;
+        writeln!(file, "{} {} {}", self.seed, self.tiles, self.moves)
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct PracticeHud;
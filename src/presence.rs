@@ -0,0 +1,116 @@
+//! Shows what's going on in the game on the player's Discord profile, for builds with the
+//! `presence` feature. Discord has to be running on the same machine, and is told about the game
+//! through the Discord application given with `--discord-app-id`.
+
+use bevy::prelude::*;
+use discord_rich_presence::activity::Activity;
+use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
+
+use crate::client::Me;
+use crate::game::{CurrentTurn, GameResult, GameState, Player};
+use crate::server::MaxPlayers;
+use crate::LabyrinthPlugin;
+
+/// The connection to the Discord client, along with what it was last told
+#[derive(Resource)]
+pub(crate) struct Presence {
+    client: DiscordIpcClient,
+    shown: String,
+}
+
+impl Presence {
+    fn connect(app_id: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut client = DiscordIpcClient::new(app_id)?;
+        client.connect()?;
+        Ok(Presence {
+            client,
+            shown: String::new(),
+        })
+    }
+}
+
+impl LabyrinthPlugin {
+    /// Carries on without rich presence if Discord isn't running
+    pub(crate) fn presence_init(commands: &mut Commands, app_id: &str) {
+        match Presence::connect(app_id) {
+            Ok(presence) => {
+                info!("Connected to Discord");
+                commands.insert_resource(presence);
+            }
+            Err(err) => warn!("Failed to connect to Discord, rich presence is disabled: {err}"),
+        }
+    }
+
+    /// Describes the game in a line like "In lobby 2/4" or "Your turn — 3 items collected", and
+    /// sends it to Discord whenever the game state, the turn or a player's items change
+    pub(crate) fn client_update_presence(
+        mut commands: Commands,
+        mut presence: ResMut<Presence>,
+        game_state: Res<State<GameState>>,
+        current_turn: Res<CurrentTurn>,
+        players: Query<&Player>,
+        changed_players: Query<(), Changed<Player>>,
+        mut removed_players: RemovedComponents<Player>,
+        me: Query<&Player, With<Me>>,
+        max_players: Option<Res<MaxPlayers>>,
+        game_result: Option<Res<GameResult>>,
+    ) {
+        let players_changed = !changed_players.is_empty() || removed_players.read().count() != 0;
+        if !game_state.is_changed()
+            && !current_turn.is_changed()
+            && !players_changed
+            && !presence.is_added()
+        {
+            return;
+        }
+        let me = me.get_single().ok();
+        let items = |player: &Player| match player.achieved_items.len() {
+            1 => "1 item collected".to_owned(),
+            count => format!("{count} items collected"),
+        };
+        let turn = match me {
+            Some(me) if me.player_number == current_turn.0 => "Your turn".to_owned(),
+            _ => match players
+                .iter()
+                .find(|player| player.player_number == current_turn.0)
+            {
+                Some(player) => format!("{}'s turn", player.display_name()),
+                None => "Playing".to_owned(),
+            },
+        };
+        let details = match game_state.get() {
+            GameState::WaitingPlayers => match max_players {
+                Some(max_players) => {
+                    format!("In lobby {}/{}", players.iter().count(), max_players.0)
+                }
+                None => "In lobby".to_owned(),
+            },
+            GameState::InGame => match me {
+                Some(me) => format!("{turn} — {}", items(me)),
+                None => format!("Watching — {turn}"),
+            },
+            GameState::Paused => match me {
+                Some(me) => format!("Paused — {}", items(me)),
+                None => "Watching — paused".to_owned(),
+            },
+            GameState::Ended => match (me, game_result.and_then(|result| result.winner)) {
+                (Some(me), Some(winner)) if me.player_number == winner => "Won".to_owned(),
+                (Some(me), _) => format!("Game over — {}", items(me)),
+                (None, _) => "Watching — game over".to_owned(),
+            },
+        };
+        if details == presence.shown {
+            return;
+        }
+
+        if let Err(err) = presence
+            .client
+            .set_activity(Activity::new().details(&details))
+        {
+            warn!("Failed to update Discord rich presence, disabling it: {err}");
+            commands.remove_resource::<Presence>();
+            return;
+        }
+        presence.shown = details;
+    }
+}
@@ -0,0 +1,295 @@
+//! Recording what the server tells its clients to a file, and playing it back in a window
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_replicon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Write as _};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::game::{
+    ChatBroadcast, CurrentTurn, Dice, DiceBundle, EmoteBroadcast, GameOver, GamePaused, GameState,
+    ItemCollected, Maze, MazeShifted, Movement, Player, PlayerBundle, PlayerStartMoveAnimation,
+    PlayerTarget, RaceState, TurnPhase,
+};
+use crate::net::VERSION;
+use crate::server::LeftGame;
+use crate::LabyrinthPlugin;
+
+/// Longer pauses than this, like waiting in the lobby, are skipped during playback
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(2);
+
+/// Something clients were told about during the match, either as an event or by replication
+#[derive(Serialize, Deserialize)]
+enum ReplayEvent {
+    Maze(Maze),
+    MazeShifted(MazeShifted),
+    Movement(Movement),
+    GameState(GameState),
+    GamePaused(GamePaused),
+    TurnPhase(TurnPhase),
+    CurrentTurn(CurrentTurn),
+    Player(Player, Option<RaceState>),
+    /// Targets aren't part of the replicated [`Player`], and anyone watching the replay sees
+    /// them all
+    PlayerTarget(PlayerTarget),
+    PlayerLeft(usize),
+    Dice(Dice),
+    StartMoveAnimation(PlayerStartMoveAnimation),
+    ItemCollected(ItemCollected),
+    Chat(ChatBroadcast),
+    Emote(EmoteBroadcast),
+    GameOver(GameOver),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReplayFrame {
+    /// How long after the server started this happened
+    time: Duration,
+    event: ReplayEvent,
+}
+
+/// Writes the replay file on the server, enabled with `--record`. The file starts with the
+/// version of the server, then has one frame for each event in the order they were sent.
+#[derive(Resource)]
+pub(crate) struct ReplayRecorder {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl ReplayRecorder {
+    pub(crate) fn create(
+        path: &Path,
+        maze: &Maze,
+        movement: Movement,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut file, VERSION)?;
+        let mut recorder = ReplayRecorder {
+            file,
+            started: Instant::now(),
+        };
+        recorder.record(ReplayEvent::Maze(maze.clone()));
+        recorder.record(ReplayEvent::Movement(movement));
+        Ok(recorder)
+    }
+
+    fn record(&mut self, event: ReplayEvent) {
+        let frame = ReplayFrame {
+            time: self.started.elapsed(),
+            event,
+        };
+        if let Err(err) = bincode::serialize_into(&mut self.file, &frame) {
+            warn!("Failed to record replay frame: {err}");
+        }
+    }
+}
+
+/// The frames of a replay that are still to be played, for `labyrinth replay`
+#[derive(Resource)]
+pub(crate) struct ReplayPlayback {
+    frames: VecDeque<ReplayFrame>,
+    speed: f32,
+    elapsed: Duration,
+    players: HashMap<usize, Entity>,
+    dice: HashMap<Option<usize>, Entity>,
+}
+
+impl ReplayPlayback {
+    pub(crate) fn load(path: &Path, speed: f32) -> Result<Self, Box<dyn Error>> {
+        if speed <= 0.0 || !speed.is_finite() {
+            return Err(format!("Replay speed must be positive, got {speed}").into());
+        }
+        let mut file = BufReader::new(File::open(path)?);
+        let version: String = bincode::deserialize_from(&mut file)?;
+        if version != VERSION {
+            return Err(format!(
+                "{} was recorded with version {version}, but this is version {VERSION}",
+                path.display()
+            )
+            .into());
+        }
+        let mut frames = VecDeque::new();
+        loop {
+            match bincode::deserialize_from(&mut file) {
+                Ok(frame) => frames.push_back(frame),
+                Err(err) => match *err {
+                    // the server may have stopped in the middle of writing a frame
+                    bincode::ErrorKind::Io(ref io_err)
+                        if io_err.kind() == ErrorKind::UnexpectedEof =>
+                    {
+                        break
+                    }
+                    _ => return Err(err.into()),
+                },
+            }
+        }
+        info!(
+            "Loaded {} replay frames from {}",
+            frames.len(),
+            path.display()
+        );
+        Ok(ReplayPlayback {
+            frames,
+            speed,
+            elapsed: Duration::ZERO,
+            players: HashMap::new(),
+            dice: HashMap::new(),
+        })
+    }
+}
+
+impl LabyrinthPlugin {
+    /// Records the events broadcast this frame and the replicated state that changed. Events sent
+    /// to a single client are left out, they're either repeats of broadcasts for clients that
+    /// joined late or only matter to that player.
+    pub(crate) fn server_record_replay(
+        mut recorder: ResMut<ReplayRecorder>,
+        mut maze_events: EventReader<ToClients<Maze>>,
+        mut maze_shifted_events: EventReader<ToClients<MazeShifted>>,
+        mut movement_events: EventReader<ToClients<Movement>>,
+        mut game_state_events: EventReader<ToClients<GameState>>,
+        mut game_paused_events: EventReader<ToClients<GamePaused>>,
+        mut turn_phase_events: EventReader<ToClients<TurnPhase>>,
+        mut current_turn_events: EventReader<ToClients<CurrentTurn>>,
+        mut start_move_animation_events: EventReader<ToClients<PlayerStartMoveAnimation>>,
+        mut item_collected_events: EventReader<ToClients<ItemCollected>>,
+        mut chat_events: EventReader<ToClients<ChatBroadcast>>,
+        mut emote_events: EventReader<ToClients<EmoteBroadcast>>,
+        mut game_over_events: EventReader<ToClients<GameOver>>,
+        players: Query<(&Player, Option<&RaceState>), Or<(Changed<Player>, Changed<RaceState>)>>,
+        left_players: Query<&Player, Added<LeftGame>>,
+        dice: Query<&Dice, Changed<Dice>>,
+    ) {
+        fn broadcasts<'a, T: Event + Clone>(
+            events: &'a mut EventReader<'_, '_, ToClients<T>>,
+        ) -> impl Iterator<Item = T> + 'a {
+            events
+                .read()
+                .filter(|event| matches!(event.mode, SendMode::Broadcast))
+                .map(|event| event.event.clone())
+        }
+
+        let mut events = Vec::new();
+        events.extend(broadcasts(&mut maze_events).map(ReplayEvent::Maze));
+        events.extend(broadcasts(&mut maze_shifted_events).map(ReplayEvent::MazeShifted));
+        events.extend(broadcasts(&mut movement_events).map(ReplayEvent::Movement));
+        events.extend(broadcasts(&mut game_paused_events).map(ReplayEvent::GamePaused));
+        events.extend(broadcasts(&mut game_state_events).map(ReplayEvent::GameState));
+        events.extend(broadcasts(&mut turn_phase_events).map(ReplayEvent::TurnPhase));
+        events.extend(broadcasts(&mut current_turn_events).map(ReplayEvent::CurrentTurn));
+        for (player, race_state) in players.iter() {
+            events.push(ReplayEvent::Player(player.clone(), race_state.cloned()));
+            events.push(ReplayEvent::PlayerTarget(PlayerTarget {
+                player_number: player.player_number,
+                target_item: player.target_item,
+            }));
+        }
+        for player in left_players.iter() {
+            events.push(ReplayEvent::PlayerLeft(player.player_number));
+        }
+        for dice in dice.iter() {
+            events.push(ReplayEvent::Dice(dice.clone()));
+        }
+        events.extend(
+            broadcasts(&mut start_move_animation_events).map(ReplayEvent::StartMoveAnimation),
+        );
+        events.extend(broadcasts(&mut item_collected_events).map(ReplayEvent::ItemCollected));
+        events.extend(broadcasts(&mut chat_events).map(ReplayEvent::Chat));
+        events.extend(broadcasts(&mut emote_events).map(ReplayEvent::Emote));
+        events.extend(broadcasts(&mut game_over_events).map(ReplayEvent::GameOver));
+
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            recorder.record(event);
+        }
+        if let Err(err) = recorder.file.flush() {
+            warn!("Failed to write replay: {err}");
+        }
+    }
+
+    /// Feeds the frames that are due to the client systems, as if they had come from the server
+    pub(crate) fn replay_play(
+        mut commands: Commands,
+        time: Res<Time>,
+        mut playback: ResMut<ReplayPlayback>,
+        mut maze_writer: EventWriter<Maze>,
+        mut maze_shifted_writer: EventWriter<MazeShifted>,
+        mut movement_writer: EventWriter<Movement>,
+        mut game_state_writer: EventWriter<GameState>,
+        mut game_paused_writer: EventWriter<GamePaused>,
+        mut turn_phase_writer: EventWriter<TurnPhase>,
+        mut current_turn_writer: EventWriter<CurrentTurn>,
+        mut start_move_animation_writer: EventWriter<PlayerStartMoveAnimation>,
+        mut item_collected_writer: EventWriter<ItemCollected>,
+        mut chat_writer: EventWriter<ChatBroadcast>,
+        mut emote_writer: EventWriter<EmoteBroadcast>,
+        mut game_over_writer: EventWriter<GameOver>,
+        mut player_target_writer: EventWriter<PlayerTarget>,
+    ) {
+        let Some(next_time) = playback.frames.front().map(|frame| frame.time) else {
+            return;
+        };
+        let delta = time.delta().mul_f32(playback.speed);
+        playback.elapsed = (playback.elapsed + delta).max(next_time.saturating_sub(MAX_REPLAY_GAP));
+
+        while playback
+            .frames
+            .front()
+            .is_some_and(|frame| frame.time <= playback.elapsed)
+        {
+            let frame = playback.frames.pop_front().unwrap();
+            match frame.event {
+                ReplayEvent::Maze(maze) => maze_writer.send(maze),
+                ReplayEvent::MazeShifted(shifted) => maze_shifted_writer.send(shifted),
+                ReplayEvent::Movement(movement) => movement_writer.send(movement),
+                ReplayEvent::GameState(game_state) => game_state_writer.send(game_state),
+                ReplayEvent::GamePaused(game_paused) => game_paused_writer.send(game_paused),
+                ReplayEvent::TurnPhase(turn_phase) => turn_phase_writer.send(turn_phase),
+                ReplayEvent::CurrentTurn(current_turn) => current_turn_writer.send(current_turn),
+                ReplayEvent::Player(player, race_state) => {
+                    let entity_id = *playback
+                        .players
+                        .entry(player.player_number)
+                        .or_insert_with(|| commands.spawn(PlayerBundle::default()).id());
+                    let mut entity = commands.entity(entity_id);
+                    entity.insert(player);
+                    if let Some(race_state) = race_state {
+                        entity.insert(race_state);
+                    }
+                }
+                ReplayEvent::PlayerTarget(target) => player_target_writer.send(target),
+                ReplayEvent::PlayerLeft(player_number) => {
+                    if let Some(entity_id) = playback.players.remove(&player_number) {
+                        commands.entity(entity_id).despawn_recursive();
+                    }
+                }
+                ReplayEvent::Dice(dice) => {
+                    let entity_id = *playback
+                        .dice
+                        .entry(dice.owner)
+                        .or_insert_with(|| commands.spawn(DiceBundle::default()).id());
+                    commands.entity(entity_id).insert(dice);
+                }
+                ReplayEvent::StartMoveAnimation(animation) => {
+                    start_move_animation_writer.send(animation)
+                }
+                ReplayEvent::ItemCollected(item_collected) => {
+                    item_collected_writer.send(item_collected)
+                }
+                ReplayEvent::Chat(chat) => chat_writer.send(chat),
+                ReplayEvent::Emote(emote) => emote_writer.send(emote),
+                ReplayEvent::GameOver(game_over) => game_over_writer.send(game_over),
+            }
+        }
+        if playback.frames.is_empty() {
+            info!("Replay finished");
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Rewinding, where observers can step back through the turns of a game that's still going on to
+//! see where everyone was, then jump back to the live board. Players can't rewind, since it would
+//! let them look back at opponents' routes.
+
+use bevy::prelude::*;
+
+use crate::client::{BoardCells, Me, PlayerMoveAnimation, WindowSize};
+use crate::game::{CurrentTurn, Player};
+use crate::palette::Palette;
+use crate::LabyrinthPlugin;
+
+const REWIND_FONT_SIZE: f32 = 20.0;
+
+/// Where a player was at the start of a turn
+struct PlayerSnapshot {
+    player_number: usize,
+    name: String,
+    coords: IVec2,
+    items: usize,
+}
+
+/// The board at the start of a turn
+struct TurnSnapshot {
+    current_turn: usize,
+    players: Vec<PlayerSnapshot>,
+}
+
+/// Every turn of the current game seen by this client, oldest first, and which of them is being
+/// looked at
+#[derive(Resource, Default)]
+pub(crate) struct TurnHistory {
+    turns: Vec<TurnSnapshot>,
+    /// The index into `turns` being shown, or `None` for the live board
+    viewing: Option<usize>,
+}
+
+/// The text saying whether the board is live or rewound
+#[derive(Component)]
+pub(crate) struct RewindLabel;
+
+impl LabyrinthPlugin {
+    /// Takes a snapshot of the board whenever a new turn starts
+    pub(crate) fn client_record_turn_history(
+        mut history: ResMut<TurnHistory>,
+        current_turn: Res<CurrentTurn>,
+        players: Query<&Player>,
+    ) {
+        let mut players: Vec<_> = players
+            .iter()
+            .map(|player| PlayerSnapshot {
+                player_number: player.player_number,
+                name: player.display_name(),
+                coords: player.coords,
+                items: player.achieved_items.len(),
+            })
+            .collect();
+        players.sort_by_key(|player| player.player_number);
+        history.turns.push(TurnSnapshot {
+            current_turn: current_turn.0,
+            players,
+        });
+    }
+
+    /// Comma steps back a turn and period steps forward, past the latest turn to the live board.
+    /// End jumps straight back to the live board. Only clients without a pawn can rewind.
+    pub(crate) fn client_handle_rewind_input(
+        keys: Res<Input<KeyCode>>,
+        me: Query<(), With<Me>>,
+        mut history: ResMut<TurnHistory>,
+    ) {
+        if !me.is_empty() || history.turns.is_empty() {
+            return;
+        }
+        let latest = history.turns.len() - 1;
+        let viewing = if keys.just_pressed(KeyCode::End) {
+            None
+        } else if keys.just_pressed(KeyCode::Comma) {
+            Some(
+                history
+                    .viewing
+                    .map_or(latest, |viewing| viewing.saturating_sub(1)),
+            )
+        } else if keys.just_pressed(KeyCode::Period) {
+            history
+                .viewing
+                .map(|viewing| viewing + 1)
+                .filter(|&viewing| viewing <= latest)
+        } else {
+            return;
+        };
+        if viewing != history.viewing {
+            history.viewing = viewing;
+        }
+    }
+
+    /// Puts the pawns where they were in the turn being looked at. Runs after the pawn animations,
+    /// which carry on underneath, so that the pawns are back in their live places as soon as
+    /// rewinding stops.
+    pub(crate) fn client_show_rewound_pawns(
+        history: Res<TurnHistory>,
+        mut players: Query<(&Player, &mut Transform)>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+    ) {
+        let Some(turn) = history
+            .viewing
+            .and_then(|viewing| history.turns.get(viewing))
+        else {
+            return;
+        };
+        let board_size = Self::calc_board_size(window_size.0);
+        for (player, mut transform) in players.iter_mut() {
+            if let Some(snapshot) = turn
+                .players
+                .iter()
+                .find(|snapshot| snapshot.player_number == player.player_number)
+            {
+                transform.translation =
+                    Self::board_pos_to_pos(snapshot.coords, board_size, cells.0).extend(0.0);
+            }
+        }
+    }
+
+    /// Puts the pawns that aren't animating back in their live places when rewinding stops
+    pub(crate) fn client_show_live_pawns(
+        history: Res<TurnHistory>,
+        mut players: Query<(&Player, &mut Transform), Without<PlayerMoveAnimation>>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+    ) {
+        if !history.is_changed() || history.viewing.is_some() {
+            return;
+        }
+        let board_size = Self::calc_board_size(window_size.0);
+        for (player, mut transform) in players.iter_mut() {
+            transform.translation =
+                Self::board_pos_to_pos(player.coords, board_size, cells.0).extend(0.0);
+        }
+    }
+
+    /// Shows clients without a pawn whether the board is live, or which turn it's rewound to and
+    /// how many items everyone had then
+    pub(crate) fn client_update_rewind_label(
+        mut commands: Commands,
+        history: Res<TurnHistory>,
+        me: Query<(), With<Me>>,
+        mut label: Query<(Entity, &mut Text), With<RewindLabel>>,
+        palette: Res<Palette>,
+    ) {
+        if !me.is_empty() {
+            for (entity_id, _) in label.iter() {
+                commands.entity(entity_id).despawn();
+            }
+            return;
+        }
+        let Ok((_, mut text)) = label.get_single_mut() else {
+            commands.spawn((
+                TextBundle::default().with_style(Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..default()
+                }),
+                RewindLabel,
+            ));
+            // filled in next frame, once the text exists
+            return;
+        };
+        if !history.is_changed() && !palette.is_changed() && !text.sections.is_empty() {
+            return;
+        }
+
+        let text_style = |color| TextStyle {
+            font_size: REWIND_FONT_SIZE,
+            color,
+            ..default()
+        };
+        let Some((viewing, turn)) = history
+            .viewing
+            .and_then(|viewing| Some((viewing, history.turns.get(viewing)?)))
+        else {
+            text.sections = vec![TextSection::new(
+                "LIVE [, to rewind]",
+                text_style(Color::WHITE),
+            )];
+            return;
+        };
+        let mut sections = vec![TextSection::new(
+            format!(
+                "REPLAY turn {}/{} [, . to step, End for live]",
+                viewing + 1,
+                history.turns.len()
+            ),
+            text_style(Color::YELLOW),
+        )];
+        for snapshot in &turn.players {
+            let items = match snapshot.items {
+                1 => "1 item".to_owned(),
+                count => format!("{count} items"),
+            };
+            let turn_marker = if snapshot.player_number == turn.current_turn {
+                " (to move)"
+            } else {
+                ""
+            };
+            sections.push(TextSection::new(
+                format!("\n{}: {items}{turn_marker}", snapshot.name),
+                text_style(palette.color(snapshot.player_number)),
+            ));
+        }
+        text.sections = sections;
+    }
+
+    /// Forgets the game that just ended, once the next one starts
+    pub(crate) fn client_reset_turn_history(
+        mut commands: Commands,
+        mut history: ResMut<TurnHistory>,
+        label: Query<Entity, With<RewindLabel>>,
+    ) {
+        *history = TurnHistory::default();
+        for entity_id in label.iter() {
+            commands.entity(entity_id).despawn();
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Hosting several games from one server, each in a room that players join with its code
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::transport::{generate_random_bytes, NETCODE_KEY_BYTES};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::net::{spawn_connect_token_server, MAX_ROOM_CODE_LENGTH, VERSION};
+use crate::shutdown;
+use crate::{Cli, LabyrinthPlugin, ServerArgs};
+
+/// How long a room stays open with nobody connected to it, so that a room someone asked for but
+/// never joined doesn't take up a port forever
+const ROOM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Rooms share the machine, so unlike a dedicated server they don't update as fast as they can
+const ROOM_UPDATE_INTERVAL: Duration = Duration::from_millis(16);
+/// How often the room server checks whether it's been stopped and every room has closed
+const ROOM_SERVER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Present in the app running a room's game, which gets its players from the room server
+#[derive(Resource)]
+pub(crate) struct Room {
+    pub(crate) code: String,
+    /// The key the room server signs connect tokens for every room with
+    pub(crate) private_key: [u8; NETCODE_KEY_BYTES],
+}
+
+/// The port each open room's game is played on, by room code
+type OpenRooms = Arc<Mutex<HashMap<String, u16>>>;
+
+/// Runs a server for `--rooms`. It hands out connect tokens for every room, and opens a room the
+/// first time a player asks for its code. Each room's game is an app of its own, running on its
+/// own thread and port, so the game doesn't need to know that it's sharing the process.
+pub fn run_rooms(server_args: ServerArgs) -> Result<(), Box<dyn Error>> {
+    let max_rooms = server_args.rooms.unwrap_or(1);
+    let port = server_args.port;
+    info!("Starting room server version {VERSION} on port {port} with up to {max_rooms} rooms");
+
+    let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+    let private_key = generate_random_bytes();
+    let open_rooms = OpenRooms::default();
+    let still_open = open_rooms.clone();
+    let public_ip = server_args.public_ip.clone();
+    let password = server_args.password.clone();
+    spawn_connect_token_server(listener, public_ip, private_key, password, move |code| {
+        if code.is_empty() {
+            return Err("The server hosts several games, pick one with --room.".to_owned());
+        }
+        if code.len() > MAX_ROOM_CODE_LENGTH {
+            return Err("The room code is too long.".to_owned());
+        }
+        let mut rooms = open_rooms.lock().unwrap();
+        if let Some(&room_port) = rooms.get(code) {
+            return Ok(room_port);
+        }
+        let room_port = (1..=max_rooms)
+            .filter_map(|room_number| port.checked_add(room_number))
+            .find(|room_port| !rooms.values().any(|used_port| used_port == room_port))
+            .ok_or_else(|| "All of the server's rooms are in use, try again later.".to_owned())?;
+        rooms.insert(code.to_owned(), room_port);
+        open_room(
+            code.to_owned(),
+            room_port,
+            &server_args,
+            private_key,
+            open_rooms.clone(),
+        );
+        Ok(room_port)
+    });
+
+    // the token server does all the work from here, until Ctrl+C closes every room
+    shutdown::catch_interrupts();
+    while !shutdown::interrupted() || !still_open.lock().unwrap().is_empty() {
+        thread::sleep(ROOM_SERVER_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+fn open_room(
+    code: String,
+    port: u16,
+    server_args: &ServerArgs,
+    private_key: [u8; NETCODE_KEY_BYTES],
+    open_rooms: OpenRooms,
+) {
+    info!("Opening room {code} on port {port}");
+    let mut room_args = server_args.clone();
+    room_args.port = port;
+    room_args.rooms = None;
+    if let Some(record) = &mut room_args.record {
+        let file_name = record.file_name().unwrap_or_default().to_string_lossy();
+        let file_name = format!("{code}-{file_name}");
+        record.set_file_name(file_name);
+    }
+
+    thread::spawn(move || {
+        let _close_room = CloseRoom {
+            code: code.clone(),
+            open_rooms,
+        };
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        app.set_runner(shutdown::server_runner(ROOM_UPDATE_INTERVAL));
+        app.insert_resource(Cli::Server(room_args));
+        app.insert_resource(Room { code, private_key });
+        app.add_plugins((ReplicationPlugins, LabyrinthPlugin));
+        app.run();
+    });
+}
+
+/// Frees up a room's code and port when its game stops, even if it stops by panicking
+struct CloseRoom {
+    code: String,
+    open_rooms: OpenRooms,
+}
+
+impl Drop for CloseRoom {
+    fn drop(&mut self) {
+        info!("Closing room {}", self.code);
+        self.open_rooms.lock().unwrap().remove(&self.code);
+    }
+}
+
+impl LabyrinthPlugin {
+    /// Stops a room's game once nobody has been connected to it for a while
+    pub(crate) fn server_close_idle_room(
+        time: Res<Time>,
+        server: Res<RenetServer>,
+        room: Res<Room>,
+        mut idle_time: Local<Duration>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        if server.connected_clients() != 0 {
+            *idle_time = Duration::ZERO;
+            return;
+        }
+        *idle_time += time.delta();
+        if *idle_time >= ROOM_IDLE_TIMEOUT {
+            info!("Nobody is in room {}, closing it", room.code);
+            app_exit_events.send(AppExit);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! House rules written in Rhai, for builds with the `scripting` feature. A server loads them when
+//! it starts, from the file given with `--rules` or from `rules.rhai` if there is one. A script
+//! can define any of these functions, and the rules it leaves out are the usual ones:
+//!
+//! - `dice_faces()` returns an array of the faces the dice has, where a face that's listed twice
+//!   is rolled twice as often. The default is `[1, 2, 2, 3, 3, 4]`.
+//! - `on_item_collected(item)` is called after a player collects an item, unless it won them
+//!   the game, with the item's name.
+//! - `on_special_cell(cell)` is called after a player steps onto a special cell, with
+//!   `"teleporter"`, `"trap"` or `"bonus"`.
+//!
+//! The hooks are called on the player, as `this`. They can read `this.number`, `this.name`,
+//! `this.x`, `this.y`, `this.items` and `this.crashes`, and change `this.bonus_steps`,
+//! `this.extra_roll` and `this.skips_next_roll`. Scripts can't get at anything else: they can't
+//! import modules or touch files, and a hook that runs for too long is stopped.
+
+use bevy::prelude::*;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Scope, AST};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::game::{GameRng, HouseRules, Item, Player, SpecialCell, MAX_DICE_FACE};
+use crate::{LabyrinthPlugin, ServerArgs};
+
+const DEFAULT_RULES_FILE: &str = "rules.rhai";
+/// How many operations a script can run per call before it's stopped, so that an endless loop
+/// can't hang the server
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_COLLECTION_SIZE: usize = 1000;
+
+pub(crate) struct ScriptedRules {
+    engine: Engine,
+    ast: AST,
+}
+
+/// What hooks see of the [`Player`] they're called on. Only the fields with setters are copied
+/// back afterwards.
+#[derive(Clone)]
+struct ScriptPlayer {
+    number: i64,
+    name: String,
+    x: i64,
+    y: i64,
+    items: i64,
+    crashes: i64,
+    bonus_steps: i64,
+    extra_roll: bool,
+    skips_next_roll: bool,
+}
+
+impl ScriptPlayer {
+    fn new(player: &Player) -> ScriptPlayer {
+        ScriptPlayer {
+            number: player.player_number as i64,
+            name: player.display_name(),
+            x: player.coords.x as i64,
+            y: player.coords.y as i64,
+            items: player.achieved_items.len() as i64,
+            crashes: player.crashes as i64,
+            bonus_steps: player.bonus_steps as i64,
+            extra_roll: player.extra_roll,
+            skips_next_roll: player.skips_next_roll,
+        }
+    }
+
+    fn apply(&self, player: &mut Player) {
+        player.bonus_steps = self.bonus_steps.clamp(0, u8::MAX as i64) as u8;
+        player.extra_roll = self.extra_roll;
+        player.skips_next_roll = self.skips_next_roll;
+    }
+}
+
+impl ScriptedRules {
+    fn load(path: &Path) -> Result<ScriptedRules, Box<dyn Error>> {
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_array_size(MAX_COLLECTION_SIZE);
+        engine.set_max_map_size(MAX_COLLECTION_SIZE);
+        engine.set_max_string_size(MAX_COLLECTION_SIZE);
+        engine.on_print(|text| info!("House rules: {text}"));
+        engine.on_debug(|text, _, pos| debug!("House rules at {pos}: {text}"));
+
+        engine
+            .register_type_with_name::<ScriptPlayer>("Player")
+            .register_get("number", |player: &mut ScriptPlayer| player.number)
+            .register_get("name", |player: &mut ScriptPlayer| player.name.clone())
+            .register_get("x", |player: &mut ScriptPlayer| player.x)
+            .register_get("y", |player: &mut ScriptPlayer| player.y)
+            .register_get("items", |player: &mut ScriptPlayer| player.items)
+            .register_get("crashes", |player: &mut ScriptPlayer| player.crashes)
+            .register_get_set(
+                "bonus_steps",
+                |player: &mut ScriptPlayer| player.bonus_steps,
+                |player: &mut ScriptPlayer, bonus_steps: i64| player.bonus_steps = bonus_steps,
+            )
+            .register_get_set(
+                "extra_roll",
+                |player: &mut ScriptPlayer| player.extra_roll,
+                |player: &mut ScriptPlayer, extra_roll: bool| player.extra_roll = extra_roll,
+            )
+            .register_get_set(
+                "skips_next_roll",
+                |player: &mut ScriptPlayer| player.skips_next_roll,
+                |player: &mut ScriptPlayer, skips_next_roll: bool| {
+                    player.skips_next_roll = skips_next_roll
+                },
+            );
+
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|err| format!("Invalid house rules {}: {err}", path.display()))?;
+        Ok(ScriptedRules { engine, ast })
+    }
+
+    fn has_fn(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == params)
+    }
+
+    /// The faces from the script's `dice_faces` function, or `None` if it doesn't have one
+    fn dice_faces(&self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if !self.has_fn("dice_faces", 0) {
+            return Ok(None);
+        }
+        let faces: Array = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, "dice_faces", ())
+            .map_err(|err| format!("House rules failed to give the dice faces: {err}"))?;
+        let faces = faces
+            .into_iter()
+            .map(|face| {
+                face.as_int()
+                    .ok()
+                    .and_then(|face| u8::try_from(face).ok())
+                    .filter(|face| (1..=MAX_DICE_FACE).contains(face))
+                    .ok_or_else(|| format!("Dice faces must be numbers from 1 to {MAX_DICE_FACE}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if faces.is_empty() {
+            return Err("The dice needs at least one face".into());
+        }
+        Ok(Some(faces))
+    }
+
+    /// Calls a hook on the player, keeping the changes it made unless it failed
+    fn call_hook(&self, name: &str, player: &mut Player, arg: String) {
+        if !self.has_fn(name, 1) {
+            return;
+        }
+        let mut this = Dynamic::from(ScriptPlayer::new(player));
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut this);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            name,
+            (arg,),
+        );
+        match result {
+            Ok(_) => this.cast::<ScriptPlayer>().apply(player),
+            Err(err) => warn!("House rules failed in {name}: {err}"),
+        }
+    }
+}
+
+impl HouseRules for ScriptedRules {
+    fn on_item_collected(&self, player: &mut Player, item: Item) {
+        self.call_hook("on_item_collected", player, item.to_string());
+    }
+
+    fn on_special_cell(&self, player: &mut Player, cell: SpecialCell) {
+        let cell = match cell {
+            SpecialCell::Teleporter(_) => "teleporter",
+            SpecialCell::Trap => "trap",
+            SpecialCell::Bonus => "bonus",
+        };
+        self.call_hook("on_special_cell", player, cell.to_owned());
+    }
+}
+
+impl LabyrinthPlugin {
+    /// Loads the house rules for a new game, if there are any, and gives `rng` their dice
+    pub(crate) fn server_load_house_rules(
+        server_args: &ServerArgs,
+        rng: &mut GameRng,
+    ) -> Result<Option<Arc<dyn HouseRules>>, Box<dyn Error>> {
+        let path = match &server_args.rules {
+            Some(path) => path.clone(),
+            None if Path::new(DEFAULT_RULES_FILE).exists() => PathBuf::from(DEFAULT_RULES_FILE),
+            None => return Ok(None),
+        };
+        info!("Loading house rules from {}", path.display());
+        let rules = ScriptedRules::load(&path)?;
+        if let Some(faces) = rules.dice_faces()? {
+            // clients check verifiable rolls against the usual dice
+            if rng.verifiable.is_some() {
+                return Err("House rules can't change the dice when rolls are verifiable".into());
+            }
+            info!("Dice faces: {faces:?}");
+            rng.faces = faces;
+        }
+        Ok(Some(Arc::new(rules)))
+    }
+}
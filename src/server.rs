@@ -0,0 +1,1973 @@
+//! Systems that run the game on the server, which has the final say on every move
+
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::transport::{
+    generate_random_bytes, NetcodeServerTransport, ServerAuthentication, ServerConfig,
+    NETCODE_USER_DATA_BYTES,
+};
+use bevy_replicon::renet::{ClientId, ConnectionConfig, ServerEvent};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::time::{Duration, SystemTime};
+
+use crate::browser::Announcer;
+use crate::coach::CoachView;
+use crate::fog::FogOfWar;
+use crate::game::{
+    to_hex, AvailableItems, ChatBroadcast, ChatMessage, CurrentTurn, Dice, DiceBundle,
+    DiceCommitment, DiceRollRequest, DiceSeedReveal, FirstPlayer, GameLimits, GameMode, GameOver,
+    GameOverReason, GameResult, GameRng, GameState, ItemCollected, MatchSeed, Maze, MoveOutcome,
+    MoveRejected, MoveRejectionReason, MoveRequest, Movement, MovementChange, PauseToggle,
+    PawnCollisions, Player, PlayerBundle, PlayerLeft, PlayerStartMoveAnimation, RaceState,
+    ReadyToggle, RematchToggle, ShiftRequest, SpecialCell, TurnLog, TurnLogBundle, TurnLogEntry,
+    TurnPhase, UndoRequest, VerifiableDice, ITEMS_TO_WIN, MATCH_SEED_LENGTH,
+    MAX_CHAT_MESSAGE_LENGTH,
+};
+use crate::net::{
+    spawn_connect_token_server, transport, ConnectionKind, LocalClientId, ReconnectToken,
+    PROTOCOL_ID, VERSION,
+};
+use crate::replay::ReplayRecorder;
+use crate::rooms::Room;
+use crate::shifting::ShiftingMaze;
+use crate::shutdown;
+use crate::undo::UndoSteps;
+use crate::{Cli, LabyrinthPlugin, ServerArgs};
+
+impl LabyrinthPlugin {
+    /// Opens the server to connections and sets up the game it runs
+    pub(crate) fn server_init(
+        commands: &mut Commands,
+        network_channels: &NetworkChannels,
+        server_args: &ServerArgs,
+        room: Option<&Room>,
+    ) -> Result<(), Box<dyn Error>> {
+        let ServerArgs {
+            port,
+            max_players,
+            max_observers,
+            ref public_ip,
+            ref password,
+            ..
+        } = *server_args;
+        transport::check_sockets_available()?;
+        info!("Starting server version {VERSION} on port {port} with {max_players} players");
+
+        let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        let public_addr = SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), port);
+        let socket = UdpSocket::bind(public_addr)?;
+        // a room's connect tokens are handed out by the room server
+        let private_key = match room {
+            Some(room) => room.private_key,
+            None => {
+                // clients get connect tokens over TCP on the same port, and can't connect
+                // without one
+                let private_key = generate_random_bytes();
+                spawn_connect_token_server(
+                    TcpListener::bind(public_addr)?,
+                    public_ip.clone(),
+                    private_key,
+                    password.clone(),
+                    move |room| {
+                        if room.is_empty() {
+                            Ok(port)
+                        } else {
+                            Err("The server only hosts one game, leave out --room.".to_owned())
+                        }
+                    },
+                );
+                // the room server catches Ctrl+C for all of its rooms
+                shutdown::catch_interrupts();
+                private_key
+            }
+        };
+        let server_config = ServerConfig {
+            current_time,
+            max_clients: max_players as usize + max_observers as usize,
+            protocol_id: PROTOCOL_ID,
+            authentication: ServerAuthentication::Secure { private_key },
+            public_addresses: public_ip
+                .iter()
+                .map(|&ip| SocketAddr::new(ip, port))
+                .collect(),
+        };
+        let transport = NetcodeServerTransport::new(server_config, socket)?;
+
+        #[cfg(feature = "metrics")]
+        if let Some(addr) = server_args.metrics {
+            Self::metrics_init(commands, addr)?;
+        }
+        if let Some(url) = &server_args.announce {
+            info!("Announcing the server to {url}");
+            commands.insert_resource(Announcer::new(url.clone()));
+        }
+        commands.insert_resource(transport);
+        Self::server_init_in_memory(commands, network_channels, server_args)
+    }
+
+    /// Sets up the server without any sockets. Clients can only be connected to it from the same
+    /// process, by adding connections to the [`RenetServer`] directly, which is how tests run it.
+    pub(crate) fn server_init_in_memory(
+        commands: &mut Commands,
+        network_channels: &NetworkChannels,
+        server_args: &ServerArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let ServerArgs {
+            max_observers,
+            reconnect_grace_secs,
+            ..
+        } = *server_args;
+        let server_channels_config = network_channels.get_server_configs();
+        let client_channels_config = network_channels.get_client_configs();
+
+        let server = RenetServer::new(ConnectionConfig {
+            server_channels_config,
+            client_channels_config,
+            ..default()
+        });
+
+        commands.insert_resource(MaxObservers(max_observers as usize));
+        commands.init_resource::<Observers>();
+        commands.insert_resource(ReconnectGracePeriod(Duration::from_secs(
+            reconnect_grace_secs,
+        )));
+        commands.insert_resource(server);
+        Self::server_init_game(commands, server_args)
+    }
+
+    /// Sets up the maze, dice and rules of a game, whether or not anyone connects to play it
+    pub(crate) fn server_init_game(
+        commands: &mut Commands,
+        server_args: &ServerArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let ServerArgs {
+            max_players,
+            tiles,
+            board_size,
+            first_player,
+            item_dealing,
+            ref target_distance,
+            log_targets,
+            ref match_seed,
+            seed,
+            max_turns,
+            max_minutes,
+            mode,
+            pawn_collisions,
+            ref maze_file,
+            maze_algo,
+            verifiable_dice,
+            crash_penalty,
+            momentum,
+            special_cells,
+            fog,
+            shifting_maze,
+            undo,
+            coach_view,
+            ref record,
+            movement,
+            ..
+        } = *server_args;
+
+        match mode {
+            GameMode::Classic => {
+                commands.spawn(DiceBundle::default());
+            }
+            GameMode::Race => {
+                for player_number in 0..max_players as usize {
+                    commands.spawn(DiceBundle {
+                        dice: Dice {
+                            owner: Some(player_number),
+                            ..default()
+                        },
+                        ..default()
+                    });
+                }
+            }
+        }
+
+        commands.spawn(TurnLogBundle::default());
+
+        commands.insert_resource(MaxPlayers(max_players as usize));
+        commands.insert_resource(first_player);
+        commands.insert_resource(mode);
+        commands.insert_resource(pawn_collisions);
+        // for whoever plays in the server's own window, other clients are sent it when they join
+        commands.insert_resource(movement);
+        if let Some(radius) = fog {
+            commands.insert_resource(FogOfWar::new(radius));
+        }
+        if shifting_maze {
+            commands.init_resource::<ShiftingMaze>();
+        }
+        if undo {
+            commands.init_resource::<UndoSteps>();
+        }
+        if coach_view {
+            commands.insert_resource(CoachView);
+        }
+        let match_seed = seed
+            .map(|seed| seed.to_string())
+            .or_else(|| match_seed.clone())
+            .unwrap_or_else(|| {
+                rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(MATCH_SEED_LENGTH)
+                    .map(char::from)
+                    .collect()
+            });
+        info!("Match seed: {match_seed}");
+        let mut rng = GameRng::from_match_seed(&match_seed);
+        commands.insert_resource(MatchSeed(match_seed));
+        if verifiable_dice {
+            rng.verifiable = Some(VerifiableDice {
+                seed: rng.rng.gen(),
+                rolls: 0,
+            });
+        }
+
+        #[cfg(feature = "scripting")]
+        let house_rules = Self::server_load_house_rules(server_args, &mut rng)?;
+        #[cfg(not(feature = "scripting"))]
+        let house_rules = None;
+
+        let maze = match maze_file {
+            Some(maze_file) => {
+                let maze: Maze = std::fs::read_to_string(maze_file)?
+                    .parse()
+                    .map_err(|err| format!("Invalid maze file {}: {err}", maze_file.display()))?;
+                if maze.size != board_size as usize {
+                    warn!(
+                        "Maze file {} is for a {size}x{size} board, ignoring --board-size",
+                        maze_file.display(),
+                        size = maze.size
+                    );
+                }
+                if !maze.is_valid() {
+                    return Err(format!(
+                        "Maze file {} leaves some cells unreachable",
+                        maze_file.display()
+                    )
+                    .into());
+                }
+                maze
+            }
+            None => {
+                let mut maze = Maze::generate(maze_algo, tiles, board_size as usize, &mut rng.rng);
+                if special_cells {
+                    maze.place_special_cells(&mut rng.rng);
+                }
+                info!("Generated maze:\n{maze}");
+                maze
+            }
+        };
+        if let Some(record) = record {
+            info!("Recording replay to {}", record.display());
+            commands.insert_resource(ReplayRecorder::create(record, &maze, movement)?);
+        }
+        commands.insert_resource(maze);
+        commands.insert_resource(rng);
+        let mut available_items = AvailableItems::new(item_dealing, log_targets);
+        if let Some(target_distance) = target_distance {
+            available_items.target_distance = target_distance.clone();
+        }
+        commands.insert_resource(available_items);
+        commands.insert_resource(GameLimits {
+            max_turns,
+            max_time: max_minutes.map(|minutes| Duration::from_secs(minutes * 60)),
+            crash_penalty,
+            momentum,
+            movement,
+            house_rules,
+            ..default()
+        });
+        Ok(())
+    }
+
+    pub(crate) fn server_receive_requests(
+        mut current_turn: ResMut<CurrentTurn>,
+        mut current_turn_writer: EventWriter<ToClients<CurrentTurn>>,
+        turn_phase: Res<State<TurnPhase>>,
+        player_count: Res<MaxPlayers>,
+        mut next_turn_phase: ResMut<NextState<TurnPhase>>,
+        mut turn_phase_writer: EventWriter<ToClients<TurnPhase>>,
+        mut requests: ClientRequests,
+        mut players: Query<&mut Player>,
+        mut move_writer: MoveEventWriter,
+        mut dice: Query<&mut Dice, Without<Player>>,
+        mut rng: ResMut<GameRng>,
+        maze: Res<Maze>,
+        mut available_items: ResMut<AvailableItems>,
+        mut game_limits: ResMut<GameLimits>,
+        pawn_collisions: Res<PawnCollisions>,
+        mut game_over_writer: GameOverWriter,
+    ) {
+        let mut turn_phase = *turn_phase.get();
+        for client_id in requests.rolls() {
+            let Some(player) = players
+                .iter()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                warn!("Client {client_id} asked to roll without a pawn");
+                continue;
+            };
+            if player.player_number != current_turn.0 {
+                move_writer.reject(player, MoveRejectionReason::NotYourTurn);
+                continue;
+            }
+            // only one roll counts per turn, even if several requests arrive in the same frame
+            if turn_phase != TurnPhase::Rolling {
+                move_writer.reject(player, MoveRejectionReason::WrongPhase);
+                continue;
+            }
+            let value = game_limits.movement.roll(&mut rng);
+            dice.single_mut().value = value;
+            move_writer.forget_steps();
+            move_writer.log(TurnLogEntry::Rolled {
+                player_number: player.player_number,
+                value,
+            });
+            next_turn_phase.set(TurnPhase::Moving { steps_taken: 0 });
+            turn_phase_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: TurnPhase::Moving { steps_taken: 0 },
+            });
+            turn_phase = TurnPhase::Moving { steps_taken: 0 }
+        }
+
+        let move_requests = requests.moves();
+        if turn_phase == TurnPhase::Rolling {
+            for &(client_id, _) in &move_requests {
+                let Some(player) = players
+                    .iter()
+                    .find(|player| player.client_id == client_id.raw())
+                else {
+                    warn!("Client {client_id} asked to move without a pawn");
+                    continue;
+                };
+                let reason = if player.player_number == current_turn.0 {
+                    MoveRejectionReason::WrongPhase
+                } else {
+                    MoveRejectionReason::NotYourTurn
+                };
+                move_writer.reject(player, reason);
+            }
+        }
+
+        if let TurnPhase::Moving { steps_taken } = turn_phase {
+            let mut new_steps_taken = steps_taken;
+            let dice_value = dice.single().value;
+            let mut winner = None;
+            let mut collected = false;
+            for &(client_id, event) in &move_requests {
+                let occupied = Self::is_pawn_in_the_way(players.iter(), client_id.raw(), &event);
+                let Some(mut player) = players
+                    .iter_mut()
+                    .find(|player| player.client_id == client_id.raw())
+                else {
+                    warn!("Client {client_id} asked to move without a pawn");
+                    continue;
+                };
+                if player.player_number != current_turn.0 {
+                    move_writer.reject(&player, MoveRejectionReason::NotYourTurn);
+                    continue;
+                }
+                if new_steps_taken >= dice_value + player.bonus_steps {
+                    move_writer.reject(&player, MoveRejectionReason::WrongPhase);
+                    continue;
+                }
+                let outcome = Self::server_move_player(
+                    &mut player,
+                    &event,
+                    &maze,
+                    occupied && *pawn_collisions == PawnCollisions::Block,
+                    &mut available_items,
+                    &mut rng.rng,
+                    &mut move_writer,
+                );
+                let stepped = matches!(
+                    outcome,
+                    MoveOutcome::Moved | MoveOutcome::Collected | MoveOutcome::Trapped
+                );
+                if let MoveOutcome::Moved | MoveOutcome::Trapped = outcome {
+                    // the cell stepped onto, as a teleporter has already moved the pawn off it
+                    if let Some(cell) = maze.special_cell(player.prev_coords + event.delta()) {
+                        game_limits.apply_special_cell_rules(&mut player, cell);
+                    }
+                }
+                let (player_number, coords) = (player.player_number, player.coords);
+                match outcome {
+                    MoveOutcome::OutOfBounds | MoveOutcome::Occupied => {}
+                    MoveOutcome::Crashed => {
+                        // past every step the player had, so the turn ends even if they crashed
+                        // on a bonus step
+                        new_steps_taken = dice_value + player.bonus_steps;
+                        player.bonus_steps = 0;
+                        game_limits.apply_crash_penalty(&mut player);
+                    }
+                    MoveOutcome::Moved => new_steps_taken += 1,
+                    MoveOutcome::Trapped => {
+                        new_steps_taken = dice_value + player.bonus_steps;
+                        player.bonus_steps = 0;
+                    }
+                    MoveOutcome::Collected => {
+                        new_steps_taken += 1;
+                        collected = true;
+                        game_limits.apply_momentum(&mut player);
+                        if let Some(&item) = player.achieved_items.last() {
+                            game_limits.apply_item_rules(&mut player, item);
+                        }
+                    }
+                    MoveOutcome::Won => {
+                        winner = Some(player.player_number);
+                        break;
+                    }
+                }
+                if stepped && *pawn_collisions == PawnCollisions::Bump {
+                    Self::server_bump_pawns(
+                        players.iter_mut(),
+                        player_number,
+                        coords,
+                        maze.size,
+                        &mut move_writer,
+                    );
+                }
+            }
+
+            if let Some(winner) = winner {
+                game_over_writer.send(Some(winner), GameOverReason::ItemsCollected, players.iter());
+                return;
+            }
+            if collected && Self::is_unwinnable(players.iter(), &available_items) {
+                info!("Nobody can collect enough items to win any more");
+                game_over_writer.send(None, GameOverReason::Unwinnable, players.iter());
+                return;
+            }
+
+            let bonus_steps = players
+                .iter()
+                .find(|player| player.player_number == current_turn.0)
+                .map_or(0, |player| player.bonus_steps);
+            if new_steps_taken != steps_taken {
+                if new_steps_taken >= dice_value + bonus_steps {
+                    game_limits.turns_taken += 1;
+                    move_writer.forget_steps();
+                    if game_limits
+                        .max_turns
+                        .is_some_and(|max_turns| game_limits.turns_taken >= max_turns)
+                    {
+                        info!("Turn limit reached");
+                        let winner = Self::find_leader(players.iter(), &mut rng.rng);
+                        game_over_writer.send(
+                            winner,
+                            Self::limit_reason(winner, GameOverReason::TurnLimit),
+                            players.iter(),
+                        );
+                        return;
+                    }
+
+                    let mut extra_roll = false;
+                    if let Some(mut player) = players
+                        .iter_mut()
+                        .find(|player| player.player_number == current_turn.0)
+                    {
+                        player.bonus_steps = 0;
+                        extra_roll = std::mem::take(&mut player.extra_roll);
+                    }
+                    if extra_roll {
+                        info!("Player {} rolls again", current_turn.0);
+                    } else {
+                        Self::advance_turn(
+                            &mut current_turn,
+                            player_count.0,
+                            &mut players.iter_mut().collect::<Vec<_>>(),
+                        );
+                        current_turn_writer.send(ToClients {
+                            mode: SendMode::Broadcast,
+                            event: *current_turn,
+                        });
+                    }
+
+                    next_turn_phase.set(TurnPhase::Rolling);
+                    turn_phase_writer.send(ToClients {
+                        mode: SendMode::Broadcast,
+                        event: TurnPhase::Rolling,
+                    });
+                    turn_phase = TurnPhase::Rolling;
+                } else {
+                    next_turn_phase.set(TurnPhase::Moving {
+                        steps_taken: new_steps_taken,
+                    });
+                    turn_phase_writer.send(ToClients {
+                        mode: SendMode::Broadcast,
+                        event: TurnPhase::Moving {
+                            steps_taken: new_steps_taken,
+                        },
+                    });
+                    turn_phase = TurnPhase::Moving {
+                        steps_taken: new_steps_taken,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Whether another pawn is standing where a player is trying to step
+    fn is_pawn_in_the_way<'a>(
+        players: impl Iterator<Item = &'a Player>,
+        client_id: u64,
+        request: &MoveRequest,
+    ) -> bool {
+        let players: Vec<_> = players.collect();
+        let Some(mover) = players.iter().find(|player| player.client_id == client_id) else {
+            return false;
+        };
+        let next_pos = mover.coords + request.delta();
+        players
+            .iter()
+            .any(|player| player.client_id != client_id && player.coords == next_pos)
+    }
+
+    /// Sends every other pawn on the cell a player just stepped onto back to its start. Clients
+    /// are told with the same animation as a crash, exploding where the pawn was standing.
+    fn server_bump_pawns<'a>(
+        players: impl Iterator<Item = Mut<'a, Player>>,
+        player_number: usize,
+        coords: IVec2,
+        maze_size: usize,
+        move_writer: &mut MoveEventWriter,
+    ) {
+        for mut bumped in players {
+            if bumped.player_number == player_number || bumped.coords != coords {
+                continue;
+            }
+            info!(
+                "Player {} bumped player {} back to their start",
+                player_number, bumped.player_number
+            );
+            move_writer.start_move_anim.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: PlayerStartMoveAnimation {
+                    client_id: bumped.client_id,
+                    fail: true,
+                    move_to: coords,
+                },
+            });
+            move_writer.log(TurnLogEntry::Bumped {
+                player_number: bumped.player_number,
+            });
+            move_writer.forget_steps();
+            bumped.prev_coords = coords;
+            bumped.coords = Self::get_player_start_coords(bumped.player_number, maze_size);
+        }
+    }
+
+    /// Moves a player one step, sending them back to their start if they hit a wall, and deals
+    /// them a new target item if they reach their current one. If another pawn is in the way and
+    /// pawns block each other, the step is refused instead.
+    fn server_move_player(
+        player: &mut Player,
+        request: &MoveRequest,
+        maze: &Maze,
+        blocked_by_pawn: bool,
+        available_items: &mut AvailableItems,
+        rng: &mut impl Rng,
+        move_writer: &mut MoveEventWriter,
+    ) -> MoveOutcome {
+        let next_pos = player.coords + request.delta();
+        if !maze.in_bounds(next_pos) {
+            move_writer.reject(player, MoveRejectionReason::OutOfBounds);
+            return MoveOutcome::OutOfBounds;
+        }
+
+        player.prev_coords = player.coords;
+        if maze.is_blocked(player.coords, next_pos) {
+            move_writer.start_move_anim.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: PlayerStartMoveAnimation {
+                    client_id: player.client_id,
+                    fail: true,
+                    move_to: next_pos,
+                },
+            });
+            move_writer.log(TurnLogEntry::Crashed {
+                player_number: player.player_number,
+            });
+            player.coords = Self::get_player_start_coords(player.player_number, maze.size);
+            player.crashes += 1;
+            move_writer.forget_steps();
+            return MoveOutcome::Crashed;
+        }
+        // checked after the walls, so that a refused step can't be used to find a wall
+        if blocked_by_pawn {
+            move_writer.reject(player, MoveRejectionReason::Occupied);
+            return MoveOutcome::Occupied;
+        }
+
+        move_writer.start_move_anim.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: PlayerStartMoveAnimation {
+                client_id: player.client_id,
+                fail: false,
+                move_to: next_pos,
+            },
+        });
+        player.coords = next_pos;
+        move_writer.log(TurnLogEntry::Moved {
+            player_number: player.player_number,
+            direction: *request,
+        });
+
+        if let Some(target_item) = player.target_item {
+            if player.coords == target_item.coords(maze.size) {
+                player.achieved_items.push(target_item);
+                move_writer.forget_steps();
+                move_writer.log(TurnLogEntry::Collected {
+                    player_number: player.player_number,
+                    item: target_item,
+                });
+                move_writer.item_collected.send(ToClients {
+                    mode: SendMode::Broadcast,
+                    event: ItemCollected {
+                        player_number: player.player_number,
+                        item: target_item,
+                    },
+                });
+                if player.achieved_items.len() >= ITEMS_TO_WIN {
+                    player.target_item = None;
+                    return MoveOutcome::Won;
+                }
+                player.target_item =
+                    available_items.take_target(player.player_number, player.coords, maze, rng);
+                return MoveOutcome::Collected;
+            }
+        }
+        let special_cell = maze.special_cell(player.coords);
+        if special_cell.is_some() {
+            move_writer.forget_steps();
+        }
+        match special_cell {
+            Some(SpecialCell::Teleporter(pair)) => {
+                if let Some(exit) = maze.teleporter_exit(player.coords, pair) {
+                    info!(
+                        "Player {} teleported from {} to {exit}",
+                        player.player_number, player.coords
+                    );
+                    player.coords = exit;
+                    move_writer.log(TurnLogEntry::Teleported {
+                        player_number: player.player_number,
+                    });
+                }
+            }
+            Some(SpecialCell::Trap) => {
+                info!("Player {} stepped onto a trap", player.player_number);
+                move_writer.log(TurnLogEntry::Trapped {
+                    player_number: player.player_number,
+                });
+                return MoveOutcome::Trapped;
+            }
+            Some(SpecialCell::Bonus) => {
+                info!("Player {} earned an extra roll", player.player_number);
+                move_writer.log(TurnLogEntry::ExtraRoll {
+                    player_number: player.player_number,
+                });
+                player.extra_roll = true;
+            }
+            None => move_writer.remember_step(player.prev_coords),
+        }
+        MoveOutcome::Moved
+    }
+
+    /// Race mode: everyone rolls, then all dice are revealed at once and everyone moves at the
+    /// same time. Each player's progress through the round is tracked in their [`RaceState`],
+    /// and the next round starts once every player has finished moving.
+    pub(crate) fn server_receive_race_requests(
+        mut requests: ClientRequests,
+        mut players: Query<(&mut Player, &mut RaceState)>,
+        mut move_writer: MoveEventWriter,
+        mut dice: Query<&mut Dice, Without<Player>>,
+        mut rng: ResMut<GameRng>,
+        maze: Res<Maze>,
+        mut available_items: ResMut<AvailableItems>,
+        mut game_limits: ResMut<GameLimits>,
+        pawn_collisions: Res<PawnCollisions>,
+        mut game_over_writer: GameOverWriter,
+    ) {
+        for client_id in requests.rolls() {
+            let Some((player, mut race_state)) = players
+                .iter_mut()
+                .find(|(player, _)| player.client_id == client_id.raw())
+            else {
+                warn!("Client {client_id} asked to roll without a pawn");
+                continue;
+            };
+            if race_state.phase == TurnPhase::Rolling {
+                race_state.waiting = true;
+            } else {
+                move_writer.reject(&player, MoveRejectionReason::WrongPhase);
+            }
+        }
+        // the round goes on without anyone who might still reconnect
+        if players.iter().all(|(player, race_state)| {
+            player.absent
+                || race_state.phase == TurnPhase::Rolling
+                    && (race_state.waiting || player.skips_next_roll)
+        }) {
+            for mut player_dice in dice.iter_mut() {
+                player_dice.value = game_limits.movement.roll(&mut rng);
+            }
+            move_writer.forget_steps();
+            for (mut player, mut race_state) in players.iter_mut() {
+                *race_state = RaceState {
+                    phase: TurnPhase::Moving { steps_taken: 0 },
+                    waiting: player.skips_next_roll,
+                };
+                let player_dice = dice
+                    .iter()
+                    .find(|dice| dice.owner == Some(player.player_number));
+                if let (false, Some(player_dice)) = (player.skips_next_roll, player_dice) {
+                    move_writer.log(TurnLogEntry::Rolled {
+                        player_number: player.player_number,
+                        value: player_dice.value,
+                    });
+                }
+                if player.skips_next_roll {
+                    player.skips_next_roll = false;
+                    info!(
+                        "Player {} misses a round for crashing",
+                        player.player_number
+                    );
+                }
+            }
+        }
+
+        let mut collected = false;
+        // process moves in player number order so that simultaneous wins are settled fairly
+        let mut move_requests: Vec<_> = requests
+            .moves()
+            .into_iter()
+            .filter_map(|(client_id, event)| {
+                let player = players
+                    .iter()
+                    .find(|(player, _)| player.client_id == client_id.raw());
+                if player.is_none() {
+                    warn!("Client {client_id} asked to move without a pawn");
+                }
+                player.map(|(player, _)| (player.player_number, event))
+            })
+            .collect();
+        move_requests.sort_by_key(|(player_number, _)| *player_number);
+
+        for (player_number, event) in move_requests {
+            let occupied = players
+                .iter()
+                .find(|(player, _)| player.player_number == player_number)
+                .is_some_and(|(player, _)| {
+                    Self::is_pawn_in_the_way(
+                        players.iter().map(|(player, _)| player),
+                        player.client_id,
+                        &event,
+                    )
+                });
+            let Some((mut player, mut race_state)) = players
+                .iter_mut()
+                .find(|(player, _)| player.player_number == player_number)
+            else {
+                continue;
+            };
+            let TurnPhase::Moving { steps_taken } = race_state.phase else {
+                move_writer.reject(&player, MoveRejectionReason::WrongPhase);
+                continue;
+            };
+            if race_state.waiting {
+                move_writer.reject(&player, MoveRejectionReason::WrongPhase);
+                continue;
+            }
+            let Some(dice_value) = dice
+                .iter()
+                .find(|dice| dice.owner == Some(player_number))
+                .map(|dice| dice.value)
+            else {
+                continue;
+            };
+
+            let outcome = Self::server_move_player(
+                &mut player,
+                &event,
+                &maze,
+                occupied && *pawn_collisions == PawnCollisions::Block,
+                &mut available_items,
+                &mut rng.rng,
+                &mut move_writer,
+            );
+            let stepped = matches!(
+                outcome,
+                MoveOutcome::Moved | MoveOutcome::Collected | MoveOutcome::Trapped
+            );
+            if let MoveOutcome::Moved | MoveOutcome::Trapped = outcome {
+                if let Some(cell) = maze.special_cell(player.prev_coords + event.delta()) {
+                    game_limits.apply_special_cell_rules(&mut player, cell);
+                }
+            }
+            let steps_taken = match outcome {
+                MoveOutcome::OutOfBounds | MoveOutcome::Occupied => continue,
+                MoveOutcome::Crashed => {
+                    player.bonus_steps = 0;
+                    game_limits.apply_crash_penalty(&mut player);
+                    dice_value
+                }
+                MoveOutcome::Moved => steps_taken + 1,
+                MoveOutcome::Trapped => {
+                    let steps_taken = dice_value + player.bonus_steps;
+                    player.bonus_steps = 0;
+                    steps_taken
+                }
+                MoveOutcome::Collected => {
+                    collected = true;
+                    game_limits.apply_momentum(&mut player);
+                    if let Some(&item) = player.achieved_items.last() {
+                        game_limits.apply_item_rules(&mut player, item);
+                    }
+                    steps_taken + 1
+                }
+                MoveOutcome::Won => {
+                    game_over_writer.send(
+                        Some(player_number),
+                        GameOverReason::ItemsCollected,
+                        players.iter().map(|(player, _)| player),
+                    );
+                    return;
+                }
+            };
+            *race_state = RaceState {
+                phase: TurnPhase::Moving { steps_taken },
+                waiting: steps_taken >= dice_value + player.bonus_steps,
+            };
+            if stepped && *pawn_collisions == PawnCollisions::Bump {
+                let coords = player.coords;
+                Self::server_bump_pawns(
+                    players.iter_mut().map(|(player, _)| player),
+                    player_number,
+                    coords,
+                    maze.size,
+                    &mut move_writer,
+                );
+            }
+        }
+
+        if collected
+            && Self::is_unwinnable(players.iter().map(|(player, _)| player), &available_items)
+        {
+            info!("Nobody can collect enough items to win any more");
+            game_over_writer.send(
+                None,
+                GameOverReason::Unwinnable,
+                players.iter().map(|(player, _)| player),
+            );
+            return;
+        }
+
+        if players.iter().all(|(player, race_state)| {
+            player.absent
+                || matches!(race_state.phase, TurnPhase::Moving { .. }) && race_state.waiting
+        }) {
+            game_limits.turns_taken += 1;
+            if game_limits
+                .max_turns
+                .is_some_and(|max_turns| game_limits.turns_taken >= max_turns)
+            {
+                info!("Turn limit reached");
+                let winner =
+                    Self::find_leader(players.iter().map(|(player, _)| player), &mut rng.rng);
+                game_over_writer.send(
+                    winner,
+                    Self::limit_reason(winner, GameOverReason::TurnLimit),
+                    players.iter().map(|(player, _)| player),
+                );
+                return;
+            }
+            for (mut player, mut race_state) in players.iter_mut() {
+                if player.bonus_steps != 0 {
+                    player.bonus_steps = 0;
+                }
+                // everyone rolls every round anyway, so bonus cells don't do anything in a race
+                if player.extra_roll {
+                    player.extra_roll = false;
+                }
+                *race_state = RaceState::default();
+            }
+        }
+    }
+
+    pub(crate) fn server_commit_dice_seed(
+        rng: Res<GameRng>,
+        mut dice_commitment_writer: EventWriter<ToClients<DiceCommitment>>,
+    ) {
+        if let Some(verifiable_dice) = &rng.verifiable {
+            let hash = blake3::hash(&verifiable_dice.seed);
+            info!("Committing to dice seed hash {hash}");
+            dice_commitment_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: DiceCommitment {
+                    hash: *hash.as_bytes(),
+                },
+            });
+        }
+    }
+
+    pub(crate) fn server_reveal_dice_seed(
+        rng: Res<GameRng>,
+        mut dice_seed_reveal_writer: EventWriter<ToClients<DiceSeedReveal>>,
+    ) {
+        if let Some(verifiable_dice) = &rng.verifiable {
+            info!(
+                "Revealing dice seed {} after {} rolls",
+                to_hex(&verifiable_dice.seed),
+                verifiable_dice.rolls
+            );
+            dice_seed_reveal_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: DiceSeedReveal {
+                    seed: verifiable_dice.seed,
+                    rolls: verifiable_dice.rolls,
+                },
+            });
+        }
+    }
+
+    pub(crate) fn server_update_time_limit(
+        time: Res<Time>,
+        mut game_limits: ResMut<GameLimits>,
+        players: Query<&Player>,
+        mut rng: ResMut<GameRng>,
+        mut game_over_writer: GameOverWriter,
+    ) {
+        let Some(max_time) = game_limits.max_time else {
+            return;
+        };
+        game_limits.elapsed += time.delta();
+        if game_limits.elapsed >= max_time {
+            info!("Time limit reached");
+            let winner = Self::find_leader(players.iter(), &mut rng.rng);
+            game_over_writer.send(
+                winner,
+                Self::limit_reason(winner, GameOverReason::TimeLimit),
+                players.iter(),
+            );
+        }
+    }
+
+    /// Why the game ended when a turn or time limit was reached. With nobody left to win, it's as
+    /// if the server had ended the game.
+    fn limit_reason(winner: Option<usize>, limit: GameOverReason) -> GameOverReason {
+        match winner {
+            Some(_) => limit,
+            None => GameOverReason::ServerEnded,
+        }
+    }
+
+    pub(crate) fn server_receive_chat(
+        mut chat_events: EventReader<FromClient<ChatMessage>>,
+        players: Query<&Player>,
+        mut chat_writer: EventWriter<ToClients<ChatBroadcast>>,
+    ) {
+        for FromClient { client_id, event } in chat_events.read() {
+            let text: String = event
+                .0
+                .trim()
+                .chars()
+                .take(MAX_CHAT_MESSAGE_LENGTH)
+                .collect();
+            if text.is_empty() {
+                continue;
+            }
+            let player_number = players
+                .iter()
+                .find(|player| player.client_id == client_id.raw())
+                .map(|player| player.player_number);
+            chat_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: ChatBroadcast {
+                    player_number,
+                    text,
+                },
+            });
+        }
+    }
+
+    /// Starts the game once the lobby is full and every player is ready
+    pub(crate) fn server_receive_ready(
+        mut ready_events: EventReader<FromClient<ReadyToggle>>,
+        mut players: Query<&mut Player>,
+        max_players: Res<MaxPlayers>,
+        first_player: Res<FirstPlayer>,
+        mut rng: ResMut<GameRng>,
+        mut current_turn: ResMut<CurrentTurn>,
+        mut current_turn_writer: EventWriter<ToClients<CurrentTurn>>,
+        mut game_state: ResMut<NextState<GameState>>,
+        mut game_state_writer: EventWriter<ToClients<GameState>>,
+    ) {
+        let mut any_toggled = false;
+        for FromClient { client_id, .. } in ready_events.read() {
+            let Some(mut player) = players
+                .iter_mut()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                continue;
+            };
+            player.ready = !player.ready;
+            any_toggled = true;
+            info!(
+                "Player {} is {}",
+                player.player_number,
+                if player.ready { "ready" } else { "not ready" }
+            );
+        }
+        if !any_toggled
+            || players.iter().count() != max_players.0
+            || !players.iter().all(|player| player.ready)
+        {
+            return;
+        }
+
+        info!("Everyone is ready, starting the game");
+        current_turn.0 = first_player.pick(max_players.0, &mut rng.rng);
+        current_turn_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: *current_turn,
+        });
+        game_state.set(GameState::InGame);
+        game_state_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: GameState::InGame,
+        });
+    }
+
+    /// Switches to the next movement rule when a player asks for it in the lobby, and makes
+    /// everyone ready up again under the new rule
+    pub(crate) fn server_receive_movement_changes(
+        mut change_events: EventReader<FromClient<MovementChange>>,
+        mut players: Query<&mut Player>,
+        mut game_limits: ResMut<GameLimits>,
+        mut movement_writer: EventWriter<ToClients<Movement>>,
+    ) {
+        let mut changed = false;
+        for FromClient { client_id, .. } in change_events.read() {
+            // observers watch the game, they don't get a say in its rules
+            let Some(player) = players
+                .iter()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                continue;
+            };
+            game_limits.movement = game_limits.movement.next();
+            changed = true;
+            info!(
+                "Player {} changed the movement rule to {}",
+                player.player_number, game_limits.movement
+            );
+        }
+        if !changed {
+            return;
+        }
+        for mut player in players.iter_mut() {
+            player.ready = false;
+        }
+        movement_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: game_limits.movement,
+        });
+    }
+
+    /// Starts a new round on a new maze once everyone still in the game has asked for a rematch,
+    /// keeping the same seats and connections
+    pub(crate) fn server_receive_rematch(
+        mut commands: Commands,
+        mut rematch_events: EventReader<FromClient<RematchToggle>>,
+        mut players: Query<(&mut Player, Option<&mut RaceState>)>,
+        mut round_reset: RoundReset,
+        mut new_round_writer: NewRoundWriter,
+    ) {
+        let mut any_toggled = false;
+        for FromClient { client_id, .. } in rematch_events.read() {
+            let Some((mut player, _)) = players
+                .iter_mut()
+                .find(|(player, _)| player.client_id == client_id.raw())
+            else {
+                continue;
+            };
+            player.wants_rematch = !player.wants_rematch;
+            any_toggled = true;
+            info!(
+                "Player {} {} a rematch",
+                player.player_number,
+                if player.wants_rematch {
+                    "wants"
+                } else {
+                    "no longer wants"
+                }
+            );
+        }
+        if !any_toggled
+            || !players
+                .iter()
+                .all(|(player, _)| player.wants_rematch || player.absent)
+        {
+            return;
+        }
+        info!("Everyone wants a rematch, starting a new round");
+        let mut players: Vec<_> = players.iter_mut().collect();
+        if round_reset.reset(&mut players) {
+            commands.remove_resource::<GameResult>();
+            new_round_writer.send(&round_reset.maze, *round_reset.current_turn);
+        }
+    }
+
+    pub(crate) fn server_on_events(
+        mut commands: Commands,
+        mut events: EventReader<ServerEvent>,
+        players: Query<&Player, Without<AwaitingReconnect>>,
+        mut reconnecting_players: Query<
+            (Entity, &mut Player, &ReconnectToken),
+            With<AwaitingReconnect>,
+        >,
+        mut connections: Connections,
+        mut available_items: ResMut<AvailableItems>,
+        current_game_state: Res<State<GameState>>,
+        mut rng: ResMut<GameRng>,
+        maze: Res<Maze>,
+        mut maze_writer: EventWriter<ToClients<Maze>>,
+        mut reconnect_token_writer: EventWriter<ToClients<ReconnectToken>>,
+        game_mode: Res<GameMode>,
+        mut request_budgets: ResMut<RequestBudgets>,
+    ) {
+        for event in events.read() {
+            match event {
+                ServerEvent::ClientConnected { client_id } => {
+                    if let Some((entity_id, mut player, &token)) =
+                        connections.reconnect_token(*client_id).and_then(|token| {
+                            reconnecting_players
+                                .iter_mut()
+                                .find(|(_, _, &player_token)| player_token == token)
+                        })
+                    {
+                        info!(
+                            "Client {client_id} reconnected as player {}",
+                            player.player_number
+                        );
+                        player.client_id = client_id.raw();
+                        player.absent = false;
+                        commands.entity(entity_id).remove::<AwaitingReconnect>();
+                        maze_writer.send(ToClients {
+                            mode: SendMode::Direct(*client_id),
+                            event: maze.clone(),
+                        });
+                        reconnect_token_writer.send(ToClients {
+                            mode: SendMode::Direct(*client_id),
+                            event: token,
+                        });
+                        continue;
+                    }
+
+                    let num_existing_players = players.iter().count();
+                    // seats can be left empty by players who disconnected before the game started
+                    let player_number = (0..connections.max_players.0)
+                        .find(|&player_number| {
+                            !players
+                                .iter()
+                                .any(|player| player.player_number == player_number)
+                        })
+                        .unwrap_or(num_existing_players);
+                    // players who can't get a seat watch instead, if there's room for them
+                    let no_seat_reason = if num_existing_players >= connections.max_players.0 {
+                        Some("the game is full")
+                    } else if *current_game_state.get() != GameState::WaitingPlayers {
+                        Some("the game has already started")
+                    } else {
+                        None
+                    };
+                    if connections.is_observer(*client_id) || no_seat_reason.is_some() {
+                        if connections.observers.0.len() >= connections.max_observers.0 {
+                            match no_seat_reason {
+                                Some(reason) => {
+                                    info!("Client {client_id} tried to join, but {reason}")
+                                }
+                                None => {
+                                    info!(
+                                        "Client {client_id} tried to observe, but there is no room"
+                                    )
+                                }
+                            }
+                            connections.server.disconnect(*client_id);
+                            continue;
+                        }
+                        match no_seat_reason {
+                            Some(reason) => {
+                                info!("Client {client_id} is observing because {reason}")
+                            }
+                            None => info!("Client {client_id} connected as an observer"),
+                        }
+                        connections.observers.0.insert(*client_id);
+                    } else {
+                        info!("Client {client_id} connected");
+                    }
+                    maze_writer.send(ToClients {
+                        mode: SendMode::Direct(*client_id),
+                        event: maze.clone(),
+                    });
+                    if connections.observers.0.contains(client_id) {
+                        continue;
+                    }
+
+                    let entity_id = Self::server_spawn_player(
+                        &mut commands,
+                        *client_id,
+                        player_number,
+                        connections.player_name(*client_id),
+                        &mut available_items,
+                        &maze,
+                        &mut rng,
+                        *game_mode,
+                    );
+                    let token = ReconnectToken(rand::thread_rng().gen_range(1..=u64::MAX));
+                    commands.entity(entity_id).insert(token);
+                    reconnect_token_writer.send(ToClients {
+                        mode: SendMode::Direct(*client_id),
+                        event: token,
+                    });
+                }
+                ServerEvent::ClientDisconnected { client_id, reason } => {
+                    info!("Client {client_id} disconnected: {reason}");
+                    connections.observers.0.remove(client_id);
+                    request_budgets.0.remove(client_id);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn server_spawn_player(
+        commands: &mut Commands,
+        client_id: ClientId,
+        player_number: usize,
+        name: Option<String>,
+        available_items: &mut AvailableItems,
+        maze: &Maze,
+        rng: &mut GameRng,
+        game_mode: GameMode,
+    ) -> Entity {
+        let coords = Self::get_player_start_coords(player_number, maze.size);
+        let mut player = commands.spawn(PlayerBundle {
+            player: Player {
+                client_id: client_id.raw(),
+                coords,
+                prev_coords: coords,
+                player_number,
+                target_item: available_items.take_target(player_number, coords, maze, &mut rng.rng),
+                name,
+                ..default()
+            },
+            ..default()
+        });
+        if game_mode == GameMode::Race {
+            player.insert(RaceState::default());
+        }
+        player.id()
+    }
+
+    /// Seats the host as the first player. They don't connect like everyone else, so they get
+    /// the maze through local events and never need a reconnect token.
+    pub(crate) fn server_spawn_host(
+        mut commands: Commands,
+        cli: Res<Cli>,
+        mut available_items: ResMut<AvailableItems>,
+        maze: Res<Maze>,
+        mut rng: ResMut<GameRng>,
+        game_mode: Res<GameMode>,
+        mut maze_writer: EventWriter<ToClients<Maze>>,
+    ) {
+        let Cli::Host { ref options, .. } = *cli else {
+            return;
+        };
+        info!("Hosting as player 0");
+        // cleaned up the same way as the names of players who connect
+        let name = ConnectionKind::name_from_user_data(&ConnectionKind::user_data(
+            false,
+            options.name.as_deref(),
+            None,
+        ));
+        Self::server_spawn_player(
+            &mut commands,
+            SERVER_ID,
+            0,
+            name,
+            &mut available_items,
+            &maze,
+            &mut rng,
+            *game_mode,
+        );
+        maze_writer.send(ToClients {
+            mode: SendMode::Direct(SERVER_ID),
+            event: maze.clone(),
+        });
+    }
+
+    /// Players who drop out during the game keep their pawn for a grace period in case they
+    /// reconnect, otherwise they leave the game straight away
+    pub(crate) fn server_on_player_disconnected(
+        mut commands: Commands,
+        mut events: EventReader<ServerEvent>,
+        mut players: Query<(Entity, &mut Player)>,
+        game_state: Res<State<GameState>>,
+        game_mode: Res<GameMode>,
+        max_players: Res<MaxPlayers>,
+        grace_period: Res<ReconnectGracePeriod>,
+        mut current_turn: ResMut<CurrentTurn>,
+        mut next_turn_writer: NextTurnWriter,
+        mut player_left_writer: EventWriter<ToClients<PlayerLeft>>,
+    ) {
+        for event in events.read() {
+            let ServerEvent::ClientDisconnected { client_id, .. } = event else {
+                continue;
+            };
+            // observers and turned away players leave the game unaffected
+            let Some((entity_id, mut player)) = players
+                .iter_mut()
+                .find(|(_, player)| player.client_id == client_id.raw())
+            else {
+                continue;
+            };
+            let in_game = matches!(game_state.get(), GameState::InGame | GameState::Paused);
+            if in_game && !grace_period.0.is_zero() {
+                info!(
+                    "Player {} disconnected, waiting {}s for them to reconnect",
+                    player.player_number,
+                    grace_period.0.as_secs()
+                );
+                player.absent = true;
+                commands
+                    .entity(entity_id)
+                    .insert(AwaitingReconnect(Timer::new(
+                        grace_period.0,
+                        TimerMode::Once,
+                    )));
+                player_left_writer.send(ToClients {
+                    mode: SendMode::Broadcast,
+                    event: PlayerLeft {
+                        player_number: player.player_number,
+                        for_good: false,
+                    },
+                });
+                // nobody waits for them to take their turn
+                if *game_mode == GameMode::Classic && player.player_number == current_turn.0 {
+                    let mut players: Vec<_> =
+                        players.iter_mut().map(|(_, player)| player).collect();
+                    Self::advance_turn(&mut current_turn, max_players.0, &mut players);
+                    next_turn_writer.send(*current_turn);
+                }
+            } else {
+                commands.entity(entity_id).insert(LeftGame);
+            }
+        }
+    }
+
+    pub(crate) fn server_update_reconnect_grace(
+        mut commands: Commands,
+        time: Res<Time>,
+        mut awaiting_reconnect: Query<(Entity, &Player, &mut AwaitingReconnect)>,
+    ) {
+        for (entity_id, player, mut awaiting_reconnect) in awaiting_reconnect.iter_mut() {
+            if awaiting_reconnect.0.tick(time.delta()).just_finished() {
+                info!("Player {} did not reconnect in time", player.player_number);
+                commands
+                    .entity(entity_id)
+                    .remove::<AwaitingReconnect>()
+                    .insert(LeftGame);
+            }
+        }
+    }
+
+    pub(crate) fn server_remove_left_players(
+        mut commands: Commands,
+        mut players: Query<(Entity, &mut Player, Has<LeftGame>)>,
+        dice: Query<(Entity, &Dice)>,
+        game_state: Res<State<GameState>>,
+        game_mode: Res<GameMode>,
+        max_players: Res<MaxPlayers>,
+        mut current_turn: ResMut<CurrentTurn>,
+        mut next_turn_writer: NextTurnWriter,
+        mut player_left_writer: EventWriter<ToClients<PlayerLeft>>,
+        mut available_items: ResMut<AvailableItems>,
+        mut game_over_writer: GameOverWriter,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        let mut current_player_left = false;
+        for (entity_id, player, left) in players.iter() {
+            if !left {
+                continue;
+            }
+            info!("Player {} left the game", player.player_number);
+            commands.entity(entity_id).despawn();
+            player_left_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: PlayerLeft {
+                    player_number: player.player_number,
+                    for_good: true,
+                },
+            });
+            for (dice_entity_id, dice) in dice.iter() {
+                if dice.owner == Some(player.player_number) {
+                    commands.entity(dice_entity_id).despawn();
+                }
+            }
+            if let Some(target_item) = player.target_item {
+                available_items.give_back(target_item);
+            }
+            current_player_left |= player.player_number == current_turn.0;
+        }
+
+        let mut remaining: Vec<_> = players
+            .iter_mut()
+            .filter(|(_, _, left)| !left)
+            .map(|(_, player, _)| player)
+            .collect();
+        let game_started = *game_state.get() != GameState::WaitingPlayers;
+        let remaining_count = remaining.len();
+
+        match *game_state.get() {
+            // the seat is free for someone else to take before the game starts
+            GameState::WaitingPlayers => {}
+            GameState::InGame | GameState::Paused => match remaining_count {
+                0 => game_over_writer.send(None, GameOverReason::ServerEnded, std::iter::empty()),
+                1 => game_over_writer.send(
+                    Some(remaining[0].player_number),
+                    GameOverReason::LastPlayerStanding,
+                    remaining.iter().map(|player| &**player),
+                ),
+                _ => {
+                    if *game_mode == GameMode::Classic && current_player_left {
+                        // anything the player had left of their move goes with them
+                        Self::advance_turn(&mut current_turn, max_players.0, &mut remaining);
+                        next_turn_writer.send(*current_turn);
+                    }
+                }
+            },
+            GameState::Ended => {}
+        }
+
+        if game_started && remaining_count == 0 {
+            info!("Stopping server");
+            app_exit_events.send(AppExit);
+        }
+    }
+
+    /// Sends a snapshot of the state that was broadcast before they joined to observers and
+    /// reconnecting players: the game state, turn and phase during the game, the dice commitment
+    /// if there is one, and the result once it's over. The maze is sent as they connect, and
+    /// everything else is replicated to them like any other client.
+    pub(crate) fn server_catch_up_clients(
+        mut events: EventReader<ServerEvent>,
+        observers: Res<Observers>,
+        players: Query<&Player>,
+        game_state: Res<State<GameState>>,
+        turn_phase: Res<State<TurnPhase>>,
+        current_turn: Res<CurrentTurn>,
+        rng: Res<GameRng>,
+        game_limits: Res<GameLimits>,
+        game_result: Option<Res<GameResult>>,
+        match_seed: Option<Res<MatchSeed>>,
+        mut snapshot: StateSnapshotWriter,
+    ) {
+        for event in events.read() {
+            let ServerEvent::ClientConnected { client_id } = event else {
+                continue;
+            };
+            let is_player = players
+                .iter()
+                .any(|player| player.client_id == client_id.raw());
+            if !observers.0.contains(client_id) && !is_player {
+                continue;
+            }
+            let mode = SendMode::Direct(*client_id);
+            snapshot.movement.send(ToClients {
+                mode,
+                event: game_limits.movement,
+            });
+            match game_state.get() {
+                GameState::InGame | GameState::Paused => {
+                    snapshot.game_state.send(ToClients {
+                        mode,
+                        event: *game_state.get(),
+                    });
+                    snapshot.turn_phase.send(ToClients {
+                        mode,
+                        event: *turn_phase.get(),
+                    });
+                    snapshot.current_turn.send(ToClients {
+                        mode,
+                        event: *current_turn,
+                    });
+                    if let Some(verifiable_dice) = &rng.verifiable {
+                        snapshot.dice_commitment.send(ToClients {
+                            mode,
+                            event: DiceCommitment {
+                                hash: *blake3::hash(&verifiable_dice.seed).as_bytes(),
+                            },
+                        });
+                    }
+                }
+                GameState::Ended => {
+                    let Some(&game_result) = game_result.as_deref() else {
+                        continue;
+                    };
+                    snapshot.game_state.send(ToClients {
+                        mode,
+                        event: GameState::Ended,
+                    });
+                    snapshot.game_over.send(ToClients {
+                        mode,
+                        event: GameOver::new(
+                            game_result,
+                            players.iter(),
+                            match_seed.as_ref().map(|match_seed| match_seed.0.clone()),
+                        ),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The events that make up the snapshot a client is sent when it joins partway through
+#[derive(SystemParam)]
+pub(crate) struct StateSnapshotWriter<'w> {
+    game_state: EventWriter<'w, ToClients<GameState>>,
+    turn_phase: EventWriter<'w, ToClients<TurnPhase>>,
+    current_turn: EventWriter<'w, ToClients<CurrentTurn>>,
+    dice_commitment: EventWriter<'w, ToClients<DiceCommitment>>,
+    game_over: EventWriter<'w, ToClients<GameOver>>,
+    movement: EventWriter<'w, ToClients<Movement>>,
+}
+
+/// How long a disconnected player's pawn is kept for them to reconnect
+#[derive(Resource)]
+pub(crate) struct ReconnectGracePeriod(pub(crate) Duration);
+
+/// On a player whose connection dropped, until they reconnect or the timer runs out
+#[derive(Component)]
+pub(crate) struct AwaitingReconnect(pub(crate) Timer);
+
+/// On a player who has left the game for good, to be removed from it
+#[derive(Component)]
+pub(crate) struct LeftGame;
+
+#[derive(Resource)]
+pub(crate) struct MaxPlayers(pub(crate) usize);
+
+#[derive(Resource)]
+pub(crate) struct MaxObservers(pub(crate) usize);
+
+/// Clients watching the game, who never get a [`Player`]
+#[derive(Resource, Default)]
+pub(crate) struct Observers(pub(crate) HashSet<ClientId>);
+
+/// The connection limits for players and observers, and what's needed to enforce them
+#[derive(SystemParam)]
+pub(crate) struct Connections<'w> {
+    pub(crate) server: ResMut<'w, RenetServer>,
+    /// Missing from servers run in memory, whose clients are all players without names
+    pub(crate) transport: Option<Res<'w, NetcodeServerTransport>>,
+    pub(crate) max_players: Res<'w, MaxPlayers>,
+    pub(crate) max_observers: Res<'w, MaxObservers>,
+    pub(crate) observers: ResMut<'w, Observers>,
+}
+
+impl Connections<'_> {
+    fn is_observer(&self, client_id: ClientId) -> bool {
+        self.user_data(client_id).is_some_and(|user_data| {
+            ConnectionKind::from_user_data(&user_data) == ConnectionKind::Observer
+        })
+    }
+
+    fn user_data(&self, client_id: ClientId) -> Option<[u8; NETCODE_USER_DATA_BYTES]> {
+        self.transport.as_ref()?.user_data(client_id)
+    }
+
+    pub(crate) fn reconnect_token(&self, client_id: ClientId) -> Option<ReconnectToken> {
+        self.user_data(client_id)
+            .and_then(|user_data| ConnectionKind::reconnect_token_from_user_data(&user_data))
+    }
+
+    fn player_name(&self, client_id: ClientId) -> Option<String> {
+        self.user_data(client_id)
+            .and_then(|user_data| ConnectionKind::name_from_user_data(&user_data))
+    }
+}
+
+#[derive(SystemParam)]
+pub(crate) struct MoveEventWriter<'w, 's> {
+    pub(crate) start_move_anim: EventWriter<'w, ToClients<PlayerStartMoveAnimation>>,
+    pub(crate) move_rejected: EventWriter<'w, ToClients<MoveRejected>>,
+    pub(crate) item_collected: EventWriter<'w, ToClients<ItemCollected>>,
+    pub(crate) local_client_id: Option<Res<'w, LocalClientId>>,
+    pub(crate) turn_log: Query<'w, 's, &'static mut TurnLog>,
+    pub(crate) undo_steps: Option<ResMut<'w, UndoSteps>>,
+}
+
+impl MoveEventWriter<'_, '_> {
+    /// Practice games don't keep a turn log
+    pub(crate) fn log(&mut self, entry: TurnLogEntry) {
+        if let Ok(mut turn_log) = self.turn_log.get_single_mut() {
+            turn_log.push(entry);
+        }
+    }
+
+    /// Remembers where a plain step started from, so that it can be taken back with `--undo`
+    pub(crate) fn remember_step(&mut self, from: IVec2) {
+        if let Some(ref mut undo_steps) = self.undo_steps {
+            undo_steps.from.push(from);
+        }
+    }
+
+    /// Called on every roll, and whenever a step does more than move the pawn, since there's no
+    /// taking back a crash, an item, a special cell or a bump
+    pub(crate) fn forget_steps(&mut self) {
+        if let Some(ref mut undo_steps) = self.undo_steps {
+            undo_steps.from.clear();
+        }
+    }
+
+    pub(crate) fn reject(&mut self, player: &Player, reason: MoveRejectionReason) {
+        info!(
+            "Rejected move from player {}: {reason:?}",
+            player.player_number
+        );
+        // whoever is playing in the server's own window is told through local events
+        let client_id = match self.local_client_id {
+            Some(ref local_client_id) if local_client_id.0 == player.client_id => SERVER_ID,
+            _ => ClientId::from_raw(player.client_id),
+        };
+        self.move_rejected.send(ToClients {
+            mode: SendMode::Direct(client_id),
+            event: MoveRejected { reason },
+        });
+    }
+}
+
+/// How many move and roll requests a client can send at once before they're dropped. Sending a
+/// planned path uses one per step, which is well under this.
+const REQUEST_BURST: f32 = 20.0;
+/// How many requests per second a client's budget recovers by
+const REQUEST_RATE: f32 = 10.0;
+
+/// Each client's allowance of move and roll requests, so that a client flooding the server with
+/// them can't slow it down for everyone else
+#[derive(Resource, Default)]
+pub(crate) struct RequestBudgets(HashMap<ClientId, RequestBudget>);
+
+struct RequestBudget {
+    remaining: f32,
+    updated: Duration,
+    /// How many requests have been dropped since the client last had budget to spare
+    dropped: u32,
+}
+
+impl RequestBudgets {
+    /// Takes a request out of the client's budget, or returns false if there's none left
+    fn spend(&mut self, client_id: ClientId, now: Duration) -> bool {
+        let budget = self.0.entry(client_id).or_insert(RequestBudget {
+            remaining: REQUEST_BURST,
+            updated: now,
+            dropped: 0,
+        });
+        budget.remaining = (budget.remaining + (now - budget.updated).as_secs_f32() * REQUEST_RATE)
+            .min(REQUEST_BURST);
+        budget.updated = now;
+        if budget.remaining < 1.0 {
+            if budget.dropped == 0 {
+                warn!("Client {client_id} is sending requests faster than anyone could play, dropping them");
+            }
+            budget.dropped += 1;
+            return false;
+        }
+        budget.remaining -= 1.0;
+        if budget.dropped != 0 {
+            warn!(
+                "Client {client_id} slowed down after {} requests were dropped",
+                budget.dropped
+            );
+            budget.dropped = 0;
+        }
+        true
+    }
+}
+
+/// The move and roll requests clients have sent, without the ones over their request budget
+#[derive(SystemParam)]
+pub(crate) struct ClientRequests<'w, 's> {
+    move_requests: EventReader<'w, 's, FromClient<MoveRequest>>,
+    roll_requests: EventReader<'w, 's, FromClient<DiceRollRequest>>,
+    shift_requests: EventReader<'w, 's, FromClient<ShiftRequest>>,
+    undo_requests: EventReader<'w, 's, FromClient<UndoRequest>>,
+    pause_requests: EventReader<'w, 's, FromClient<PauseToggle>>,
+    budgets: ResMut<'w, RequestBudgets>,
+    time: Res<'w, Time>,
+    local_client_id: Option<Res<'w, LocalClientId>>,
+}
+
+impl ClientRequests<'_, '_> {
+    fn rolls(&mut self) -> Vec<ClientId> {
+        let now = self.time.elapsed();
+        let local_client_id = self.local_client_id.as_deref().copied();
+        self.roll_requests
+            .read()
+            .map(|request| Self::sender(request.client_id, local_client_id))
+            .filter(|&client_id| self.budgets.spend(client_id, now))
+            .collect()
+    }
+
+    fn moves(&mut self) -> Vec<(ClientId, MoveRequest)> {
+        let now = self.time.elapsed();
+        let local_client_id = self.local_client_id.as_deref().copied();
+        self.move_requests
+            .read()
+            .map(|request| {
+                (
+                    Self::sender(request.client_id, local_client_id),
+                    request.event,
+                )
+            })
+            .filter(|&(client_id, _)| self.budgets.spend(client_id, now))
+            .collect()
+    }
+
+    pub(crate) fn pause_toggles(&mut self) -> Vec<ClientId> {
+        let now = self.time.elapsed();
+        let local_client_id = self.local_client_id.as_deref().copied();
+        self.pause_requests
+            .read()
+            .map(|request| Self::sender(request.client_id, local_client_id))
+            .filter(|&client_id| self.budgets.spend(client_id, now))
+            .collect()
+    }
+
+    pub(crate) fn shifts(&mut self) -> Vec<(ClientId, ShiftRequest)> {
+        let now = self.time.elapsed();
+        let local_client_id = self.local_client_id.as_deref().copied();
+        self.shift_requests
+            .read()
+            .map(|request| {
+                (
+                    Self::sender(request.client_id, local_client_id),
+                    request.event,
+                )
+            })
+            .filter(|&(client_id, _)| self.budgets.spend(client_id, now))
+            .collect()
+    }
+
+    pub(crate) fn undos(&mut self) -> Vec<ClientId> {
+        let now = self.time.elapsed();
+        let local_client_id = self.local_client_id.as_deref().copied();
+        self.undo_requests
+            .read()
+            .map(|request| Self::sender(request.client_id, local_client_id))
+            .filter(|&client_id| self.budgets.spend(client_id, now))
+            .collect()
+    }
+
+    /// Requests made in the server's own window come from whoever is playing in it, which
+    /// changes every turn in a hot-seat game
+    fn sender(client_id: ClientId, local_client_id: Option<LocalClientId>) -> ClientId {
+        match local_client_id {
+            Some(LocalClientId(local_client_id)) if client_id == SERVER_ID => {
+                ClientId::from_raw(local_client_id)
+            }
+            _ => client_id,
+        }
+    }
+}
+
+/// Everything the server needs to deal out a new round on a new maze
+#[derive(SystemParam)]
+pub(crate) struct RoundReset<'w> {
+    pub(crate) cli: Res<'w, Cli>,
+    pub(crate) first_player: Res<'w, FirstPlayer>,
+    pub(crate) rng: ResMut<'w, GameRng>,
+    pub(crate) available_items: ResMut<'w, AvailableItems>,
+    pub(crate) game_limits: ResMut<'w, GameLimits>,
+    pub(crate) maze: ResMut<'w, Maze>,
+    pub(crate) current_turn: ResMut<'w, CurrentTurn>,
+}
+
+impl RoundReset<'_> {
+    /// Generates a new maze, sends everyone back to their corner with a new target and picks who
+    /// goes first, keeping the same seats. Returns `false` if this game wasn't set up by a server
+    /// and so has nothing to generate a maze from.
+    pub(crate) fn reset(&mut self, players: &mut [(Mut<Player>, Option<Mut<RaceState>>)]) -> bool {
+        let (Cli::Server(server_args)
+        | Cli::Host {
+            server: server_args,
+            ..
+        }) = &*self.cli
+        else {
+            return false;
+        };
+        let rng = &mut *self.rng;
+
+        // a maze from a file is played again as it is
+        if server_args.maze_file.is_none() {
+            *self.maze = Maze::generate(
+                server_args.maze_algo,
+                server_args.tiles,
+                server_args.board_size as usize,
+                &mut rng.rng,
+            );
+            if server_args.special_cells {
+                self.maze.place_special_cells(&mut rng.rng);
+            }
+            info!("Generated maze:\n{}", *self.maze);
+        }
+        // the old dice seed has been revealed, so the new round needs a new one to commit to
+        if rng.verifiable.is_some() {
+            let seed = rng.rng.gen();
+            rng.verifiable = Some(VerifiableDice { seed, rolls: 0 });
+        }
+        // the same band as the last game, which may have come from `--target-distance`
+        *self.available_items = AvailableItems {
+            target_distance: self.available_items.target_distance.clone(),
+            ..AvailableItems::new(server_args.item_dealing, server_args.log_targets)
+        };
+        self.game_limits.turns_taken = 0;
+        self.game_limits.elapsed = Duration::ZERO;
+
+        players.sort_by_key(|(player, _)| player.player_number);
+        for (player, race_state) in players.iter_mut() {
+            let coords =
+                LabyrinthPlugin::get_player_start_coords(player.player_number, self.maze.size);
+            player.coords = coords;
+            player.prev_coords = coords;
+            player.target_item = self.available_items.take_target(
+                player.player_number,
+                coords,
+                &self.maze,
+                &mut rng.rng,
+            );
+            player.achieved_items.clear();
+            player.crashes = 0;
+            player.skips_next_roll = false;
+            player.bonus_steps = 0;
+            player.extra_roll = false;
+            player.wants_rematch = false;
+            if let Some(race_state) = race_state {
+                **race_state = RaceState::default();
+            }
+        }
+
+        // seats may have been left empty during the last round
+        if !players.is_empty() {
+            self.current_turn.0 = players[self.first_player.pick(players.len(), &mut rng.rng)]
+                .0
+                .player_number;
+        }
+        true
+    }
+}
+
+/// Everything the server needs to start the next player's turn and tell the clients about it
+#[derive(SystemParam)]
+pub(crate) struct NextTurnWriter<'w> {
+    pub(crate) current_turn_writer: EventWriter<'w, ToClients<CurrentTurn>>,
+    pub(crate) next_turn_phase: ResMut<'w, NextState<TurnPhase>>,
+    pub(crate) turn_phase_writer: EventWriter<'w, ToClients<TurnPhase>>,
+}
+
+impl NextTurnWriter<'_> {
+    /// Anything the last player had left of their move is lost
+    pub(crate) fn send(&mut self, current_turn: CurrentTurn) {
+        self.current_turn_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: current_turn,
+        });
+        self.next_turn_phase.set(TurnPhase::Rolling);
+        self.turn_phase_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: TurnPhase::Rolling,
+        });
+    }
+}
+
+/// Everything the server needs to start a new round and tell the clients about it
+#[derive(SystemParam)]
+pub(crate) struct NewRoundWriter<'w> {
+    pub(crate) next_game_state: ResMut<'w, NextState<GameState>>,
+    pub(crate) next_turn_phase: ResMut<'w, NextState<TurnPhase>>,
+    pub(crate) maze_writer: EventWriter<'w, ToClients<Maze>>,
+    pub(crate) current_turn_writer: EventWriter<'w, ToClients<CurrentTurn>>,
+    pub(crate) turn_phase_writer: EventWriter<'w, ToClients<TurnPhase>>,
+    pub(crate) game_state_writer: EventWriter<'w, ToClients<GameState>>,
+}
+
+impl NewRoundWriter<'_> {
+    fn send(&mut self, maze: &Maze, current_turn: CurrentTurn) {
+        self.maze_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: maze.clone(),
+        });
+        self.current_turn_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: current_turn,
+        });
+        self.next_turn_phase.set(TurnPhase::Rolling);
+        self.turn_phase_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: TurnPhase::Rolling,
+        });
+        self.next_game_state.set(GameState::InGame);
+        self.game_state_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: GameState::InGame,
+        });
+    }
+}
+
+/// Everything the server needs to end the game and tell the clients about it
+#[derive(SystemParam)]
+pub(crate) struct GameOverWriter<'w, 's> {
+    pub(crate) commands: Commands<'w, 's>,
+    pub(crate) next_game_state: ResMut<'w, NextState<GameState>>,
+    pub(crate) game_state_writer: EventWriter<'w, ToClients<GameState>>,
+    pub(crate) game_over_writer: EventWriter<'w, ToClients<GameOver>>,
+    pub(crate) match_seed: Option<Res<'w, MatchSeed>>,
+}
+
+impl GameOverWriter<'_, '_> {
+    pub(crate) fn send<'a>(
+        &mut self,
+        winner: Option<usize>,
+        reason: GameOverReason,
+        players: impl Iterator<Item = &'a Player>,
+    ) {
+        let result = GameResult { winner, reason };
+        self.commands.insert_resource(result);
+        self.next_game_state.set(GameState::Ended);
+        self.game_state_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: GameState::Ended,
+        });
+        let match_seed = self
+            .match_seed
+            .as_ref()
+            .map(|match_seed| match_seed.0.clone());
+        self.game_over_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: GameOver::new(result, players, match_seed),
+        });
+    }
+}
@@ -0,0 +1,196 @@
+//! The shifting maze, where players may push the walls around a row or column of cells one cell
+//! along before they roll, like sliding in the spare tile in the board game
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use std::time::Duration;
+
+use crate::client::{BoardCells, Ease, MazeWall, Me, WindowSize};
+use crate::game::{
+    CurrentTurn, GameLimits, Maze, MazeShifted, MoveRejectionReason, MoveRequest, Player,
+    ShiftRequest, TurnLogEntry, TurnPhase,
+};
+use crate::server::{ClientRequests, MoveEventWriter};
+use crate::LabyrinthPlugin;
+
+const SLIDE_DURATION: Duration = Duration::from_millis(400);
+
+/// Present on a server started with `--shifting-maze`
+#[derive(Resource, Default)]
+pub(crate) struct ShiftingMaze {
+    /// The player number and [`GameLimits::turns_taken`] of the last turn a push was made in,
+    /// since only one push is allowed per turn
+    shifted_on: Option<(usize, u32)>,
+}
+
+/// The walls of the row or column that was just pushed, sliding into place on the client
+#[derive(Resource)]
+pub(crate) struct MazeSlide {
+    shift: ShiftRequest,
+    time: Duration,
+}
+
+impl LabyrinthPlugin {
+    /// Runs before the roll requests are handled, so that a push and a roll sent together both
+    /// count
+    pub(crate) fn server_receive_shifts(
+        mut requests: ClientRequests,
+        mut shifting: Option<ResMut<ShiftingMaze>>,
+        players: Query<&Player>,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        game_limits: Res<GameLimits>,
+        mut maze: ResMut<Maze>,
+        mut move_writer: MoveEventWriter,
+        mut maze_writer: EventWriter<ToClients<Maze>>,
+        mut shifted_writer: EventWriter<ToClients<MazeShifted>>,
+    ) {
+        for (client_id, shift) in requests.shifts() {
+            let Some(player) = players
+                .iter()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                warn!("Client {client_id} asked to push the maze without a pawn");
+                continue;
+            };
+            if player.player_number != current_turn.0 {
+                move_writer.reject(player, MoveRejectionReason::NotYourTurn);
+                continue;
+            }
+            if *turn_phase.get() != TurnPhase::Rolling {
+                move_writer.reject(player, MoveRejectionReason::WrongPhase);
+                continue;
+            }
+            let turn = (current_turn.0, game_limits.turns_taken);
+            let Some(shifting) = shifting
+                .as_deref_mut()
+                .filter(|shifting| shifting.shifted_on != Some(turn))
+            else {
+                move_writer.reject(player, MoveRejectionReason::CantShift);
+                continue;
+            };
+
+            let mut shifted_maze = maze.clone();
+            if !shifted_maze.shift(shift) {
+                move_writer.reject(player, MoveRejectionReason::OutOfBounds);
+                continue;
+            }
+            if !shifted_maze.is_valid() {
+                move_writer.reject(player, MoveRejectionReason::ShiftDisconnects);
+                continue;
+            }
+            *maze = shifted_maze;
+            shifting.shifted_on = Some(turn);
+            move_writer.log(TurnLogEntry::Shifted {
+                player_number: player.player_number,
+                shift,
+            });
+            maze_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: maze.clone(),
+            });
+            shifted_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: MazeShifted(shift),
+            });
+        }
+    }
+
+    /// Holding Shift and pressing an arrow key pushes the row or column under the cursor that
+    /// way, or the one the pawn is in if the cursor isn't over the board
+    pub(crate) fn client_handle_shift_input(
+        keys: Res<Input<KeyCode>>,
+        window: Query<&Window>,
+        camera: Query<(&Camera, &GlobalTransform)>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        me: Query<&Player, With<Me>>,
+        mut shift_requests: EventWriter<ShiftRequest>,
+    ) {
+        if !keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            return;
+        }
+        let Some(direction) = [
+            (KeyCode::Up, MoveRequest::Up),
+            (KeyCode::Down, MoveRequest::Down),
+            (KeyCode::Left, MoveRequest::Left),
+            (KeyCode::Right, MoveRequest::Right),
+        ]
+        .into_iter()
+        .find(|&(key, _)| keys.just_pressed(key))
+        .map(|(_, direction)| direction) else {
+            return;
+        };
+        let (camera, camera_transform) = camera.single();
+        let board_size = Self::calc_board_size(window_size.0);
+        let Some(coords) = window
+            .single()
+            .cursor_position()
+            .and_then(|screen_pos| camera.viewport_to_world_2d(camera_transform, screen_pos))
+            .and_then(|pos| Self::pos_to_board_pos(pos, board_size, cells.0))
+            .or_else(|| me.get_single().ok().map(|me| me.coords))
+        else {
+            return;
+        };
+        let index = match direction {
+            MoveRequest::Left | MoveRequest::Right => coords.y,
+            MoveRequest::Up | MoveRequest::Down => coords.x,
+        };
+        shift_requests.send(ShiftRequest {
+            index: index as usize,
+            direction,
+        });
+    }
+
+    pub(crate) fn client_on_maze_shifted(
+        mut commands: Commands,
+        mut shifted_events: EventReader<MazeShifted>,
+    ) {
+        if let Some(&MazeShifted(shift)) = shifted_events.read().last() {
+            commands.insert_resource(MazeSlide {
+                shift,
+                time: Duration::ZERO,
+            });
+        }
+    }
+
+    /// The new maze has already been drawn by the time it's pushed, so the pushed walls start a
+    /// cell back and slide forwards into where they are now
+    pub(crate) fn client_update_maze_slide(
+        mut commands: Commands,
+        mut slide: ResMut<MazeSlide>,
+        time: Res<Time>,
+        window_size: Res<WindowSize>,
+        cells: Res<BoardCells>,
+        ease: Res<Ease>,
+        mut walls: Query<(&MazeWall, &mut Transform)>,
+    ) {
+        slide.time = (slide.time + time.delta()).min(SLIDE_DURATION);
+        let progress = ease.apply(slide.time.as_secs_f32() / SLIDE_DURATION.as_secs_f32());
+        let board_size = Self::calc_board_size(window_size.0);
+        let cell_size = Self::calc_cell_size(board_size, cells.0);
+        let ShiftRequest { index, direction } = slide.shift;
+        let index = index as i32;
+        let offset = -direction.delta().as_vec2() * cell_size * (1.0 - progress);
+        for (wall, mut transform) in walls.iter_mut() {
+            // the walls between the pushed cells, and along either side of them
+            let pushed = match direction {
+                MoveRequest::Left | MoveRequest::Right if wall.horizontal => {
+                    wall.coords.y == index || wall.coords.y == index - 1
+                }
+                MoveRequest::Left | MoveRequest::Right => wall.coords.y == index,
+                MoveRequest::Up | MoveRequest::Down if wall.horizontal => wall.coords.x == index,
+                MoveRequest::Up | MoveRequest::Down => {
+                    wall.coords.x == index || wall.coords.x == index - 1
+                }
+            };
+            if pushed {
+                transform.translation = (Self::calc_wall_pos(board_size, cells.0, wall) + offset)
+                    .extend(transform.translation.z);
+            }
+        }
+        if slide.time >= SLIDE_DURATION {
+            commands.remove_resource::<MazeSlide>();
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Stopping a server without leaving its clients hanging. Whether it's stopped from the console,
+//! with Ctrl+C or by crashing, the clients are told why and the server waits for that to reach
+//! them before it exits, instead of them only finding out when the connection times out.
+
+use bevy::app::{AppExit, PluginsState};
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::DisconnectMessage;
+use crate::game::{GameOverReason, GameState, Player, ServerShutdown};
+use crate::server::GameOverWriter;
+use crate::LabyrinthPlugin;
+
+/// The longest the server waits for clients to acknowledge everything it sent them before it
+/// exits anyway
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Set by the Ctrl+C handler, and picked up by every server app in the process
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Present while the server is shutting down
+#[derive(Resource)]
+pub(crate) struct ShuttingDown {
+    reason: String,
+    /// When the clients were told, or `None` if they haven't been yet
+    announced_at: Option<Duration>,
+}
+
+impl ShuttingDown {
+    pub(crate) fn new(reason: impl Into<String>) -> Self {
+        ShuttingDown {
+            reason: reason.into(),
+            announced_at: None,
+        }
+    }
+}
+
+/// Has the server shut down gracefully on Ctrl+C. A second Ctrl+C exits straight away, in case
+/// the server is stuck.
+pub(crate) fn catch_interrupts() {
+    // there's no Ctrl+C to catch in a browser
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let result = ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+        });
+        if let Err(err) = result {
+            warn!(
+                "Failed to set a Ctrl+C handler, clients won't be told when the server stops: \
+                 {err}"
+            );
+        }
+    }
+}
+
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Runs a server app the way [`bevy::app::ScheduleRunnerPlugin`] does, updating at most once per
+/// `wait`, except that a panic during an update shuts the server down properly instead of
+/// dropping every client. If the server panics again while shutting down, it gives up.
+pub fn server_runner(wait: Duration) -> impl FnOnce(App) + Send + 'static {
+    move |mut app| {
+        if app.plugins_state() != PluginsState::Cleaned {
+            while app.plugins_state() == PluginsState::Adding {
+                #[cfg(not(target_arch = "wasm32"))]
+                bevy::tasks::tick_global_task_pools_on_main_thread();
+            }
+            app.finish();
+            app.cleanup();
+        }
+
+        let mut crashed = false;
+        loop {
+            let update_start = Instant::now();
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| app.update())) {
+                if crashed {
+                    panic::resume_unwind(payload);
+                }
+                crashed = true;
+                error!("The server crashed, shutting down");
+                if !app.world.contains_resource::<ShuttingDown>() {
+                    app.world
+                        .insert_resource(ShuttingDown::new("The server crashed."));
+                }
+            }
+            if app
+                .world
+                .get_resource::<Events<AppExit>>()
+                .is_some_and(|app_exit_events| !app_exit_events.is_empty())
+            {
+                break;
+            }
+            if let Some(remaining) = wait.checked_sub(update_start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    }
+}
+
+impl LabyrinthPlugin {
+    pub(crate) fn server_check_interrupted(
+        mut commands: Commands,
+        shutting_down: Option<Res<ShuttingDown>>,
+    ) {
+        if shutting_down.is_none() && interrupted() {
+            info!("Interrupted");
+            commands.insert_resource(ShuttingDown::new("The server was stopped."));
+        }
+    }
+
+    /// Ends the game and tells the clients why, then exits once everything sent to them has been
+    /// acknowledged
+    pub(crate) fn server_shut_down(
+        mut shutting_down: ResMut<ShuttingDown>,
+        time: Res<Time>,
+        server: Option<Res<RenetServer>>,
+        network_channels: Res<NetworkChannels>,
+        players: Query<&Player>,
+        game_state: Res<State<GameState>>,
+        mut game_over_writer: GameOverWriter,
+        mut shutdown_writer: EventWriter<ToClients<ServerShutdown>>,
+        mut app_exit_events: ResMut<Events<AppExit>>,
+    ) {
+        let Some(announced_at) = shutting_down.announced_at else {
+            info!("Stopping server: {}", shutting_down.reason);
+            if matches!(game_state.get(), GameState::InGame | GameState::Paused) {
+                game_over_writer.send(None, GameOverReason::ServerEnded, players.iter());
+            }
+            shutdown_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: ServerShutdown {
+                    reason: shutting_down.reason.clone(),
+                },
+            });
+            shutting_down.announced_at = Some(time.elapsed());
+            return;
+        };
+
+        let drained = server.map_or(true, |server| {
+            let channels = network_channels.get_server_configs();
+            server.clients_id().into_iter().all(|client_id| {
+                channels.iter().all(|channel| {
+                    server.channel_available_memory(client_id, channel.channel_id)
+                        >= channel.max_memory_usage_bytes
+                })
+            })
+        });
+        if drained || time.elapsed() - announced_at >= SHUTDOWN_DRAIN_TIMEOUT {
+            app_exit_events.send(AppExit);
+        }
+    }
+
+    /// Shows why the server closed and disconnects, rather than waiting for the connection to
+    /// time out
+    pub(crate) fn client_on_server_shutdown(
+        mut commands: Commands,
+        mut shutdown_events: EventReader<ServerShutdown>,
+        mut client: ResMut<RenetClient>,
+        disconnect_message: Query<Entity, With<DisconnectMessage>>,
+    ) {
+        let Some(ServerShutdown { reason }) = shutdown_events.read().last() else {
+            return;
+        };
+        info!("Server closed the game: {reason}");
+        client.disconnect();
+        for entity_id in disconnect_message.iter() {
+            commands.entity(entity_id).despawn_recursive();
+        }
+        Self::spawn_overlay(
+            &mut commands,
+            format!("Server closed the game: {reason}\nPress Esc to quit."),
+            AlignItems::Center,
+            DisconnectMessage,
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Cosmetic pawn skins, pictures in `assets/skins/` that players can pick in the lobby instead of
+//! the plain pawn in their colour. Skins aren't sent over the network, only their names, so a
+//! player whose skin another client doesn't have is drawn there as a plain pawn.
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+
+use crate::client::Me;
+use crate::game::{is_valid_skin_name, Player, SkinChange};
+use crate::palette::Palette;
+use crate::LabyrinthPlugin;
+
+/// Where skins are found, relative to the assets folder
+const SKINS_DIR: &str = "skins";
+
+/// The skins this client has, by name, in the order the lobby cycles through them
+#[derive(Resource, Default)]
+pub(crate) struct Skins(Vec<String>);
+
+impl Skins {
+    /// Finds the skins in `assets/skins/`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn scan() -> Skins {
+        let dir = bevy::asset::io::file::FileAssetReader::get_base_path()
+            .join("assets")
+            .join(SKINS_DIR);
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Skins::default();
+        };
+        let mut skins: Vec<_> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "png" {
+                    return None;
+                }
+                let name = path.file_stem()?.to_str()?;
+                is_valid_skin_name(name).then(|| name.to_owned())
+            })
+            .collect();
+        skins.sort();
+        if !skins.is_empty() {
+            info!("Found {} pawn skins in {}", skins.len(), dir.display());
+        }
+        Skins(skins)
+    }
+
+    /// Browsers can't list the files the game is served with, so they don't have any skins
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn scan() -> Skins {
+        Skins::default()
+    }
+
+    /// The skin a player is drawn with here, if they picked one and this client has it
+    fn get<'a>(&self, player: &'a Player) -> Option<&'a str> {
+        player
+            .skin
+            .as_deref()
+            .filter(|skin| self.0.iter().any(|name| name == skin))
+    }
+
+    /// The skin to pick after `current`, going back to the plain pawn after the last one
+    fn next(&self, current: Option<&str>) -> Option<String> {
+        let index = match current {
+            Some(current) => self.0.iter().position(|name| name == current)? + 1,
+            None => 0,
+        };
+        self.0.get(index).cloned()
+    }
+}
+
+/// The skin picked with `--skin`, asked for as soon as the player has a pawn in the lobby
+#[derive(Resource)]
+pub(crate) struct ChosenSkin(pub(crate) String);
+
+impl LabyrinthPlugin {
+    pub(crate) fn pawn_texture(
+        player: &Player,
+        skins: &Skins,
+        assets: &AssetServer,
+    ) -> Handle<Image> {
+        match skins.get(player) {
+            Some(skin) => assets.load(format!("{SKINS_DIR}/{skin}.png")),
+            None => assets.load("pawn.png"),
+        }
+    }
+
+    /// Players who might still reconnect are greyed out until they do. Otherwise skins are drawn
+    /// in their own colours, and the plain pawn is tinted in the player's.
+    pub(crate) fn pawn_color(player: &Player, palette: Palette, skins: &Skins) -> Color {
+        if player.absent {
+            Color::GRAY.with_a(0.5)
+        } else if skins.get(player).is_some() {
+            Color::WHITE
+        } else {
+            palette.color(player.player_number)
+        }
+    }
+
+    /// S picks the next skin, and the one from `--skin` is asked for when the player joins
+    pub(crate) fn client_handle_skin_input(
+        keys: Res<Input<KeyCode>>,
+        me: Query<(&Player, Ref<Me>)>,
+        skins: Res<Skins>,
+        chosen_skin: Option<Res<ChosenSkin>>,
+        mut skin_change_writer: EventWriter<SkinChange>,
+    ) {
+        let Ok((me, me_ref)) = me.get_single() else {
+            return;
+        };
+        if me_ref.is_added() {
+            if let Some(chosen_skin) = chosen_skin {
+                skin_change_writer.send(SkinChange(Some(chosen_skin.0.clone())));
+            }
+        }
+        if keys.just_pressed(KeyCode::S) && !skins.0.is_empty() {
+            skin_change_writer.send(SkinChange(skins.next(me.skin.as_deref())));
+        }
+    }
+
+    pub(crate) fn server_receive_skin_changes(
+        mut skin_change_events: EventReader<FromClient<SkinChange>>,
+        mut players: Query<&mut Player>,
+    ) {
+        for FromClient { client_id, event } in skin_change_events.read() {
+            let Some(mut player) = players
+                .iter_mut()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                continue;
+            };
+            if let Some(skin) = &event.0 {
+                if !is_valid_skin_name(skin) {
+                    warn!("Player {} asked for an invalid skin", player.player_number);
+                    continue;
+                }
+            }
+            if player.skin != event.0 {
+                info!(
+                    "Player {} picked the skin {}",
+                    player.player_number,
+                    event.0.as_deref().unwrap_or("none")
+                );
+                player.skin = event.0.clone();
+            }
+        }
+    }
+}
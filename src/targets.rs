@@ -0,0 +1,137 @@
+//! Keeping each player's target item to themselves. Replicon sends a component to every client
+//! or none of them, so [`Player::target_item`] isn't replicated, and the server sends players
+//! their own target as an event instead.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::ClientId;
+
+use crate::client::{ItemDisplay, TextureAtlases, WindowSize};
+use crate::coach::CoachView;
+use crate::game::{Item, Player, PlayerTarget};
+use crate::net::LocalClientId;
+use crate::server::Observers;
+use crate::LabyrinthPlugin;
+
+/// The target items this client has been told about, by player number
+#[derive(Resource, Default, PartialEq)]
+pub(crate) struct KnownTargets(HashMap<usize, Item>);
+
+impl KnownTargets {
+    pub(crate) fn get(&self, player_number: usize) -> Option<Item> {
+        self.0.get(&player_number).copied()
+    }
+}
+
+impl LabyrinthPlugin {
+    /// Sends players their target whenever it changes, and again when they reconnect. Observers
+    /// of a server started with `--coach-view` are sent everyone's. Whoever is playing in the
+    /// server's own window already has their pawn's target, so isn't sent it.
+    pub(crate) fn server_send_targets(
+        mut events: EventReader<ServerEvent>,
+        mut sent: Local<HashMap<usize, Option<Item>>>,
+        players: Query<&Player>,
+        observers: Res<Observers>,
+        coach_view: Option<Res<CoachView>>,
+        local_client_id: Option<Res<LocalClientId>>,
+        mut target_writer: EventWriter<ToClients<PlayerTarget>>,
+    ) {
+        let coaches: Vec<_> = match coach_view {
+            Some(_) => observers.0.iter().copied().collect(),
+            None => Vec::new(),
+        };
+        let mut send = |client_id, player: &Player| {
+            target_writer.send(ToClients {
+                mode: SendMode::Direct(client_id),
+                event: PlayerTarget {
+                    player_number: player.player_number,
+                    target_item: player.target_item,
+                },
+            });
+        };
+
+        for event in events.read() {
+            let ServerEvent::ClientConnected { client_id } = event else {
+                continue;
+            };
+            for player in players.iter() {
+                if player.client_id == client_id.raw() || coaches.contains(client_id) {
+                    send(*client_id, player);
+                }
+            }
+        }
+
+        for player in players.iter() {
+            if sent.get(&player.player_number) == Some(&player.target_item) {
+                continue;
+            }
+            sent.insert(player.player_number, player.target_item);
+            let is_local = local_client_id
+                .as_ref()
+                .is_some_and(|local_client_id| local_client_id.0 == player.client_id);
+            if !is_local {
+                send(ClientId::from_raw(player.client_id), player);
+            }
+            for &client_id in &coaches {
+                send(client_id, player);
+            }
+        }
+    }
+
+    pub(crate) fn client_on_player_target(
+        mut target_events: EventReader<PlayerTarget>,
+        mut targets: ResMut<KnownTargets>,
+    ) {
+        for event in target_events.read() {
+            match event.target_item {
+                Some(target_item) => targets.0.insert(event.player_number, target_item),
+                None => targets.0.remove(&event.player_number),
+            };
+        }
+    }
+
+    /// Whoever is playing in the server's own window reads their target straight off their pawn.
+    /// In a hot seat game that's only the player whose seat it is.
+    pub(crate) fn client_sync_local_targets(
+        players: Query<&Player>,
+        changed_players: Query<(), Changed<Player>>,
+        local_client_id: Res<LocalClientId>,
+        mut targets: ResMut<KnownTargets>,
+    ) {
+        if changed_players.is_empty() && !local_client_id.is_changed() {
+            return;
+        }
+        let local_targets = players
+            .iter()
+            .filter(|player| player.client_id == local_client_id.0)
+            .filter_map(|player| Some((player.player_number, player.target_item?)))
+            .collect();
+        targets.set_if_neq(KnownTargets(local_targets));
+    }
+
+    /// Targets can arrive separately from the pawns they belong to, so the item displays are
+    /// brought up to date with them here too
+    pub(crate) fn client_update_target_displays(
+        mut commands: Commands,
+        targets: Res<KnownTargets>,
+        players: Query<&Player>,
+        mut items_query: Query<(Entity, &ItemDisplay, &mut TextureAtlasSprite)>,
+        atlases: Res<TextureAtlases>,
+        window_size: Res<WindowSize>,
+    ) {
+        if !targets.is_changed() {
+            return;
+        }
+        for player in players.iter() {
+            Self::sync_player_items(
+                &mut commands,
+                player,
+                targets.get(player.player_number),
+                &mut items_query,
+                &atlases,
+                window_size.0,
+            );
+        }
+    }
+}
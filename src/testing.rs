@@ -0,0 +1,159 @@
+//! Running a server in the same process as the code driving it, without any sockets, so that
+//! integration tests can play through games with as many simulated clients as they need
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_replicon::prelude::*;
+use bevy_replicon::renet::ClientId;
+use std::time::Duration;
+
+use crate::game::{
+    CurrentTurn, Dice, GameResult, GameRng, GameState, Maze, Player, TurnLog, TurnLogEntry,
+    TurnPhase,
+};
+use crate::shutdown::ShuttingDown;
+use crate::{Cli, LabyrinthPlugin, ServerArgs};
+
+/// How much time passes each frame, comfortably within the request budget of one request per
+/// frame
+const FRAME_TIME: Duration = Duration::from_millis(100);
+
+/// Present when the server was started by a [`TestServer`] rather than from the command line
+#[derive(Resource)]
+pub(crate) struct InMemoryServer;
+
+/// A headless server with simulated clients. Clients are connected straight to the
+/// [`RenetServer`], and their requests are delivered as the events replicon would have received
+/// from them, so everything past the network runs as it does in a real game.
+pub struct TestServer {
+    pub app: App,
+}
+
+impl TestServer {
+    pub fn new(server_args: ServerArgs) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        // so that tests don't depend on how fast they run
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(FRAME_TIME));
+        app.insert_resource(Cli::Server(server_args));
+        app.insert_resource(InMemoryServer);
+        app.add_plugins((ReplicationPlugins, LabyrinthPlugin));
+        let mut server = Self { app };
+        server.update();
+        server
+    }
+
+    pub fn update(&mut self) {
+        self.app.update();
+    }
+
+    /// Makes the next dice roll come up as `value`, before any random ones
+    pub fn decide_next_roll(&mut self, value: u8) {
+        self.app
+            .world
+            .resource_mut::<GameRng>()
+            .decide_rolls([value]);
+    }
+
+    /// Connects a client, which takes a seat if there's one free
+    pub fn connect(&mut self, client_id: u64) {
+        self.app
+            .world
+            .resource_mut::<RenetServer>()
+            .add_connection(ClientId::from_raw(client_id));
+        self.update();
+    }
+
+    /// Stops the server the way the `shutdown` console command does
+    pub fn shut_down(&mut self) {
+        self.app
+            .world
+            .insert_resource(ShuttingDown::new("The test is over."));
+        self.update();
+    }
+
+    pub fn disconnect(&mut self, client_id: u64) {
+        self.app
+            .world
+            .resource_mut::<RenetServer>()
+            .disconnect(ClientId::from_raw(client_id));
+        self.update();
+    }
+
+    /// Sends an event as if the client had sent it, and runs a frame to handle it
+    pub fn send<T: Event>(&mut self, client_id: u64, event: T) {
+        self.queue(client_id, event);
+        self.update();
+    }
+
+    /// Sends an event as if the client had sent it, to be handled with any others sent before the
+    /// next frame
+    pub fn queue<T: Event>(&mut self, client_id: u64, event: T) {
+        self.app.world.send_event(FromClient {
+            client_id: ClientId::from_raw(client_id),
+            event,
+        });
+    }
+
+    pub fn player(&mut self, client_id: u64) -> Player {
+        self.app
+            .world
+            .query::<&Player>()
+            .iter(&self.app.world)
+            .find(|player| player.client_id == client_id)
+            .cloned()
+            .unwrap_or_else(|| panic!("Client {client_id} has no pawn"))
+    }
+
+    pub fn player_mut(&mut self, client_id: u64) -> Mut<Player> {
+        self.app
+            .world
+            .query::<&mut Player>()
+            .iter_mut(&mut self.app.world)
+            .find(|player| player.client_id == client_id)
+            .unwrap_or_else(|| panic!("Client {client_id} has no pawn"))
+    }
+
+    /// The dice the player rolls with, which is shared in a classic game
+    pub fn dice_value(&mut self, player_number: usize) -> u8 {
+        let current_turn = self.current_turn();
+        self.app
+            .world
+            .query::<&Dice>()
+            .iter(&self.app.world)
+            .find(|dice| dice.corner(current_turn) == player_number)
+            .map(|dice| dice.value)
+            .unwrap_or_else(|| panic!("Player {player_number} has no dice"))
+    }
+
+    pub fn maze_mut(&mut self) -> Mut<Maze> {
+        self.app.world.resource_mut::<Maze>()
+    }
+
+    pub fn current_turn(&self) -> usize {
+        self.app.world.resource::<CurrentTurn>().0
+    }
+
+    pub fn game_state(&self) -> GameState {
+        *self.app.world.resource::<State<GameState>>().get()
+    }
+
+    pub fn turn_phase(&self) -> TurnPhase {
+        *self.app.world.resource::<State<TurnPhase>>().get()
+    }
+
+    pub fn turn_log(&mut self) -> Vec<TurnLogEntry> {
+        self.app
+            .world
+            .query::<&TurnLog>()
+            .get_single(&self.app.world)
+            .map_or_else(
+                |_| Vec::new(),
+                |turn_log| turn_log.0.iter().cloned().collect(),
+            )
+    }
+
+    pub fn game_result(&self) -> Option<GameResult> {
+        self.app.world.get_resource::<GameResult>().copied()
+    }
+}
@@ -0,0 +1,91 @@
+//! Playing on a touch screen, for the phone and tablet ports built with the `touch` feature.
+//! Swiping across the board steps that way, tapping the dice rolls, and pinching zooms.
+
+use bevy::prelude::*;
+
+use crate::camera::CameraController;
+use crate::client::{Me, TurnActions, TurnControls, WindowSize};
+use crate::game::{MoveRequest, Player};
+use crate::LabyrinthPlugin;
+
+/// How far in pixels a finger has to move before lifting for it to count as a swipe rather than
+/// a tap
+const SWIPE_MIN_DISTANCE: f32 = 40.0;
+
+impl LabyrinthPlugin {
+    /// The touch screen's counterpart to [`LabyrinthPlugin::client_handle_keyboard_input`]. A
+    /// gesture is read once the finger lifts, and fingers that were pinching don't count.
+    pub(crate) fn client_handle_touch_input(
+        touches: Res<Touches>,
+        mut pinching: Local<bool>,
+        camera: Query<(&Camera, &GlobalTransform)>,
+        window_size: Res<WindowSize>,
+        me: Query<&Player, With<Me>>,
+        mut controls: TurnControls,
+    ) {
+        if touches.iter().count() >= 2 {
+            *pinching = true;
+        }
+        let mut actions = TurnActions::default();
+        for touch in touches.iter_just_released() {
+            if *pinching {
+                continue;
+            }
+            let swipe = touch.position() - touch.start_position();
+            if swipe.length() >= SWIPE_MIN_DISTANCE {
+                // the window's y axis points down, but the board's points up
+                let delta = if swipe.x.abs() > swipe.y.abs() {
+                    IVec2::new(swipe.x.signum() as i32, 0)
+                } else {
+                    IVec2::new(0, -swipe.y.signum() as i32)
+                };
+                actions.step = MoveRequest::from_delta(delta);
+            } else if let Ok(me) = me.get_single() {
+                let (camera, camera_transform) = camera.single();
+                let Some(pos) = camera.viewport_to_world_2d(camera_transform, touch.position())
+                else {
+                    continue;
+                };
+                let board_size = Self::calc_board_size(window_size.0);
+                let dice_pos = Self::calc_dice_pos(window_size.0, board_size, me.player_number);
+                let dice_size = Self::calc_dice_size(window_size.0, board_size);
+                actions.roll |= ((pos - dice_pos).abs() * 2.0).cmple(dice_size).all();
+            }
+        }
+        if touches.iter().next().is_none() {
+            *pinching = false;
+        }
+        controls.apply(actions);
+    }
+
+    /// Pinching with two fingers zooms in and out around them, and moving them together pans
+    pub(crate) fn client_handle_pinch_zoom(
+        touches: Res<Touches>,
+        mut controller: ResMut<CameraController>,
+        window: Query<&Window>,
+        window_size: Res<WindowSize>,
+    ) {
+        let mut fingers = touches.iter();
+        let (Some(first), Some(second), None) = (fingers.next(), fingers.next(), fingers.next())
+        else {
+            return;
+        };
+        let window = window.single();
+        let previous_middle = (first.previous_position() + second.previous_position()) * 0.5;
+        let middle = (first.position() + second.position()) * 0.5;
+        let previous_spread = first
+            .previous_position()
+            .distance(second.previous_position());
+        let spread = first.position().distance(second.position());
+        if previous_spread > 0.0 {
+            controller.zoom_around(
+                spread / previous_spread,
+                Some(middle),
+                window_size.0,
+                window,
+            );
+        }
+        controller.drag(middle - previous_middle, window_size.0, window);
+        controller.clamp_pan(window_size.0);
+    }
+}
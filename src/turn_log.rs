@@ -0,0 +1,137 @@
+//! The turn log panel, so that a player who looked away can see what everyone else did
+
+use bevy::prelude::*;
+
+use crate::game::{MoveRequest, Player, TurnLog, TurnLogEntry, COLOR_NAMES};
+use crate::palette::Palette;
+use crate::LabyrinthPlugin;
+
+/// How many of the latest entries the panel shows when it's expanded
+const TURN_LOG_SHOWN_ENTRIES: usize = 12;
+const TURN_LOG_FONT_SIZE: f32 = 16.0;
+
+/// The side panel showing the [`TurnLog`]. Collapsed, only its header is shown.
+#[derive(Component)]
+pub(crate) struct TurnLogPanel {
+    expanded: bool,
+}
+
+#[derive(Component)]
+pub(crate) struct TurnLogText;
+
+impl LabyrinthPlugin {
+    /// L expands and collapses the turn log panel
+    pub(crate) fn client_toggle_turn_log(
+        keys: Res<Input<KeyCode>>,
+        mut panel: Query<&mut TurnLogPanel>,
+    ) {
+        if !keys.just_pressed(KeyCode::L) {
+            return;
+        }
+        if let Ok(mut panel) = panel.get_single_mut() {
+            panel.expanded = !panel.expanded;
+        }
+    }
+
+    /// Spawns the panel once the server has replicated a turn log, and rewrites it whenever the
+    /// log grows or the panel is toggled
+    pub(crate) fn client_update_turn_log(
+        mut commands: Commands,
+        turn_log: Query<Ref<TurnLog>>,
+        players: Query<&Player>,
+        panel: Query<(Entity, Ref<TurnLogPanel>)>,
+        mut text: Query<&mut Text, With<TurnLogText>>,
+        palette: Res<Palette>,
+    ) {
+        let Ok(turn_log) = turn_log.get_single() else {
+            // the game the log belonged to is over
+            for (entity_id, _) in panel.iter() {
+                commands.entity(entity_id).despawn_recursive();
+            }
+            return;
+        };
+        let Ok((_, panel)) = panel.get_single() else {
+            commands
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(10.0),
+                            top: Val::Percent(30.0),
+                            max_width: Val::Px(280.0),
+                            padding: UiRect::all(Val::Px(8.0)),
+                            ..default()
+                        },
+                        background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                        ..default()
+                    },
+                    TurnLogPanel { expanded: true },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((TextBundle::default(), TurnLogText));
+                });
+            // filled in next frame, once the text exists
+            return;
+        };
+        if !turn_log.is_changed() && !panel.is_changed() && !palette.is_changed() {
+            return;
+        }
+        let Ok(mut text) = text.get_single_mut() else {
+            return;
+        };
+
+        let text_style = |color| TextStyle {
+            font_size: TURN_LOG_FONT_SIZE,
+            color,
+            ..default()
+        };
+        let mut sections = vec![TextSection::new("Turn log [L]", text_style(Color::WHITE))];
+        if panel.expanded {
+            let skip = turn_log.0.len().saturating_sub(TURN_LOG_SHOWN_ENTRIES);
+            for entry in turn_log.0.iter().skip(skip) {
+                let player_number = entry.player_number();
+                let name = players
+                    .iter()
+                    .find(|player| player.player_number == player_number)
+                    .map(Player::display_name)
+                    .or_else(|| COLOR_NAMES.get(player_number).map(|name| name.to_string()))
+                    .unwrap_or_else(|| format!("Player {player_number}"));
+                let color = palette.color(player_number);
+                sections.push(TextSection::new(format!("\n{name}"), text_style(color)));
+                sections.push(TextSection::new(
+                    describe_entry(entry),
+                    text_style(Color::WHITE),
+                ));
+            }
+        }
+        text.sections = sections;
+    }
+}
+
+fn describe_entry(entry: &TurnLogEntry) -> String {
+    match entry {
+        TurnLogEntry::Rolled { value, .. } => format!(" rolled a {value}"),
+        TurnLogEntry::Moved { direction, .. } => format!(
+            " moved {}",
+            match direction {
+                MoveRequest::Up => "north",
+                MoveRequest::Down => "south",
+                MoveRequest::Left => "west",
+                MoveRequest::Right => "east",
+            }
+        ),
+        TurnLogEntry::Crashed { .. } => " hit a wall".to_owned(),
+        TurnLogEntry::Collected { item, .. } => format!(" collected {item}"),
+        TurnLogEntry::Teleported { .. } => " was teleported".to_owned(),
+        TurnLogEntry::Trapped { .. } => " stepped on a trap".to_owned(),
+        TurnLogEntry::ExtraRoll { .. } => " earned an extra roll".to_owned(),
+        TurnLogEntry::Bumped { .. } => " was bumped back to their start".to_owned(),
+        TurnLogEntry::Shifted { shift, .. } => match shift.direction {
+            MoveRequest::Up => format!(" pushed column {} north", shift.index + 1),
+            MoveRequest::Down => format!(" pushed column {} south", shift.index + 1),
+            MoveRequest::Left => format!(" pushed row {} west", shift.index + 1),
+            MoveRequest::Right => format!(" pushed row {} east", shift.index + 1),
+        },
+        TurnLogEntry::Undone { .. } => " took back a step".to_owned(),
+    }
+}
@@ -0,0 +1,200 @@
+//! A scripted game on a small hand-made maze that walks new players through rolling, moving,
+//! walls and collecting items. The dice rolls are decided in advance so that every lesson can be
+//! finished, and each prompt waits for the player to do what it asks before moving on.
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+
+use crate::client::InputOptions;
+use crate::game::{
+    AvailableItems, DiceBundle, GameLimits, GameMode, GameRng, Item, ItemCollected, ItemDealing,
+    Maze, PawnCollisions, Player, PlayerBundle, PlayerStartMoveAnimation, TurnPhase,
+    DEFAULT_BOARD_SIZE, ITEMS_TO_WIN,
+};
+use crate::net::LocalClientId;
+use crate::palette::{Palette, PawnMarks};
+use crate::server::MaxPlayers;
+use crate::{Ease, LabyrinthPlugin, MirrorControls};
+
+/// Seeds the items dealt and the rolls made once the script runs out
+const TUTORIAL_SEED: &str = "tutorial";
+
+/// One step to try moving, then enough to walk into the wall, then enough to go around it
+const SCRIPTED_ROLLS: [u8; 4] = [1, 3, 4, 4];
+
+/// The first target, two cells to the right of the start with the wall in between
+const FIRST_TARGET: Item = Item::Bracelet;
+
+/// The lesson the player is on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TutorialStep {
+    Roll,
+    Move,
+    Walls,
+    Collect,
+    Finished,
+}
+
+impl TutorialStep {
+    fn prompt(self) -> String {
+        match self {
+            TutorialStep::Roll => {
+                "Welcome to Labyrinth!\nPress Space or click the dice to roll it".to_owned()
+            }
+            TutorialStep::Move => "The dice shows how many steps you can take.\n\
+                                   Use the arrow keys to step to the right"
+                .to_owned(),
+            TutorialStep::Walls => "The walls of the maze are hidden. Walking into one sends \
+                                    you back to your corner and ends your turn.\n\
+                                    There's one on the way to the bracelet, roll and keep \
+                                    walking right"
+                .to_owned(),
+            TutorialStep::Collect => "Now you know where that wall is. Your target is shown \
+                                      beside the board.\n\
+                                      Go around the wall and step onto the bracelet to collect it"
+                .to_owned(),
+            TutorialStep::Finished => format!(
+                "That's everything! The first to collect {ITEMS_TO_WIN} items wins.\n\
+                 A new target is dealt each time you collect one"
+            ),
+        }
+    }
+}
+
+/// Progress through the tutorial
+#[derive(Resource)]
+pub(crate) struct Tutorial {
+    step: TutorialStep,
+}
+
+/// The text of the current lesson
+#[derive(Component)]
+pub(crate) struct TutorialPrompt;
+
+impl LabyrinthPlugin {
+    pub(crate) fn tutorial_init(
+        commands: &mut Commands,
+        window: &Window,
+        assets: &AssetServer,
+        texture_atlases: &mut Assets<TextureAtlas>,
+    ) {
+        // a single wall across the bottom row, between the start and the first target
+        let mut maze = Maze::new(DEFAULT_BOARD_SIZE);
+        maze.vertical_bars[0][1] = true;
+
+        let mut rng = GameRng::from_match_seed(TUTORIAL_SEED);
+        rng.decide_rolls(SCRIPTED_ROLLS);
+        let mut available_items = AvailableItems::new(ItemDealing::Random, false);
+        available_items.items.retain(|&item| item != FIRST_TARGET);
+        let coords = Self::get_player_start_coords(0, maze.size);
+        commands.spawn(PlayerBundle {
+            player: Player {
+                client_id: SERVER_ID.raw(),
+                coords,
+                prev_coords: coords,
+                player_number: 0,
+                target_item: Some(FIRST_TARGET),
+                ..default()
+            },
+            ..default()
+        });
+        commands.spawn(DiceBundle::default());
+
+        commands.insert_resource(MaxPlayers(1));
+        commands.insert_resource(GameMode::Classic);
+        commands.insert_resource(PawnCollisions::Share);
+        commands.init_resource::<GameLimits>();
+        commands.insert_resource(maze);
+        commands.insert_resource(rng);
+        commands.insert_resource(available_items);
+        commands.insert_resource(Tutorial {
+            step: TutorialStep::Roll,
+        });
+        commands.insert_resource(LocalClientId(SERVER_ID.raw()));
+        commands.insert_resource(InputOptions {
+            numpad: false,
+            mirror_controls: MirrorControls::Off,
+        });
+        commands.insert_resource(Ease::Sine);
+        commands.insert_resource(Palette::Classic);
+        commands.insert_resource(PawnMarks::Off);
+
+        Self::spawn_board(commands, window, assets, texture_atlases);
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    bottom: Val::Px(16.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            padding: UiRect::all(Val::Px(12.0)),
+                            ..default()
+                        },
+                        background_color: Color::rgba(0.0, 0.0, 0.0, 0.75).into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn((
+                            TextBundle::from_section(
+                                TutorialStep::Roll.prompt(),
+                                TextStyle {
+                                    font_size: 24.0,
+                                    color: Color::WHITE,
+                                    ..default()
+                                },
+                            )
+                            .with_text_alignment(TextAlignment::Center),
+                            TutorialPrompt,
+                        ));
+                    });
+            });
+    }
+
+    /// Moves on to the next lesson once the player has done what the current one asks. Walking
+    /// into a wall or collecting the item early skips the lessons that would have led up to it.
+    pub(crate) fn tutorial_check_progress(
+        mut tutorial: ResMut<Tutorial>,
+        mut turn_phase_events: EventReader<TurnPhase>,
+        mut move_anim_events: EventReader<PlayerStartMoveAnimation>,
+        mut item_collected_events: EventReader<ItemCollected>,
+    ) {
+        let mut step = tutorial.step;
+        // a crash also ends the turn, so it's checked before the turn phase
+        let crashed = move_anim_events.read().filter(|event| event.fail).count() != 0;
+        if crashed && matches!(step, TutorialStep::Move | TutorialStep::Walls) {
+            step = TutorialStep::Collect;
+        }
+        for turn_phase in turn_phase_events.read() {
+            step = match (step, turn_phase) {
+                (TutorialStep::Roll, TurnPhase::Moving { .. }) => TutorialStep::Move,
+                (TutorialStep::Move, TurnPhase::Rolling) => TutorialStep::Walls,
+                (step, _) => step,
+            };
+        }
+        if item_collected_events.read().count() != 0 {
+            step = TutorialStep::Finished;
+        }
+        if step != tutorial.step {
+            info!("Tutorial step: {step:?}");
+            tutorial.step = step;
+        }
+    }
+
+    pub(crate) fn tutorial_update_prompt(
+        tutorial: Res<Tutorial>,
+        mut prompt: Query<&mut Text, With<TutorialPrompt>>,
+    ) {
+        if !tutorial.is_changed() {
+            return;
+        }
+        prompt.single_mut().sections[0].value = tutorial.step.prompt();
+    }
+}
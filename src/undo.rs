@@ -0,0 +1,95 @@
+//! The undo house rule, where the player whose turn it is may take back the steps they've taken
+//! since they rolled, as long as all each step did was move their pawn
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+
+use crate::game::{
+    CurrentTurn, MoveRejectionReason, Player, PlayerStartMoveAnimation, TurnLogEntry, TurnPhase,
+    UndoRequest,
+};
+use crate::server::{ClientRequests, MoveEventWriter};
+use crate::LabyrinthPlugin;
+
+/// Present on a server started with `--undo`
+#[derive(Resource, Default)]
+pub(crate) struct UndoSteps {
+    /// The cells the current player's steps since their roll started from, oldest first. Cleared
+    /// by anything that can't be taken back, see [`MoveEventWriter::forget_steps`].
+    pub(crate) from: Vec<IVec2>,
+}
+
+impl LabyrinthPlugin {
+    /// Runs after the move requests are handled and reads the turn phase they left behind, so
+    /// that a step and an undo sent together are taken in that order
+    pub(crate) fn server_receive_undos(
+        mut requests: ClientRequests,
+        mut players: Query<&mut Player>,
+        current_turn: Res<CurrentTurn>,
+        turn_phase: Res<State<TurnPhase>>,
+        mut next_turn_phase: ResMut<NextState<TurnPhase>>,
+        mut turn_phase_writer: EventWriter<ToClients<TurnPhase>>,
+        mut move_writer: MoveEventWriter,
+    ) {
+        let mut turn_phase = next_turn_phase.0.unwrap_or(*turn_phase.get());
+        for client_id in requests.undos() {
+            let Some(mut player) = players
+                .iter_mut()
+                .find(|player| player.client_id == client_id.raw())
+            else {
+                warn!("Client {client_id} asked to undo without a pawn");
+                continue;
+            };
+            if player.player_number != current_turn.0 {
+                move_writer.reject(&player, MoveRejectionReason::NotYourTurn);
+                continue;
+            }
+            let TurnPhase::Moving { steps_taken } = turn_phase else {
+                move_writer.reject(&player, MoveRejectionReason::WrongPhase);
+                continue;
+            };
+            let Some(from) = move_writer
+                .undo_steps
+                .as_mut()
+                .and_then(|undo_steps| undo_steps.from.pop())
+            else {
+                move_writer.reject(&player, MoveRejectionReason::CantUndo);
+                continue;
+            };
+
+            info!("Player {} took back a step", player.player_number);
+            move_writer.start_move_anim.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: PlayerStartMoveAnimation {
+                    client_id: player.client_id,
+                    fail: false,
+                    move_to: from,
+                },
+            });
+            move_writer.log(TurnLogEntry::Undone {
+                player_number: player.player_number,
+            });
+            player.prev_coords = player.coords;
+            player.coords = from;
+
+            turn_phase = TurnPhase::Moving {
+                steps_taken: steps_taken.saturating_sub(1),
+            };
+            next_turn_phase.set(turn_phase);
+            turn_phase_writer.send(ToClients {
+                mode: SendMode::Broadcast,
+                event: turn_phase,
+            });
+        }
+    }
+
+    /// Backspace takes back the last step
+    pub(crate) fn client_handle_undo_input(
+        keys: Res<Input<KeyCode>>,
+        mut undo_requests: EventWriter<UndoRequest>,
+    ) {
+        if keys.just_pressed(KeyCode::Back) {
+            undo_requests.send(UndoRequest);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! How the window is shown: windowed, borderless or fullscreen, with or without vsync, and at
+//! which resolution. Changed from the settings menu and kept in a file, so that the game opens
+//! the same way next time.
+
+use bevy::prelude::*;
+use bevy::window::{PresentMode, PrimaryWindow, WindowMode};
+use clap::ValueEnum;
+
+use crate::LabyrinthPlugin;
+
+const VIDEO_SETTINGS_FILE: &str = "video_settings.txt";
+
+/// The resolutions the settings menu cycles through, after the size the window was opened with
+const RESOLUTIONS: [UVec2; 5] = [
+    UVec2::new(1280, 720),
+    UVec2::new(1366, 768),
+    UVec2::new(1600, 900),
+    UVec2::new(1920, 1080),
+    UVec2::new(2560, 1440),
+];
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) enum DisplayMode {
+    #[default]
+    Windowed,
+    /// A window without a border covering the whole monitor
+    Borderless,
+    /// Exclusive fullscreen, switching the monitor to the chosen resolution if there is one
+    Fullscreen,
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub(crate) struct VideoSettings {
+    pub(crate) display_mode: DisplayMode,
+    pub(crate) vsync: bool,
+    /// `None` keeps the size the window was opened with, from `--width` and `--height`
+    pub(crate) resolution: Option<UVec2>,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            display_mode: DisplayMode::Windowed,
+            vsync: true,
+            resolution: None,
+        }
+    }
+}
+
+impl VideoSettings {
+    /// Reads the settings saved last time, using the defaults for anything missing or invalid
+    pub(crate) fn load() -> VideoSettings {
+        let mut settings = VideoSettings::default();
+        let file = match std::fs::read_to_string(VIDEO_SETTINGS_FILE) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return settings,
+            Err(err) => {
+                warn!("Failed to read {VIDEO_SETTINGS_FILE}: {err}");
+                return settings;
+            }
+        };
+        for line in file.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let valid = match key.trim() {
+                "display_mode" => DisplayMode::from_str(value, true)
+                    .map(|display_mode| settings.display_mode = display_mode)
+                    .is_ok(),
+                "vsync" => value.parse().map(|vsync| settings.vsync = vsync).is_ok(),
+                "resolution" => parse_resolution(value)
+                    .map(|resolution| settings.resolution = Some(resolution))
+                    .is_some(),
+                _ => true,
+            };
+            if !valid {
+                warn!("Ignoring invalid line in {VIDEO_SETTINGS_FILE}: {line}");
+            }
+        }
+        settings
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let mut file = format!(
+            "display_mode={}\nvsync={}\n",
+            self.display_mode
+                .to_possible_value()
+                .map_or_else(String::new, |value| value.get_name().to_owned()),
+            self.vsync
+        );
+        if let Some(resolution) = self.resolution {
+            file += &format!("resolution={}x{}\n", resolution.x, resolution.y);
+        }
+        std::fs::write(VIDEO_SETTINGS_FILE, file)
+    }
+
+    /// Switches to the resolution after or before this one in [`RESOLUTIONS`], with the
+    /// window's own size at the start
+    pub(crate) fn cycle_resolution(&mut self, forwards: bool) {
+        let options: Vec<_> = std::iter::once(None).chain(RESOLUTIONS.map(Some)).collect();
+        let index = options
+            .iter()
+            .position(|&resolution| resolution == self.resolution)
+            .unwrap_or(0);
+        let index = if forwards {
+            (index + 1) % options.len()
+        } else {
+            (index + options.len() - 1) % options.len()
+        };
+        self.resolution = options[index];
+    }
+
+    pub(crate) fn resolution_name(&self) -> String {
+        match self.resolution {
+            Some(resolution) => format!("{}x{}", resolution.x, resolution.y),
+            None => "window size".to_owned(),
+        }
+    }
+}
+
+fn parse_resolution(resolution: &str) -> Option<UVec2> {
+    let (width, height) = resolution.split_once('x')?;
+    let resolution = UVec2::new(width.trim().parse().ok()?, height.trim().parse().ok()?);
+    (resolution.min_element() > 0).then_some(resolution)
+}
+
+impl LabyrinthPlugin {
+    /// Applies the settings to the window whenever they change, and saves them if it was the
+    /// player who changed them
+    pub(crate) fn client_apply_video_settings(
+        settings: Res<VideoSettings>,
+        mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    ) {
+        if !settings.is_changed() {
+            return;
+        }
+        let Ok(mut window) = windows.get_single_mut() else {
+            return;
+        };
+        window.mode = match settings.display_mode {
+            DisplayMode::Windowed => WindowMode::Windowed,
+            DisplayMode::Borderless => WindowMode::BorderlessFullscreen,
+            // the video mode closest to the window's size, so the resolution has to be set too
+            DisplayMode::Fullscreen if settings.resolution.is_some() => WindowMode::SizedFullscreen,
+            DisplayMode::Fullscreen => WindowMode::Fullscreen,
+        };
+        window.present_mode = if settings.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        };
+        if let Some(resolution) = settings.resolution {
+            window
+                .resolution
+                .set(resolution.x as f32, resolution.y as f32);
+        }
+
+        if !settings.is_added() {
+            if let Err(err) = settings.save() {
+                warn!("Failed to save video settings to {VIDEO_SETTINGS_FILE}: {err}");
+            }
+        }
+    }
+}
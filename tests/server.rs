@@ -0,0 +1,409 @@
+//! Plays through games on an in-memory server, checking the rules it enforces
+
+use bevy::prelude::*;
+use labyrinth::game::{
+    AvailableItems, DiceRollRequest, GameOverReason, GameState, Maze, MoveRequest, Movement,
+    MovementChange, PauseToggle, ReadyToggle, ShiftRequest, TurnLogEntry, TurnPhase, UndoRequest,
+    DEFAULT_MOVEMENT_BUDGET, ITEMS_TO_WIN,
+};
+use labyrinth::testing::TestServer;
+use labyrinth::ServerArgs;
+
+const RED: u64 = 1;
+const GREEN: u64 = 2;
+
+/// A game between two players on a maze without any walls, so that tests can put walls exactly
+/// where they need them
+fn start_game() -> TestServer {
+    start_game_with(ServerArgs::default())
+}
+
+fn start_game_with(server_args: ServerArgs) -> TestServer {
+    let mut server = TestServer::new(ServerArgs {
+        max_players: 2,
+        match_seed: Some("integration-tests".to_owned()),
+        ..server_args
+    });
+    let size = server.maze_mut().size;
+    *server.maze_mut() = Maze::new(size);
+    server.connect(RED);
+    server.connect(GREEN);
+    server.send(RED, ReadyToggle);
+    server.send(GREEN, ReadyToggle);
+    server.update();
+    assert_eq!(GameState::InGame, server.game_state());
+    server
+}
+
+/// A step from `coords` that stays on the board
+fn step_on_board(coords: IVec2, size: usize) -> MoveRequest {
+    if coords.x == 0 {
+        MoveRequest::Right
+    } else if coords.x == size as i32 - 1 {
+        MoveRequest::Left
+    } else if coords.y == 0 {
+        MoveRequest::Up
+    } else {
+        MoveRequest::Down
+    }
+}
+
+/// Puts a wall between two neighbouring cells
+fn place_wall(maze: &mut Maze, a: IVec2, b: IVec2) {
+    let (low, high) = (a.min(b), a.max(b));
+    if low.x == high.x {
+        maze.horizontal_bars[low.y as usize][low.x as usize] = true;
+    } else {
+        maze.vertical_bars[low.y as usize][low.x as usize] = true;
+    }
+}
+
+/// Moves a player next to their target item, returning the step that collects it
+fn move_next_to_target(server: &mut TestServer, client_id: u64) -> MoveRequest {
+    let size = server.maze_mut().size;
+    let target = server
+        .player(client_id)
+        .target_item
+        .expect("players have a target during the game")
+        .coords(size);
+    let step = step_on_board(target, size);
+    server.player_mut(client_id).coords = target - step.delta();
+    step
+}
+
+/// Uses up the rest of a player's roll walking about, without stepping onto their target
+fn use_up_roll(server: &mut TestServer, client_id: u64) {
+    let player_number = server.player(client_id).player_number;
+    let size = server.maze_mut().size;
+    let on_board = |coords: IVec2| {
+        coords.cmpge(IVec2::ZERO).all() && coords.cmplt(IVec2::splat(size as i32)).all()
+    };
+    while let TurnPhase::Moving { steps_taken } = server.turn_phase() {
+        if server.current_turn() != player_number || steps_taken >= server.dice_value(player_number)
+        {
+            break;
+        }
+        let player = server.player(client_id);
+        let target = player.target_item.map(|item| item.coords(size));
+        let step = [
+            MoveRequest::Up,
+            MoveRequest::Down,
+            MoveRequest::Left,
+            MoveRequest::Right,
+        ]
+        .into_iter()
+        .find(|step| {
+            let to = player.coords + step.delta();
+            on_board(to) && Some(to) != target
+        })
+        .unwrap();
+        server.send(client_id, step);
+    }
+}
+
+#[test]
+fn players_take_turns_in_order() {
+    let mut server = start_game();
+    assert_eq!(0, server.current_turn());
+    assert_eq!(TurnPhase::Rolling, server.turn_phase());
+
+    // it isn't green's turn, so their requests are ignored
+    let size = server.maze_mut().size;
+    let green_coords = server.player(GREEN).coords;
+    server.send(GREEN, DiceRollRequest);
+    assert_eq!(TurnPhase::Rolling, server.turn_phase());
+    server.send(GREEN, step_on_board(green_coords, size));
+    assert_eq!(green_coords, server.player(GREEN).coords);
+
+    server.send(RED, DiceRollRequest);
+    assert!(matches!(server.turn_phase(), TurnPhase::Moving { .. }));
+    use_up_roll(&mut server, RED);
+    assert_eq!(1, server.current_turn());
+    assert_eq!(TurnPhase::Rolling, server.turn_phase());
+
+    server.send(GREEN, DiceRollRequest);
+    use_up_roll(&mut server, GREEN);
+    assert_eq!(0, server.current_turn());
+}
+
+#[test]
+fn only_one_roll_counts_when_two_arrive_in_the_same_frame() {
+    let mut server = start_game();
+    server.queue(RED, DiceRollRequest);
+    server.queue(RED, DiceRollRequest);
+    server.update();
+
+    assert_eq!(TurnPhase::Moving { steps_taken: 0 }, server.turn_phase());
+    let rolls: Vec<_> = server
+        .turn_log()
+        .into_iter()
+        .filter_map(|entry| match entry {
+            TurnLogEntry::Rolled { value, .. } => Some(value),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec![server.dice_value(0)], rolls);
+}
+
+#[test]
+fn walking_into_a_wall_sends_the_player_back_and_ends_their_turn() {
+    let mut server = start_game();
+    let size = server.maze_mut().size;
+    let start = server.player(RED).coords;
+    let step = step_on_board(start, size);
+    server.decide_next_roll(2);
+    server.send(RED, DiceRollRequest);
+    // a step that doesn't crash first, so that there's somewhere to be sent back from
+    server.send(RED, step);
+    let coords = server.player(RED).coords;
+    assert_eq!(start + step.delta(), coords);
+
+    place_wall(&mut server.maze_mut(), coords, coords + step.delta());
+    server.send(RED, step);
+    let red = server.player(RED);
+    assert_eq!(start, red.coords);
+    assert_eq!(1, red.crashes);
+    assert_eq!(1, server.current_turn());
+}
+
+#[test]
+fn stepping_onto_the_target_item_collects_it() {
+    let mut server = start_game();
+    let target = server.player(RED).target_item;
+    let step = move_next_to_target(&mut server, RED);
+    server.send(RED, DiceRollRequest);
+    server.send(RED, step);
+
+    let red = server.player(RED);
+    assert_eq!(1, red.achieved_items.len());
+    assert_eq!(
+        target.map(|item| item.to_string()),
+        red.achieved_items.first().map(|item| item.to_string())
+    );
+    assert!(red.target_item.is_some());
+    assert_eq!(GameState::InGame, server.game_state());
+}
+
+#[test]
+fn crashing_on_a_momentum_step_ends_the_turn() {
+    let mut server = start_game_with(ServerArgs {
+        momentum: true,
+        ..default()
+    });
+    let step = move_next_to_target(&mut server, RED);
+    server.send(RED, DiceRollRequest);
+    // collecting the item earns a step on top of the roll
+    server.send(RED, step);
+    let red = server.player(RED);
+    assert_eq!(1, red.achieved_items.len());
+    assert_eq!(1, red.bonus_steps);
+    use_up_roll(&mut server, RED);
+    assert_eq!(0, server.current_turn());
+
+    let size = server.maze_mut().size;
+    let coords = server.player(RED).coords;
+    let step = step_on_board(coords, size);
+    place_wall(&mut server.maze_mut(), coords, coords + step.delta());
+    server.send(RED, step);
+    assert_eq!(1, server.player(RED).crashes);
+    assert_eq!(1, server.current_turn());
+    assert_eq!(TurnPhase::Rolling, server.turn_phase());
+}
+
+#[test]
+fn collecting_the_last_item_wins() {
+    let mut server = start_game();
+    let target = server.player(RED).target_item.unwrap();
+    server.player_mut(RED).achieved_items = vec![target; ITEMS_TO_WIN - 1];
+    let step = move_next_to_target(&mut server, RED);
+    server.send(RED, DiceRollRequest);
+    server.send(RED, step);
+    server.update();
+
+    assert_eq!(GameState::Ended, server.game_state());
+    let result = server
+        .game_result()
+        .expect("the game has a result once it's over");
+    assert_eq!(Some(0), result.winner);
+    assert_eq!(GameOverReason::ItemsCollected, result.reason);
+}
+
+#[test]
+fn the_game_is_a_draw_once_nobody_can_win() {
+    let mut server = start_game();
+    // nothing is left to deal, so nobody can collect enough items any more
+    server
+        .app
+        .world
+        .resource_mut::<AvailableItems>()
+        .items
+        .clear();
+    let step = move_next_to_target(&mut server, RED);
+    server.send(RED, DiceRollRequest);
+    server.send(RED, step);
+    server.update();
+
+    assert_eq!(GameState::Ended, server.game_state());
+    let result = server.game_result().unwrap();
+    assert_eq!(None, result.winner);
+    assert_eq!(GameOverReason::Unwinnable, result.reason);
+}
+
+#[test]
+fn the_leader_wins_at_the_turn_limit() {
+    let mut server = start_game_with(ServerArgs {
+        max_turns: Some(1),
+        ..default()
+    });
+    let target = server.player(RED).target_item.unwrap();
+    server.player_mut(RED).achieved_items.push(target);
+    server.send(RED, DiceRollRequest);
+    use_up_roll(&mut server, RED);
+    server.update();
+
+    assert_eq!(GameState::Ended, server.game_state());
+    let result = server.game_result().unwrap();
+    assert_eq!(Some(0), result.winner);
+    assert_eq!(GameOverReason::TurnLimit, result.reason);
+}
+
+#[test]
+fn the_leader_wins_at_the_time_limit() {
+    let mut server = start_game_with(ServerArgs {
+        max_minutes: Some(1),
+        ..default()
+    });
+    let target = server.player(GREEN).target_item.unwrap();
+    server.player_mut(GREEN).achieved_items.push(target);
+    let mut frames = 0;
+    while server.game_state() == GameState::InGame {
+        server.update();
+        frames += 1;
+        assert!(frames < 1000, "the time limit never ended the game");
+    }
+
+    let result = server.game_result().unwrap();
+    assert_eq!(Some(1), result.winner);
+    assert_eq!(GameOverReason::TimeLimit, result.reason);
+}
+
+#[test]
+fn the_last_player_standing_wins() {
+    let mut server = start_game_with(ServerArgs {
+        reconnect_grace_secs: 0,
+        ..default()
+    });
+    server.disconnect(GREEN);
+    server.update();
+
+    assert_eq!(GameState::Ended, server.game_state());
+    let result = server.game_result().unwrap();
+    assert_eq!(Some(0), result.winner);
+    assert_eq!(GameOverReason::LastPlayerStanding, result.reason);
+}
+
+#[test]
+fn shutting_the_server_down_ends_the_game_in_a_draw() {
+    let mut server = start_game();
+    server.shut_down();
+    server.update();
+
+    assert_eq!(GameState::Ended, server.game_state());
+    let result = server.game_result().unwrap();
+    assert_eq!(None, result.winner);
+    assert_eq!(GameOverReason::ServerEnded, result.reason);
+}
+
+#[test]
+fn pushing_a_row_slides_its_walls_once_per_turn() {
+    let mut server = start_game_with(ServerArgs {
+        shifting_maze: true,
+        ..default()
+    });
+    place_wall(&mut server.maze_mut(), IVec2::new(1, 2), IVec2::new(2, 2));
+    let push = ShiftRequest {
+        index: 2,
+        direction: MoveRequest::Right,
+    };
+
+    // only the player whose turn it is can push
+    server.send(GREEN, push);
+    assert!(server.maze_mut().vertical_bars[2][1]);
+
+    server.send(RED, push);
+    assert!(!server.maze_mut().vertical_bars[2][1]);
+    assert!(server.maze_mut().vertical_bars[2][2]);
+    server.send(RED, push);
+    assert!(server.maze_mut().vertical_bars[2][2]);
+
+    server.send(RED, DiceRollRequest);
+    assert!(matches!(server.turn_phase(), TurnPhase::Moving { .. }));
+}
+
+#[test]
+fn undoing_takes_back_the_last_step() {
+    let mut server = start_game_with(ServerArgs {
+        undo: true,
+        movement: Movement::Budget(DEFAULT_MOVEMENT_BUDGET),
+        ..default()
+    });
+    let size = server.maze_mut().size;
+    let start = server.player(RED).coords;
+    let step = step_on_board(start, size);
+
+    // nothing to take back before the roll
+    server.send(RED, UndoRequest);
+    assert_eq!(TurnPhase::Rolling, server.turn_phase());
+
+    server.send(RED, DiceRollRequest);
+    server.send(RED, step);
+    assert_eq!(TurnPhase::Moving { steps_taken: 1 }, server.turn_phase());
+    server.send(GREEN, UndoRequest);
+    assert_eq!(start + step.delta(), server.player(RED).coords);
+
+    server.send(RED, UndoRequest);
+    assert_eq!(start, server.player(RED).coords);
+    assert_eq!(TurnPhase::Moving { steps_taken: 0 }, server.turn_phase());
+    server.send(RED, UndoRequest);
+    assert_eq!(TurnPhase::Moving { steps_taken: 0 }, server.turn_phase());
+}
+
+#[test]
+fn nothing_happens_while_the_game_is_paused() {
+    let mut server = start_game();
+    server.send(RED, PauseToggle);
+    assert_eq!(GameState::Paused, server.game_state());
+
+    server.send(RED, DiceRollRequest);
+    assert_eq!(TurnPhase::Rolling, server.turn_phase());
+    // green can't resume on red's turn
+    server.send(GREEN, PauseToggle);
+    assert_eq!(GameState::Paused, server.game_state());
+
+    server.send(RED, PauseToggle);
+    assert_eq!(GameState::InGame, server.game_state());
+    server.send(RED, DiceRollRequest);
+    assert!(matches!(server.turn_phase(), TurnPhase::Moving { .. }));
+}
+
+#[test]
+fn changing_the_movement_rule_makes_everyone_ready_up_again() {
+    let mut server = TestServer::new(ServerArgs {
+        max_players: 2,
+        ..default()
+    });
+    server.connect(RED);
+    server.connect(GREEN);
+    server.send(RED, ReadyToggle);
+    // two dice, then a fixed budget
+    server.send(GREEN, MovementChange);
+    server.send(GREEN, MovementChange);
+    assert!(!server.player(RED).ready);
+
+    server.send(GREEN, ReadyToggle);
+    assert_eq!(GameState::WaitingPlayers, server.game_state());
+    server.send(RED, ReadyToggle);
+    assert_eq!(GameState::InGame, server.game_state());
+    server.send(RED, DiceRollRequest);
+    assert_eq!(DEFAULT_MOVEMENT_BUDGET, server.dice_value(0));
+}